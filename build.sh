@@ -13,10 +13,10 @@ sed -i "s/$OLD/$NEW/g" Cargo.toml
 rm -f libminiz_oxide_c_api.a
 
 if [[ ($# == 0 || $1 == "--release" ) ]]; then
-    RUSTFLAGS="-g" cargo build --release --features=miniz_zip -- || exit 1
+    RUSTFLAGS="-g" cargo build --release -- || exit 1
     cp target/release/libminiz_oxide_c_api.a .
 elif [[ $1 == "--debug" ]]; then
-    cargo build --features=miniz_zip || exit 1
+    cargo build || exit 1
     cp target/debug/libminiz_oxide_c_api.a .
 else
     echo --relese or --debug
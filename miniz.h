@@ -93,6 +93,19 @@
 
 #define TINFL_LZ_DICT_SIZE 32768
 
+/**
+ * Size in bytes of `tdefl_compressor`, as declared in the `miniz.h` generated for this crate.
+ *
+ * This is larger than the 319352 bytes of the struct in the original `miniz.h`, so C code that
+ * allocates the struct itself has to be compiled against the generated header.
+ */
+#define TDEFL_COMPRESSOR_SIZE 319488
+
+/**
+ * Size in bytes of `tinfl_decompressor`, the same as in the original `miniz.h`.
+ */
+#define TINFL_DECOMPRESSOR_SIZE 11000
+
 /**
  * Should we try to parse a zlib header?
  *
@@ -197,6 +210,7 @@ typedef enum StateTypeEnum {
   None = 0,
   InflateType,
   DeflateType,
+  InflateBackType,
 } StateTypeEnum;
 
 typedef enum tdefl_flush {
@@ -225,15 +239,15 @@ typedef enum tinfl_status {
 
 /**
  * Main compression struct. Not the same as `CompressorOxide`
- * #[repr(C)]
- */
-typedef struct tdefl_compressor tdefl_compressor;
-
-/**
- * Main decompression struct.
  *
+ * The compressor and the buffers it uses are stored inline so C code can place the struct
+ * wherever it wants, like the one in miniz. `tdefl_init` has to be called before it is used,
+ * after which it must not be moved, as the compressor refers to the buffers. It holds no other
+ * resources, so it doesn't need to be freed.
  */
-typedef struct DecompressorOxide DecompressorOxide;
+typedef struct tdefl_compressor {
+  uint64_t m_storage[TDEFL_COMPRESSOR_SIZE / 8];
+} tdefl_compressor;
 
 typedef struct InternalState InternalState;
 
@@ -272,17 +286,17 @@ typedef struct mz_stream {
   const char *msg;
   /**
    * Compressor or decompressor, if it exists.
-   * This is boxed to work with the current C API.
+   * This is allocated with `zalloc` to work with the current C API.
    */
   struct InternalState *state;
   /**
    * Allocation function to use for allocating the internal compressor/decompressor.
-   * Uses `mz_default_alloc_func` if set to `None`.
+   * Uses `miniz_def_alloc_func` if set to `None`, and is set to it when the stream is used.
    */
   mz_alloc_callback zalloc;
   /**
-   * Free function to use for allocating the internal compressor/decompressor.
-   * Uses `mz_default_free_func` if `None`.
+   * Free function to use for freeing the internal compressor/decompressor.
+   * Uses `miniz_def_free_func` if set to `None`, and is set to it when the stream is used.
    */
   mz_free_callback zfree;
   /**
@@ -306,8 +320,21 @@ typedef struct mz_stream {
 
 typedef int32_t (*tdefl_put_buf_func_ptr)(const void*, int, void*);
 
+/**
+ * Decompression callback function type, called with each chunk of decompressed data.
+ * Returning 0 stops decompression.
+ */
+typedef int (*tinfl_put_buf_func_ptr)(const void*, int, void*);
+
+/**
+ * Main decompression struct.
+ *
+ * The decompressor is stored inline so C code can place the struct wherever it wants, like
+ * the one in miniz, as long as `tinfl_init` is called before it is used. It holds no other
+ * resources, so it doesn't need to be freed.
+ */
 typedef struct tinfl_decompressor {
-  struct DecompressorOxide *inner;
+  uint64_t m_storage[TINFL_DECOMPRESSOR_SIZE / 8];
 } tinfl_decompressor;
 
 /**
@@ -322,6 +349,17 @@ typedef void (*mz_free_func)(void*, void*);
 
 typedef void *(*mz_realloc_func)(void*, void*, size_t, size_t);
 
+/**
+ * Input function for `mz_inflateBack`. Sets `*buf` to the next input and returns its length,
+ * or 0 at the end of the input.
+ */
+typedef unsigned int (*mz_in_func)(void*, const uint8_t**);
+
+/**
+ * Output function for `mz_inflateBack`. Returns non-zero to stop decompression.
+ */
+typedef int (*mz_out_func)(void*, uint8_t*, unsigned int);
+
 #ifdef __cplusplus
 extern "C" {
 #endif // __cplusplus
@@ -332,6 +370,8 @@ int mz_deflateEnd(struct mz_stream *stream);
 
 int mz_deflateReset(struct mz_stream *stream);
 
+int mz_deflateParams(struct mz_stream *stream, int level, int strategy);
+
 int mz_inflate(struct mz_stream *stream, int flush);
 
 int mz_inflateEnd(struct mz_stream *stream);
@@ -347,6 +387,31 @@ int mz_deflateInit2(struct mz_stream *stream,
 
 int mz_inflateInit2(struct mz_stream *stream, int window_bits);
 
+/**
+ * Set up a stream for `mz_inflateBack`, which decompresses raw deflate data using the
+ * `1 << window_bits` bytes at `window` as its only buffer.
+ *
+ * `window_bits` has to be from 8 to 15, and the window has to stay valid until
+ * `mz_inflateBackEnd` is called.
+ */
+int mz_inflateBackInit(struct mz_stream *stream, int window_bits, uint8_t *window);
+
+/**
+ * Decompress a whole raw deflate stream, starting with the input in `next_in` and calling
+ * `in_func` for more, and passing the output to `out_func` each time the window is full.
+ *
+ * Returns `MZ_STREAM_END` when done, with `next_in` and `avail_in` set to the unused
+ * input. Returns `MZ_BUF_ERROR` if `in_func` returned no input, in which case `next_in` is
+ * null, or if `out_func` returned non-zero.
+ */
+int mz_inflateBack(struct mz_stream *stream,
+                   mz_in_func in_func,
+                   void *in_desc,
+                   mz_out_func out_func,
+                   void *out_desc);
+
+int mz_inflateBackEnd(struct mz_stream *stream);
+
 int mz_compress(uint8_t *dest,
                 unsigned long *dest_len,
                 const uint8_t *source,
@@ -369,6 +434,16 @@ int mz_uncompress(uint8_t *dest,
 
 unsigned long mz_compressBound(unsigned long source_len);
 
+/**
+ * Get a description of the return code `err`, or null if it isn't one.
+ */
+const char *mz_error(int err);
+
+/**
+ * Get the version of the miniz API that is implemented.
+ */
+const char *mz_version(void);
+
 enum tdefl_status tdefl_compress(struct tdefl_compressor *d,
                                  const void *in_buf,
                                  uintptr_t *in_size,
@@ -386,24 +461,31 @@ enum tdefl_status tdefl_compress_buffer(struct tdefl_compressor *d,
  *
  * This does initialize the struct, but not the inner constructor,
  * tdefl_init has to be called before doing anything with it.
+ * Returns null if allocation fails.
  */
 struct tdefl_compressor *tdefl_allocate(void);
 
 /**
- * Deallocate the compressor. (Does nothing if the argument is null).
- *
- * This also calles the compressor's destructor, freeing the internal memory
- * allocated by it.
+ * Deallocate a compressor allocated with `tdefl_allocate`. (Does nothing if the argument
+ * is null).
  */
 void tdefl_deallocate(struct tdefl_compressor *c);
 
 /**
- * Initialize the compressor struct in the space pointed to by `d`.
+ * Allocate a compressor, the same as `tdefl_allocate`.
+ */
+struct tdefl_compressor *tdefl_compressor_alloc(void);
+
+/**
+ * Deallocate a compressor allocated with `tdefl_compressor_alloc`, the same as
+ * `tdefl_deallocate`.
+ */
+void tdefl_compressor_free(struct tdefl_compressor *c);
+
+/**
+ * Initialize the compressor struct in the space pointed to by `d`, which does not have to
+ * be initialized already.
  * if d is null, an error is returned.
- *
- * Deinitialization is handled by tdefl_deallocate, and thus
- * Compressor should not be allocated or freed manually, but only through
- * tdefl_allocate and tdefl_deallocate
  */
 enum tdefl_status tdefl_init(struct tdefl_compressor *d,
                              tdefl_put_buf_func_ptr put_buf_func,
@@ -433,6 +515,31 @@ uintptr_t tdefl_compress_mem_to_mem(void *out_buf,
 
 unsigned int tdefl_create_comp_flags_from_zip_params(int level, int window_bits, int strategy);
 
+/**
+ * Write an image with `num_chans` 8 bit channels (1 to 4, gray, gray+alpha, RGB or RGBA)
+ * to a PNG file in a newly allocated buffer, which should be freed with `mz_free`.
+ *
+ * If `flip` is non-zero the image is flipped vertically. Returns null and sets
+ * `*len_out` to 0 on failure.
+ */
+void *tdefl_write_image_to_png_file_in_memory_ex(const void *image,
+                                                 int w,
+                                                 int h,
+                                                 int num_chans,
+                                                 size_t *len_out,
+                                                 unsigned int level,
+                                                 int flip);
+
+/**
+ * Write an image to a PNG file in memory using the default compression level and no
+ * flipping. See `tdefl_write_image_to_png_file_in_memory_ex`.
+ */
+void *tdefl_write_image_to_png_file_in_memory(const void *image,
+                                              int w,
+                                              int h,
+                                              int num_chans,
+                                              size_t *len_out);
+
 int32_t tinfl_decompress(struct tinfl_decompressor *r,
                          const uint8_t *in_buf,
                          uintptr_t *in_buf_size,
@@ -460,21 +567,36 @@ void *tinfl_decompress_mem_to_heap(const void *p_src_buf,
                                    int flags);
 
 /**
- * Allocate a compressor.
+ * Decompress data from `p_in_buf` into a 32 KiB dictionary, passing the output to
+ * `put_buf_func` one chunk at a time.
  *
- * This does initialize the struct, but not the inner constructor,
- * tdefl_init has to be called before doing anything with it.
+ * Sets `p_in_buf_size` to the number of input bytes consumed.
+ * Returns 1 if the end of the stream was reached, and 0 if decompression failed or the
+ * callback returned 0.
  */
-struct tinfl_decompressor *tinfl_decompressor_alloc(void);
+int tinfl_decompress_mem_to_callback(const void *p_in_buf,
+                                     size_t *p_in_buf_size,
+                                     tinfl_put_buf_func_ptr put_buf_func,
+                                     void *put_buf_user,
+                                     int flags);
 
 /**
- * Deallocate the compressor. (Does nothing if the argument is null).
+ * Allocate a decompressor.
  *
- * This also calles the compressor's destructor, freeing the internal memory
- * allocated by it.
+ * The decompressor is initialized, so `tinfl_init` only has to be called to reuse it.
+ */
+struct tinfl_decompressor *tinfl_decompressor_alloc(void);
+
+/**
+ * Deallocate a decompressor allocated with `tinfl_decompressor_alloc`. (Does nothing if
+ * the argument is null).
  */
 void tinfl_decompressor_free(struct tinfl_decompressor *c);
 
+/**
+ * Initialize the decompressor in the space pointed to by `c`, which does not have to be
+ * initialized already.
+ */
 void tinfl_init(struct tinfl_decompressor *c);
 
 int tinfl_get_adler32(struct tinfl_decompressor *c);
@@ -491,6 +613,12 @@ void miniz_def_free_func(void *_opaque, void *address);
 
 void *miniz_def_realloc_func(void *_opaque, void *address, size_t items, size_t size);
 
+/**
+ * Free memory allocated by the library and returned to the caller, such as the PNG image
+ * from `tdefl_write_image_to_png_file_in_memory`, using `free`.
+ */
+void mz_free(void *p);
+
 /**
  * Calculate adler32 checksum of the provided buffer with the initial adler32 checksum of `adler`.
  * If c_ulong is wider than 32 bits, only the lower 32 bits will be used.
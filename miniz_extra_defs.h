@@ -136,6 +136,7 @@ typedef void *const voidpc;
 #define deflateInit mz_deflateInit
 #define deflateInit2 mz_deflateInit2
 #define deflateReset mz_deflateReset
+#define deflateParams mz_deflateParams
 #define deflate mz_deflate
 #define deflateEnd mz_deflateEnd
 #define deflateBound mz_deflateBound
@@ -147,6 +148,11 @@ typedef void *const voidpc;
 #define inflateReset mz_inflateReset
 #define inflate mz_inflate
 #define inflateEnd mz_inflateEnd
+#define in_func mz_in_func
+#define out_func mz_out_func
+#define inflateBackInit mz_inflateBackInit
+#define inflateBack mz_inflateBack
+#define inflateBackEnd mz_inflateBackEnd
 #define uncompress mz_uncompress
 #define uncompress2 mz_uncompress2
 #define crc32 mz_crc32
@@ -164,8 +170,6 @@ typedef void *const voidpc;
 #define zlib_version mz_version()
 #endif /* #ifndef MINIZ_NO_ZLIB_COMPATIBLE_NAMES */
 
-void mz_free(void *p);
-
 typedef int (*tinfl_put_buf_func_ptr)(const void *pBuf, int len, void *pUser);
 /* Not implemented yet.*/
 int tinfl_decompress_mem_to_callback(const void *pIn_buf, size_t *pIn_buf_size, tinfl_put_buf_func_ptr pPut_buf_func, void *pPut_buf_user, int flags);
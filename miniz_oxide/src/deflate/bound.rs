@@ -0,0 +1,333 @@
+//! Upper bounds on the size of compressed data.
+
+use core::cmp;
+
+use crate::deflate::buffer::{fast_raw_block_limit, raw_block_limit};
+use crate::deflate::core::{BufferSizes, CompressionParams, MIN_COMPRESSIBLE_AGAIN_SIZE};
+use crate::DataFormat;
+
+/// Bits used by a stored block in addition to the data: the 3 bit block header, up to 7 bits of
+/// padding to reach a byte boundary and the 16 bit length and its complement.
+const STORED_BLOCK_BITS: u64 = 3 + 7 + 32;
+/// Bits used by a stored block that starts on a byte boundary, as all blocks do when only stored
+/// blocks are written.
+const ALIGNED_STORED_BLOCK_BITS: u64 = 3 + 5 + 32;
+/// Size of the zlib header and adler32 checksum.
+const ZLIB_FRAMING_SIZE: u64 = 2 + 4;
+/// Size of a gzip header without any of the optional fields, and the CRC-32 and size trailer.
+pub const GZIP_FRAMING_SIZE: usize = 10 + 8;
+
+/// Get an upper bound on the size of the output of compressing `len` bytes in one go with the
+/// given parameters and the default buffer sizes.
+///
+/// This can be used to size the output buffer so that compressing with
+/// [`TDEFLFlush::Finish`][crate::deflate::core::TDEFLFlush::Finish] always completes in a single
+/// call.
+///
+/// The compressor stores any block that would take more space coded than stored, so the bound
+/// is the size of the input plus a few bytes for each block. With
+/// [`raw_blocks`](CompressionParams::raw_blocks), and thus compression level 0, the output consists of stored blocks of up to 31 KiB only and
+/// the bound is exact for data that fills whole blocks. With the other strategies and the default
+/// buffer sizes blocks can be as small as 1 KiB, and the bound allows for 5 bytes of overhead for
+/// each of them.
+///
+/// The bound includes the zlib header and checksum if the format is
+/// [`DataFormat::Zlib`]. For data wrapped in a gzip member by the caller, use
+/// [`gzip_compress_bound`].
+///
+/// ```
+/// use miniz_oxide::deflate::compress_bound;
+/// use miniz_oxide::deflate::core::CompressionParams;
+///
+/// let params = CompressionParams::from_level(0);
+/// assert_eq!(compress_bound(1000, &params), 1000 + 5 + 6);
+/// ```
+pub fn compress_bound(len: usize, params: &CompressionParams) -> usize {
+    compress_bound_with_sizes(len, params, &BufferSizes::default(), 0)
+}
+
+/// Get an upper bound on the size of a gzip member holding `len` bytes compressed in one go with
+/// the given parameters and the default buffer sizes.
+///
+/// The compressor doesn't write the gzip wrapper, so the deflate data is counted as raw whatever
+/// the [`format`](CompressionParams::format) of `params`, and [`GZIP_FRAMING_SIZE`] bytes are
+/// added for a header without optional fields and the trailer. With other buffer sizes or
+/// flushes, add [`GZIP_FRAMING_SIZE`] to the bound from [`compress_bound_with_sizes`] for
+/// [`DataFormat::Raw`] instead.
+///
+/// ```
+/// use miniz_oxide::deflate::gzip_compress_bound;
+/// use miniz_oxide::deflate::core::CompressionParams;
+///
+/// let params = CompressionParams::from_level(0);
+/// assert_eq!(gzip_compress_bound(1000, &params), 1000 + 5 + 18);
+/// ```
+pub fn gzip_compress_bound(len: usize, params: &CompressionParams) -> usize {
+    let mut raw_params = *params;
+    raw_params.format = DataFormat::Raw;
+    compress_bound(len, &raw_params).saturating_add(GZIP_FRAMING_SIZE)
+}
+
+/// Get an upper bound on the size of the output of compressing `len` bytes with the given
+/// parameters and buffer sizes, with up to `flushes` calls using
+/// [`TDEFLFlush::Sync`][crate::deflate::core::TDEFLFlush::Sync] or
+/// [`TDEFLFlush::Full`][crate::deflate::core::TDEFLFlush::Full] before the stream is finished.
+/// Partial and block flushes write less and can be counted the same way.
+///
+/// Each flush ends the current block early and writes an empty stored block as a marker.
+/// Changing the strategy of a compressor in the middle of a stream also ends the current block
+/// and should be counted as a flush, using the parameters that give the larger bound. In
+/// rsyncable mode the compressor is reset as if flushing at most once for every 2048 bytes of
+/// input, so `len / 2048` flushes should be added.
+///
+/// See [`compress_bound`] for details.
+pub fn compress_bound_with_sizes(
+    len: usize,
+    params: &CompressionParams,
+    sizes: &BufferSizes,
+    flushes: usize,
+) -> usize {
+    let len = len as u64;
+    let flushes = flushes as u64;
+
+    let window_size = sizes.window_size();
+    // A block is only ended before reaching this size by a flush or the end of the stream.
+    let full_block_size = cmp::min(
+        raw_block_limit(window_size) + 1,
+        // Each literal takes a byte in the code buffer, and there is a flag byte for every 8
+        // codes. Matches need less space in the buffer per byte of input.
+        (sizes.lz_code_buf_size() - 10) * 8 / 9,
+    ) as u64;
+
+    let bits = if params.raw_blocks {
+        let blocks = len / full_block_size + flushes + 1;
+        len * 8 + (blocks + flushes) * ALIGNED_STORED_BLOCK_BITS
+    } else {
+        // Blocks that may take more space coded than stored are ended while they can still be
+        // stored, which is sooner at level 1, and stored blocks are ended early when the input
+        // looks compressible again.
+        let min_block_size = cmp::min(
+            cmp::min(
+                full_block_size,
+                fast_raw_block_limit(window_size) as u64 + 1,
+            ),
+            u64::from(MIN_COMPRESSIBLE_AGAIN_SIZE),
+        );
+        let blocks = len / min_block_size + flushes + 1;
+        // Every block is either stored or takes less space coded, apart from the bits of the
+        // last code added to a block that no longer fits in the window. Blocks of up to 32 bytes
+        // are always coded with the fixed huffman codes, which take at most 9 bits per byte and
+        // so no more than a stored block. The final block is padded to a byte boundary.
+        len * 8 + (blocks + flushes) * STORED_BLOCK_BITS + 7
+    };
+
+    let framing = match params.format {
+        DataFormat::Zlib | DataFormat::ZLibIgnoreChecksum => ZLIB_FRAMING_SIZE,
+        DataFormat::Raw => 0,
+    };
+
+    let bound = (bits + 7) / 8 + framing;
+    cmp::min(bound, usize::max_value() as u64) as usize
+}
+
+#[cfg(all(test, feature = "with-alloc"))]
+mod test {
+    use super::{
+        compress_bound, compress_bound_with_sizes, gzip_compress_bound, GZIP_FRAMING_SIZE,
+    };
+    use crate::deflate::core::{
+        compress, BufferSizes, CompressionParams, CompressionStrategy, CompressorOxide, TDEFLFlush,
+        TDEFLStatus,
+    };
+    use crate::DataFormat;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::cmp;
+
+    /// Data that doesn't compress, with an option to only use bytes that take 9 bits to encode
+    /// with the fixed huffman codes.
+    fn random_data(len: usize, nine_bit_literals: bool) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                if nine_bit_literals {
+                    144 + (state % 112) as u8
+                } else {
+                    state as u8
+                }
+            })
+            .collect()
+    }
+
+    /// Random data with a match repeating 4 bytes from far back after every 20 bytes, which has
+    /// enough matches to not look incompressible but could take more than 8 bits per byte with
+    /// the fixed huffman codes.
+    fn far_matches_data(len: usize) -> Vec<u8> {
+        let random = random_data(len, false);
+        let mut data = Vec::with_capacity(len + len / 5);
+        for chunk in random.chunks(20) {
+            data.extend_from_slice(chunk);
+            if data.len() > 20_000 {
+                let from = data.len() - 20_000;
+                let repeat = [data[from], data[from + 1], data[from + 2], data[from + 3]];
+                data.extend_from_slice(&repeat);
+            }
+        }
+        data.truncate(len);
+        data
+    }
+
+    /// Compress `data` in one call with a buffer of exactly `bound` bytes, splitting the input
+    /// into `flushes + 1` chunks each ended by a sync flush, and return the compressed size.
+    fn compress_with_bound(
+        data: &[u8],
+        params: &CompressionParams,
+        sizes: BufferSizes,
+        flushes: usize,
+    ) -> usize {
+        let bound = compress_bound_with_sizes(data.len(), params, &sizes, flushes);
+        let mut output = vec![0; bound];
+        let mut compressor = CompressorOxide::new_with_buffer_sizes(params.to_flags(), sizes);
+        compressor.set_params(*params);
+
+        let chunk_size = data.len() / (flushes + 1) + 1;
+        let mut out_pos = 0;
+        let mut chunks = data.chunks(chunk_size).peekable();
+        for _ in 0..flushes {
+            let chunk = chunks.next().unwrap_or(&[]);
+            let (status, in_pos, written) = compress(
+                &mut compressor,
+                chunk,
+                &mut output[out_pos..],
+                TDEFLFlush::Sync,
+            );
+            assert_eq!(status, TDEFLStatus::Okay);
+            assert_eq!(in_pos, chunk.len());
+            out_pos += written;
+        }
+        let rest = chunks.next().unwrap_or(&[]);
+        assert!(chunks.peek().is_none());
+        let (status, in_pos, written) = compress(
+            &mut compressor,
+            rest,
+            &mut output[out_pos..],
+            TDEFLFlush::Finish,
+        );
+        assert_eq!(
+            status,
+            TDEFLStatus::Done,
+            "bound {} too small for {:?} {:?}",
+            bound,
+            params,
+            sizes
+        );
+        assert_eq!(in_pos, rest.len());
+        out_pos + written
+    }
+
+    #[test]
+    fn bound_holds() {
+        let strategies = [
+            CompressionStrategy::Default,
+            CompressionStrategy::Filtered,
+            CompressionStrategy::HuffmanOnly,
+            CompressionStrategy::RLE,
+            CompressionStrategy::Fixed,
+        ];
+        let sizes = [
+            BufferSizes::default(),
+            BufferSizes::new(10, 8, 4096),
+            BufferSizes::low_memory(),
+        ];
+        for &len in &[0, 1, 31, 33, 48, 1000, 5000, 40_000] {
+            for &nine_bit in &[false, true] {
+                let data = random_data(len, nine_bit);
+                for level in [0, 1, 6].iter() {
+                    for strategy in strategies.iter() {
+                        for buffer_sizes in sizes.iter() {
+                            for flushes in [0, 3].iter() {
+                                let mut params = CompressionParams::from_level(*level);
+                                if *level != 0 {
+                                    params.strategy = *strategy;
+                                }
+                                compress_with_bound(&data, &params, *buffer_sizes, *flushes);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The bound used by `mz_deflateBound` in the C API.
+    fn old_bound(len: usize) -> usize {
+        cmp::max(
+            128 + (len * 110) / 100,
+            128 + len + ((len / (31 * 1024)) + 1) * 5,
+        )
+    }
+
+    #[test]
+    fn bound_holds_for_far_matches() {
+        let data = far_matches_data(300_000);
+        for level in 1..=10 {
+            let params = CompressionParams::from_level(level);
+            for buffer_sizes in [BufferSizes::default(), BufferSizes::low_memory()].iter() {
+                compress_with_bound(&data, &params, *buffer_sizes, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn bound_within_old_bound() {
+        for &len in &[0, 1, 100, 5000, 100_000, 1_000_000, 1 << 30] {
+            for level in 0..=10 {
+                let mut params = CompressionParams::from_level(level);
+                params.format = DataFormat::Zlib;
+                assert!(compress_bound(len, &params) <= old_bound(len));
+            }
+        }
+        let params = CompressionParams::from_level(6);
+        assert!(compress_bound(1_000_000, &params) < 1_006_000);
+    }
+
+    #[test]
+    fn stored_bound_is_tight() {
+        let data = random_data(100_000, false);
+        let mut params = CompressionParams::from_level(0);
+        for &format in &[DataFormat::Raw, DataFormat::Zlib] {
+            params.format = format;
+            let len = compress_with_bound(&data, &params, BufferSizes::default(), 0);
+            assert_eq!(len, compress_bound(data.len(), &params));
+        }
+    }
+
+    #[test]
+    fn gzip_bound_adds_framing() {
+        let data = random_data(100_000, false);
+        for &level in &[0, 6] {
+            let mut raw_params = CompressionParams::from_level(level);
+            raw_params.format = DataFormat::Raw;
+            let raw_bound = compress_bound(data.len(), &raw_params);
+            for &format in &[DataFormat::Raw, DataFormat::Zlib] {
+                let mut params = raw_params;
+                params.format = format;
+                assert_eq!(
+                    gzip_compress_bound(data.len(), &params),
+                    raw_bound + GZIP_FRAMING_SIZE
+                );
+            }
+        }
+        // Stored blocks fill the bound, leaving exactly enough room for the gzip wrapper.
+        let mut params = CompressionParams::from_level(0);
+        params.format = DataFormat::Raw;
+        let len = compress_with_bound(&data, &params, BufferSizes::default(), 0);
+        assert_eq!(
+            len + GZIP_FRAMING_SIZE,
+            gzip_compress_bound(data.len(), &params)
+        );
+    }
+}
@@ -1,58 +1,275 @@
-//! Buffer wrappers implementing default so we can allocate the buffers with `Box::default()`
-//! to avoid stack copies. Box::new() doesn't at the moment, and using a vec means we would lose
-//! static length info.
+//! Buffers used by the compressor, and the settings that determine their sizes.
+//!
+//! The buffers are either allocated as boxed slices of the requested size so large buffers are
+//! never placed on the stack, or borrowed from a caller-provided workspace when compressing
+//! without an allocator.
 
-use crate::deflate::core::{LZ_DICT_SIZE, MAX_MATCH_LEN};
+#[cfg(feature = "with-alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "with-alloc")]
+use alloc::vec;
+use core::cmp;
+use core::marker::PhantomData;
+use core::ops::DerefMut;
 
-/// Size of the buffer of lz77 encoded data.
+use crate::deflate::core::{
+    memset, HuffmanOxide, COMP_FAST_LOOKAHEAD_SIZE, LZ_DICT_SIZE, MAX_MATCH_LEN,
+};
+
+/// Default size of the buffer of lz77 encoded data.
 pub const LZ_CODE_BUF_SIZE: usize = 64 * 1024;
-/// Size of the output buffer.
-pub const OUT_BUF_SIZE: usize = (LZ_CODE_BUF_SIZE * 13) / 10;
-pub const LZ_DICT_FULL_SIZE: usize = LZ_DICT_SIZE + MAX_MATCH_LEN - 1 + 1;
 
-/// Size of hash values in the hash chains.
+/// Default size of hash values in the hash chains.
 pub const LZ_HASH_BITS: i32 = 15;
-/// How many bits to shift when updating the current hash value.
-pub const LZ_HASH_SHIFT: i32 = (LZ_HASH_BITS + 2) / 3;
-/// Size of the chained hash tables.
+
+/// Default size of the output buffer.
+pub const OUT_BUF_SIZE: usize = (LZ_CODE_BUF_SIZE * 13) / 10;
+/// Default size of the dictionary including the padding used to simplify matching.
+pub const LZ_DICT_FULL_SIZE: usize = LZ_DICT_SIZE + MAX_MATCH_LEN;
+/// Default size of the chained hash tables.
 pub const LZ_HASH_SIZE: usize = 1 << LZ_HASH_BITS;
 
+/// Update the hash value with a new byte. `shift` is the number of bits to shift the current hash
+/// by and `mask` the mask that keeps the result within the size of the hash table.
 #[inline]
-pub fn update_hash(current_hash: u16, byte: u8) -> u16 {
-    ((current_hash << LZ_HASH_SHIFT) ^ u16::from(byte)) & (LZ_HASH_SIZE as u16 - 1)
+pub fn update_hash(current_hash: u16, byte: u8, shift: u32, mask: u16) -> u16 {
+    ((current_hash << shift) ^ u16::from(byte)) & mask
 }
 
-pub struct HashBuffers {
-    pub dict: [u8; LZ_DICT_FULL_SIZE],
-    pub next: [u16; LZ_DICT_SIZE],
-    pub hash: [u16; LZ_DICT_SIZE],
+/// Number of bytes a block that does not compress well may grow to before it is flushed, for a
+/// dictionary of `window_size` bytes.
+///
+/// The block has to still be in the dictionary when it is flushed for it to be possible to
+/// output it as a stored block instead, so this needs to leave room for the lookahead
+/// and the last match.
+pub(crate) fn raw_block_limit(window_size: usize) -> usize {
+    cmp::min(31 * 1024, window_size - 2 * MAX_MATCH_LEN)
 }
 
-impl HashBuffers {
-    #[inline]
-    pub fn reset(&mut self) {
-        *self = HashBuffers::default();
+/// The same as [`raw_block_limit`] for the fast compressor used at level 1, which has a larger
+/// lookahead.
+pub(crate) fn fast_raw_block_limit(window_size: usize) -> usize {
+    raw_block_limit(window_size) - cmp::min(COMP_FAST_LOOKAHEAD_SIZE, window_size / 4)
+}
+
+/// Sizes of the buffers used by the compressor.
+///
+/// The default sizes are the same as the ones used by miniz, and need a bit over 300 KiB of
+/// memory in total. Reducing them makes it possible to compress on devices with little memory,
+/// at some cost in compression ratio:
+///
+/// * `window_bits` sets the size of the dictionary (the sliding window) to `2^window_bits`
+///   bytes. Matches can not refer further back than the window size, so a smaller window finds
+///   fewer and shorter matches in data that repeats over long distances. The zlib header
+///   written by the compressor states the reduced window size, so a decompressor can also use a
+///   smaller window. Valid values are 10 to 15.
+/// * `hash_bits` sets the number of entries in the hash table used to look up matches to
+///   `2^hash_bits`. A smaller table results in more hash collisions, so more of the probes
+///   allowed by the compression level are spent on positions that do not match.
+///   Valid values are 8 to 15.
+/// * `lz_code_buf_size` sets the size of the buffer that holds the lz77 codes of the current
+///   block, and thus the maximum size of a block. The size of the internal output buffer is
+///   proportional to it. Smaller blocks mean more block headers, and the header of a block with
+///   dynamic huffman codes can take up to a few hundred bytes, so very small buffers will make
+///   the output larger. Valid values are 4096 to 65536 bytes.
+///
+/// Values outside of the valid ranges are clamped to the nearest valid value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BufferSizes {
+    window_bits: u8,
+    hash_bits: u8,
+    // At most `MAX_LZ_CODE_BUF_SIZE`, stored as u32 to keep the compressor state small.
+    lz_code_buf_size: u32,
+}
+
+impl BufferSizes {
+    /// Smallest supported window size, in bits.
+    pub const MIN_WINDOW_BITS: u8 = 10;
+    /// Largest supported window size, in bits.
+    pub const MAX_WINDOW_BITS: u8 = 15;
+    /// Smallest supported hash table size, in bits.
+    pub const MIN_HASH_BITS: u8 = 8;
+    /// Largest supported hash table size, in bits.
+    pub const MAX_HASH_BITS: u8 = LZ_HASH_BITS as u8;
+    /// Smallest supported size of the lz77 code buffer.
+    pub const MIN_LZ_CODE_BUF_SIZE: usize = 4 * 1024;
+    /// Largest supported size of the lz77 code buffer.
+    pub const MAX_LZ_CODE_BUF_SIZE: usize = LZ_CODE_BUF_SIZE;
+
+    /// Create a new set of buffer sizes, clamping the values to the supported ranges.
+    pub const fn new(window_bits: u8, hash_bits: u8, lz_code_buf_size: usize) -> BufferSizes {
+        BufferSizes {
+            window_bits: clamp_u8(window_bits, Self::MIN_WINDOW_BITS, Self::MAX_WINDOW_BITS),
+            hash_bits: clamp_u8(hash_bits, Self::MIN_HASH_BITS, Self::MAX_HASH_BITS),
+            lz_code_buf_size: if lz_code_buf_size < Self::MIN_LZ_CODE_BUF_SIZE {
+                Self::MIN_LZ_CODE_BUF_SIZE as u32
+            } else if lz_code_buf_size > Self::MAX_LZ_CODE_BUF_SIZE {
+                Self::MAX_LZ_CODE_BUF_SIZE as u32
+            } else {
+                lz_code_buf_size as u32
+            },
+        }
+    }
+
+    /// Buffer sizes suitable for memory constrained systems, using a 4 KiB window, a 4096 entry
+    /// hash table and an 8 KiB code buffer for a total of around 40 KiB.
+    pub const fn low_memory() -> BufferSizes {
+        BufferSizes::new(12, 12, 8 * 1024)
+    }
+
+    /// Size of the dictionary in bits.
+    pub const fn window_bits(&self) -> u8 {
+        self.window_bits
+    }
+
+    /// Number of entries in the hash table in bits.
+    pub const fn hash_bits(&self) -> u8 {
+        self.hash_bits
+    }
+
+    /// Size of the buffer of lz77 encoded data.
+    pub const fn lz_code_buf_size(&self) -> usize {
+        self.lz_code_buf_size as usize
+    }
+
+    /// Size of the dictionary in bytes.
+    pub const fn window_size(&self) -> usize {
+        1 << self.window_bits
+    }
+
+    /// Size of the dictionary buffer in bytes, which is padded after the dictionary so matches
+    /// can be compared without wrapping around.
+    pub const fn dict_size(&self) -> usize {
+        self.window_size() + MAX_MATCH_LEN
+    }
+
+    /// Number of entries in the hash table.
+    pub const fn hash_size(&self) -> usize {
+        1 << self.hash_bits
+    }
+
+    /// Size of the internal output buffer.
+    pub const fn out_buf_size(&self) -> usize {
+        (self.lz_code_buf_size() * 13) / 10
+    }
+
+    /// Approximate number of bytes of memory used by the buffers of a compressor using these
+    /// sizes.
+    pub const fn memory_usage(&self) -> usize {
+        let dict = self.dict_size();
+        let chains = (self.window_size() + self.hash_size()) * 2;
+        dict + chains + self.lz_code_buf_size() + self.out_buf_size()
     }
 }
 
-impl Default for HashBuffers {
-    fn default() -> HashBuffers {
+impl Default for BufferSizes {
+    fn default() -> BufferSizes {
+        BufferSizes::new(
+            LZ_DICT_SIZE.trailing_zeros() as u8,
+            LZ_HASH_BITS as u8,
+            LZ_CODE_BUF_SIZE,
+        )
+    }
+}
+
+const fn clamp_u8(val: u8, min: u8, max: u8) -> u8 {
+    if val < min {
+        min
+    } else if val > max {
+        max
+    } else {
+        val
+    }
+}
+
+/// The types used to hold the buffers of a compressor.
+pub(crate) trait BufferStorage {
+    type Bytes: DerefMut<Target = [u8]>;
+    type Words: DerefMut<Target = [u16]>;
+    type Huffman: DerefMut<Target = HuffmanOxide>;
+}
+
+/// Buffers allocated on the heap and owned by the compressor.
+#[cfg(feature = "with-alloc")]
+pub(crate) struct HeapStorage;
+
+#[cfg(feature = "with-alloc")]
+impl BufferStorage for HeapStorage {
+    type Bytes = Box<[u8]>;
+    type Words = Box<[u16]>;
+    type Huffman = Box<HuffmanOxide>;
+}
+
+/// Buffers borrowed from a [`CompressorWorkspace`](super::core::CompressorWorkspace).
+pub(crate) struct BorrowedStorage<'a>(PhantomData<&'a mut ()>);
+
+impl<'a> BufferStorage for BorrowedStorage<'a> {
+    type Bytes = &'a mut [u8];
+    type Words = &'a mut [u16];
+    type Huffman = &'a mut HuffmanOxide;
+}
+
+pub(crate) struct HashBuffers<S: BufferStorage> {
+    /// The dictionary, followed by a copy of its first `MAX_MATCH_LEN - 1` bytes and a padding
+    /// byte so matches can be compared without wrapping around.
+    pub dict: S::Bytes,
+    pub next: S::Words,
+    pub hash: S::Words,
+}
+
+#[cfg(feature = "with-alloc")]
+impl HashBuffers<HeapStorage> {
+    pub fn new(sizes: &BufferSizes) -> Self {
         HashBuffers {
-            dict: [0; LZ_DICT_FULL_SIZE],
-            next: [0; LZ_DICT_SIZE],
-            hash: [0; LZ_DICT_SIZE],
+            dict: vec![0; sizes.dict_size()].into_boxed_slice(),
+            next: vec![0; sizes.window_size()].into_boxed_slice(),
+            hash: vec![0; sizes.hash_size()].into_boxed_slice(),
         }
     }
 }
 
-pub struct LocalBuf {
-    pub b: [u8; OUT_BUF_SIZE],
+#[cfg(feature = "with-alloc")]
+impl Clone for HashBuffers<HeapStorage> {
+    fn clone(&self) -> Self {
+        HashBuffers {
+            dict: self.dict.clone(),
+            next: self.next.clone(),
+            hash: self.hash.clone(),
+        }
+    }
 }
 
-impl Default for LocalBuf {
-    fn default() -> LocalBuf {
+impl<S: BufferStorage> HashBuffers<S> {
+    #[inline]
+    pub fn reset(&mut self) {
+        memset(&mut self.dict[..], 0);
+        memset(&mut self.next[..], 0);
+        memset(&mut self.hash[..], 0);
+    }
+
+    pub fn copy_from<T: BufferStorage>(&mut self, other: &HashBuffers<T>) {
+        self.dict.copy_from_slice(&other.dict);
+        self.next.copy_from_slice(&other.next);
+        self.hash.copy_from_slice(&other.hash);
+    }
+}
+
+pub(crate) struct LocalBuf<S: BufferStorage> {
+    pub b: S::Bytes,
+}
+
+#[cfg(feature = "with-alloc")]
+impl LocalBuf<HeapStorage> {
+    pub fn new(sizes: &BufferSizes) -> Self {
         LocalBuf {
-            b: [0; OUT_BUF_SIZE],
+            b: vec![0; sizes.out_buf_size()].into_boxed_slice(),
         }
     }
 }
+
+#[cfg(feature = "with-alloc")]
+impl Clone for LocalBuf<HeapStorage> {
+    fn clone(&self) -> Self {
+        LocalBuf { b: self.b.clone() }
+    }
+}
@@ -0,0 +1,322 @@
+//! A reusable compressor with a builder for its settings.
+
+use alloc::vec::Vec;
+
+use crate::deflate::buffer::{LZ_CODE_BUF_SIZE, LZ_HASH_BITS};
+use crate::deflate::core::{
+    compress_to_output, deflate_flags::TDEFL_COMPUTE_ADLER32, BufferSizes, CompressionParams,
+    CompressionStrategy, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+use crate::deflate::stream::deflate;
+use crate::deflate::CompressionLevel;
+use crate::{DataFormat, MZFlush, StreamResult};
+
+/// Builder for a [`Compressor`].
+///
+/// By default, the compressor uses the default compression level and strategy, a zlib wrapper,
+/// a 32 KiB window and no output limit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CompressorBuilder {
+    level: u8,
+    strategy: CompressionStrategy,
+    format: DataFormat,
+    window_bits: u8,
+    output_limit: usize,
+    compute_checksum: bool,
+}
+
+impl CompressorBuilder {
+    /// Create a builder using the default settings.
+    pub fn new() -> CompressorBuilder {
+        CompressorBuilder {
+            level: CompressionLevel::DefaultLevel as u8,
+            strategy: CompressionStrategy::Default,
+            format: DataFormat::Zlib,
+            window_bits: BufferSizes::MAX_WINDOW_BITS,
+            output_limit: usize::max_value(),
+            compute_checksum: false,
+        }
+    }
+
+    /// Set the compression level (0-10).
+    pub fn level(mut self, level: u8) -> CompressorBuilder {
+        self.level = level;
+        self
+    }
+
+    /// Set the compression strategy.
+    pub fn strategy(mut self, strategy: CompressionStrategy) -> CompressorBuilder {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set how the compressed data is wrapped.
+    pub fn format(mut self, format: DataFormat) -> CompressorBuilder {
+        self.format = format;
+        self
+    }
+
+    /// Set the size of the window to `2^window_bits` bytes.
+    ///
+    /// See [`BufferSizes`] for details.
+    pub fn window_bits(mut self, window_bits: u8) -> CompressorBuilder {
+        self.window_bits = window_bits;
+        self
+    }
+
+    /// Set the maximum number of bytes [`Compressor::compress_into`] may output.
+    pub fn output_limit(mut self, output_limit: usize) -> CompressorBuilder {
+        self.output_limit = output_limit;
+        self
+    }
+
+    /// Compute the adler32 checksum of the input even when not using a zlib wrapper.
+    ///
+    /// The checksum can be read with [`Compressor::adler32`]. It is always computed when using
+    /// a zlib wrapper.
+    pub fn compute_checksum(mut self, compute_checksum: bool) -> CompressorBuilder {
+        self.compute_checksum = compute_checksum;
+        self
+    }
+
+    /// Create the compressor.
+    pub fn build(self) -> Compressor {
+        let mut params = CompressionParams::from_level(self.level);
+        params.format = self.format;
+        // Level 0 uses the raw strategy, keep it unless another one was asked for.
+        if self.strategy != CompressionStrategy::Default {
+            params.strategy = self.strategy;
+        }
+
+        let flags = if self.compute_checksum {
+            params.to_flags() | TDEFL_COMPUTE_ADLER32
+        } else {
+            params.to_flags()
+        };
+        let sizes = BufferSizes::new(self.window_bits, LZ_HASH_BITS as u8, LZ_CODE_BUF_SIZE);
+        let mut inner = CompressorOxide::new_with_buffer_sizes(flags, sizes);
+        inner.set_params(params);
+
+        Compressor {
+            inner,
+            output_limit: self.output_limit,
+            fresh: true,
+            finished: false,
+        }
+    }
+}
+
+impl Default for CompressorBuilder {
+    fn default() -> CompressorBuilder {
+        CompressorBuilder::new()
+    }
+}
+
+/// A compressor that can be reused for many inputs without allocating new buffers.
+///
+/// ```
+/// use miniz_oxide::deflate::Compressor;
+/// use miniz_oxide::DataFormat;
+///
+/// let mut compressor = Compressor::builder().level(9).format(DataFormat::Raw).build();
+/// let mut output = Vec::new();
+/// for input in [&b"first input"[..], &b"second input"[..]].iter() {
+///     output.clear();
+///     compressor.compress_into(input, &mut output).unwrap();
+/// }
+/// ```
+pub struct Compressor {
+    inner: CompressorOxide,
+    output_limit: usize,
+    /// Whether the compressor is in its initial state.
+    fresh: bool,
+    /// Whether the last use was a call to `compress_into`, which ends the stream.
+    finished: bool,
+}
+
+impl Compressor {
+    /// Create a builder for a compressor.
+    pub fn builder() -> CompressorBuilder {
+        CompressorBuilder::new()
+    }
+
+    /// Compress all of `input` as a separate stream, appending the output to `output`.
+    ///
+    /// Any stream in progress is discarded first, and the compressor is ready to start a new
+    /// stream afterwards.
+    ///
+    /// Returns the number of bytes appended to `output`. If the output would be larger than the
+    /// output limit, nothing is appended and [`TDEFLStatus::PutBufFailed`] is returned.
+    pub fn compress_into(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<usize, TDEFLStatus> {
+        if !self.fresh {
+            self.inner.reset();
+        }
+        self.fresh = false;
+        self.finished = true;
+
+        let start = output.len();
+        let limit = self.output_limit;
+        let (status, _) = compress_to_output(&mut self.inner, input, TDEFLFlush::Finish, |buf| {
+            if output.len() - start + buf.len() > limit {
+                return false;
+            }
+            output.extend_from_slice(buf);
+            true
+        });
+
+        if status == TDEFLStatus::Done {
+            Ok(output.len() - start)
+        } else {
+            output.truncate(start);
+            Err(status)
+        }
+    }
+
+    /// Compress from `input` to `output`, as part of a stream that may be split over several
+    /// calls.
+    ///
+    /// See [`deflate`](crate::deflate::stream::deflate) for details. The output limit does not
+    /// apply here.
+    ///
+    /// Once the stream is finished, whether by this function or by
+    /// [`compress_into`](Compressor::compress_into), the next call starts a new stream.
+    pub fn compress(&mut self, input: &[u8], output: &mut [u8], flush: MZFlush) -> StreamResult {
+        if self.finished || self.inner.prev_return_status() == TDEFLStatus::Done {
+            self.reset();
+        }
+        self.fresh = false;
+        deflate(&mut self.inner, input, output, flush)
+    }
+
+    /// Reset the compressor to start a new stream, keeping the settings and buffers.
+    pub fn reset(&mut self) {
+        self.inner.reset();
+        self.fresh = true;
+        self.finished = false;
+    }
+
+    /// Get the adler32 checksum of the data compressed in the current or last stream.
+    pub fn adler32(&self) -> u32 {
+        self.inner.adler32()
+    }
+
+    /// Access the inner compressor.
+    pub fn inner(&mut self) -> &mut CompressorOxide {
+        &mut self.inner
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Compressor;
+    use crate::deflate::core::{CompressionStrategy, TDEFLStatus};
+    use crate::inflate::{decompress_to_vec, decompress_to_vec_zlib};
+    use crate::{DataFormat, MZFlush, MZStatus};
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn compress_into() {
+        let data = include_bytes!("../../tests/test_data/numbers.txt");
+
+        let mut compressor = Compressor::builder()
+            .level(9)
+            .strategy(CompressionStrategy::Filtered)
+            .format(DataFormat::Raw)
+            .window_bits(12)
+            .compute_checksum(true)
+            .build();
+        let mut output = vec![1, 2, 3];
+        for _ in 0..3 {
+            output.truncate(3);
+            let len = compressor.compress_into(data, &mut output).unwrap();
+            assert_eq!(len, output.len() - 3);
+            assert_eq!(&output[..3], &[1, 2, 3]);
+            assert!(decompress_to_vec(&output[3..]).unwrap() == &data[..]);
+            assert_eq!(compressor.adler32(), crate::mz_adler32_oxide(1, data));
+        }
+
+        let mut zlib_output = Vec::new();
+        let len = Compressor::builder()
+            .build()
+            .compress_into(data, &mut zlib_output)
+            .unwrap();
+        zlib_output.clear();
+        let mut limited = Compressor::builder().output_limit(len - 1).build();
+        assert_eq!(
+            limited.compress_into(data, &mut zlib_output),
+            Err(TDEFLStatus::PutBufFailed)
+        );
+        assert!(zlib_output.is_empty());
+        // The failed call doesn't leave the compressor in a bad state.
+        let mut limited = Compressor::builder().output_limit(len).build();
+        assert_eq!(limited.compress_into(data, &mut zlib_output), Ok(len));
+    }
+
+    #[test]
+    fn streaming() {
+        let data = include_bytes!("../../tests/test_data/numbers.txt");
+        let mut compressor = Compressor::builder().level(1).build();
+        let mut expected = Vec::new();
+        compressor.compress_into(data, &mut expected).unwrap();
+
+        for _ in 0..2 {
+            let mut output = vec![0; data.len()];
+            let mut in_pos = 0;
+            let mut out_pos = 0;
+            loop {
+                let in_end = core::cmp::min(in_pos + 1000, data.len());
+                let flush = if in_end == data.len() {
+                    MZFlush::Finish
+                } else {
+                    MZFlush::None
+                };
+                let res = compressor.compress(
+                    &data[in_pos..in_end],
+                    &mut output[out_pos..out_pos + 100],
+                    flush,
+                );
+                in_pos += res.bytes_consumed;
+                out_pos += res.bytes_written;
+                if res.status == Ok(MZStatus::StreamEnd) {
+                    break;
+                }
+                res.status.unwrap();
+            }
+            assert!(output[..out_pos] == expected[..]);
+            assert!(decompress_to_vec_zlib(&output[..out_pos]).unwrap() == &data[..]);
+            compressor.reset();
+        }
+    }
+
+    #[test]
+    fn finished_stream() {
+        let data = b"Some data to compress";
+        let mut expected = Vec::new();
+        Compressor::builder()
+            .build()
+            .compress_into(data, &mut expected)
+            .unwrap();
+
+        // Compressing after either way of finishing a stream starts a new one.
+        for &use_compress_into in &[false, true] {
+            let mut compressor = Compressor::builder().build();
+            let mut output = [0; 100];
+            if use_compress_into {
+                compressor.compress_into(data, &mut Vec::new()).unwrap();
+            } else {
+                let res = compressor.compress(data, &mut output, MZFlush::Finish);
+                assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+            }
+
+            let res = compressor.compress(data, &mut output, MZFlush::Finish);
+            assert_eq!(res.bytes_consumed, data.len());
+            assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+            assert!(output[..res.bytes_written] == expected[..]);
+        }
+    }
+}
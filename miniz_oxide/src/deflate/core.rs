@@ -1,19 +1,29 @@
 //! Streaming compression functionality.
 
+#[cfg(feature = "with-alloc")]
 use alloc::boxed::Box;
+#[cfg(feature = "with-alloc")]
+use alloc::vec;
 use core::convert::TryInto;
 use core::{cmp, mem};
 
+use self::deflate_flags::*;
 use super::super::*;
-use super::deflate_flags::*;
 use super::CompressionLevel;
+#[cfg(feature = "with-alloc")]
+use crate::deflate::buffer::HeapStorage;
 use crate::deflate::buffer::{
-    update_hash, HashBuffers, LocalBuf, LZ_CODE_BUF_SIZE, LZ_DICT_FULL_SIZE, LZ_HASH_BITS,
-    LZ_HASH_SHIFT, LZ_HASH_SIZE, OUT_BUF_SIZE,
+    fast_raw_block_limit, raw_block_limit, update_hash, BorrowedStorage, BufferStorage,
+    HashBuffers, LocalBuf, LZ_CODE_BUF_SIZE, LZ_DICT_FULL_SIZE, LZ_HASH_BITS, LZ_HASH_SIZE,
+    OUT_BUF_SIZE,
 };
+#[cfg(feature = "with-alloc")]
+use crate::deflate::verify::Verifier;
 use crate::shared::{update_adler32, HUFFMAN_LENGTH_ORDER, MZ_ADLER32_INIT};
 use crate::DataFormat;
 
+pub use crate::deflate::buffer::BufferSizes;
+
 // Currently not bubbled up outside this module, so can fill in with more
 // context eventually if needed.
 type Result<T, E = Error> = core::result::Result<T, E>;
@@ -206,6 +216,150 @@ pub enum CompressionStrategy {
     Fixed = 4,
 }
 
+/// Parameters controlling how the compressor looks for matches and what kind of output it
+/// produces.
+///
+/// This is a typed alternative to building the raw compressor flags with
+/// [`create_comp_flags_from_zip_params`]. Start from the parameters of a compression level with
+/// [`from_level`](CompressionParams::from_level) and adjust the fields as needed:
+///
+/// ```
+/// use miniz_oxide::deflate::core::{CompressionParams, CompressionStrategy, CompressorOxide};
+///
+/// let mut params = CompressionParams::from_level(9);
+/// params.strategy = CompressionStrategy::Filtered;
+/// params.nice_length = 128;
+/// let compressor = CompressorOxide::new_with_params(params);
+/// ```
+///
+/// Values outside of the valid ranges are clamped to the nearest valid value when the
+/// parameters are applied to a compressor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct CompressionParams {
+    /// How hard to look for matches in the hash chains.
+    ///
+    /// The compression levels use values from 1 (level 1) to 1500 (level 10). About a third of
+    /// this value is the number of earlier positions that are checked for a match, and a
+    /// twelfth of it when looking for a better match after a match of `good_length` bytes.
+    /// Valid values are 0 to 4095. 0 disables matching, the same as
+    /// [`CompressionStrategy::HuffmanOnly`].
+    pub probes: u16,
+    /// Use greedy parsing, using the first match found right away, rather than lazy parsing,
+    /// where a match is only used if there is no longer match at the next position.
+    pub greedy: bool,
+    /// With lazy parsing, matches at least this long are used right away without checking for
+    /// a longer match at the next position. The compression levels use 128.
+    pub max_lazy: u16,
+    /// With lazy parsing, fewer probes are done when looking for a longer match at the next
+    /// position if the current match is at least this long. The compression levels use 32.
+    pub good_length: u16,
+    /// Stop looking for a longer match once a match at least this long has been found.
+    /// The compression levels use 258, the maximum match length.
+    pub nice_length: u16,
+    /// Matches shorter than this are not used. Valid values are 3 to 258, the compression
+    /// levels use 3. [`CompressionStrategy::Filtered`] raises this to at least 6.
+    pub min_match_len: u16,
+    /// Special-case compression variant to use.
+    pub strategy: CompressionStrategy,
+    /// Don't compress the data, only output raw/uncompressed blocks. This overrides the other
+    /// settings, and is what compression level 0 uses.
+    pub raw_blocks: bool,
+    /// How the compressed data is wrapped.
+    ///
+    /// Changing this after compression has started will result in a corrupted stream.
+    pub format: DataFormat,
+}
+
+impl CompressionParams {
+    /// Get the parameters used by the given compression level (0-10), with a zlib wrapper.
+    ///
+    /// Levels above 10 are treated as 10.
+    pub fn from_level(level: u8) -> CompressionParams {
+        CompressionParams::from_flags(create_comp_flags_from_zip_params(
+            level.into(),
+            DataFormat::Zlib.to_window_bits(),
+            CompressionStrategy::Default as i32,
+        ))
+    }
+
+    /// Get the parameters corresponding to the given raw compressor flags.
+    pub fn from_flags(flags: u32) -> CompressionParams {
+        let probes = (flags & MAX_PROBES_MASK as u32) as u16;
+        let raw_blocks = flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0;
+        let strategy = if flags & TDEFL_RLE_MATCHES != 0 {
+            CompressionStrategy::RLE
+        } else if flags & TDEFL_FILTER_MATCHES != 0 {
+            CompressionStrategy::Filtered
+        } else if flags & TDEFL_FORCE_ALL_STATIC_BLOCKS != 0 {
+            CompressionStrategy::Fixed
+        } else if probes == 0 && !raw_blocks {
+            CompressionStrategy::HuffmanOnly
+        } else {
+            CompressionStrategy::Default
+        };
+
+        CompressionParams {
+            probes,
+            greedy: flags & TDEFL_GREEDY_PARSING_FLAG != 0,
+            max_lazy: DEFAULT_MAX_LAZY,
+            good_length: DEFAULT_GOOD_LENGTH,
+            nice_length: MAX_MATCH_LEN as u16,
+            min_match_len: MIN_MATCH_LEN.into(),
+            strategy,
+            raw_blocks,
+            format: if flags & TDEFL_WRITE_ZLIB_HEADER != 0 {
+                DataFormat::Zlib
+            } else {
+                DataFormat::Raw
+            },
+        }
+    }
+
+    /// Get the raw compressor flags corresponding to these parameters.
+    ///
+    /// The flags do not include `max_lazy`, `good_length`, `nice_length` and `min_match_len`.
+    pub fn to_flags(&self) -> u32 {
+        let mut flags = u32::from(cmp::min(self.probes, MAX_PROBES_MASK as u16));
+        if self.greedy {
+            flags |= TDEFL_GREEDY_PARSING_FLAG;
+        }
+        if self.format != DataFormat::Raw {
+            flags |= TDEFL_WRITE_ZLIB_HEADER;
+        }
+        match self.strategy {
+            CompressionStrategy::Default => (),
+            CompressionStrategy::Filtered => flags |= TDEFL_FILTER_MATCHES,
+            CompressionStrategy::HuffmanOnly => flags &= !(MAX_PROBES_MASK as u32),
+            CompressionStrategy::RLE => flags |= TDEFL_RLE_MATCHES,
+            CompressionStrategy::Fixed => flags |= TDEFL_FORCE_ALL_STATIC_BLOCKS,
+        }
+        if self.raw_blocks {
+            flags |= TDEFL_FORCE_ALL_RAW_BLOCKS;
+        }
+        flags
+    }
+
+    /// Clamp the values to the valid ranges.
+    fn clamped(&self) -> CompressionParams {
+        let mut params = *self;
+        params.probes = cmp::min(params.probes, MAX_PROBES_MASK as u16);
+        params.nice_length = cmp::min(params.nice_length, MAX_MATCH_LEN as u16);
+        params.min_match_len = cmp::min(
+            cmp::max(params.min_match_len, MIN_MATCH_LEN.into()),
+            MAX_MATCH_LEN as u16,
+        );
+        params
+    }
+}
+
+impl Default for CompressionParams {
+    /// The parameters of the default compression level, with a zlib wrapper.
+    fn default() -> CompressionParams {
+        CompressionParams::from_level(CompressionLevel::DefaultLevel as u8)
+    }
+}
+
 /// A list of deflate flush types.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum TDEFLFlush {
@@ -231,10 +385,22 @@ impl From<MZFlush> for TDEFLFlush {
     fn from(flush: MZFlush) -> Self {
         match flush {
             MZFlush::None => TDEFLFlush::None,
-            MZFlush::Sync => TDEFLFlush::Sync,
+            // There is no partial or block flush here, and a sync flush also outputs all the
+            // data. `deflate::stream::deflate` supports them.
+            MZFlush::Partial | MZFlush::Sync | MZFlush::Block => TDEFLFlush::Sync,
             MZFlush::Full => TDEFLFlush::Full,
             MZFlush::Finish => TDEFLFlush::Finish,
-            _ => TDEFLFlush::None, // TODO: ??? What to do ???
+        }
+    }
+}
+
+impl From<TDEFLFlush> for MZFlush {
+    fn from(flush: TDEFLFlush) -> Self {
+        match flush {
+            TDEFLFlush::None => MZFlush::None,
+            TDEFLFlush::Sync => MZFlush::Sync,
+            TDEFLFlush::Full => MZFlush::Full,
+            TDEFLFlush::Finish => MZFlush::Finish,
         }
     }
 }
@@ -278,6 +444,8 @@ pub enum TDEFLStatus {
 const MAX_HUFF_SYMBOLS: usize = 288;
 /// Size of hash chain for fast compression mode.
 const LEVEL1_HASH_SIZE_MASK: u32 = 4095;
+/// The most bits that can be waiting to be output before a block is started.
+const MAX_PRIMED_BITS: u32 = 24;
 /// The number of huffman tables used by the compressor.
 /// Literal/length, Distances and Length of the huffman codes for the other two tables.
 const MAX_HUFF_TABLES: usize = 3;
@@ -289,20 +457,26 @@ const MAX_HUFF_SYMBOLS_1: usize = 32;
 const MAX_HUFF_SYMBOLS_2: usize = 19;
 /// Size of the chained hash table.
 pub(crate) const LZ_DICT_SIZE: usize = 32_768;
-/// Mask used when stepping through the hash chains.
-const LZ_DICT_SIZE_MASK: usize = (LZ_DICT_SIZE as u32 - 1) as usize;
 /// The minimum length of a match.
 const MIN_MATCH_LEN: u8 = 3;
+/// Matches at least this long are not checked for a longer match at the next position, unless
+/// changed with [`CompressionParams::max_lazy`].
+const DEFAULT_MAX_LAZY: u16 = 128;
+/// Matches at least this long use fewer probes when looking for a longer match, unless changed
+/// with [`CompressionParams::good_length`].
+const DEFAULT_GOOD_LENGTH: u16 = 32;
+/// Shortest match used with [`CompressionStrategy::Filtered`].
+const FILTER_MIN_MATCH_LEN: u32 = 6;
 /// The maximum length of a match.
 pub(crate) const MAX_MATCH_LEN: usize = 258;
 
+#[cfg_attr(not(feature = "with-alloc"), allow(dead_code))]
 const DEFAULT_FLAGS: u32 = NUM_PROBES[4] | TDEFL_WRITE_ZLIB_HEADER;
 
 mod zlib {
     const DEFAULT_CM: u8 = 8;
-    const DEFAULT_CINFO: u8 = 7 << 4;
-    const _DEFAULT_FDICT: u8 = 0;
-    const DEFAULT_CMF: u8 = DEFAULT_CM | DEFAULT_CINFO;
+    /// Set in FLG if the header is followed by the id of a preset dictionary.
+    const FDICT: u8 = 0b0010_0000;
     /// The 16-bit value consisting of CMF and FLG must be divisible by this to be valid.
     const FCHECK_DIVISOR: u8 = 31;
 
@@ -310,11 +484,11 @@ mod zlib {
     /// specification, i.e (CMF*256 + FCHK) % 31 = 0.
     /// Returns flg with the FCHKECK bits added (any existing FCHECK bits are ignored).
     fn add_fcheck(cmf: u8, flg: u8) -> u8 {
-        let rem = ((usize::from(cmf) * 256) + usize::from(flg)) % usize::from(FCHECK_DIVISOR);
-
         // Clear existing FCHECK if any
         let flg = flg & 0b11100000;
 
+        let rem = ((usize::from(cmf) * 256) + usize::from(flg)) % usize::from(FCHECK_DIVISOR);
+
         // Casting is safe as rem can't overflow since it is a value mod 31
         // We can simply add the value to flg as (31 - rem) will never be above 2^5
         flg + (FCHECK_DIVISOR - rem as u8)
@@ -337,18 +511,26 @@ mod zlib {
         }
     }
 
-    /// Get the zlib header for the level using the default window size and no
+    /// Get the zlib header for the level using the given window size and no
     /// dictionary.
-    fn header_from_level(level: u8) -> [u8; 2] {
-        let cmf = DEFAULT_CMF;
+    fn header_from_level(level: u8, window_bits: u8) -> [u8; 2] {
+        // CINFO is the base-2 logarithm of the window size minus 8.
+        let cmf = DEFAULT_CM | ((window_bits - 8) << 4);
         [cmf, add_fcheck(cmf, (level as u8) << 6)]
     }
 
-    /// Create a zlib header from the given compression flags.
-    /// Only level is considered.
-    pub fn header_from_flags(flags: u32) -> [u8; 2] {
+    /// Create a zlib header from the given compression flags and window size.
+    /// Only level is considered from the flags.
+    ///
+    /// If `preset_dictionary` is set, FDICT is set to indicate that the header is followed by
+    /// the id of the dictionary.
+    pub fn header_from_flags(flags: u32, window_bits: u8, preset_dictionary: bool) -> [u8; 2] {
         let level = zlib_level_from_flags(flags);
-        header_from_level(level)
+        let mut header = header_from_level(level, window_bits);
+        if preset_dictionary {
+            header[1] = add_fcheck(header[0], header[1] | FDICT);
+        }
+        header
     }
 
     #[cfg(test)]
@@ -381,16 +563,31 @@ mod zlib {
 
         #[test]
         fn test_header() {
-            let header = super::header_from_level(3);
+            let header = super::header_from_level(3, 15);
             assert_eq!(
                 ((usize::from(header[0]) * 256) + usize::from(header[1])) % 31,
                 0
             );
+            assert_eq!(header[0], 0x78);
+
+            let header = super::header_from_level(2, 10);
+            assert_eq!(
+                ((usize::from(header[0]) * 256) + usize::from(header[1])) % 31,
+                0
+            );
+            assert_eq!(header[0], 0x28);
+
+            let header = super::header_from_flags(super::super::DEFAULT_FLAGS, 15, true);
+            assert_eq!(
+                ((usize::from(header[0]) * 256) + usize::from(header[1])) % 31,
+                0
+            );
+            assert_eq!(header[1] & super::FDICT, super::FDICT);
         }
     }
 }
 
-fn memset<T: Copy>(slice: &mut [T], val: T) {
+pub(crate) fn memset<T: Copy>(slice: &mut [T], val: T) {
     for x in slice {
         *x = val
     }
@@ -411,38 +608,63 @@ const fn read_u16_le(slice: &[u8], pos: usize) -> u16 {
 }
 
 /// Main compression struct.
+#[cfg(feature = "with-alloc")]
+#[derive(Clone)]
 pub struct CompressorOxide {
-    lz: LZOxide,
-    params: ParamsOxide,
-    huff: Box<HuffmanOxide>,
-    dict: DictOxide,
+    state: CompressorState<HeapStorage>,
+    verifier: Option<Verifier>,
 }
 
+#[cfg(feature = "with-alloc")]
 impl CompressorOxide {
     /// Create a new `CompressorOxide` with the given flags.
     ///
     /// # Notes
     /// This function may be changed to take different parameters in the future.
     pub fn new(flags: u32) -> Self {
+        CompressorOxide::new_with_buffer_sizes(flags, BufferSizes::default())
+    }
+
+    /// Create a new `CompressorOxide` with the given flags, using buffers of the given sizes.
+    ///
+    /// See [`BufferSizes`] for how the sizes affect memory usage and compression.
+    pub fn new_with_buffer_sizes(flags: u32, sizes: BufferSizes) -> Self {
         CompressorOxide {
-            lz: LZOxide::new(),
-            params: ParamsOxide::new(flags),
-            /// Put HuffmanOxide on the heap with default trick to avoid
-            /// excessive stack copies.
-            huff: Box::default(),
-            dict: DictOxide::new(flags),
+            state: CompressorState {
+                lz: LZOxide::new(vec![0; sizes.lz_code_buf_size()].into_boxed_slice()),
+                params: ParamsOxide::new(flags, LocalBuf::new(&sizes)),
+                // Put HuffmanOxide on the heap with default trick to avoid
+                // excessive stack copies.
+                huff: Box::default(),
+                dict: DictOxide::new(flags, &sizes, HashBuffers::new(&sizes)),
+                sizes,
+                pending_params: None,
+            },
+            verifier: None,
         }
     }
 
+    /// Create a new `CompressorOxide` using the given parameters.
+    pub fn new_with_params(params: CompressionParams) -> Self {
+        let mut compressor = CompressorOxide::new(params.to_flags());
+        compressor.set_params(params);
+        compressor
+    }
+
+    /// Get the sizes of the buffers used by this compressor.
+    pub const fn buffer_sizes(&self) -> BufferSizes {
+        self.state.sizes
+    }
+
     /// Get the adler32 checksum of the currently encoded data.
     pub const fn adler32(&self) -> u32 {
-        self.params.adler32
+        self.state.params.adler32
     }
 
     /// Get the return status of the previous [`compress`](fn.compress.html)
     /// call with this compressor.
     pub const fn prev_return_status(&self) -> TDEFLStatus {
-        self.params.prev_return_status
+        self.state.params.prev_return_status
     }
 
     /// Get the raw compressor flags.
@@ -450,232 +672,860 @@ impl CompressorOxide {
     /// # Notes
     /// This function may be deprecated or changed in the future to use more rust-style flags.
     pub const fn flags(&self) -> i32 {
-        self.params.flags as i32
+        self.state.params.flags as i32
     }
 
     /// Returns whether the compressor is wrapping the data in a zlib format or not.
     pub fn data_format(&self) -> DataFormat {
-        if (self.params.flags & TDEFL_WRITE_ZLIB_HEADER) != 0 {
-            DataFormat::Zlib
-        } else {
-            DataFormat::Raw
-        }
+        self.state.data_format()
     }
 
     /// Reset the state of the compressor, keeping the same parameters.
     ///
     /// This avoids re-allocating data.
     pub fn reset(&mut self) {
-        // LZ buf and huffman has no settings or dynamic memory
-        // that needs to be saved, so we simply replace them.
-        self.lz = LZOxide::new();
-        self.params.reset();
-        *self.huff = HuffmanOxide::default();
-        self.dict.reset();
+        self.state.reset();
+        if let Some(verifier) = self.verifier.as_mut() {
+            verifier.reset();
+        }
     }
 
     /// Set the compression level of the compressor.
     ///
     /// Using this to change level after compression has started is supported.
     /// # Notes
-    /// The compression strategy will be reset to the default one when this is called.
+    /// The compression strategy is kept, and level 0 uses raw blocks. As with `set_params`, some
+    /// changes take effect after the current block.
     pub fn set_compression_level(&mut self, level: CompressionLevel) {
-        let format = self.data_format();
-        self.set_format_and_level(format, level as u8);
+        self.state.set_compression_level_raw(level as u8);
     }
 
     /// Set the compression level of the compressor using an integer value.
     ///
     /// Using this to change level after compression has started is supported.
     /// # Notes
-    /// The compression strategy will be reset to the default one when this is called.
+    /// The compression strategy is kept, and level 0 uses raw blocks. As with `set_params`, some
+    /// changes take effect after the current block.
     pub fn set_compression_level_raw(&mut self, level: u8) {
-        let format = self.data_format();
-        self.set_format_and_level(format, level);
+        self.state.set_compression_level_raw(level);
     }
 
     /// Update the compression settings of the compressor.
     ///
     /// Changing the `DataFormat` after compression has started will result in
-    /// a corrupted stream.
+    /// a corrupted stream. The compression strategy is set to the default one.
     ///
     /// # Notes
     /// This function mainly intended for setting the initial settings after e.g creating with
     /// `default` or after calling `CompressorOxide::reset()`, and behaviour may be changed
     /// to disallow calling it after starting compression in the future.
     pub fn set_format_and_level(&mut self, data_format: DataFormat, level: u8) {
-        let flags = create_comp_flags_from_zip_params(
-            level.into(),
-            data_format.to_window_bits(),
-            CompressionStrategy::Default as i32,
-        );
-        self.params.update_flags(flags);
-        self.dict.update_flags(flags);
+        self.state.set_format_and_level(data_format, level);
+    }
+
+    /// Get the current compression parameters.
+    ///
+    /// If a change of parameters is waiting for the current block to finish, this returns the
+    /// new parameters.
+    pub fn params(&self) -> CompressionParams {
+        self.state.params()
+    }
+
+    /// Set the compression parameters.
+    ///
+    /// Using this to change the parameters after compression has started is supported, apart
+    /// from the format, and the new parameters are used for the data compressed from then on.
+    /// Settings that are not part of [`CompressionParams`], such as rsyncable mode, are kept.
+    ///
+    /// Changing the strategy, or switching between the fast compression mode used by level 1
+    /// and the normal one, can't be done in the middle of a block. If compression has started,
+    /// the next call to [`compress`] or [`compress_to_output`] will first finish the current
+    /// block using the old parameters before compressing any new input. This may take more
+    /// than one call if the output buffer is small. Other changes take effect right away.
+    pub fn set_params(&mut self, params: CompressionParams) {
+        self.state.set_params(&params);
+    }
+
+    /// Enable or disable rsyncable mode.
+    ///
+    /// In rsyncable mode, the compressor is reset at points in the input determined by a rolling
+    /// hash of the data, the same way as when using [`TDEFLFlush::Full`]. As the reset points
+    /// only depend on the data close to them, a local change in the input only changes the
+    /// compressed output up to the next reset point, which lets tools like rsync or
+    /// deduplicating backup programs find the unchanged parts of the compressed data.
+    ///
+    /// This makes the output slightly larger, as each reset point adds a few bytes and prevents
+    /// matches from referring to data before it. The output is still a normal deflate stream.
+    ///
+    /// The setting is kept when calling [`reset`](CompressorOxide::reset) or changing the
+    /// compression level.
+    pub fn set_rsyncable(&mut self, rsyncable: bool) {
+        self.state.params.rsyncable = rsyncable;
+    }
+
+    /// Returns whether rsyncable mode is enabled.
+    pub const fn rsyncable(&self) -> bool {
+        self.state.params.rsyncable
+    }
+
+    /// Enable or disable verification of the compressed output.
+    ///
+    /// When enabled, the output is decompressed as it is produced and compared against the
+    /// input. If they differ, compression stops with [`TDEFLStatus::BadParam`] and
+    /// [`verification_failed`](CompressorOxide::verification_failed) returns true. When
+    /// using [`compress_to_output`], the output is checked before it is passed to the callback,
+    /// so the callback never sees the mismatching data.
+    ///
+    /// This roughly doubles the time spent on compression, and the compressor keeps a copy of
+    /// the input that has not been verified yet.
+    ///
+    /// Verification has to be enabled before compression starts, or after a call to
+    /// [`reset`](CompressorOxide::reset), otherwise it will fail.
+    pub fn set_verify(&mut self, verify: bool) {
+        if !verify {
+            self.verifier = None;
+        } else if self.verifier.is_none() {
+            self.verifier = Some(Verifier::new());
+        }
+    }
+
+    /// Returns whether verification of the compressed output is enabled.
+    pub fn verify(&self) -> bool {
+        self.verifier.is_some()
+    }
+
+    /// Returns true if verification is enabled and the compressed output did not decompress to
+    /// the input, which indicates a bug in the compressor.
+    ///
+    /// This is reset by [`reset`](CompressorOxide::reset).
+    pub fn verification_failed(&self) -> bool {
+        self.verifier.as_ref().map_or(false, Verifier::failed)
+    }
+
+    /// Set a preset dictionary, data that the compressed data can refer back to as if it had
+    /// come right before the input.
+    ///
+    /// Only the last window size bytes of the dictionary are used. With a zlib wrapper, the
+    /// adler32 checksum of the whole dictionary is stored in the header to identify it, and the
+    /// same dictionary has to be given to the decompressor.
+    ///
+    /// This has to be done before compression starts, or after a call to
+    /// [`reset`](CompressorOxide::reset), and after setting the compression parameters, as
+    /// switching between the fast compression mode used by level 1 and the normal one
+    /// afterwards prevents matches in the dictionary from being found.
+    ///
+    /// Returns false if compression has started or verification is enabled, in which case
+    /// nothing is changed.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> bool {
+        self.verifier.is_none() && self.state.set_dictionary(dictionary)
+    }
+
+    /// Insert the lowest `bits` bits of `value` into the output before the next block.
+    ///
+    /// This is intended for raw deflate streams, for example to continue a bit stream that was
+    /// started elsewhere. With a zlib wrapper, bits inserted before compression starts are
+    /// written after the header, as in zlib.
+    ///
+    /// At most 16 bits can be inserted at a time, and 24 in total before the next block.
+    /// Returns false if there is no room for the bits, the stream has been finished or
+    /// verification is enabled.
+    pub fn prime(&mut self, bits: u32, value: u32) -> bool {
+        self.verifier.is_none() && self.state.prime(bits, value)
+    }
+
+    /// Get the number of bytes and bits of compressed output that has been produced but not
+    /// written to an output buffer yet, as there was not enough room.
+    ///
+    /// This does not include input that has been consumed but not compressed yet.
+    pub fn pending_output(&self) -> (usize, u32) {
+        self.state.pending_output()
+    }
+
+    /// Get the number of bytes of input that have been consumed but are not part of a finished
+    /// block yet.
+    ///
+    /// This is 0 after a flush has completed.
+    pub fn pending_input(&self) -> usize {
+        self.state.pending_input()
     }
 }
 
+#[cfg(feature = "with-alloc")]
 impl Default for CompressorOxide {
     /// Initialize the compressor with a level of 4, zlib wrapper and
     /// the default strategy.
     fn default() -> Self {
-        CompressorOxide {
-            lz: LZOxide::new(),
-            params: ParamsOxide::new(DEFAULT_FLAGS),
-            /// Put HuffmanOxide on the heap with default trick to avoid
-            /// excessive stack copies.
-            huff: Box::default(),
-            dict: DictOxide::new(DEFAULT_FLAGS),
-        }
+        CompressorOxide::new(DEFAULT_FLAGS)
     }
 }
 
-/// Callback function and user used in `compress_to_output`.
-pub struct CallbackFunc<'a> {
-    pub put_buf_func: &'a mut dyn FnMut(&[u8]) -> bool,
+/// Storage for the buffers of a [`WorkspaceCompressor`].
+///
+/// This holds all the memory the compressor needs, using the default [`BufferSizes`], so
+/// compression can be done without an allocator. The workspace is large (a bit over 300 KiB),
+/// so it should normally be placed in a `static` or some other memory that is not on the stack.
+/// [`CompressorWorkspace::new`] is a `const fn` so it can be used to initialize a `static`.
+///
+/// As the buffers have room for the largest sizes, the workspace can also be used for a
+/// compressor with smaller [`BufferSizes`] through [`buffers`](CompressorWorkspace::buffers),
+/// though that does not save any memory. Use [`CompressorBuffers`] with buffers of the
+/// required sizes for that.
+pub struct CompressorWorkspace {
+    huff: HuffmanTables,
+    dict: [u8; LZ_DICT_FULL_SIZE],
+    next: [u16; LZ_DICT_SIZE],
+    hash: [u16; LZ_HASH_SIZE],
+    codes: [u8; LZ_CODE_BUF_SIZE],
+    local_buf: [u8; OUT_BUF_SIZE],
 }
 
-impl<'a> CallbackFunc<'a> {
-    fn flush_output(
-        &mut self,
-        saved_output: SavedOutputBufferOxide,
-        params: &mut ParamsOxide,
-    ) -> i32 {
-        // TODO: As this could be unsafe since
-        // we can't verify the function pointer
-        // this whole function should maybe be unsafe as well.
-        let call_success = (self.put_buf_func)(&params.local_buf.b[0..saved_output.pos as usize]);
-
-        if !call_success {
-            params.prev_return_status = TDEFLStatus::PutBufFailed;
-            return params.prev_return_status as i32;
+impl CompressorWorkspace {
+    /// Create a new zeroed workspace.
+    pub const fn new() -> Self {
+        CompressorWorkspace {
+            huff: HuffmanTables::new(),
+            dict: [0; LZ_DICT_FULL_SIZE],
+            next: [0; LZ_DICT_SIZE],
+            hash: [0; LZ_HASH_SIZE],
+            codes: [0; LZ_CODE_BUF_SIZE],
+            local_buf: [0; OUT_BUF_SIZE],
         }
+    }
 
-        params.flush_remaining as i32
+    /// Borrow the buffers of the workspace, which are large enough for any [`BufferSizes`].
+    pub fn buffers(&mut self) -> CompressorBuffers<'_> {
+        CompressorBuffers {
+            huffman: &mut self.huff,
+            dict: &mut self.dict[..],
+            next: &mut self.next[..],
+            hash: &mut self.hash[..],
+            codes: &mut self.codes[..],
+            out_buf: &mut self.local_buf[..],
+        }
     }
 }
 
-struct CallbackBuf<'a> {
-    pub out_buf: &'a mut [u8],
+impl Default for CompressorWorkspace {
+    fn default() -> Self {
+        CompressorWorkspace::new()
+    }
 }
 
-impl<'a> CallbackBuf<'a> {
-    fn flush_output(
-        &mut self,
-        saved_output: SavedOutputBufferOxide,
-        params: &mut ParamsOxide,
-    ) -> i32 {
-        if saved_output.local {
-            let n = cmp::min(
-                saved_output.pos as usize,
-                self.out_buf.len() - params.out_buf_ofs,
-            );
-            (&mut self.out_buf[params.out_buf_ofs..params.out_buf_ofs + n])
-                .copy_from_slice(&params.local_buf.b[..n]);
-
-            params.out_buf_ofs += n;
-            if saved_output.pos != n {
-                params.flush_ofs = n as u32;
-                params.flush_remaining = (saved_output.pos - n) as u32;
-            }
-        } else {
-            params.out_buf_ofs += saved_output.pos;
-        }
+/// The huffman tables of a compressor, which have the same size whatever the [`BufferSizes`].
+pub struct HuffmanTables(HuffmanOxide);
 
-        params.flush_remaining as i32
+impl HuffmanTables {
+    /// Create new zeroed tables.
+    pub const fn new() -> Self {
+        HuffmanTables(HuffmanOxide::new())
     }
 }
 
-enum CallbackOut<'a> {
-    Func(CallbackFunc<'a>),
-    Buf(CallbackBuf<'a>),
+impl Default for HuffmanTables {
+    fn default() -> Self {
+        HuffmanTables::new()
+    }
 }
 
-impl<'a> CallbackOut<'a> {
-    fn new_output_buffer<'b>(
-        &'b mut self,
-        local_buf: &'b mut [u8],
-        out_buf_ofs: usize,
-    ) -> OutputBufferOxide<'b> {
-        let is_local;
-        let buf_len = OUT_BUF_SIZE - 16;
-        let chosen_buffer = match *self {
-            CallbackOut::Buf(ref mut cb) if cb.out_buf.len() - out_buf_ofs >= OUT_BUF_SIZE => {
-                is_local = false;
-                &mut cb.out_buf[out_buf_ofs..out_buf_ofs + buf_len]
-            }
-            _ => {
-                is_local = true;
-                &mut local_buf[..buf_len]
-            }
-        };
+/// Buffers provided by the caller for a [`WorkspaceCompressor`], so a compressor using smaller
+/// [`BufferSizes`] only needs as much memory as those sizes require.
+///
+/// Each buffer has to be at least as large as stated for the sizes used, and only that many
+/// elements of it are used. The buffers do not need to be zeroed.
+pub struct CompressorBuffers<'a> {
+    /// The huffman tables.
+    pub huffman: &'a mut HuffmanTables,
+    /// The dictionary, of [`BufferSizes::dict_size`] bytes.
+    pub dict: &'a mut [u8],
+    /// The hash chains, of [`BufferSizes::window_size`] entries.
+    pub next: &'a mut [u16],
+    /// The hash table, of [`BufferSizes::hash_size`] entries.
+    pub hash: &'a mut [u16],
+    /// The lz77 code buffer, of [`BufferSizes::lz_code_buf_size`] bytes.
+    pub codes: &'a mut [u8],
+    /// The output buffer, of [`BufferSizes::out_buf_size`] bytes.
+    pub out_buf: &'a mut [u8],
+}
 
-        OutputBufferOxide {
-            inner: chosen_buffer,
-            inner_pos: 0,
-            local: is_local,
-            bit_buffer: 0,
-            bits_in: 0,
-        }
+impl<'a> CompressorBuffers<'a> {
+    /// Whether the buffers are large enough for `sizes`.
+    fn fit(&self, sizes: &BufferSizes) -> bool {
+        self.dict.len() >= sizes.dict_size()
+            && self.next.len() >= sizes.window_size()
+            && self.hash.len() >= sizes.hash_size()
+            && self.codes.len() >= sizes.lz_code_buf_size()
+            && self.out_buf.len() >= sizes.out_buf_size()
     }
 }
 
-struct CallbackOxide<'a> {
-    in_buf: Option<&'a [u8]>,
-    in_buf_size: Option<&'a mut usize>,
-    out_buf_size: Option<&'a mut usize>,
-    out: CallbackOut<'a>,
+/// A compressor that keeps its buffers in a caller-provided [`CompressorWorkspace`] instead of
+/// allocating them, for use without the `with-alloc` feature.
+///
+/// This works the same way as [`CompressorOxide`](struct.CompressorOxide.html), with
+/// compression done through [`compress`](WorkspaceCompressor::compress) and
+/// [`compress_to_output`](WorkspaceCompressor::compress_to_output) methods instead of the free
+/// functions.
+pub struct WorkspaceCompressor<'a> {
+    state: CompressorState<BorrowedStorage<'a>>,
 }
 
-impl<'a> CallbackOxide<'a> {
-    fn new_callback_buf(in_buf: &'a [u8], out_buf: &'a mut [u8]) -> Self {
-        CallbackOxide {
-            in_buf: Some(in_buf),
-            in_buf_size: None,
-            out_buf_size: None,
-            out: CallbackOut::Buf(CallbackBuf { out_buf }),
-        }
+impl<'a> WorkspaceCompressor<'a> {
+    /// Create a new compressor with the given flags, using the buffers in `workspace`.
+    ///
+    /// The workspace does not need to be zeroed, previous contents are cleared.
+    pub fn new(flags: u32, workspace: &'a mut CompressorWorkspace) -> Self {
+        WorkspaceCompressor::from_buffers(flags, BufferSizes::default(), workspace.buffers())
     }
 
-    fn new_callback_func(in_buf: &'a [u8], callback_func: CallbackFunc<'a>) -> Self {
-        CallbackOxide {
-            in_buf: Some(in_buf),
-            in_buf_size: None,
-            out_buf_size: None,
-            out: CallbackOut::Func(callback_func),
+    /// Create a new compressor with the given flags and buffer sizes, using the buffers in
+    /// `buffers`.
+    ///
+    /// Returns `None` if any of the buffers is too small for `sizes`.
+    pub fn with_buffers(
+        flags: u32,
+        sizes: BufferSizes,
+        buffers: CompressorBuffers<'a>,
+    ) -> Option<Self> {
+        if buffers.fit(&sizes) {
+            Some(WorkspaceCompressor::from_buffers(flags, sizes, buffers))
+        } else {
+            None
         }
     }
 
-    fn update_size(&mut self, in_size: Option<usize>, out_size: Option<usize>) {
-        if let (Some(in_size), Some(size)) = (in_size, self.in_buf_size.as_mut()) {
-            **size = in_size;
-        }
+    /// Create the compressor from buffers that are known to be large enough.
+    fn from_buffers(flags: u32, sizes: BufferSizes, buffers: CompressorBuffers<'a>) -> Self {
+        let mut state = CompressorState {
+            lz: LZOxide::new(&mut buffers.codes[..sizes.lz_code_buf_size()]),
+            params: ParamsOxide::new(
+                flags,
+                LocalBuf {
+                    b: &mut buffers.out_buf[..sizes.out_buf_size()],
+                },
+            ),
+            huff: &mut buffers.huffman.0,
+            dict: DictOxide::new(
+                flags,
+                &sizes,
+                HashBuffers {
+                    dict: &mut buffers.dict[..sizes.dict_size()],
+                    next: &mut buffers.next[..sizes.window_size()],
+                    hash: &mut buffers.hash[..sizes.hash_size()],
+                },
+            ),
+            sizes,
+            pending_params: None,
+        };
+        state.reset();
+        WorkspaceCompressor { state }
+    }
 
-        if let (Some(out_size), Some(size)) = (out_size, self.out_buf_size.as_mut()) {
-            **size = out_size
-        }
+    /// Create a copy of the compressor, including any input and output it holds that has not
+    /// been written yet, using the buffers in `workspace`.
+    ///
+    /// This allows a compressor to be copied without an allocator, into a workspace placed
+    /// wherever the caller wants.
+    pub fn copy_into<'b>(&self, workspace: &'b mut CompressorWorkspace) -> WorkspaceCompressor<'b> {
+        let mut copy = WorkspaceCompressor::from_buffers(
+            self.state.params.flags,
+            self.state.sizes,
+            workspace.buffers(),
+        );
+        copy.state.copy_from(&self.state);
+        copy
     }
 
-    fn flush_output(
-        &mut self,
-        saved_output: SavedOutputBufferOxide,
-        params: &mut ParamsOxide,
-    ) -> i32 {
-        if saved_output.pos == 0 {
-            return params.flush_remaining as i32;
-        }
+    /// Create a copy of the compressor using the buffers in `buffers`.
+    ///
+    /// Returns `None` if any of the buffers is too small for the buffer sizes of this
+    /// compressor.
+    pub fn copy_into_buffers<'b>(
+        &self,
+        buffers: CompressorBuffers<'b>,
+    ) -> Option<WorkspaceCompressor<'b>> {
+        let mut copy =
+            WorkspaceCompressor::with_buffers(self.state.params.flags, self.state.sizes, buffers)?;
+        copy.state.copy_from(&self.state);
+        Some(copy)
+    }
 
-        self.update_size(Some(params.src_pos), None);
-        match self.out {
-            CallbackOut::Func(ref mut cf) => cf.flush_output(saved_output, params),
-            CallbackOut::Buf(ref mut cb) => cb.flush_output(saved_output, params),
-        }
+    /// Get the sizes of the buffers used by this compressor.
+    pub const fn buffer_sizes(&self) -> BufferSizes {
+        self.state.sizes
     }
-}
 
-struct OutputBufferOxide<'a> {
-    pub inner: &'a mut [u8],
-    pub inner_pos: usize,
-    pub local: bool,
+    /// Get the adler32 checksum of the currently encoded data.
+    pub const fn adler32(&self) -> u32 {
+        self.state.params.adler32
+    }
+
+    /// Get the return status of the previous compress call with this compressor.
+    pub const fn prev_return_status(&self) -> TDEFLStatus {
+        self.state.params.prev_return_status
+    }
+
+    /// Get the raw compressor flags.
+    pub const fn flags(&self) -> i32 {
+        self.state.params.flags as i32
+    }
+
+    /// Returns whether the compressor is wrapping the data in a zlib format or not.
+    pub fn data_format(&self) -> DataFormat {
+        self.state.data_format()
+    }
+
+    /// Reset the state of the compressor, keeping the same parameters.
+    pub fn reset(&mut self) {
+        self.state.reset();
+    }
+
+    /// Set the compression level of the compressor.
+    ///
+    /// Using this to change level after compression has started is supported.
+    /// # Notes
+    /// The compression strategy is kept, and level 0 uses raw blocks. As with `set_params`, some
+    /// changes take effect after the current block.
+    pub fn set_compression_level(&mut self, level: CompressionLevel) {
+        self.state.set_compression_level_raw(level as u8);
+    }
+
+    /// Update the compression settings of the compressor.
+    ///
+    /// Changing the `DataFormat` after compression has started will result in
+    /// a corrupted stream. The compression strategy is set to the default one.
+    pub fn set_format_and_level(&mut self, data_format: DataFormat, level: u8) {
+        self.state.set_format_and_level(data_format, level);
+    }
+
+    /// Get the current compression parameters.
+    pub fn params(&self) -> CompressionParams {
+        self.state.params()
+    }
+
+    /// Set the compression parameters.
+    ///
+    /// See [`CompressorOxide::set_params`](struct.CompressorOxide.html#method.set_params).
+    pub fn set_params(&mut self, params: CompressionParams) {
+        self.state.set_params(&params);
+    }
+
+    /// Enable or disable rsyncable mode.
+    ///
+    /// See [`CompressorOxide::set_rsyncable`](struct.CompressorOxide.html#method.set_rsyncable).
+    pub fn set_rsyncable(&mut self, rsyncable: bool) {
+        self.state.params.rsyncable = rsyncable;
+    }
+
+    /// Returns whether rsyncable mode is enabled.
+    pub const fn rsyncable(&self) -> bool {
+        self.state.params.rsyncable
+    }
+
+    /// Set a preset dictionary.
+    ///
+    /// See [`CompressorOxide::set_dictionary`](struct.CompressorOxide.html#method.set_dictionary).
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> bool {
+        self.state.set_dictionary(dictionary)
+    }
+
+    /// Insert bits into the output before the next block.
+    ///
+    /// See [`CompressorOxide::prime`](struct.CompressorOxide.html#method.prime).
+    pub fn prime(&mut self, bits: u32, value: u32) -> bool {
+        self.state.prime(bits, value)
+    }
+
+    /// Get the number of bytes and bits of output that has not been written yet.
+    ///
+    /// See [`CompressorOxide::pending_output`](struct.CompressorOxide.html#method.pending_output).
+    pub fn pending_output(&self) -> (usize, u32) {
+        self.state.pending_output()
+    }
+
+    /// Get the number of bytes of input that are not part of a finished block yet.
+    ///
+    /// See [`CompressorOxide::pending_input`](struct.CompressorOxide.html#method.pending_input).
+    pub fn pending_input(&self) -> usize {
+        self.state.pending_input()
+    }
+
+    /// Compress as much as possible from `in_buf` into `out_buf`.
+    ///
+    /// See [`compress`](fn.compress.html) for details.
+    pub fn compress(
+        &mut self,
+        in_buf: &[u8],
+        out_buf: &mut [u8],
+        flush: TDEFLFlush,
+    ) -> (TDEFLStatus, usize, usize) {
+        self.compress_with_flush(in_buf, out_buf, flush.into())
+    }
+
+    /// Version of [`compress`](WorkspaceCompressor::compress) that also supports the partial and
+    /// block flushes of [`MZFlush`].
+    pub(crate) fn compress_with_flush(
+        &mut self,
+        in_buf: &[u8],
+        out_buf: &mut [u8],
+        flush: MZFlush,
+    ) -> (TDEFLStatus, usize, usize) {
+        compress_inner(
+            &mut self.state,
+            &mut CallbackOxide::new_callback_buf(in_buf, out_buf),
+            flush,
+        )
+    }
+
+    /// Compress as much as possible from `in_buf`, passing the output to `callback_func`.
+    ///
+    /// See [`compress_to_output`](fn.compress_to_output.html) for details.
+    pub fn compress_to_output(
+        &mut self,
+        in_buf: &[u8],
+        flush: TDEFLFlush,
+        mut callback_func: impl FnMut(&[u8]) -> bool,
+    ) -> (TDEFLStatus, usize) {
+        let res = compress_inner(
+            &mut self.state,
+            &mut CallbackOxide::new_callback_func(
+                in_buf,
+                CallbackFunc {
+                    put_buf_func: &mut callback_func,
+                },
+            ),
+            flush.into(),
+        );
+
+        (res.0, res.1)
+    }
+}
+
+/// The state of a compressor, generic over how the buffers are stored.
+struct CompressorState<S: BufferStorage> {
+    lz: LZOxide<S>,
+    params: ParamsOxide<S>,
+    huff: S::Huffman,
+    dict: DictOxide<S>,
+    sizes: BufferSizes,
+    /// Parameters to switch to once the current block has been finished.
+    pending_params: Option<CompressionParams>,
+}
+
+impl<S: BufferStorage> CompressorState<S> {
+    /// Copy the state of `other`, which has buffers of the same sizes but may store them
+    /// differently.
+    fn copy_from<T: BufferStorage>(&mut self, other: &CompressorState<T>) {
+        self.lz.copy_from(&other.lz);
+        self.params.copy_from(&other.params);
+        (*self.huff).clone_from(&*other.huff);
+        self.dict.copy_from(&other.dict);
+        self.sizes = other.sizes;
+        self.pending_params = other.pending_params;
+    }
+
+    fn data_format(&self) -> DataFormat {
+        if (self.params.flags & TDEFL_WRITE_ZLIB_HEADER) != 0 {
+            DataFormat::Zlib
+        } else {
+            DataFormat::Raw
+        }
+    }
+
+    fn reset(&mut self) {
+        // Huffman has no settings or dynamic memory
+        // that needs to be saved, so we simply replace it.
+        self.lz.reset();
+        self.params.reset();
+        *self.huff = HuffmanOxide::new();
+        self.dict.reset();
+        if let Some(params) = self.pending_params.take() {
+            self.apply_params(&params);
+        }
+    }
+
+    fn set_compression_level_raw(&mut self, level: u8) {
+        let strategy = self.params().strategy;
+        let flags = create_comp_flags_from_zip_params(
+            level.into(),
+            self.data_format().to_window_bits(),
+            strategy as i32,
+        );
+        // Level 0 ignores the strategy, but it should still apply when changing to another level.
+        let mut params = CompressionParams::from_flags(flags);
+        params.strategy = strategy;
+        self.set_params(&params);
+    }
+
+    fn set_format_and_level(&mut self, data_format: DataFormat, level: u8) {
+        let flags = create_comp_flags_from_zip_params(
+            level.into(),
+            data_format.to_window_bits(),
+            CompressionStrategy::Default as i32,
+        );
+        self.set_params(&CompressionParams::from_flags(flags));
+    }
+
+    fn params(&self) -> CompressionParams {
+        self.pending_params.unwrap_or_else(|| self.current_params())
+    }
+
+    /// The parameters used for the current block.
+    fn current_params(&self) -> CompressionParams {
+        let mut params = CompressionParams::from_flags(self.params.flags);
+        params.max_lazy = self.params.max_lazy;
+        params.good_length = self.dict.good_length as u16;
+        params.nice_length = self.dict.nice_length as u16;
+        params.min_match_len = self.params.min_match_len;
+        params
+    }
+
+    /// Set the parameters, waiting until the current block is finished if the change can't be
+    /// done in the middle of a block.
+    fn set_params(&mut self, params: &CompressionParams) {
+        let params = params.clamped();
+        let current = self.current_params();
+        // The block type decisions depend on the strategy, and the fast and normal compression
+        // functions keep their state differently, so changing either needs a new block.
+        let new_block = current.strategy != params.strategy
+            || use_fast_compression(current.to_flags(), current.min_match_len.into())
+                != use_fast_compression(params.to_flags(), params.min_match_len.into());
+        let in_block = self.dict.lookahead_size != 0 || self.lz.total_bytes != 0;
+
+        if new_block && in_block {
+            self.pending_params = Some(params);
+        } else {
+            self.pending_params = None;
+            self.apply_params(&params);
+        }
+    }
+
+    fn apply_params(&mut self, params: &CompressionParams) {
+        // Keep the flags that are not covered by the parameters.
+        let flags = params.to_flags() | (self.params.flags & TDEFL_COMPUTE_ADLER32);
+        self.params.update_flags(flags);
+        self.dict.update_flags(flags);
+        self.params.max_lazy = params.max_lazy;
+        self.params.min_match_len = params.min_match_len;
+        self.dict.good_length = params.good_length.into();
+        self.dict.nice_length = params.nice_length.into();
+    }
+
+    /// Returns true if no input has been compressed or output produced since the compressor was
+    /// created or reset.
+    fn at_start(&self) -> bool {
+        self.params.block_index == 0
+            && !self.params.finished
+            && self.params.flush_remaining == 0
+            && self.lz.total_bytes == 0
+            && self.dict.lookahead_size == 0
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> bool {
+        if !self.at_start() {
+            return false;
+        }
+
+        self.dict.reset();
+        let fast = use_fast_compression(self.params.flags, self.params.min_match_len.into());
+        self.dict.load(dictionary, fast);
+        self.params.dict_id = if self.data_format() == DataFormat::Zlib {
+            Some(update_adler32(MZ_ADLER32_INIT, dictionary))
+        } else {
+            None
+        };
+        true
+    }
+
+    fn prime(&mut self, bits: u32, value: u32) -> bool {
+        // The saved bits are put in front of the first bits of the next block, so there has to
+        // be room for those as well.
+        if bits > 16
+            || u32::from(self.params.saved_bits_in) + bits > MAX_PRIMED_BITS
+            || self.params.finished
+        {
+            return false;
+        }
+
+        let value = value & ((1 << bits) - 1);
+        self.params.saved_bit_buffer |= value << self.params.saved_bits_in;
+        self.params.saved_bits_in += bits as u8;
+        true
+    }
+
+    fn pending_output(&self) -> (usize, u32) {
+        let saved_bits = u32::from(self.params.saved_bits_in);
+        (
+            self.params.flush_remaining as usize + (saved_bits / 8) as usize,
+            saved_bits % 8,
+        )
+    }
+
+    fn pending_input(&self) -> usize {
+        self.dict.lookahead_size + self.lz.total_bytes as usize
+    }
+}
+
+#[cfg(feature = "with-alloc")]
+impl Clone for CompressorState<HeapStorage> {
+    fn clone(&self) -> Self {
+        CompressorState {
+            lz: self.lz.clone(),
+            params: self.params.clone(),
+            huff: self.huff.clone(),
+            dict: self.dict.clone(),
+            sizes: self.sizes,
+            pending_params: self.pending_params,
+        }
+    }
+}
+
+/// Callback function and user used in `compress_to_output`.
+pub struct CallbackFunc<'a> {
+    pub put_buf_func: &'a mut dyn FnMut(&[u8]) -> bool,
+}
+
+impl<'a> CallbackFunc<'a> {
+    fn flush_output<S: BufferStorage>(
+        &mut self,
+        saved_output: SavedOutputBufferOxide,
+        params: &mut ParamsOxide<S>,
+    ) -> i32 {
+        // TODO: As this could be unsafe since
+        // we can't verify the function pointer
+        // this whole function should maybe be unsafe as well.
+        let call_success = (self.put_buf_func)(&params.local_buf.b[0..saved_output.pos as usize]);
+
+        if !call_success {
+            params.prev_return_status = TDEFLStatus::PutBufFailed;
+            return params.prev_return_status as i32;
+        }
+
+        params.flush_remaining as i32
+    }
+}
+
+struct CallbackBuf<'a> {
+    pub out_buf: &'a mut [u8],
+}
+
+impl<'a> CallbackBuf<'a> {
+    fn flush_output<S: BufferStorage>(
+        &mut self,
+        saved_output: SavedOutputBufferOxide,
+        params: &mut ParamsOxide<S>,
+    ) -> i32 {
+        if saved_output.local {
+            let n = cmp::min(
+                saved_output.pos as usize,
+                self.out_buf.len() - params.out_buf_ofs,
+            );
+            (&mut self.out_buf[params.out_buf_ofs..params.out_buf_ofs + n])
+                .copy_from_slice(&params.local_buf.b[..n]);
+
+            params.out_buf_ofs += n;
+            if saved_output.pos != n {
+                params.flush_ofs = n as u32;
+                params.flush_remaining = (saved_output.pos - n) as u32;
+            }
+        } else {
+            params.out_buf_ofs += saved_output.pos;
+        }
+
+        params.flush_remaining as i32
+    }
+}
+
+enum CallbackOut<'a> {
+    Func(CallbackFunc<'a>),
+    Buf(CallbackBuf<'a>),
+}
+
+impl<'a> CallbackOut<'a> {
+    fn new_output_buffer<'b>(
+        &'b mut self,
+        local_buf: &'b mut [u8],
+        out_buf_ofs: usize,
+    ) -> OutputBufferOxide<'b> {
+        let is_local;
+        let buf_len = local_buf.len() - 16;
+        let chosen_buffer = match *self {
+            CallbackOut::Buf(ref mut cb) if cb.out_buf.len() - out_buf_ofs >= local_buf.len() => {
+                is_local = false;
+                &mut cb.out_buf[out_buf_ofs..out_buf_ofs + buf_len]
+            }
+            _ => {
+                is_local = true;
+                &mut local_buf[..buf_len]
+            }
+        };
+
+        OutputBufferOxide {
+            inner: chosen_buffer,
+            inner_pos: 0,
+            local: is_local,
+            bit_buffer: 0,
+            bits_in: 0,
+        }
+    }
+}
+
+struct CallbackOxide<'a> {
+    in_buf: Option<&'a [u8]>,
+    in_buf_size: Option<&'a mut usize>,
+    out_buf_size: Option<&'a mut usize>,
+    out: CallbackOut<'a>,
+}
+
+impl<'a> CallbackOxide<'a> {
+    fn new_callback_buf(in_buf: &'a [u8], out_buf: &'a mut [u8]) -> Self {
+        CallbackOxide {
+            in_buf: Some(in_buf),
+            in_buf_size: None,
+            out_buf_size: None,
+            out: CallbackOut::Buf(CallbackBuf { out_buf }),
+        }
+    }
+
+    fn new_callback_func(in_buf: &'a [u8], callback_func: CallbackFunc<'a>) -> Self {
+        CallbackOxide {
+            in_buf: Some(in_buf),
+            in_buf_size: None,
+            out_buf_size: None,
+            out: CallbackOut::Func(callback_func),
+        }
+    }
+
+    fn update_size(&mut self, in_size: Option<usize>, out_size: Option<usize>) {
+        if let (Some(in_size), Some(size)) = (in_size, self.in_buf_size.as_mut()) {
+            **size = in_size;
+        }
+
+        if let (Some(out_size), Some(size)) = (out_size, self.out_buf_size.as_mut()) {
+            **size = out_size
+        }
+    }
+
+    fn flush_output<S: BufferStorage>(
+        &mut self,
+        saved_output: SavedOutputBufferOxide,
+        params: &mut ParamsOxide<S>,
+    ) -> i32 {
+        if saved_output.pos == 0 {
+            return params.flush_remaining as i32;
+        }
+
+        self.update_size(Some(params.src_pos), None);
+        match self.out {
+            CallbackOut::Func(ref mut cf) => cf.flush_output(saved_output, params),
+            CallbackOut::Buf(ref mut cb) => cb.flush_output(saved_output, params),
+        }
+    }
+}
+
+struct OutputBufferOxide<'a> {
+    pub inner: &'a mut [u8],
+    pub inner_pos: usize,
+    pub local: bool,
 
     pub bit_buffer: u32,
     pub bits_in: u32,
@@ -759,7 +1609,8 @@ impl BitBuffer {
 /// NOTE: Only the literal/lengths have enough symbols to actually use
 /// the full array. It's unclear why it's defined like this in miniz,
 /// it could be for cache/alignment reasons.
-struct HuffmanOxide {
+#[derive(Clone)]
+pub(crate) struct HuffmanOxide {
     /// Number of occurrences of each symbol.
     pub count: [[u16; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
     /// The bits of the huffman code assigned to the symbol
@@ -844,15 +1695,19 @@ fn write(src: &[u8], dst: &mut [u8], dst_pos: &mut usize) -> Result<()> {
 
 impl Default for HuffmanOxide {
     fn default() -> Self {
+        HuffmanOxide::new()
+    }
+}
+
+impl HuffmanOxide {
+    const fn new() -> Self {
         HuffmanOxide {
             count: [[0; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
             codes: [[0; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
             code_sizes: [[0; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
         }
     }
-}
 
-impl HuffmanOxide {
     fn radix_sort_symbols<'a>(
         symbols0: &'a mut [SymFreq],
         symbols1: &'a mut [SymFreq],
@@ -1194,13 +2049,25 @@ impl HuffmanOxide {
     }
 }
 
-struct DictOxide {
+struct DictOxide<S: BufferStorage> {
     /// The maximum number of checks in the hash chain, for the initial,
     /// and the lazy match respectively.
     pub max_probes: [u32; 2],
+    /// Matches at least this long use the second, lower, number of probes.
+    pub good_length: u32,
+    /// Stop looking for longer matches once a match at least this long is found.
+    pub nice_length: u32,
     /// Buffer of input data.
     /// Padded with 1 byte to simplify matching code in `compress_fast`.
-    pub b: Box<HashBuffers>,
+    pub b: HashBuffers<S>,
+
+    /// Mask applied to positions to get the position in the dictionary.
+    /// The size of the dictionary is this plus one.
+    pub window_mask: usize,
+    /// How many bits to shift when updating the current hash value.
+    pub hash_shift: u32,
+    /// Mask applied to hash values to keep them within the hash table.
+    pub hash_mask: u32,
 
     pub code_buf_dict_pos: usize,
     pub lookahead_size: usize,
@@ -1208,6 +2075,16 @@ struct DictOxide {
     pub size: usize,
 }
 
+#[cfg(feature = "with-alloc")]
+impl Clone for DictOxide<HeapStorage> {
+    fn clone(&self) -> Self {
+        DictOxide {
+            b: self.b.clone(),
+            ..*self
+        }
+    }
+}
+
 const fn probes_from_flags(flags: u32) -> [u32; 2] {
     [
         1 + ((flags & 0xFFF) + 2) / 3,
@@ -1215,11 +2092,16 @@ const fn probes_from_flags(flags: u32) -> [u32; 2] {
     ]
 }
 
-impl DictOxide {
-    fn new(flags: u32) -> Self {
+impl<S: BufferStorage> DictOxide<S> {
+    fn new(flags: u32, sizes: &BufferSizes, b: HashBuffers<S>) -> Self {
         DictOxide {
             max_probes: probes_from_flags(flags),
-            b: Box::default(),
+            good_length: DEFAULT_GOOD_LENGTH.into(),
+            nice_length: MAX_MATCH_LEN as u32,
+            b,
+            window_mask: sizes.window_size() - 1,
+            hash_shift: (u32::from(sizes.hash_bits()) + 2) / 3,
+            hash_mask: sizes.hash_size() as u32 - 1,
             code_buf_dict_pos: 0,
             lookahead_size: 0,
             lookahead_pos: 0,
@@ -1231,6 +2113,52 @@ impl DictOxide {
         self.max_probes = probes_from_flags(flags);
     }
 
+    fn copy_from<T: BufferStorage>(&mut self, other: &DictOxide<T>) {
+        self.max_probes = other.max_probes;
+        self.good_length = other.good_length;
+        self.nice_length = other.nice_length;
+        self.b.copy_from(&other.b);
+        self.window_mask = other.window_mask;
+        self.hash_shift = other.hash_shift;
+        self.hash_mask = other.hash_mask;
+        self.code_buf_dict_pos = other.code_buf_dict_pos;
+        self.lookahead_size = other.lookahead_size;
+        self.lookahead_pos = other.lookahead_pos;
+        self.size = other.size;
+    }
+
+    /// Size of the dictionary.
+    #[inline]
+    const fn window_size(&self) -> usize {
+        self.window_mask + 1
+    }
+
+    /// Number of bytes a block that does not compress well may grow to before it is flushed.
+    #[inline]
+    fn raw_block_limit(&self) -> u32 {
+        raw_block_limit(self.window_size()) as u32
+    }
+
+    /// Forget the previous data so later matches can't refer to it, as done on a full flush.
+    fn clear_matches(&mut self) {
+        memset(&mut self.b.hash[..], 0);
+        memset(&mut self.b.next[..], 0);
+        self.size = 0;
+    }
+
+    /// Forget the previous data and continue from position 0, as if the dictionary was new. The
+    /// positions stored in the hash chains and the checks on them then no longer depend on how
+    /// much was compressed before, so the matches found are the same as for a new compressor.
+    ///
+    /// The lookahead has to be empty and the current block flushed.
+    fn restart(&mut self) {
+        debug_assert!(self.lookahead_size == 0);
+        debug_assert!(self.code_buf_dict_pos == self.lookahead_pos);
+        self.clear_matches();
+        self.code_buf_dict_pos = 0;
+        self.lookahead_pos = 0;
+    }
+
     fn reset(&mut self) {
         self.b.reset();
         self.code_buf_dict_pos = 0;
@@ -1239,15 +2167,50 @@ impl DictOxide {
         self.size = 0;
     }
 
+    /// Fill the empty dictionary with the end of `dictionary`, as if it had been compressed
+    /// before the input.
+    ///
+    /// The positions are added to the hash table used by the fast compression function if
+    /// `fast` is set, otherwise to the hash chains used by the normal one.
+    fn load(&mut self, dictionary: &[u8], fast: bool) {
+        let window_size = self.window_size();
+        let dictionary = &dictionary[dictionary.len().saturating_sub(window_size)..];
+        let len = dictionary.len();
+        self.b.dict[..len].copy_from_slice(dictionary);
+        let mirrored = cmp::min(len, MAX_MATCH_LEN - 1);
+        self.b.dict[window_size..window_size + mirrored].copy_from_slice(&dictionary[..mirrored]);
+
+        // The last two positions are hashed once the next bytes are known.
+        let level1_hash_mask = cmp::min(LEVEL1_HASH_SIZE_MASK, self.hash_mask);
+        for pos in 0..len.saturating_sub(2) {
+            if fast {
+                let trigram = self.read_unaligned_u32(pos) & 0xFF_FFFF;
+                let hash = (trigram ^ (trigram >> (24 - (LZ_HASH_BITS - 8)))) & level1_hash_mask;
+                self.b.hash[hash as usize] = pos as u16;
+            } else {
+                let hash = ((u32::from(self.b.dict[pos]) << (self.hash_shift * 2))
+                    ^ (u32::from(self.b.dict[pos + 1]) << self.hash_shift)
+                    ^ u32::from(self.b.dict[pos + 2]))
+                    & self.hash_mask;
+                self.b.next[pos] = self.b.hash[hash as usize];
+                self.b.hash[hash as usize] = pos as u16;
+            }
+        }
+
+        self.size = len;
+        self.lookahead_pos = len;
+        self.code_buf_dict_pos = len;
+    }
+
     /// Do an unaligned read of the data at `pos` in the dictionary and treat it as if it was of
     /// type T.
     #[inline]
     fn read_unaligned_u32(&self, pos: usize) -> u32 {
         // Masking the value here helps avoid bounds checks.
-        let pos = (pos & LZ_DICT_SIZE_MASK) as usize;
+        let pos = pos & self.window_mask;
         let end = pos + 4;
         // Somehow this assertion makes things faster.
-        assert!(end < LZ_DICT_FULL_SIZE);
+        assert!(end < self.b.dict.len());
 
         let bytes: [u8; 4] = self.b.dict[pos..end].try_into().unwrap();
         u32::from_le_bytes(bytes)
@@ -1289,10 +2252,10 @@ impl DictOxide {
         let max_match_len = cmp::min(MAX_MATCH_LEN as u32, max_match_len);
         match_len = cmp::max(match_len, 1);
 
-        let pos = lookahead_pos as usize & LZ_DICT_SIZE_MASK;
+        let pos = lookahead_pos as usize & self.window_mask;
         let mut probe_pos = pos;
         // Number of probes into the hash chains.
-        let mut num_probes_left = self.max_probes[(match_len >= 32) as usize];
+        let mut num_probes_left = self.max_probes[(match_len >= self.good_length) as usize];
 
         // If we already have a match of the full length don't bother searching for another one.
         if max_match_len <= match_len {
@@ -1327,7 +2290,7 @@ impl DictOxide {
 
                     // Mask the position value to get the position in the hash chain of the next
                     // position to match against.
-                    probe_pos = next_probe_pos & LZ_DICT_SIZE_MASK;
+                    probe_pos = next_probe_pos & self.window_mask;
 
                     if self.read_as_u16((probe_pos + match_len as usize - 1) as usize) == c01 {
                         break 'found;
@@ -1365,9 +2328,9 @@ impl DictOxide {
                     if probe_len > match_len as usize {
                         match_dist = dist as u32;
                         match_len = cmp::min(max_match_len, probe_len as u32);
-                        if match_len == max_match_len {
-                            // We found a match that had the maximum allowed length,
-                            // so there is now point searching further.
+                        if match_len == max_match_len || match_len >= self.nice_length {
+                            // We found a match that had the maximum allowed length, or one that
+                            // is long enough, so there is no point searching further.
                             return (match_dist, match_len);
                         }
                         // We found a better match, so save the last two bytes for further match
@@ -1383,16 +2346,102 @@ impl DictOxide {
     }
 }
 
-struct ParamsOxide {
+/// Minimum number of bytes between two reset points in rsyncable mode.
+const RSYNC_MIN_CHUNK: usize = 2048;
+/// A reset point is placed where the bits of the rolling hash in this mask are all zero, which
+/// happens on average once every 4096 bytes after the minimum chunk size.
+const RSYNC_HASH_MASK: u64 = 0xFFF << 52;
+
+/// Maximum number of bytes to look ahead for a reset point in rsyncable mode.
+const RSYNC_MAX_SCAN: usize = 64 * 1024;
+
+/// Random values for each byte used by the rolling hash.
+static RSYNC_GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    // Fill the table using the splitmix64 generator.
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// State of the rolling hash used to find the points where the compressor is reset in
+/// rsyncable mode.
+///
+/// The hash is shifted one bit to the left for each byte, so it only depends on the last 64
+/// bytes of input, and the reset points are determined by the content of the data rather than
+/// their position.
+#[derive(Copy, Clone)]
+struct RsyncState {
+    hash: u64,
+    /// Number of bytes since the last reset point.
+    chunk_len: usize,
+    /// The input up to the next reset point has been consumed, but the compressor could not be
+    /// reset yet as the output buffer was full.
+    reset_pending: bool,
+}
+
+impl RsyncState {
+    const fn new() -> Self {
+        RsyncState {
+            hash: 0,
+            chunk_len: 0,
+            reset_pending: false,
+        }
+    }
+
+    /// Update the hash with the consumed bytes in `data`.
+    fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.hash = (self.hash << 1).wrapping_add(RSYNC_GEAR[usize::from(b)]);
+        }
+        self.chunk_len += data.len();
+    }
+
+    /// Find the next reset point in `data`.
+    ///
+    /// Returns the number of bytes up to and including the byte the compressor should be reset
+    /// after, if any.
+    fn next_reset_point(&self, data: &[u8]) -> Option<usize> {
+        if self.reset_pending {
+            return Some(0);
+        }
+
+        let mut hash = self.hash;
+        let mut chunk_len = self.chunk_len;
+        for (i, &b) in data.iter().enumerate() {
+            hash = (hash << 1).wrapping_add(RSYNC_GEAR[usize::from(b)]);
+            chunk_len += 1;
+            if chunk_len >= RSYNC_MIN_CHUNK && hash & RSYNC_HASH_MASK == 0 {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+struct ParamsOxide<S: BufferStorage> {
     pub flags: u32,
     pub greedy_parsing: bool,
+    /// Matches at least this long are used without checking the next position when using lazy
+    /// parsing.
+    pub max_lazy: u16,
+    /// Shortest match to use, not counting the higher minimum of `TDEFL_FILTER_MATCHES`.
+    pub min_match_len: u16,
     pub block_index: u32,
 
     pub saved_match_dist: u32,
     pub saved_match_len: u32,
     pub saved_lit: u8,
 
-    pub flush: TDEFLFlush,
+    pub flush: MZFlush,
     pub flush_ofs: u32,
     pub flush_remaining: u32,
     pub finished: bool,
@@ -1405,21 +2454,44 @@ struct ParamsOxide {
     pub prev_return_status: TDEFLStatus,
 
     pub saved_bit_buffer: u32,
-    pub saved_bits_in: u32,
-
-    pub local_buf: Box<LocalBuf>,
+    /// At most `MAX_PRIMED_BITS`, kept small to fit the `tdefl_compressor` of the C API.
+    pub saved_bits_in: u8,
+
+    /// Adler-32 checksum of the preset dictionary, written after the zlib header.
+    pub dict_id: Option<u32>,
+
+    /// Whether to reset the compressor at content-defined points.
+    pub rsyncable: bool,
+    pub rsync: RsyncState,
+
+    /// Whether the input looks incompressible, in which case the match search is skipped and the
+    /// blocks are stored.
+    pub incompressible: bool,
+    /// Whether the current block has been checked for looking incompressible.
+    pub block_checked: bool,
+    /// Number of positions where a match was looked for in the current block while the input
+    /// looks incompressible, and the number of those where one was found. At most one position
+    /// is sampled for each byte of a block, so these fit in 16 bits.
+    pub samples: u16,
+    pub sample_matches: u16,
+    /// One bit for each of the last 8 sampled positions, set if a match was found there.
+    pub recent_samples: u8,
+
+    pub local_buf: LocalBuf<S>,
 }
 
-impl ParamsOxide {
-    fn new(flags: u32) -> Self {
+impl<S: BufferStorage> ParamsOxide<S> {
+    fn new(flags: u32, local_buf: LocalBuf<S>) -> Self {
         ParamsOxide {
             flags,
             greedy_parsing: flags & TDEFL_GREEDY_PARSING_FLAG != 0,
+            max_lazy: DEFAULT_MAX_LAZY,
+            min_match_len: MIN_MATCH_LEN.into(),
             block_index: 0,
             saved_match_dist: 0,
             saved_match_len: 0,
             saved_lit: 0,
-            flush: TDEFLFlush::None,
+            flush: MZFlush::None,
             flush_ofs: 0,
             flush_remaining: 0,
             finished: false,
@@ -1429,7 +2501,15 @@ impl ParamsOxide {
             prev_return_status: TDEFLStatus::Okay,
             saved_bit_buffer: 0,
             saved_bits_in: 0,
-            local_buf: Box::default(),
+            dict_id: None,
+            rsyncable: false,
+            rsync: RsyncState::new(),
+            incompressible: false,
+            block_checked: false,
+            samples: 0,
+            sample_matches: 0,
+            recent_samples: 0,
+            local_buf,
         }
     }
 
@@ -1438,13 +2518,43 @@ impl ParamsOxide {
         self.greedy_parsing = self.flags & TDEFL_GREEDY_PARSING_FLAG != 0;
     }
 
+    fn copy_from<T: BufferStorage>(&mut self, other: &ParamsOxide<T>) {
+        self.flags = other.flags;
+        self.greedy_parsing = other.greedy_parsing;
+        self.max_lazy = other.max_lazy;
+        self.min_match_len = other.min_match_len;
+        self.block_index = other.block_index;
+        self.saved_match_dist = other.saved_match_dist;
+        self.saved_match_len = other.saved_match_len;
+        self.saved_lit = other.saved_lit;
+        self.flush = other.flush;
+        self.flush_ofs = other.flush_ofs;
+        self.flush_remaining = other.flush_remaining;
+        self.finished = other.finished;
+        self.adler32 = other.adler32;
+        self.src_pos = other.src_pos;
+        self.out_buf_ofs = other.out_buf_ofs;
+        self.prev_return_status = other.prev_return_status;
+        self.saved_bit_buffer = other.saved_bit_buffer;
+        self.saved_bits_in = other.saved_bits_in;
+        self.dict_id = other.dict_id;
+        self.rsyncable = other.rsyncable;
+        self.rsync = other.rsync;
+        self.incompressible = other.incompressible;
+        self.block_checked = other.block_checked;
+        self.samples = other.samples;
+        self.sample_matches = other.sample_matches;
+        self.recent_samples = other.recent_samples;
+        self.local_buf.b.copy_from_slice(&other.local_buf.b);
+    }
+
     /// Reset state, saving settings.
     fn reset(&mut self) {
         self.block_index = 0;
         self.saved_match_len = 0;
         self.saved_match_dist = 0;
         self.saved_lit = 0;
-        self.flush = TDEFLFlush::None;
+        self.flush = MZFlush::None;
         self.flush_ofs = 0;
         self.flush_remaining = 0;
         self.finished = false;
@@ -1454,12 +2564,29 @@ impl ParamsOxide {
         self.prev_return_status = TDEFLStatus::Okay;
         self.saved_bit_buffer = 0;
         self.saved_bits_in = 0;
-        self.local_buf.b = [0; OUT_BUF_SIZE];
+        self.dict_id = None;
+        self.rsync = RsyncState::new();
+        self.incompressible = false;
+        self.block_checked = false;
+        self.samples = 0;
+        self.sample_matches = 0;
+        self.recent_samples = 0;
+        memset(&mut self.local_buf.b[..], 0);
+    }
+}
+
+#[cfg(feature = "with-alloc")]
+impl Clone for ParamsOxide<HeapStorage> {
+    fn clone(&self) -> Self {
+        ParamsOxide {
+            local_buf: self.local_buf.clone(),
+            ..*self
+        }
     }
 }
 
-struct LZOxide {
-    pub codes: [u8; LZ_CODE_BUF_SIZE],
+struct LZOxide<S: BufferStorage> {
+    pub codes: S::Bytes,
     pub code_position: usize,
     pub flag_position: usize,
 
@@ -1467,19 +2594,64 @@ struct LZOxide {
     // (Could maybe use usize, but it's not possible to exceed a block size of )
     pub total_bytes: u32,
     pub num_flags_left: u32,
+    /// Number of bits the codes in the current block take with the fixed huffman codes.
+    pub fixed_bits: u32,
 }
 
-impl LZOxide {
-    const fn new() -> Self {
+#[cfg(feature = "with-alloc")]
+impl Clone for LZOxide<HeapStorage> {
+    fn clone(&self) -> Self {
         LZOxide {
-            codes: [0; LZ_CODE_BUF_SIZE],
+            codes: self.codes.clone(),
+            ..*self
+        }
+    }
+}
+
+impl<S: BufferStorage> LZOxide<S> {
+    fn new(codes: S::Bytes) -> Self {
+        LZOxide {
+            codes,
             code_position: 1,
             flag_position: 0,
             total_bytes: 0,
             num_flags_left: 8,
+            fixed_bits: 0,
         }
     }
 
+    fn copy_from<T: BufferStorage>(&mut self, other: &LZOxide<T>) {
+        self.codes.copy_from_slice(&other.codes);
+        self.code_position = other.code_position;
+        self.flag_position = other.flag_position;
+        self.total_bytes = other.total_bytes;
+        self.num_flags_left = other.num_flags_left;
+        self.fixed_bits = other.fixed_bits;
+    }
+
+    fn reset(&mut self) {
+        memset(&mut self.codes[..], 0);
+        self.code_position = 1;
+        self.flag_position = 0;
+        self.total_bytes = 0;
+        self.num_flags_left = 8;
+        self.fixed_bits = 0;
+    }
+
+    /// Whether the buffer is close to full, and the block needs to be flushed.
+    #[inline]
+    fn is_tight(&self) -> bool {
+        self.code_position > self.codes.len() - 8
+    }
+
+    /// Whether coding the block could take more space than storing it. The dynamic huffman codes
+    /// never take more space for the data than the fixed ones, but the block header can be
+    /// large.
+    #[inline]
+    fn may_expand(&self) -> bool {
+        self.fixed_bits + MAX_DYNAMIC_BLOCK_BITS > self.total_bytes * 8
+    }
+
     fn write_code(&mut self, val: u8) {
         self.codes[self.code_position] = val;
         self.code_position += 1;
@@ -1610,10 +2782,10 @@ fn compress_lz_codes(
     Ok(true)
 }
 
-fn compress_block(
+fn compress_block<S: BufferStorage>(
     huff: &mut HuffmanOxide,
     output: &mut OutputBufferOxide,
-    lz: &LZOxide,
+    lz: &LZOxide<S>,
     static_block: bool,
 ) -> Result<bool> {
     if static_block {
@@ -1625,21 +2797,26 @@ fn compress_block(
     compress_lz_codes(huff, output, &lz.codes[..lz.code_position])
 }
 
-fn flush_block(
-    d: &mut CompressorOxide,
+fn flush_block<S: BufferStorage>(
+    d: &mut CompressorState<S>,
     callback: &mut CallbackOxide,
-    flush: TDEFLFlush,
+    flush: MZFlush,
 ) -> Result<i32> {
     let mut saved_buffer;
+    let leave_incompressible =
+        d.params.incompressible && looks_compressible(&d.params, &d.huff.count[0][..256]);
     {
         let mut output = callback
             .out
             .new_output_buffer(&mut d.params.local_buf.b, d.params.out_buf_ofs);
-        output.bit_buffer = d.params.saved_bit_buffer;
-        output.bits_in = d.params.saved_bits_in;
 
-        let use_raw_block = (d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0)
-            && (d.dict.lookahead_pos - d.dict.code_buf_dict_pos) <= d.dict.size;
+        let fits_in_dict = (d.dict.lookahead_pos - d.dict.code_buf_dict_pos) <= d.dict.size;
+        // Blocks compressed while the input looked incompressible only contain codes for some of
+        // the data, so they have to be stored.
+        debug_assert!(!d.params.incompressible || fits_in_dict);
+        let use_raw_block = (d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0
+            || d.params.incompressible)
+            && fits_in_dict;
 
         assert!(d.params.flush_remaining == 0);
         d.params.flush_ofs = 0;
@@ -1647,64 +2824,82 @@ fn flush_block(
 
         d.lz.init_flag();
 
-        // If we are at the start of the stream, write the zlib header if requested.
+        // If we are at the start of the stream, write the zlib header if requested. Bits inserted
+        // with `prime` before that go after the header, as in zlib.
         if d.params.flags & TDEFL_WRITE_ZLIB_HEADER != 0 && d.params.block_index == 0 {
-            let header = zlib::header_from_flags(d.params.flags as u32);
+            let header = zlib::header_from_flags(
+                d.params.flags as u32,
+                d.sizes.window_bits(),
+                d.params.dict_id.is_some(),
+            );
             output.put_bits(header[0].into(), 8);
             output.put_bits(header[1].into(), 8);
+            if let Some(dict_id) = d.params.dict_id {
+                for &byte in dict_id.to_be_bytes().iter() {
+                    output.put_bits(byte.into(), 8);
+                }
+            }
         }
+        output.put_bits(d.params.saved_bit_buffer, d.params.saved_bits_in.into());
 
-        // Output the block header.
-        output.put_bits((flush == TDEFLFlush::Finish) as u32, 1);
-
-        saved_buffer = output.save();
-
-        let comp_success = if !use_raw_block {
-            let use_static =
-                (d.params.flags & TDEFL_FORCE_ALL_STATIC_BLOCKS != 0) || (d.lz.total_bytes < 48);
-            compress_block(&mut d.huff, &mut output, &d.lz, use_static)?
-        } else {
-            false
-        };
+        // Partial and block flushes only end the current block, so if there is nothing in it
+        // there is no block to write, as in zlib.
+        let skip_block =
+            d.lz.total_bytes == 0 && (flush == MZFlush::Partial || flush == MZFlush::Block);
 
-        // If we failed to compress anything and the output would take up more space than the output
-        // data, output a stored block instead, which has at most 5 bytes of overhead.
-        // We only use some simple heuristics for now.
-        // A stored block will have an overhead of at least 4 bytes containing the block length
-        // but usually more due to the length parameters having to start at a byte boundary and thus
-        // requiring up to 5 bytes of padding.
-        // As a static block will have an overhead of at most 1 bit per byte
-        // (as literals are either 8 or 9 bytes), a raw block will
-        // never take up less space if the number of input bytes are less than 32.
-        let expanded = (d.lz.total_bytes > 32)
-            && (output.inner_pos - saved_buffer.pos + 1 >= (d.lz.total_bytes as usize))
-            && (d.dict.lookahead_pos - d.dict.code_buf_dict_pos <= d.dict.size);
+        if !skip_block {
+            // Output the block header.
+            output.put_bits((flush == MZFlush::Finish) as u32, 1);
 
-        if use_raw_block || expanded {
-            output.load(saved_buffer);
+            saved_buffer = output.save();
 
-            // Block header.
-            output.put_bits(0, 2);
+            let comp_success = if !use_raw_block {
+                let use_static = (d.params.flags & TDEFL_FORCE_ALL_STATIC_BLOCKS != 0)
+                    || (d.lz.total_bytes < 48);
+                compress_block(&mut d.huff, &mut output, &d.lz, use_static)?
+            } else {
+                false
+            };
 
-            // Block length has to start on a byte boundary, s opad.
-            output.pad_to_bytes();
+            // If we failed to compress anything and the output would take up more space than the output
+            // data, output a stored block instead, which has at most 5 bytes of overhead.
+            // We only use some simple heuristics for now.
+            // A stored block will have an overhead of at least 4 bytes containing the block length
+            // but usually more due to the length parameters having to start at a byte boundary and thus
+            // requiring up to 5 bytes of padding.
+            // As a static block will have an overhead of at most 1 bit per byte
+            // (as literals are either 8 or 9 bytes), a raw block will
+            // never take up less space if the number of input bytes are less than 32.
+            let expanded = (d.lz.total_bytes > 32)
+                && (output.inner_pos - saved_buffer.pos + 1 >= (d.lz.total_bytes as usize))
+                && fits_in_dict;
+
+            if use_raw_block || expanded {
+                output.load(saved_buffer);
+
+                // Block header.
+                output.put_bits(0, 2);
+
+                // Block length has to start on a byte boundary, s opad.
+                output.pad_to_bytes();
 
-            // Block length and ones complement of block length.
-            output.put_bits(d.lz.total_bytes & 0xFFFF, 16);
-            output.put_bits(!d.lz.total_bytes & 0xFFFF, 16);
+                // Block length and ones complement of block length.
+                output.put_bits(d.lz.total_bytes & 0xFFFF, 16);
+                output.put_bits(!d.lz.total_bytes & 0xFFFF, 16);
 
-            // Write the actual bytes.
-            for i in 0..d.lz.total_bytes {
-                let pos = (d.dict.code_buf_dict_pos + i as usize) & LZ_DICT_SIZE_MASK;
-                output.put_bits(u32::from(d.dict.b.dict[pos as usize]), 8);
+                // Write the actual bytes.
+                for i in 0..d.lz.total_bytes {
+                    let pos = (d.dict.code_buf_dict_pos + i as usize) & d.dict.window_mask;
+                    output.put_bits(u32::from(d.dict.b.dict[pos as usize]), 8);
+                }
+            } else if !comp_success {
+                output.load(saved_buffer);
+                compress_block(&mut d.huff, &mut output, &d.lz, true)?;
             }
-        } else if !comp_success {
-            output.load(saved_buffer);
-            compress_block(&mut d.huff, &mut output, &d.lz, true)?;
         }
 
-        if flush != TDEFLFlush::None {
-            if flush == TDEFLFlush::Finish {
+        match flush {
+            MZFlush::Finish => {
                 output.pad_to_bytes();
                 if d.params.flags & TDEFL_WRITE_ZLIB_HEADER != 0 {
                     let mut adler = d.params.adler32;
@@ -1713,15 +2908,31 @@ fn flush_block(
                         adler <<= 8;
                     }
                 }
-            } else {
-                // Sync or Full flush.
+            }
+            MZFlush::Sync | MZFlush::Full => {
                 // Output an empty raw block.
                 output.put_bits(0, 3);
                 output.pad_to_bytes();
                 output.put_bits(0, 16);
                 output.put_bits(0xFFFF, 16);
             }
+            MZFlush::Partial => {
+                // Output an empty static block, which is just the block header and the 7 bit end
+                // of block code. This pushes out the end of the previous block, leaving at most
+                // 7 bits of the new block in the bit buffer.
+                output.put_bits(1 << 1, 3);
+                output.put_bits(0, 7);
+            }
+            MZFlush::None | MZFlush::Block => (),
+        }
+
+        if leave_incompressible {
+            d.params.incompressible = false;
         }
+        d.params.block_checked = false;
+        d.params.samples = 0;
+        d.params.sample_matches = 0;
+        d.params.recent_samples = 0;
 
         memset(&mut d.huff.count[0][..MAX_HUFF_SYMBOLS_0], 0);
         memset(&mut d.huff.count[1][..MAX_HUFF_SYMBOLS_1], 0);
@@ -1731,18 +2942,19 @@ fn flush_block(
         d.lz.num_flags_left = 8;
         d.dict.code_buf_dict_pos += d.lz.total_bytes as usize;
         d.lz.total_bytes = 0;
+        d.lz.fixed_bits = 0;
         d.params.block_index += 1;
 
         saved_buffer = output.save();
 
         d.params.saved_bit_buffer = saved_buffer.bit_buffer;
-        d.params.saved_bits_in = saved_buffer.bits_in;
+        d.params.saved_bits_in = saved_buffer.bits_in as u8;
     }
 
     Ok(callback.flush_output(saved_buffer, &mut d.params))
 }
 
-fn record_literal(h: &mut HuffmanOxide, lz: &mut LZOxide, lit: u8) {
+fn record_literal<S: BufferStorage>(h: &mut HuffmanOxide, lz: &mut LZOxide<S>, lit: u8) {
     lz.total_bytes += 1;
     lz.write_code(lit);
 
@@ -1750,9 +2962,15 @@ fn record_literal(h: &mut HuffmanOxide, lz: &mut LZOxide, lit: u8) {
     lz.consume_flag();
 
     h.count[0][lit as usize] += 1;
+    lz.fixed_bits += fixed_literal_bits(lit);
 }
 
-fn record_match(h: &mut HuffmanOxide, lz: &mut LZOxide, mut match_len: u32, mut match_dist: u32) {
+fn record_match<S: BufferStorage>(
+    h: &mut HuffmanOxide,
+    lz: &mut LZOxide<S>,
+    mut match_len: u32,
+    mut match_dist: u32,
+) {
     assert!(match_len >= MIN_MATCH_LEN.into());
     assert!(match_dist >= 1);
     assert!(match_dist as usize <= LZ_DICT_SIZE);
@@ -1775,9 +2993,113 @@ fn record_match(h: &mut HuffmanOxide, lz: &mut LZOxide, mut match_len: u32, mut
     } as usize;
     h.count[1][symbol] += 1;
     h.count[0][LEN_SYM[match_len as usize] as usize] += 1;
+    lz.fixed_bits += fixed_match_bits(match_len, match_dist);
+}
+
+/// Number of bits a literal takes with the fixed huffman codes.
+#[inline]
+fn fixed_literal_bits(lit: u8) -> u32 {
+    if lit < 144 {
+        8
+    } else {
+        9
+    }
+}
+
+/// Number of bits a match takes with the fixed huffman codes, given the length minus
+/// `MIN_MATCH_LEN` and the distance minus one as they are stored in the lz codes.
+#[inline]
+fn fixed_match_bits(match_len: u32, match_dist: u32) -> u32 {
+    let len_code_bits = if LEN_SYM[match_len as usize] < 280 {
+        7
+    } else {
+        8
+    };
+    let dist_extra = if match_dist < 512 {
+        SMALL_DIST_EXTRA[match_dist as usize]
+    } else {
+        LARGE_DIST_EXTRA[((match_dist >> 8) & 127) as usize]
+    };
+    len_code_bits + u32::from(LEN_EXTRA[match_len as usize]) + 5 + u32::from(dist_extra)
+}
+
+/// Upper bound on the number of bits a block with dynamic huffman codes takes in addition to the
+/// coded data: the block header, the counts of codes, the lengths of the code length codes, at
+/// most 7 bits for each of the 286 + 30 code lengths and at most 15 bits for the end of block
+/// code.
+pub(crate) const MAX_DYNAMIC_BLOCK_BITS: u32 = 3 + 5 + 5 + 4 + 19 * 3 + 316 * 7 + 15;
+/// Minimum size of a block stored since the input looked incompressible that is ended as soon as
+/// the input looks compressible again, so the few bytes a stored block adds stay small compared
+/// to the data.
+pub(crate) const MIN_COMPRESSIBLE_AGAIN_SIZE: u32 = 1024;
+
+/// Number of bytes in a block after which the block is checked for whether the input looks
+/// incompressible.
+const INCOMPRESSIBLE_CHECK_SIZE: u32 = 4096;
+/// Estimated cost of the literals, in 1/16ths of a bit per byte, above which the input is
+/// considered incompressible.
+const INCOMPRESSIBLE_COST: u32 = 124;
+/// Estimated cost of the literals, in 1/16ths of a bit per byte, below which incompressible input
+/// is considered compressible again. Lower than `INCOMPRESSIBLE_COST` to avoid switching back and
+/// forth on data that is on the edge.
+const COMPRESSIBLE_COST: u32 = 120;
+
+/// `16 * log2(1 + i / 32)`, rounded.
+const LOG2_FRACTION: [u8; 32] = [
+    0, 1, 1, 2, 3, 3, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 13, 14, 14,
+    15, 15, 15, 16,
+];
+
+/// Approximate `16 * log2(val)` for a non-zero `val`.
+fn log2_fixed(val: u32) -> u32 {
+    let bits = 31 - val.leading_zeros();
+    // Look up the fractional part using the 5 bits below the highest set bit.
+    let fraction = if bits >= 5 {
+        (val >> (bits - 5)) & 31
+    } else {
+        (val << (5 - bits)) & 31
+    };
+    bits * 16 + u32::from(LOG2_FRACTION[fraction as usize])
+}
+
+/// Estimate the number of bits needed to code the literals with the given counts using an
+/// optimal code, in 1/16ths of a bit, along with the number of literals.
+fn literal_cost(counts: &[u16]) -> (u32, u32) {
+    let total: u32 = counts.iter().map(|&c| u32::from(c)).sum();
+    if total == 0 {
+        return (0, 0);
+    }
+    let log_total = log2_fixed(total);
+    let cost = counts
+        .iter()
+        .filter(|&&c| c != 0)
+        .map(|&c| u32::from(c) * (log_total - log2_fixed(c.into())))
+        .sum();
+    (cost, total)
+}
+
+/// Whether matches were found at enough of the last sampled positions to end an incompressible
+/// block early.
+fn recently_compressible<S: BufferStorage>(params: &ParamsOxide<S>) -> bool {
+    params.recent_samples.count_ones() >= 4
 }
 
-fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool {
+/// Whether a block that was stored since the input looked incompressible suggests that the input
+/// has become compressible again, either because matches were found at the sampled positions or
+/// because the distribution of the bytes has become skewed.
+fn looks_compressible<S: BufferStorage>(params: &ParamsOxide<S>, counts: &[u16]) -> bool {
+    if recently_compressible(params) || u32::from(params.sample_matches) * 8 > params.samples.into()
+    {
+        return true;
+    }
+    let (cost, total) = literal_cost(counts);
+    total >= INCOMPRESSIBLE_CHECK_SIZE / 4 && cost < total * COMPRESSIBLE_COST
+}
+
+fn compress_normal<S: BufferStorage>(
+    d: &mut CompressorState<S>,
+    callback: &mut CallbackOxide,
+) -> bool {
     let mut src_pos = d.params.src_pos;
     let in_buf = match callback.in_buf {
         None => return true,
@@ -1790,7 +3112,17 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
     let mut saved_match_dist = d.params.saved_match_dist;
     let mut saved_match_len = d.params.saved_match_len;
 
-    while src_pos < in_buf.len() || (d.params.flush != TDEFLFlush::None && lookahead_size != 0) {
+    let window_size = d.dict.window_size();
+    let window_mask = d.dict.window_mask;
+    let hash_shift = d.dict.hash_shift;
+    let hash_mask = d.dict.hash_mask;
+    let min_match_len = if d.params.flags & TDEFL_FILTER_MATCHES != 0 {
+        cmp::max(d.params.min_match_len.into(), FILTER_MIN_MATCH_LEN)
+    } else {
+        d.params.min_match_len.into()
+    };
+
+    while src_pos < in_buf.len() || (d.params.flush != MZFlush::None && lookahead_size != 0) {
         let src_buf_left = in_buf.len() - src_pos;
         let num_bytes_to_process = cmp::min(src_buf_left, MAX_MATCH_LEN - lookahead_size as usize);
 
@@ -1799,12 +3131,14 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
         {
             let dictb = &mut d.dict.b;
 
-            let mut dst_pos = (lookahead_pos + lookahead_size as usize) & LZ_DICT_SIZE_MASK;
+            let mut dst_pos = (lookahead_pos + lookahead_size as usize) & window_mask;
             let mut ins_pos = lookahead_pos + lookahead_size as usize - 2;
             // Start the hash value from the first two bytes
             let mut hash = update_hash(
-                u16::from(dictb.dict[(ins_pos & LZ_DICT_SIZE_MASK) as usize]),
-                dictb.dict[((ins_pos + 1) & LZ_DICT_SIZE_MASK) as usize],
+                u16::from(dictb.dict[ins_pos & window_mask]),
+                dictb.dict[(ins_pos + 1) & window_mask],
+                hash_shift,
+                hash_mask as u16,
             );
 
             lookahead_size += num_bytes_to_process;
@@ -1813,38 +3147,36 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
                 // Add byte to input buffer.
                 dictb.dict[dst_pos as usize] = c;
                 if (dst_pos as usize) < MAX_MATCH_LEN - 1 {
-                    dictb.dict[LZ_DICT_SIZE + dst_pos as usize] = c;
+                    dictb.dict[window_size + dst_pos as usize] = c;
                 }
 
                 // Generate hash from the current byte,
-                hash = update_hash(hash, c);
-                dictb.next[(ins_pos & LZ_DICT_SIZE_MASK) as usize] = dictb.hash[hash as usize];
+                hash = update_hash(hash, c, hash_shift, hash_mask as u16);
+                dictb.next[ins_pos & window_mask] = dictb.hash[hash as usize];
                 // and insert it into the hash chain.
                 dictb.hash[hash as usize] = ins_pos as u16;
-                dst_pos = (dst_pos + 1) & LZ_DICT_SIZE_MASK;
+                dst_pos = (dst_pos + 1) & window_mask;
                 ins_pos += 1;
             }
             src_pos += num_bytes_to_process;
         } else {
             let dictb = &mut d.dict.b;
             for &c in &in_buf[src_pos..src_pos + num_bytes_to_process] {
-                let dst_pos = (lookahead_pos + lookahead_size) & LZ_DICT_SIZE_MASK;
+                let dst_pos = (lookahead_pos + lookahead_size) & window_mask;
                 dictb.dict[dst_pos as usize] = c;
                 if (dst_pos as usize) < MAX_MATCH_LEN - 1 {
-                    dictb.dict[LZ_DICT_SIZE + dst_pos as usize] = c;
+                    dictb.dict[window_size + dst_pos as usize] = c;
                 }
 
                 lookahead_size += 1;
                 if lookahead_size + d.dict.size >= MIN_MATCH_LEN.into() {
                     let ins_pos = lookahead_pos + lookahead_size - 3;
-                    let hash = ((u32::from(dictb.dict[(ins_pos & LZ_DICT_SIZE_MASK) as usize])
-                        << (LZ_HASH_SHIFT * 2))
-                        ^ ((u32::from(dictb.dict[((ins_pos + 1) & LZ_DICT_SIZE_MASK) as usize])
-                            << LZ_HASH_SHIFT)
+                    let hash = ((u32::from(dictb.dict[ins_pos & window_mask]) << (hash_shift * 2))
+                        ^ ((u32::from(dictb.dict[(ins_pos + 1) & window_mask]) << hash_shift)
                             ^ u32::from(c)))
-                        & (LZ_HASH_SIZE as u32 - 1);
+                        & hash_mask;
 
-                    dictb.next[(ins_pos & LZ_DICT_SIZE_MASK) as usize] = dictb.hash[hash as usize];
+                    dictb.next[ins_pos & window_mask] = dictb.hash[hash as usize];
                     dictb.hash[hash as usize] = ins_pos as u16;
                 }
             }
@@ -1852,8 +3184,8 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
             src_pos += num_bytes_to_process;
         }
 
-        d.dict.size = cmp::min(LZ_DICT_SIZE - lookahead_size, d.dict.size);
-        if d.params.flush == TDEFLFlush::None && (lookahead_size as usize) < MAX_MATCH_LEN {
+        d.dict.size = cmp::min(window_size - lookahead_size, d.dict.size);
+        if d.params.flush == MZFlush::None && (lookahead_size as usize) < MAX_MATCH_LEN {
             break;
         }
 
@@ -1864,96 +3196,147 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
         } else {
             u32::from(MIN_MATCH_LEN) - 1
         };
-        let cur_pos = lookahead_pos & LZ_DICT_SIZE_MASK;
-        if d.params.flags & (TDEFL_RLE_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS) != 0 {
-            // If TDEFL_RLE_MATCHES is set, we only look for repeating sequences of the current byte.
-            if d.dict.size != 0 && d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS == 0 {
-                let c = d.dict.b.dict[((cur_pos.wrapping_sub(1)) & LZ_DICT_SIZE_MASK) as usize];
-                cur_match_len = d.dict.b.dict[cur_pos as usize..(cur_pos + lookahead_size) as usize]
-                    .iter()
-                    .take_while(|&x| *x == c)
-                    .count() as u32;
-                if cur_match_len < MIN_MATCH_LEN.into() {
-                    cur_match_len = 0
-                } else {
-                    cur_match_dist = 1
-                }
+        let cur_pos = lookahead_pos & window_mask;
+        if d.params.incompressible && saved_match_len == 0 {
+            // Only look for a match at the start of each chunk, to notice when the input becomes
+            // compressible again, and pass the whole lookahead on to be stored.
+            if lookahead_size >= MIN_MATCH_LEN.into() {
+                let (dist, len) = d.dict.find_match(
+                    lookahead_pos,
+                    d.dict.size,
+                    lookahead_size as u32,
+                    cur_match_dist,
+                    cur_match_len,
+                );
+                let far_and_small = len == MIN_MATCH_LEN.into() && dist >= 8 * 1024;
+                let found = dist != 0 && len >= min_match_len && !far_and_small;
+                d.params.samples += 1;
+                d.params.sample_matches += found as u16;
+                d.params.recent_samples = (d.params.recent_samples << 1) | found as u8;
+            }
+
+            let block_space = (d.dict.raw_block_limit() + 1 - d.lz.total_bytes) as usize;
+            len_to_move = cmp::min(lookahead_size, block_space);
+            for i in 0..len_to_move {
+                let lit = d.dict.b.dict[(cur_pos + i) & window_mask];
+                d.huff.count[0][lit as usize] += 1;
             }
+            d.lz.total_bytes += len_to_move as u32;
         } else {
-            // Try to find a match for the bytes at the current position.
-            let dist_len = d.dict.find_match(
-                lookahead_pos,
-                d.dict.size,
-                lookahead_size as u32,
-                cur_match_dist,
-                cur_match_len,
-            );
-            cur_match_dist = dist_len.0;
-            cur_match_len = dist_len.1;
-        }
+            if d.params.flags & (TDEFL_RLE_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS) != 0 {
+                // If TDEFL_RLE_MATCHES is set, we only look for repeating sequences of the current
+                // byte.
+                if d.dict.size != 0 && d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS == 0 {
+                    let c = d.dict.b.dict[cur_pos.wrapping_sub(1) & window_mask];
+                    cur_match_len = d.dict.b.dict
+                        [cur_pos as usize..(cur_pos + lookahead_size) as usize]
+                        .iter()
+                        .take_while(|&x| *x == c)
+                        .count() as u32;
+                    if cur_match_len < MIN_MATCH_LEN.into() {
+                        cur_match_len = 0
+                    } else {
+                        cur_match_dist = 1
+                    }
+                }
+            } else {
+                // Try to find a match for the bytes at the current position.
+                let dist_len = d.dict.find_match(
+                    lookahead_pos,
+                    d.dict.size,
+                    lookahead_size as u32,
+                    cur_match_dist,
+                    cur_match_len,
+                );
+                cur_match_dist = dist_len.0;
+                cur_match_len = dist_len.1;
+            }
 
-        let far_and_small = cur_match_len == MIN_MATCH_LEN.into() && cur_match_dist >= 8 * 1024;
-        let filter_small = d.params.flags & TDEFL_FILTER_MATCHES != 0 && cur_match_len <= 5;
-        if far_and_small || filter_small || cur_pos == cur_match_dist as usize {
-            cur_match_dist = 0;
-            cur_match_len = 0;
-        }
+            let far_and_small = cur_match_len == MIN_MATCH_LEN.into() && cur_match_dist >= 8 * 1024;
+            let filter_small = cur_match_len < min_match_len;
+            if far_and_small || filter_small || cur_pos == cur_match_dist as usize {
+                cur_match_dist = 0;
+                cur_match_len = 0;
+            }
 
-        if saved_match_len != 0 {
-            if cur_match_len > saved_match_len {
-                record_literal(&mut d.huff, &mut d.lz, saved_lit);
-                if cur_match_len >= 128 {
-                    record_match(&mut d.huff, &mut d.lz, cur_match_len, cur_match_dist);
-                    saved_match_len = 0;
-                    len_to_move = cur_match_len as usize;
+            if saved_match_len != 0 {
+                if cur_match_len > saved_match_len {
+                    record_literal(&mut d.huff, &mut d.lz, saved_lit);
+                    if cur_match_len >= d.params.max_lazy.into() {
+                        record_match(&mut d.huff, &mut d.lz, cur_match_len, cur_match_dist);
+                        saved_match_len = 0;
+                        len_to_move = cur_match_len as usize;
+                    } else {
+                        saved_lit = d.dict.b.dict[cur_pos as usize];
+                        saved_match_dist = cur_match_dist;
+                        saved_match_len = cur_match_len;
+                    }
                 } else {
-                    saved_lit = d.dict.b.dict[cur_pos as usize];
-                    saved_match_dist = cur_match_dist;
-                    saved_match_len = cur_match_len;
+                    record_match(&mut d.huff, &mut d.lz, saved_match_len, saved_match_dist);
+                    len_to_move = (saved_match_len - 1) as usize;
+                    saved_match_len = 0;
                 }
+            } else if cur_match_dist == 0 {
+                record_literal(
+                    &mut d.huff,
+                    &mut d.lz,
+                    d.dict.b.dict[cmp::min(cur_pos as usize, d.dict.b.dict.len() - 1)],
+                );
+            } else if d.params.greedy_parsing
+                || (d.params.flags & TDEFL_RLE_MATCHES != 0)
+                || cur_match_len >= d.params.max_lazy.into()
+            {
+                // If we are using lazy matching, check for matches at the next byte if the current
+                // match was shorter than `max_lazy` bytes.
+                record_match(&mut d.huff, &mut d.lz, cur_match_len, cur_match_dist);
+                len_to_move = cur_match_len as usize;
             } else {
-                record_match(&mut d.huff, &mut d.lz, saved_match_len, saved_match_dist);
-                len_to_move = (saved_match_len - 1) as usize;
-                saved_match_len = 0;
-            }
-        } else if cur_match_dist == 0 {
-            record_literal(
-                &mut d.huff,
-                &mut d.lz,
-                d.dict.b.dict[cmp::min(cur_pos as usize, d.dict.b.dict.len() - 1)],
-            );
-        } else if d.params.greedy_parsing
-            || (d.params.flags & TDEFL_RLE_MATCHES != 0)
-            || cur_match_len >= 128
-        {
-            // If we are using lazy matching, check for matches at the next byte if the current
-            // match was shorter than 128 bytes.
-            record_match(&mut d.huff, &mut d.lz, cur_match_len, cur_match_dist);
-            len_to_move = cur_match_len as usize;
-        } else {
-            saved_lit = d.dict.b.dict[cmp::min(cur_pos as usize, d.dict.b.dict.len() - 1)];
-            saved_match_dist = cur_match_dist;
-            saved_match_len = cur_match_len;
+                saved_lit = d.dict.b.dict[cmp::min(cur_pos as usize, d.dict.b.dict.len() - 1)];
+                saved_match_dist = cur_match_dist;
+                saved_match_len = cur_match_len;
+            }
         }
 
         lookahead_pos += len_to_move;
         assert!(lookahead_size >= len_to_move);
         lookahead_size -= len_to_move;
-        d.dict.size = cmp::min(d.dict.size + len_to_move, LZ_DICT_SIZE);
+        d.dict.size = cmp::min(d.dict.size + len_to_move, window_size);
 
-        let lz_buf_tight = d.lz.code_position > LZ_CODE_BUF_SIZE - 8;
+        let lz_buf_tight = d.lz.is_tight();
         let raw = d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0;
         let fat = ((d.lz.code_position * 115) >> 7) >= d.lz.total_bytes as usize;
-        let fat_or_raw = (d.lz.total_bytes > 31 * 1024) && (fat || raw);
 
-        if lz_buf_tight || fat_or_raw {
+        // Check once per block whether the input looks incompressible: mostly literals, and the
+        // literals are spread out evenly enough that coding them would not save much.
+        if !d.params.block_checked
+            && d.lz.total_bytes >= INCOMPRESSIBLE_CHECK_SIZE
+            && saved_match_len == 0
+        {
+            d.params.block_checked = true;
+            if fat && !raw && !d.params.incompressible {
+                let (cost, total) = literal_cost(&d.huff.count[0][..256]);
+                d.params.incompressible = cost >= total * INCOMPRESSIBLE_COST;
+            }
+        }
+
+        // Blocks that may take more space coded than stored are ended while they are still in
+        // the dictionary, so they can be stored instead.
+        let fat_or_raw = (d.lz.total_bytes > d.dict.raw_block_limit())
+            && (fat || raw || d.params.incompressible || d.lz.may_expand());
+        // End the block as soon as the input looks compressible again rather than storing the
+        // rest of it.
+        let compressible_again = d.params.incompressible
+            && d.lz.total_bytes >= MIN_COMPRESSIBLE_AGAIN_SIZE
+            && recently_compressible(&d.params);
+
+        if lz_buf_tight || fat_or_raw || compressible_again {
             d.params.src_pos = src_pos;
             // These values are used in flush_block, so we need to write them back here.
             d.dict.lookahead_size = lookahead_size;
             d.dict.lookahead_pos = lookahead_pos;
 
-            let n = flush_block(d, callback, TDEFLFlush::None)
-                .unwrap_or(TDEFLStatus::PutBufFailed as i32);
+            let n =
+                flush_block(d, callback, MZFlush::None).unwrap_or(TDEFLStatus::PutBufFailed as i32);
             if n != 0 {
                 d.params.saved_lit = saved_lit;
                 d.params.saved_match_dist = saved_match_dist;
@@ -1972,46 +3355,57 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
     true
 }
 
-const COMP_FAST_LOOKAHEAD_SIZE: usize = 4096;
+pub(crate) const COMP_FAST_LOOKAHEAD_SIZE: usize = 4096;
 
-fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool {
+fn compress_fast<S: BufferStorage>(
+    d: &mut CompressorState<S>,
+    callback: &mut CallbackOxide,
+) -> bool {
     let mut src_pos = d.params.src_pos;
     let mut lookahead_size = d.dict.lookahead_size;
     let mut lookahead_pos = d.dict.lookahead_pos;
 
-    let mut cur_pos = lookahead_pos & LZ_DICT_SIZE_MASK;
+    // Incompressible input is only handled specially by `compress_normal`.
+    d.params.incompressible = false;
+
+    let window_size = d.dict.window_size();
+    let window_mask = d.dict.window_mask;
+    // Keep the lookahead small enough compared to the dictionary to leave room for matches.
+    let fast_lookahead_size = cmp::min(COMP_FAST_LOOKAHEAD_SIZE, window_size / 4);
+    let raw_block_limit = fast_raw_block_limit(window_size) as u32;
+    let level1_hash_mask = cmp::min(LEVEL1_HASH_SIZE_MASK, d.dict.hash_mask);
+
+    let mut cur_pos = lookahead_pos & window_mask;
     let in_buf = match callback.in_buf {
         None => return true,
         Some(in_buf) => in_buf,
     };
 
-    debug_assert!(d.lz.code_position < LZ_CODE_BUF_SIZE - 2);
+    debug_assert!(d.lz.code_position < d.lz.codes.len() - 2);
 
-    while src_pos < in_buf.len() || (d.params.flush != TDEFLFlush::None && lookahead_size > 0) {
-        let mut dst_pos = ((lookahead_pos + lookahead_size) & LZ_DICT_SIZE_MASK) as usize;
-        let mut num_bytes_to_process = cmp::min(
-            in_buf.len() - src_pos,
-            (COMP_FAST_LOOKAHEAD_SIZE - lookahead_size) as usize,
-        );
+    while src_pos < in_buf.len() || (d.params.flush != MZFlush::None && lookahead_size > 0) {
+        let mut dst_pos = (lookahead_pos + lookahead_size) & window_mask;
+        let mut num_bytes_to_process =
+            cmp::min(in_buf.len() - src_pos, fast_lookahead_size - lookahead_size);
         lookahead_size += num_bytes_to_process;
 
         while num_bytes_to_process != 0 {
-            let n = cmp::min(LZ_DICT_SIZE - dst_pos, num_bytes_to_process);
+            let n = cmp::min(window_size - dst_pos, num_bytes_to_process);
             d.dict.b.dict[dst_pos..dst_pos + n].copy_from_slice(&in_buf[src_pos..src_pos + n]);
 
             if dst_pos < MAX_MATCH_LEN - 1 {
                 let m = cmp::min(n, MAX_MATCH_LEN - 1 - dst_pos);
-                d.dict.b.dict[dst_pos + LZ_DICT_SIZE..dst_pos + LZ_DICT_SIZE + m]
+                d.dict.b.dict[dst_pos + window_size..dst_pos + window_size + m]
                     .copy_from_slice(&in_buf[src_pos..src_pos + m]);
             }
 
             src_pos += n;
-            dst_pos = (dst_pos + n) & LZ_DICT_SIZE_MASK as usize;
+            dst_pos = (dst_pos + n) & window_mask;
             num_bytes_to_process -= n;
         }
 
-        d.dict.size = cmp::min(LZ_DICT_SIZE - lookahead_size, d.dict.size);
-        if d.params.flush == TDEFLFlush::None && lookahead_size < COMP_FAST_LOOKAHEAD_SIZE {
+        d.dict.size = cmp::min(window_size - lookahead_size, d.dict.size);
+        if d.params.flush == MZFlush::None && lookahead_size < fast_lookahead_size {
             break;
         }
 
@@ -2020,15 +3414,15 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
 
             let first_trigram = d.dict.read_unaligned_u32(cur_pos) & 0xFF_FFFF;
 
-            let hash = (first_trigram ^ (first_trigram >> (24 - (LZ_HASH_BITS - 8))))
-                & LEVEL1_HASH_SIZE_MASK;
+            let hash =
+                (first_trigram ^ (first_trigram >> (24 - (LZ_HASH_BITS - 8)))) & level1_hash_mask;
 
             let mut probe_pos = usize::from(d.dict.b.hash[hash as usize]);
             d.dict.b.hash[hash as usize] = lookahead_pos as u16;
 
             let mut cur_match_dist = (lookahead_pos - probe_pos as usize) as u16;
             if cur_match_dist as usize <= d.dict.size {
-                probe_pos &= LZ_DICT_SIZE_MASK;
+                probe_pos &= window_mask;
 
                 let trigram = d.dict.read_unaligned_u32(probe_pos) & 0xFF_FFFF;
 
@@ -2065,13 +3459,14 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
                         d.lz.write_code(lit);
                         *d.lz.get_flag() >>= 1;
                         d.huff.count[0][lit as usize] += 1;
+                        d.lz.fixed_bits += fixed_literal_bits(lit);
                     } else {
                         // Limit the match to the length of the lookahead so we don't create a match
                         // that ends after the end of the input data.
                         cur_match_len = cmp::min(cur_match_len, lookahead_size as u32);
                         debug_assert!(cur_match_len >= MIN_MATCH_LEN.into());
                         debug_assert!(cur_match_dist >= 1);
-                        debug_assert!(cur_match_dist as usize <= LZ_DICT_SIZE);
+                        debug_assert!(cur_match_dist as usize <= window_size);
                         cur_match_dist -= 1;
 
                         d.lz.write_code((cur_match_len - u32::from(MIN_MATCH_LEN)) as u8);
@@ -2089,26 +3484,31 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
 
                         d.huff.count[0][LEN_SYM[(cur_match_len - u32::from(MIN_MATCH_LEN)) as usize]
                             as usize] += 1;
+                        d.lz.fixed_bits += fixed_match_bits(
+                            cur_match_len - u32::from(MIN_MATCH_LEN),
+                            u32::from(cur_match_dist),
+                        );
                     }
                 } else {
                     d.lz.write_code(first_trigram as u8);
                     *d.lz.get_flag() >>= 1;
                     d.huff.count[0][first_trigram as u8 as usize] += 1;
+                    d.lz.fixed_bits += fixed_literal_bits(first_trigram as u8);
                 }
 
                 d.lz.consume_flag();
                 d.lz.total_bytes += cur_match_len;
                 lookahead_pos += cur_match_len as usize;
-                d.dict.size = cmp::min(d.dict.size + cur_match_len as usize, LZ_DICT_SIZE);
-                cur_pos = (cur_pos + cur_match_len as usize) & LZ_DICT_SIZE_MASK;
+                d.dict.size = cmp::min(d.dict.size + cur_match_len as usize, window_size);
+                cur_pos = (cur_pos + cur_match_len as usize) & window_mask;
                 lookahead_size -= cur_match_len as usize;
 
-                if d.lz.code_position > LZ_CODE_BUF_SIZE - 8 {
+                if d.lz.is_tight() || (d.lz.total_bytes > raw_block_limit && d.lz.may_expand()) {
                     // These values are used in flush_block, so we need to write them back here.
                     d.dict.lookahead_size = lookahead_size;
                     d.dict.lookahead_pos = lookahead_pos;
 
-                    let n = match flush_block(d, callback, TDEFLFlush::None) {
+                    let n = match flush_block(d, callback, MZFlush::None) {
                         Err(_) => {
                             d.params.src_pos = src_pos;
                             d.params.prev_return_status = TDEFLStatus::PutBufFailed;
@@ -2120,7 +3520,7 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
                         d.params.src_pos = src_pos;
                         return n > 0;
                     }
-                    debug_assert!(d.lz.code_position < LZ_CODE_BUF_SIZE - 2);
+                    debug_assert!(d.lz.code_position < d.lz.codes.len() - 2);
 
                     lookahead_size = d.dict.lookahead_size;
                     lookahead_pos = d.dict.lookahead_pos;
@@ -2136,17 +3536,18 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
             d.lz.consume_flag();
 
             d.huff.count[0][lit as usize] += 1;
+            d.lz.fixed_bits += fixed_literal_bits(lit);
             lookahead_pos += 1;
-            d.dict.size = cmp::min(d.dict.size + 1, LZ_DICT_SIZE);
-            cur_pos = (cur_pos + 1) & LZ_DICT_SIZE_MASK;
+            d.dict.size = cmp::min(d.dict.size + 1, window_size);
+            cur_pos = (cur_pos + 1) & window_mask;
             lookahead_size -= 1;
 
-            if d.lz.code_position > LZ_CODE_BUF_SIZE - 8 {
+            if d.lz.is_tight() || (d.lz.total_bytes > raw_block_limit && d.lz.may_expand()) {
                 // These values are used in flush_block, so we need to write them back here.
                 d.dict.lookahead_size = lookahead_size;
                 d.dict.lookahead_pos = lookahead_pos;
 
-                let n = match flush_block(d, callback, TDEFLFlush::None) {
+                let n = match flush_block(d, callback, MZFlush::None) {
                     Err(_) => {
                         d.params.prev_return_status = TDEFLStatus::PutBufFailed;
                         d.params.src_pos = src_pos;
@@ -2171,7 +3572,10 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
     true
 }
 
-fn flush_output_buffer(c: &mut CallbackOxide, p: &mut ParamsOxide) -> (TDEFLStatus, usize, usize) {
+fn flush_output_buffer<S: BufferStorage>(
+    c: &mut CallbackOxide,
+    p: &mut ParamsOxide<S>,
+) -> (TDEFLStatus, usize, usize) {
     let mut res = (TDEFLStatus::Okay, p.src_pos, 0);
     if let CallbackOut::Buf(ref mut cb) = c.out {
         let n = cmp::min(cb.out_buf.len() - p.out_buf_ofs, p.flush_remaining as usize);
@@ -2207,17 +3611,46 @@ fn flush_output_buffer(c: &mut CallbackOxide, p: &mut ParamsOxide) -> (TDEFLStat
 /// # Returns
 /// Returns a tuple containing the current status of the compressor, the current position
 /// in the input buffer and the current position in the output buffer.
+#[cfg(feature = "with-alloc")]
 pub fn compress(
     d: &mut CompressorOxide,
     in_buf: &[u8],
     out_buf: &mut [u8],
     flush: TDEFLFlush,
 ) -> (TDEFLStatus, usize, usize) {
-    compress_inner(
-        d,
+    compress_with_flush(d, in_buf, out_buf, flush.into())
+}
+
+/// Version of [`compress`] that also supports the partial and block flushes of [`MZFlush`].
+#[cfg(feature = "with-alloc")]
+pub(crate) fn compress_with_flush(
+    d: &mut CompressorOxide,
+    in_buf: &[u8],
+    out_buf: &mut [u8],
+    flush: MZFlush,
+) -> (TDEFLStatus, usize, usize) {
+    let res = compress_inner(
+        &mut d.state,
         &mut CallbackOxide::new_callback_buf(in_buf, out_buf),
         flush,
-    )
+    );
+
+    match d.verifier.as_mut() {
+        Some(verifier) if res.0 == TDEFLStatus::Okay || res.0 == TDEFLStatus::Done => {
+            let zlib = d.state.params.flags & TDEFL_WRITE_ZLIB_HEADER != 0;
+            let ok = verifier.check_output(in_buf, &out_buf[..res.2], zlib)
+                && verifier.end_call(in_buf, res.1)
+                && (res.0 != TDEFLStatus::Done || verifier.finished());
+            if ok {
+                res
+            } else {
+                verifier.fail();
+                d.state.params.prev_return_status = TDEFLStatus::BadParam;
+                (TDEFLStatus::BadParam, res.1, res.2)
+            }
+        }
+        _ => res,
+    }
 }
 
 /// Main compression function. Callbacks output.
@@ -2228,14 +3661,20 @@ pub fn compress(
 ///
 /// The caller is responsible for ensuring the `CallbackFunc` struct will not cause undefined
 /// behaviour.
+#[cfg(feature = "with-alloc")]
 pub fn compress_to_output(
     d: &mut CompressorOxide,
     in_buf: &[u8],
     flush: TDEFLFlush,
     mut callback_func: impl FnMut(&[u8]) -> bool,
 ) -> (TDEFLStatus, usize) {
+    let flush = MZFlush::from(flush);
+    if let Some(verifier) = d.verifier.as_mut() {
+        return compress_to_output_verified(&mut d.state, verifier, in_buf, flush, callback_func);
+    }
+
     let res = compress_inner(
-        d,
+        &mut d.state,
         &mut CallbackOxide::new_callback_func(
             in_buf,
             CallbackFunc {
@@ -2248,16 +3687,205 @@ pub fn compress_to_output(
     (res.0, res.1)
 }
 
-fn compress_inner(
-    d: &mut CompressorOxide,
+/// Version of [`compress_to_output`] that checks the output before passing it on to the
+/// callback.
+#[cfg(feature = "with-alloc")]
+fn compress_to_output_verified(
+    d: &mut CompressorState<HeapStorage>,
+    verifier: &mut Verifier,
+    in_buf: &[u8],
+    flush: MZFlush,
+    mut callback_func: impl FnMut(&[u8]) -> bool,
+) -> (TDEFLStatus, usize) {
+    let zlib = d.params.flags & TDEFL_WRITE_ZLIB_HEADER != 0;
+    // The output is produced before we know how much of the input was consumed, so it is checked
+    // against all of it and the amount consumed is checked afterwards.
+    let mut mismatch = false;
+    let res = compress_inner(
+        d,
+        &mut CallbackOxide::new_callback_func(
+            in_buf,
+            CallbackFunc {
+                put_buf_func: &mut |buf: &[u8]| {
+                    if verifier.check_output(in_buf, buf, zlib) {
+                        callback_func(buf)
+                    } else {
+                        mismatch = true;
+                        false
+                    }
+                },
+            },
+        ),
+        flush,
+    );
+
+    let consumed = verifier.end_call(in_buf, res.1);
+    let failed = mismatch
+        || match res.0 {
+            TDEFLStatus::Okay => !consumed,
+            TDEFLStatus::Done => !consumed || !verifier.finished(),
+            _ => false,
+        };
+    if !failed {
+        (res.0, res.1)
+    } else {
+        verifier.fail();
+        d.params.prev_return_status = TDEFLStatus::BadParam;
+        (TDEFLStatus::BadParam, res.1)
+    }
+}
+
+/// Whether the settings allow using the fast compression function.
+fn use_fast_compression(flags: u32, min_match_len: u32) -> bool {
+    let one_probe = flags & MAX_PROBES_MASK as u32 == 1;
+    let greedy = flags & TDEFL_GREEDY_PARSING_FLAG != 0;
+    let filter_or_rle_or_raw =
+        flags & (TDEFL_FILTER_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS | TDEFL_RLE_MATCHES) != 0;
+    let default_min_match = min_match_len == MIN_MATCH_LEN.into();
+
+    one_probe && greedy && !filter_or_rle_or_raw && default_min_match
+}
+
+/// Finish the current block using the current parameters, and switch to the pending ones.
+///
+/// Returns false if an error occurred. If there was not enough space for the output, the
+/// parameters are still pending afterwards, and this has to be called again.
+fn switch_params<S: BufferStorage>(
+    d: &mut CompressorState<S>,
     callback: &mut CallbackOxide,
-    flush: TDEFLFlush,
+    params: &CompressionParams,
+) -> bool {
+    // Compress the data in the lookahead buffer without taking any new input.
+    let in_buf = callback.in_buf;
+    let flush = d.params.flush;
+    callback.in_buf = Some(&[]);
+    d.params.flush = MZFlush::Sync;
+    let success = compress_data(d, callback);
+    callback.in_buf = in_buf;
+    d.params.flush = flush;
+
+    if !success || d.dict.lookahead_size != 0 || d.params.flush_remaining != 0 {
+        return success;
+    }
+
+    if d.lz.total_bytes != 0 {
+        match flush_block(d, callback, MZFlush::None) {
+            Err(_) => {
+                d.params.prev_return_status = TDEFLStatus::PutBufFailed;
+                return false;
+            }
+            Ok(x) if x < 0 => return false,
+            _ => (),
+        }
+    }
+
+    d.pending_params = None;
+    d.apply_params(params);
+    true
+}
+
+/// Compress the input using the function suited to the current settings.
+fn compress_data<S: BufferStorage>(
+    d: &mut CompressorState<S>,
+    callback: &mut CallbackOxide,
+) -> bool {
+    if use_fast_compression(d.params.flags, d.params.min_match_len.into()) {
+        compress_fast(d, callback)
+    } else {
+        compress_normal(d, callback)
+    }
+}
+
+/// Compress the input in rsyncable mode.
+///
+/// The input is split at the reset points found by the rolling hash, and each part is compressed
+/// and ended with the equivalent of a [`TDEFLFlush::Full`] flush. As the output after a reset
+/// point does not depend on anything before it, a local change in the input only changes the
+/// output up to the next reset point.
+fn compress_rsyncable<S: BufferStorage>(
+    d: &mut CompressorState<S>,
+    callback: &mut CallbackOxide,
+    flush: MZFlush,
+) -> bool {
+    let in_buf = match callback.in_buf {
+        Some(in_buf) => in_buf,
+        None => return compress_data(d, callback),
+    };
+
+    loop {
+        let start = d.params.src_pos;
+        // Limit how far ahead we look for a reset point, so the same input is not scanned
+        // over and over if the output buffer is small.
+        let scan_end = cmp::min(in_buf.len(), start + RSYNC_MAX_SCAN);
+        let end = match d.params.rsync.next_reset_point(&in_buf[start..scan_end]) {
+            Some(len) => start + len,
+            None if scan_end == in_buf.len() => {
+                let success = compress_data(d, callback);
+                d.params.rsync.update(&in_buf[start..d.params.src_pos]);
+                return success;
+            }
+            None => {
+                callback.in_buf = Some(&in_buf[..scan_end]);
+                d.params.flush = MZFlush::None;
+                let success = compress_data(d, callback);
+                callback.in_buf = Some(in_buf);
+                d.params.flush = flush;
+                d.params.rsync.update(&in_buf[start..d.params.src_pos]);
+                if !success || d.params.src_pos != scan_end || d.params.flush_remaining != 0 {
+                    return success;
+                }
+                continue;
+            }
+        };
+
+        // Only let the compressor see the input up to the reset point, and make it process all
+        // of it as if flushing.
+        callback.in_buf = Some(&in_buf[..end]);
+        d.params.flush = MZFlush::Full;
+        let success = compress_data(d, callback);
+        callback.in_buf = Some(in_buf);
+        d.params.flush = flush;
+        d.params.rsync.update(&in_buf[start..d.params.src_pos]);
+
+        if !success {
+            return false;
+        }
+
+        if d.params.src_pos != end || d.dict.lookahead_size != 0 || d.params.flush_remaining != 0 {
+            // The output buffer is full, continue on the next call.
+            d.params.rsync.reset_pending = d.params.src_pos == end;
+            return true;
+        }
+
+        match flush_block(d, callback, MZFlush::Full) {
+            Err(_) => {
+                d.params.prev_return_status = TDEFLStatus::PutBufFailed;
+                return false;
+            }
+            Ok(x) if x < 0 => return false,
+            _ => {
+                d.dict.restart();
+                d.params.rsync.reset_pending = false;
+                d.params.rsync.chunk_len = 0;
+            }
+        }
+
+        if d.params.flush_remaining != 0 {
+            return true;
+        }
+    }
+}
+
+fn compress_inner<S: BufferStorage>(
+    d: &mut CompressorState<S>,
+    callback: &mut CallbackOxide,
+    flush: MZFlush,
 ) -> (TDEFLStatus, usize, usize) {
     d.params.out_buf_ofs = 0;
     d.params.src_pos = 0;
 
     let prev_ok = d.params.prev_return_status == TDEFLStatus::Okay;
-    let flush_finish_once = d.params.flush != TDEFLFlush::Finish || flush == TDEFLFlush::Finish;
+    let flush_finish_once = d.params.flush != MZFlush::Finish || flush == MZFlush::Finish;
 
     d.params.flush = flush;
     if !prev_ok || !flush_finish_once {
@@ -2271,16 +3899,21 @@ fn compress_inner(
         return res;
     }
 
-    let one_probe = d.params.flags & MAX_PROBES_MASK as u32 == 1;
-    let greedy = d.params.flags & TDEFL_GREEDY_PARSING_FLAG != 0;
-    let filter_or_rle_or_raw = d.params.flags
-        & (TDEFL_FILTER_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS | TDEFL_RLE_MATCHES)
-        != 0;
+    if let Some(params) = d.pending_params {
+        if !switch_params(d, callback, &params) {
+            return (d.params.prev_return_status, 0, d.params.out_buf_ofs);
+        }
+        if d.pending_params.is_some() || d.params.flush_remaining != 0 {
+            let res = flush_output_buffer(callback, &mut d.params);
+            d.params.prev_return_status = res.0;
+            return res;
+        }
+    }
 
-    let compress_success = if one_probe && greedy && !filter_or_rle_or_raw {
-        compress_fast(d, callback)
+    let compress_success = if d.params.rsyncable {
+        compress_rsyncable(d, callback, flush)
     } else {
-        compress_normal(d, callback)
+        compress_data(d, callback)
     };
 
     if !compress_success {
@@ -2297,7 +3930,7 @@ fn compress_inner(
         }
     }
 
-    let flush_none = d.params.flush == TDEFLFlush::None;
+    let flush_none = d.params.flush == MZFlush::None;
     let in_left = callback.in_buf.map_or(0, |buf| buf.len()) - d.params.src_pos;
     let remaining = in_left != 0 || d.params.flush_remaining != 0;
     if !flush_none && d.dict.lookahead_size == 0 && !remaining {
@@ -2319,11 +3952,9 @@ fn compress_inner(
                 )
             }
             _ => {
-                d.params.finished = d.params.flush == TDEFLFlush::Finish;
-                if d.params.flush == TDEFLFlush::Full {
-                    memset(&mut d.dict.b.hash[..], 0);
-                    memset(&mut d.dict.b.next[..], 0);
-                    d.dict.size = 0;
+                d.params.finished = d.params.flush == MZFlush::Finish;
+                if d.params.flush == MZFlush::Full {
+                    d.dict.clear_matches();
                 }
             }
         }
@@ -2382,12 +4013,49 @@ pub fn create_comp_flags_from_zip_params(level: i32, window_bits: i32, strategy:
 #[cfg(test)]
 mod test {
     use super::{
-        compress_to_output, create_comp_flags_from_zip_params, read_u16_le, write_u16_le,
-        CompressionStrategy, CompressorOxide, TDEFLFlush, TDEFLStatus, DEFAULT_FLAGS,
+        compress, compress_to_output, compress_with_flush, create_comp_flags_from_zip_params,
+        read_u16_le, write_u16_le, BufferSizes, CompressionLevel, CompressionParams,
+        CompressionStrategy, CompressorBuffers, CompressorOxide, CompressorWorkspace,
+        HuffmanTables, TDEFLFlush, TDEFLStatus, WorkspaceCompressor, DEFAULT_FLAGS,
         MZ_DEFAULT_WINDOW_BITS,
     };
-    use crate::inflate::decompress_to_vec;
+    use crate::inflate::{decompress_to_vec, decompress_to_vec_zlib};
+    use crate::{DataFormat, MZFlush};
+    use ::core::cmp;
     use alloc::vec;
+    use alloc::vec::Vec;
+
+    /// Generate some data that compresses reasonably well, with repetitions both close by and
+    /// far apart, and some runs of incompressible bytes.
+    fn get_test_data(len: usize) -> Vec<u8> {
+        const WORDS: [&[u8]; 8] = [
+            b"deflate ",
+            b"window ",
+            b"hash ",
+            b"the ",
+            b"compressor ",
+            b"of ",
+            b"match ",
+            b"\n",
+        ];
+        let mut state: u32 = 12345;
+        let mut next = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            state >> 16
+        };
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            if next() % 16 == 0 {
+                for _ in 0..next() % 64 {
+                    data.push(next() as u8);
+                }
+            } else {
+                data.extend_from_slice(WORDS[next() as usize % WORDS.len()]);
+            }
+        }
+        data.truncate(len);
+        data
+    }
 
     #[test]
     fn u16_to_slice() {
@@ -2459,4 +4127,713 @@ mod test {
         let decoded = decompress_to_vec(&encoded[..]).unwrap();
         assert_eq!(&decoded[..], &slice[..]);
     }
+
+    #[test]
+    fn compress_buffer_sizes() {
+        let data = get_test_data(150_000);
+        let sizes = [
+            BufferSizes::default(),
+            BufferSizes::low_memory(),
+            BufferSizes::new(0, 0, 0),
+            BufferSizes::new(15, 9, 4096),
+        ];
+        for &sizes in sizes.iter() {
+            for level in 0..=10 {
+                let flags = create_comp_flags_from_zip_params(
+                    level,
+                    MZ_DEFAULT_WINDOW_BITS,
+                    CompressionStrategy::Default as i32,
+                );
+                let mut d = CompressorOxide::new_with_buffer_sizes(flags, sizes);
+                assert_eq!(d.buffer_sizes(), sizes);
+                let mut encoded = vec![];
+                let (status, in_consumed) =
+                    compress_to_output(&mut d, &data, TDEFLFlush::Finish, |out: &[u8]| {
+                        encoded.extend_from_slice(out);
+                        true
+                    });
+
+                assert_eq!(status, TDEFLStatus::Done);
+                assert_eq!(in_consumed, data.len());
+                // The window size is stored in the zlib header.
+                assert_eq!(encoded[0] >> 4, sizes.window_bits() - 8);
+
+                let decoded = decompress_to_vec_zlib(&encoded[..]).unwrap();
+                assert!(decoded == data, "sizes: {:?} level: {}", sizes, level);
+            }
+        }
+    }
+
+    #[test]
+    fn buffer_sizes_clamped() {
+        let sizes = BufferSizes::new(0, 20, 1 << 20);
+        assert_eq!(sizes.window_bits(), BufferSizes::MIN_WINDOW_BITS);
+        assert_eq!(sizes.hash_bits(), BufferSizes::MAX_HASH_BITS);
+        assert_eq!(sizes.lz_code_buf_size(), BufferSizes::MAX_LZ_CODE_BUF_SIZE);
+        assert_eq!(BufferSizes::default().window_size(), 32768);
+        assert!(BufferSizes::low_memory().memory_usage() < 48 * 1024);
+    }
+
+    #[test]
+    fn compress_workspace() {
+        let data = get_test_data(100_000);
+        let mut workspace = CompressorWorkspace::new();
+        for &level in [0, 1, 6, 9].iter() {
+            let flags = create_comp_flags_from_zip_params(
+                level,
+                MZ_DEFAULT_WINDOW_BITS,
+                CompressionStrategy::Default as i32,
+            );
+
+            let mut expected = vec![];
+            let mut d = CompressorOxide::new(flags);
+            compress_to_output(&mut d, &data, TDEFLFlush::Finish, |out: &[u8]| {
+                expected.extend_from_slice(out);
+                true
+            });
+
+            let mut c = WorkspaceCompressor::new(flags, &mut workspace);
+            // Compress twice to check that resetting works.
+            for _ in 0..2 {
+                let mut encoded = vec![0; expected.len() + 100];
+                let (status, in_consumed, out_len) =
+                    c.compress(&data, &mut encoded, TDEFLFlush::Finish);
+                assert_eq!(status, TDEFLStatus::Done);
+                assert_eq!(in_consumed, data.len());
+                assert_eq!(&encoded[..out_len], &expected[..]);
+                assert_eq!(c.adler32(), d.adler32());
+                c.reset();
+            }
+        }
+
+        let decoded = decompress_to_vec_zlib(&{
+            let mut encoded = vec![];
+            let mut c = WorkspaceCompressor::new(DEFAULT_FLAGS, &mut workspace);
+            let (status, _) = c.compress_to_output(&data, TDEFLFlush::Finish, |out: &[u8]| {
+                encoded.extend_from_slice(out);
+                true
+            });
+            assert_eq!(status, TDEFLStatus::Done);
+            encoded
+        })
+        .unwrap();
+        assert!(decoded == data);
+    }
+
+    #[test]
+    fn compress_workspace_buffers() {
+        let data = get_test_data(100_000);
+        let sizes = BufferSizes::low_memory();
+        let mut expected = vec![];
+        let mut d = CompressorOxide::new_with_buffer_sizes(DEFAULT_FLAGS, sizes);
+        compress_to_output(&mut d, &data, TDEFLFlush::Finish, |out: &[u8]| {
+            expected.extend_from_slice(out);
+            true
+        });
+
+        let mut huffman = HuffmanTables::new();
+        let mut dict = vec![0; sizes.dict_size()];
+        let mut next = vec![0; sizes.window_size()];
+        let mut hash = vec![0; sizes.hash_size()];
+        let mut codes = vec![0; sizes.lz_code_buf_size()];
+        let mut out_buf = vec![0; sizes.out_buf_size()];
+
+        // Buffers that are too small are rejected.
+        let small = CompressorBuffers {
+            huffman: &mut huffman,
+            dict: &mut dict[..],
+            next: &mut next[..],
+            hash: &mut hash[..],
+            codes: &mut codes[..],
+            out_buf: &mut out_buf[..sizes.out_buf_size() - 1],
+        };
+        assert!(WorkspaceCompressor::with_buffers(DEFAULT_FLAGS, sizes, small).is_none());
+
+        let buffers = CompressorBuffers {
+            huffman: &mut huffman,
+            dict: &mut dict[..],
+            next: &mut next[..],
+            hash: &mut hash[..],
+            codes: &mut codes[..],
+            out_buf: &mut out_buf[..],
+        };
+        let mut c = WorkspaceCompressor::with_buffers(DEFAULT_FLAGS, sizes, buffers).unwrap();
+        assert_eq!(c.buffer_sizes(), sizes);
+
+        // Compress half of the data, then finish with a copy placed in a larger workspace.
+        let half = data.len() / 2;
+        let mut encoded = vec![0; expected.len() + 100];
+        let (status, in_consumed, mut out_len) =
+            c.compress(&data[..half], &mut encoded, TDEFLFlush::None);
+        assert_eq!(status, TDEFLStatus::Okay);
+        assert_eq!(in_consumed, half);
+
+        let mut workspace = CompressorWorkspace::new();
+        let mut copy = c.copy_into(&mut workspace);
+        assert_eq!(copy.buffer_sizes(), sizes);
+        let (status, in_consumed, copy_len) =
+            copy.compress(&data[half..], &mut encoded[out_len..], TDEFLFlush::Finish);
+        assert_eq!(status, TDEFLStatus::Done);
+        assert_eq!(in_consumed, data.len() - half);
+        out_len += copy_len;
+        assert_eq!(&encoded[..out_len], &expected[..]);
+    }
+
+    /// Compress `data` in rsyncable mode with an output buffer of `out_chunk` bytes, feeding the
+    /// input in chunks of `in_chunk` bytes.
+    fn compress_rsyncable(data: &[u8], level: i32, in_chunk: usize, out_chunk: usize) -> Vec<u8> {
+        let flags = create_comp_flags_from_zip_params(
+            level,
+            MZ_DEFAULT_WINDOW_BITS,
+            CompressionStrategy::Default as i32,
+        );
+        let mut d = CompressorOxide::new(flags);
+        d.set_rsyncable(true);
+        let mut encoded = vec![];
+        let mut out = vec![0; out_chunk];
+        let mut in_pos = 0;
+        loop {
+            let in_end = cmp::min(in_pos + in_chunk, data.len());
+            let flush = if in_end == data.len() {
+                TDEFLFlush::Finish
+            } else {
+                TDEFLFlush::None
+            };
+            let (status, in_consumed, out_len) =
+                compress(&mut d, &data[in_pos..in_end], &mut out, flush);
+            in_pos += in_consumed;
+            encoded.extend_from_slice(&out[..out_len]);
+            match status {
+                TDEFLStatus::Done => return encoded,
+                TDEFLStatus::Okay => (),
+                _ => panic!("Compression failed: {:?}", status),
+            }
+        }
+    }
+
+    #[test]
+    fn rsyncable() {
+        let data = get_test_data(300_000);
+        let mut changed = data.clone();
+        changed[1000] ^= 0x55;
+
+        for &level in [1, 6, 9].iter() {
+            let encoded = compress_rsyncable(&data, level, data.len(), data.len() * 2);
+            assert!(decompress_to_vec_zlib(&encoded).unwrap() == data);
+
+            // The reset points only depend on the data, so how it is passed to the compressor
+            // does not matter.
+            assert!(compress_rsyncable(&data, level, 1000, 100) == encoded);
+            assert!(compress_rsyncable(&data, level, 100_000, 7) == encoded);
+
+            // The output should be the same after a short distance after the changed byte.
+            let encoded_changed = compress_rsyncable(&changed, level, data.len(), data.len() * 2);
+            assert!(decompress_to_vec_zlib(&encoded_changed).unwrap() == changed);
+            let common_suffix = encoded
+                .iter()
+                .rev()
+                .zip(encoded_changed.iter().rev())
+                .skip(4) // Skip the adler32 checksum.
+                .take_while(|(a, b)| a == b)
+                .count();
+            assert!(common_suffix > encoded.len() - 20_000);
+        }
+    }
+
+    /// Split rsyncable output into the parts ended by the empty stored blocks written at the reset
+    /// points.
+    fn rsync_segments(encoded: &[u8]) -> Vec<&[u8]> {
+        let mut segments = vec![];
+        let mut start = 0;
+        let mut i = 0;
+        while i + 4 <= encoded.len() {
+            if encoded[i..i + 4] == [0, 0, 0xFF, 0xFF] {
+                segments.push(&encoded[start..i + 4]);
+                start = i + 4;
+                i = start;
+            } else {
+                i += 1;
+            }
+        }
+        segments.push(&encoded[start..]);
+        segments
+    }
+
+    #[test]
+    fn rsyncable_insert_and_delete() {
+        let data = get_test_data(300_000);
+        let mut inserted = data.clone();
+        inserted.insert(1000, b'#');
+        let mut deleted = data.clone();
+        deleted.remove(1000);
+
+        for level in 1..=10 {
+            let encoded = compress_rsyncable(&data, level, data.len(), data.len() * 2);
+            let segments = rsync_segments(&encoded[..encoded.len() - 4]);
+            assert!(segments.len() > 50);
+            for changed in [&inserted, &deleted].iter() {
+                let encoded_changed =
+                    compress_rsyncable(changed, level, changed.len(), changed.len() * 2);
+                assert!(decompress_to_vec_zlib(&encoded_changed).unwrap() == **changed);
+                // Everything after the reset point following the edit is the same.
+                let changed_segments =
+                    rsync_segments(&encoded_changed[..encoded_changed.len() - 4]);
+                let common = segments
+                    .iter()
+                    .rev()
+                    .zip(changed_segments.iter().rev())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                assert!(
+                    common >= segments.len() - 2,
+                    "level {}: {} of {} segments differ",
+                    level,
+                    segments.len() - common,
+                    segments.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn verify() {
+        let data = get_test_data(200_000);
+
+        for &level in [0, 1, 6, 10].iter() {
+            let flags = create_comp_flags_from_zip_params(
+                level,
+                MZ_DEFAULT_WINDOW_BITS,
+                CompressionStrategy::Default as i32,
+            );
+            let mut d = CompressorOxide::new(flags);
+            d.set_verify(true);
+            assert!(d.verify());
+
+            // Small input and output chunks, with a sync flush in the middle.
+            let mut encoded = vec![];
+            let mut out = vec![0; 777];
+            let mut in_pos = 0;
+            loop {
+                let in_end = cmp::min(in_pos + 3000, data.len());
+                let flush = if in_end == data.len() {
+                    TDEFLFlush::Finish
+                } else if in_pos == 99_000 {
+                    TDEFLFlush::Sync
+                } else {
+                    TDEFLFlush::None
+                };
+                let (status, in_consumed, out_len) =
+                    compress(&mut d, &data[in_pos..in_end], &mut out, flush);
+                in_pos += in_consumed;
+                encoded.extend_from_slice(&out[..out_len]);
+                match status {
+                    TDEFLStatus::Done => break,
+                    TDEFLStatus::Okay => (),
+                    _ => panic!("Compression failed: {:?}", status),
+                }
+            }
+            assert!(decompress_to_vec_zlib(&encoded).unwrap() == data);
+
+            // Reuse the compressor with the callback interface.
+            d.reset();
+            d.set_rsyncable(true);
+            let mut encoded = vec![];
+            for (i, chunk) in data.chunks(10_000).enumerate() {
+                let flush = if (i + 1) * 10_000 >= data.len() {
+                    TDEFLFlush::Finish
+                } else {
+                    TDEFLFlush::None
+                };
+                let (status, in_consumed) = compress_to_output(&mut d, chunk, flush, |out| {
+                    encoded.extend_from_slice(out);
+                    true
+                });
+                assert_ne!(status, TDEFLStatus::BadParam);
+                assert_eq!(in_consumed, chunk.len());
+            }
+            assert_eq!(d.prev_return_status(), TDEFLStatus::Done);
+            assert!(!d.verification_failed());
+            assert!(decompress_to_vec_zlib(&encoded).unwrap() == data);
+
+            // Enabling verification after compression started can't work.
+            d.reset();
+            d.set_verify(false);
+            let mut out = vec![0; 300_000];
+            let (status, in_consumed, _) =
+                compress(&mut d, &data[..50_000], &mut out, TDEFLFlush::Sync);
+            assert_eq!((status, in_consumed), (TDEFLStatus::Okay, 50_000));
+            d.set_verify(true);
+            let (status, _, _) = compress(&mut d, &data[50_000..], &mut out, TDEFLFlush::Finish);
+            assert_eq!(status, TDEFLStatus::BadParam);
+            assert!(d.verification_failed());
+            d.reset();
+            assert!(!d.verification_failed());
+        }
+    }
+
+    #[test]
+    fn compression_params() {
+        for level in 0..=10 {
+            for strategy in 0..=5 {
+                let flags = create_comp_flags_from_zip_params(level, 0, strategy);
+                let params = CompressionParams::from_flags(flags);
+                assert_eq!(params.to_flags(), flags);
+            }
+            let flags = create_comp_flags_from_zip_params(level, MZ_DEFAULT_WINDOW_BITS, 0);
+            assert_eq!(CompressionParams::from_level(level as u8).to_flags(), flags);
+        }
+
+        let data = get_test_data(100_000);
+        let mut params = CompressionParams::from_level(9);
+        params.format = DataFormat::Raw;
+        let default_len = {
+            let mut d = CompressorOxide::new_with_params(params);
+            let mut out = vec![0; data.len() * 2];
+            compress(&mut d, &data, &mut out, TDEFLFlush::Finish).2
+        };
+
+        let mut tuned = vec![];
+        for &(max_lazy, good_length, nice_length, min_match_len) in [
+            (0, 0, 3, 3),
+            (258, 258, 258, 3),
+            (16, 8, 32, 4),
+            (128, 32, 258, 300),
+        ]
+        .iter()
+        {
+            params.max_lazy = max_lazy;
+            params.good_length = good_length;
+            params.nice_length = nice_length;
+            params.min_match_len = min_match_len;
+            let mut d = CompressorOxide::new_with_params(params);
+            let got = d.params();
+            assert_eq!(got.nice_length, nice_length);
+            assert_eq!(got.min_match_len, cmp::min(min_match_len, 258));
+            d.set_verify(true);
+
+            let mut out = vec![0; data.len() * 2];
+            let (status, _, out_len) = compress(&mut d, &data, &mut out, TDEFLFlush::Finish);
+            assert_eq!(status, TDEFLStatus::Done);
+            assert!(decompress_to_vec(&out[..out_len]).unwrap() == data);
+            tuned.push(out_len);
+        }
+        // Short nice lengths and no lazy matching, or only using very long matches, should make
+        // the output larger.
+        assert!(tuned[0] > default_len);
+        assert!(tuned[3] > default_len);
+
+        // Changing the level keeps the strategy, also across level 0.
+        let mut d = CompressorOxide::new_with_params(CompressionParams::from_level(6));
+        let mut params = d.params();
+        params.strategy = CompressionStrategy::Filtered;
+        d.set_params(params);
+        d.set_compression_level_raw(9);
+        assert_eq!(d.params().strategy, CompressionStrategy::Filtered);
+        assert_eq!(d.params().probes, CompressionParams::from_level(9).probes);
+        assert!(!d.params().raw_blocks);
+        d.set_compression_level(CompressionLevel::NoCompression);
+        assert!(d.params().raw_blocks);
+        d.set_compression_level(CompressionLevel::BestSpeed);
+        assert_eq!(d.params().strategy, CompressionStrategy::Filtered);
+        assert!(!d.params().raw_blocks);
+
+        // Change the parameters in the middle of the stream, keeping the other settings.
+        let mut d = CompressorOxide::new_with_params(CompressionParams::from_level(6));
+        d.set_rsyncable(true);
+        let mut encoded = vec![];
+        for (i, chunk) in data.chunks(10_000).enumerate() {
+            let mut params = d.params();
+            params.strategy = match i % 4 {
+                0 => CompressionStrategy::Filtered,
+                2 => CompressionStrategy::RLE,
+                _ => CompressionStrategy::Default,
+            };
+            params.raw_blocks = i % 4 == 1;
+            params.greedy = i % 3 == 0;
+            params.min_match_len = 3 + i as u16;
+            d.set_params(params);
+            assert_eq!(d.params().strategy, params.strategy);
+            assert!(d.rsyncable());
+
+            let flush = if (i + 1) * 10_000 >= data.len() {
+                TDEFLFlush::Finish
+            } else {
+                TDEFLFlush::None
+            };
+            let (status, _) = compress_to_output(&mut d, chunk, flush, |out| {
+                encoded.extend_from_slice(out);
+                true
+            });
+            assert!(status == TDEFLStatus::Okay || status == TDEFLStatus::Done);
+        }
+        assert!(decompress_to_vec_zlib(&encoded).unwrap() == data);
+    }
+
+    #[test]
+    fn set_params_mid_block() {
+        let data = get_test_data(200_000);
+        let strategies = [
+            None,
+            Some(CompressionStrategy::Default),
+            Some(CompressionStrategy::Fixed),
+            Some(CompressionStrategy::HuffmanOnly),
+            Some(CompressionStrategy::RLE),
+            Some(CompressionStrategy::Filtered),
+        ];
+
+        for &out_chunk in [5, 1000, 400_000].iter() {
+            let mut d = CompressorOxide::new_with_params(CompressionParams::from_level(6));
+            d.set_verify(true);
+            let mut encoded = vec![];
+            let mut out = vec![0; out_chunk];
+            let mut in_pos = 0;
+            let mut chunk_index = 0;
+            loop {
+                if in_pos >= chunk_index * 7_000 {
+                    // Alternate between the strategies, and between the fast and normal modes.
+                    let level = if chunk_index % 3 == 0 { 1 } else { 6 };
+                    let mut params = CompressionParams::from_level(level);
+                    // `None` uses raw blocks.
+                    match strategies[chunk_index % strategies.len()] {
+                        Some(strategy) => params.strategy = strategy,
+                        None => params.raw_blocks = true,
+                    }
+                    d.set_params(params);
+                    assert_eq!(d.params(), params);
+                    chunk_index += 1;
+                }
+
+                let in_end = cmp::min(chunk_index * 7_000, data.len());
+                let flush = if in_end == data.len() {
+                    TDEFLFlush::Finish
+                } else {
+                    TDEFLFlush::None
+                };
+                let (status, in_consumed, out_len) =
+                    compress(&mut d, &data[in_pos..in_end], &mut out, flush);
+                in_pos += in_consumed;
+                encoded.extend_from_slice(&out[..out_len]);
+                match status {
+                    TDEFLStatus::Done => break,
+                    TDEFLStatus::Okay => (),
+                    _ => panic!("Compression failed: {:?}", status),
+                }
+            }
+            assert!(decompress_to_vec_zlib(&encoded).unwrap() == data);
+        }
+
+        // The part after switching to raw blocks should not be compressed.
+        let mut d = CompressorOxide::new_with_params(CompressionParams::from_level(6));
+        let mut out = vec![0; 400_000];
+        let (_, _, first_len) = compress(&mut d, &data[..100_000], &mut out, TDEFLFlush::None);
+        let mut params = d.params();
+        params.raw_blocks = true;
+        d.set_params(params);
+        let (status, _, second_len) = compress(
+            &mut d,
+            &data[100_000..],
+            &mut out[first_len..],
+            TDEFLFlush::Finish,
+        );
+        assert_eq!(status, TDEFLStatus::Done);
+        let total = first_len + second_len;
+        assert!(decompress_to_vec_zlib(&out[..total]).unwrap() == data);
+        assert!(total > 100_000);
+    }
+
+    #[test]
+    fn incompressible_input() {
+        assert_eq!(super::log2_fixed(1), 0);
+        assert_eq!(super::log2_fixed(3), 25);
+        assert_eq!(super::log2_fixed(4096), 192);
+
+        let text = get_test_data(100_000);
+        let mut state = 0x9E37_79B9_u32;
+        let random: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let mut data = text.clone();
+        data.extend_from_slice(&random);
+        data.extend_from_slice(&text);
+
+        let mut d = CompressorOxide::new_with_params(CompressionParams::from_level(6));
+        d.set_verify(true);
+        let mut out = vec![0; data.len() + 1000];
+        let (status, in_len, first_len) =
+            compress(&mut d, &data[..300_000], &mut out, TDEFLFlush::None);
+        assert_eq!((status, in_len), (TDEFLStatus::Okay, 300_000));
+        assert!(d.state.params.incompressible);
+
+        let (status, _, second_len) = compress(
+            &mut d,
+            &data[300_000..],
+            &mut out[first_len..],
+            TDEFLFlush::Finish,
+        );
+        assert_eq!(status, TDEFLStatus::Done);
+        assert!(!d.state.params.incompressible);
+        let total = first_len + second_len;
+        assert!(decompress_to_vec_zlib(&out[..total]).unwrap() == data);
+        // The text parts should still be compressed, and the random part not grow much.
+        let text_len = crate::deflate::compress_to_vec(&text, 6).len();
+        assert!(
+            total < random.len() + random.len() / 100 + 2 * text_len,
+            "{} {}",
+            total,
+            text_len
+        );
+    }
+
+    #[test]
+    fn preset_dictionary() {
+        use crate::inflate::stream::{inflate, InflateState};
+        use crate::{MZError, MZFlush, MZStatus};
+
+        let dictionary = get_test_data(40_000);
+        let mut data = dictionary[10_000..20_000].to_vec();
+        data.extend_from_slice(&dictionary[35_000..]);
+
+        for &level in [1, 6].iter() {
+            for &format in [DataFormat::Raw, DataFormat::Zlib].iter() {
+                let mut params = CompressionParams::from_level(level);
+                params.format = format;
+                let mut out = vec![0; 20_000];
+
+                let mut d = CompressorOxide::new_with_params(params);
+                let (_, _, plain_len) = compress(&mut d, &data, &mut out, TDEFLFlush::Finish);
+
+                d.reset();
+                assert!(d.set_dictionary(&dictionary));
+                let (status, _, out_len) = compress(&mut d, &data, &mut out, TDEFLFlush::Finish);
+                assert_eq!(status, TDEFLStatus::Done);
+                assert!(out_len < plain_len / 2, "{} {}", out_len, plain_len);
+                assert!(!d.set_dictionary(&dictionary));
+
+                let mut state = InflateState::new_boxed(format);
+                let mut decoded = vec![0; data.len()];
+                if format == DataFormat::Zlib {
+                    let res = inflate(&mut state, &out[..out_len], &mut decoded, MZFlush::None);
+                    assert_eq!(res.status, Ok(MZStatus::NeedDict));
+                    assert_eq!(res.bytes_consumed, 6);
+                    assert_eq!(
+                        state.decompressor().dictionary_id(),
+                        Some(crate::shared::update_adler32(1, &dictionary))
+                    );
+                    assert_eq!(state.set_dictionary(&data), Err(MZError::Data));
+                    assert_eq!(state.set_dictionary(&dictionary), Ok(MZStatus::Ok));
+                    let res = inflate(&mut state, &out[6..out_len], &mut decoded, MZFlush::Finish);
+                    assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+                } else {
+                    assert_eq!(state.set_dictionary(&dictionary), Ok(MZStatus::Ok));
+                    let res = inflate(&mut state, &out[..out_len], &mut decoded, MZFlush::Finish);
+                    assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+                }
+                assert!(decoded == data);
+            }
+        }
+    }
+
+    #[test]
+    fn prime_and_pending() {
+        let mut params = CompressionParams::from_level(6);
+        params.format = DataFormat::Raw;
+        let mut d = CompressorOxide::new_with_params(params);
+        assert!(!d.prime(17, 0));
+        assert!(d.prime(16, 0x1234));
+        assert!(d.prime(8, 0x1AB));
+        assert!(!d.prime(1, 0));
+        assert_eq!(d.pending_output(), (3, 0));
+
+        let mut out = vec![0; 100];
+        let (status, _, out_len) = compress(&mut d, &[], &mut out, TDEFLFlush::Finish);
+        assert_eq!(status, TDEFLStatus::Done);
+        assert_eq!(&out[..3], &[0x34, 0x12, 0xAB]);
+        assert_eq!(decompress_to_vec(&out[3..out_len]).unwrap(), []);
+        assert!(!d.prime(1, 0));
+
+        // With a zlib wrapper the bits go after the header.
+        let mut d = CompressorOxide::new_with_params(CompressionParams::from_level(6));
+        assert!(d.prime(8, 0xAB));
+        let (status, _, out_len) = compress(&mut d, b"primed", &mut out, TDEFLFlush::Finish);
+        assert_eq!(status, TDEFLStatus::Done);
+        assert_eq!(&out[..3], &[0x78, 0x9C, 0xAB]);
+        assert_eq!(decompress_to_vec(&out[3..out_len - 4]).unwrap(), b"primed");
+
+        // Output that doesn't fit in the output buffer is pending.
+        let data = get_test_data(50_000);
+        let mut d = CompressorOxide::new_with_params(params);
+        let mut out = vec![0; 10];
+        let (status, _, out_len) = compress(&mut d, &data, &mut out, TDEFLFlush::Finish);
+        assert_eq!((status, out_len), (TDEFLStatus::Okay, 10));
+        assert!(d.pending_output().0 > 0);
+    }
+
+    #[test]
+    fn clone_compressor() {
+        let data = get_test_data(100_000);
+        let mut d = CompressorOxide::new_with_params(CompressionParams::from_level(6));
+        let mut out = vec![0; 100_000];
+        let (_, in_len, first_len) = compress(&mut d, &data[..50_000], &mut out, TDEFLFlush::None);
+        assert_eq!(in_len, 50_000);
+
+        let mut copy = d.clone();
+        let mut out_copy = out.clone();
+        let (_, _, second_len) = compress(
+            &mut d,
+            &data[50_000..],
+            &mut out[first_len..],
+            TDEFLFlush::Finish,
+        );
+        let (status, _, copy_len) = compress(
+            &mut copy,
+            &data[50_000..],
+            &mut out_copy[first_len..],
+            TDEFLFlush::Finish,
+        );
+        assert_eq!(status, TDEFLStatus::Done);
+        assert_eq!(second_len, copy_len);
+        assert!(out == out_copy);
+        assert!(decompress_to_vec_zlib(&out_copy[..first_len + copy_len]).unwrap() == data);
+    }
+
+    #[test]
+    fn partial_and_block_flush() {
+        // Output of zlib for the data at level 6 with a partial flush, and with a block flush.
+        let data = b"Hello, partial flush! ";
+        let partial = [
+            0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0x28, 0x48, 0x2c, 0x2a, 0xc9, 0x4c, 0xcc,
+            0x51, 0x48, 0xcb, 0x29, 0x2d, 0xce, 0x50, 0x54, 0x00, 0x08,
+        ];
+        let block = &partial[..partial.len() - 1];
+
+        for &(flush, expected) in [(MZFlush::Partial, &partial[..]), (MZFlush::Block, block)].iter()
+        {
+            let mut d = CompressorOxide::new_with_params(CompressionParams {
+                format: DataFormat::Raw,
+                ..CompressionParams::from_level(6)
+            });
+            let mut out = vec![0; 100];
+            let (_, in_len, out_len) = compress(&mut d, &data[..5], &mut out, TDEFLFlush::None);
+            assert_eq!((in_len, out_len), (5, 0));
+            assert_eq!(d.pending_input(), 5);
+            let (status, in_len, out_len) =
+                compress_with_flush(&mut d, &data[5..], &mut out, flush);
+            assert_eq!(status, TDEFLStatus::Okay);
+            assert_eq!(in_len, data.len() - 5);
+            assert_eq!(&out[..out_len], expected);
+            assert_eq!(d.pending_input(), 0);
+
+            let (status, _, len) = compress(&mut d, data, &mut out[out_len..], TDEFLFlush::Finish);
+            assert_eq!(status, TDEFLStatus::Done);
+            assert_eq!(
+                decompress_to_vec(&out[..out_len + len]).unwrap(),
+                [&data[..], data].concat()
+            );
+        }
+    }
 }
@@ -1,11 +1,25 @@
 //! This module contains functionality for compression.
 
+#[cfg(feature = "with-alloc")]
 use crate::alloc::vec;
+#[cfg(feature = "with-alloc")]
 use crate::alloc::vec::Vec;
 
+mod bound;
 mod buffer;
+#[cfg(feature = "with-alloc")]
+mod compressor;
 pub mod core;
+#[cfg(feature = "with-alloc")]
 pub mod stream;
+#[cfg(feature = "with-alloc")]
+mod verify;
+pub use self::bound::{
+    compress_bound, compress_bound_with_sizes, gzip_compress_bound, GZIP_FRAMING_SIZE,
+};
+#[cfg(feature = "with-alloc")]
+pub use self::compressor::{Compressor, CompressorBuilder};
+#[cfg(feature = "with-alloc")]
 use self::core::*;
 
 /// How much processing the compressor should do to compress the data.
@@ -107,21 +121,79 @@ fn tdefl_compress_mem_to_mem(
 ) -> usize*/
 
 /// Compress the input data to a vector, using the specified compression level (0-10).
+#[cfg(feature = "with-alloc")]
 pub fn compress_to_vec(input: &[u8], level: u8) -> Vec<u8> {
     compress_to_vec_inner(input, level, 0, 0)
 }
 
 /// Compress the input data to a vector, using the specified compression level (0-10), and with a
 /// zlib wrapper.
+#[cfg(feature = "with-alloc")]
 pub fn compress_to_vec_zlib(input: &[u8], level: u8) -> Vec<u8> {
     compress_to_vec_inner(input, level, 1, 0)
 }
 
+/// Error returned when the compressed output did not decompress to the input data.
+///
+/// See [`CompressorOxide::set_verify`](core::CompressorOxide::set_verify) for details.
+#[cfg(feature = "with-alloc")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct VerificationError;
+
+#[cfg(feature = "with-alloc")]
+impl ::core::fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        f.write_str("Compressed data did not match the input")
+    }
+}
+
+/// Implement Error trait only if std feature is requested as it requires std.
+#[cfg(all(feature = "std", feature = "with-alloc"))]
+impl std::error::Error for VerificationError {}
+
+/// Compress the input data to a vector, using the specified compression level (0-10), and
+/// check that the output decompresses to the input data.
+///
+/// Returns a [`VerificationError`] if the check failed, which indicates a bug in the compressor.
+#[cfg(feature = "with-alloc")]
+pub fn compress_to_vec_verified(input: &[u8], level: u8) -> Result<Vec<u8>, VerificationError> {
+    compress_to_vec_checked(input, level, 0, 0, true)
+}
+
+/// Compress the input data to a vector, using the specified compression level (0-10), and with a
+/// zlib wrapper, and check that the output decompresses to the input data.
+///
+/// Returns a [`VerificationError`] if the check failed, which indicates a bug in the compressor.
+#[cfg(feature = "with-alloc")]
+pub fn compress_to_vec_zlib_verified(
+    input: &[u8],
+    level: u8,
+) -> Result<Vec<u8>, VerificationError> {
+    compress_to_vec_checked(input, level, 1, 0, true)
+}
+
 /// Simple function to compress data to a vec.
+#[cfg(feature = "with-alloc")]
 fn compress_to_vec_inner(input: &[u8], level: u8, window_bits: i32, strategy: i32) -> Vec<u8> {
+    match compress_to_vec_checked(input, level, window_bits, strategy, false) {
+        Ok(output) => output,
+        Err(_) => unreachable!(),
+    }
+}
+
+/// Compress data to a vec, optionally verifying the output.
+#[cfg(feature = "with-alloc")]
+fn compress_to_vec_checked(
+    input: &[u8],
+    level: u8,
+    window_bits: i32,
+    strategy: i32,
+    verify: bool,
+) -> Result<Vec<u8>, VerificationError> {
     // The comp flags function sets the zlib flag if the window_bits parameter is > 0.
     let flags = create_comp_flags_from_zip_params(level.into(), window_bits, strategy);
     let mut compressor = CompressorOxide::new(flags);
+    compressor.set_verify(verify);
     let mut output = vec![0; ::core::cmp::max(input.len() / 2, 2)];
 
     let mut in_pos = 0;
@@ -148,17 +220,23 @@ fn compress_to_vec_inner(input: &[u8], level: u8, window_bits: i32, strategy: i3
                     output.resize(output.len() * 2, 0)
                 }
             }
+            TDEFLStatus::BadParam if compressor.verification_failed() => {
+                return Err(VerificationError)
+            }
             // Not supposed to happen unless there is a bug.
             _ => panic!("Bug! Unexpectedly failed to compress!"),
         }
     }
 
-    output
+    Ok(output)
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "with-alloc"))]
 mod test {
-    use super::{compress_to_vec, compress_to_vec_inner, CompressionStrategy};
+    use super::{
+        compress_to_vec, compress_to_vec_inner, compress_to_vec_verified,
+        compress_to_vec_zlib_verified, CompressionStrategy,
+    };
     use crate::inflate::decompress_to_vec;
     use alloc::vec;
 
@@ -224,4 +302,15 @@ mod test {
         // as neither checks matches against the byte at index 0.)
         assert!(c.len() <= 6);
     }
+
+    #[test]
+    fn verified() {
+        let data = include_bytes!("../../tests/test_data/numbers.txt");
+        for level in 0..=10 {
+            let c = compress_to_vec_verified(data, level).unwrap();
+            assert_eq!(c, compress_to_vec(data, level));
+            let c = compress_to_vec_zlib_verified(data, level).unwrap();
+            assert_eq!(c, super::compress_to_vec_zlib(data, level));
+        }
+    }
 }
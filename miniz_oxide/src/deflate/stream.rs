@@ -4,7 +4,9 @@
 //!
 //! There is no DeflateState as the needed state is contained in the compressor struct itself.
 
-use crate::deflate::core::{compress, CompressorOxide, TDEFLFlush, TDEFLStatus};
+use crate::deflate::core::{
+    compress_with_flush, CompressorOxide, TDEFLStatus, WorkspaceCompressor,
+};
 use crate::{MZError, MZFlush, MZStatus, StreamResult};
 
 /// Try to compress from input to output with the given [`CompressorOxide`].
@@ -19,17 +21,54 @@ use crate::{MZError, MZFlush, MZStatus, StreamResult};
 ///
 /// Returns [`MZError::Stream`] when lower-level decompressor returns a
 /// [`TDEFLStatus::PutBufFailed`]; may not actually be possible.
+///
+/// Returns [`MZError::Data`] if verification is enabled on the compressor and the output did not
+/// decompress to the input. See [`CompressorOxide::set_verify`].
 pub fn deflate(
     compressor: &mut CompressorOxide,
     input: &[u8],
     output: &mut [u8],
     flush: MZFlush,
+) -> StreamResult {
+    let prev_status = compressor.prev_return_status();
+    let mut res = deflate_inner(prev_status, input, output, flush, |input, output, flush| {
+        compress_with_flush(compressor, input, output, flush)
+    });
+    if res.status == Err(MZError::Param) && compressor.verification_failed() {
+        res.status = Err(MZError::Data);
+    }
+    res
+}
+
+/// Try to compress from input to output with the given [`WorkspaceCompressor`].
+///
+/// This works the same way as [`deflate`], for compressors that keep their buffers in a
+/// caller-provided workspace.
+pub fn deflate_workspace(
+    compressor: &mut WorkspaceCompressor,
+    input: &[u8],
+    output: &mut [u8],
+    flush: MZFlush,
+) -> StreamResult {
+    let prev_status = compressor.prev_return_status();
+    deflate_inner(prev_status, input, output, flush, |input, output, flush| {
+        compressor.compress_with_flush(input, output, flush)
+    })
+}
+
+/// Run the compression loop of [`deflate`], using `compress` to compress each part of the data.
+fn deflate_inner(
+    prev_status: TDEFLStatus,
+    input: &[u8],
+    output: &mut [u8],
+    flush: MZFlush,
+    mut compress: impl FnMut(&[u8], &mut [u8], MZFlush) -> (TDEFLStatus, usize, usize),
 ) -> StreamResult {
     if output.is_empty() {
         return StreamResult::error(MZError::Buf);
     }
 
-    if compressor.prev_return_status() == TDEFLStatus::Done {
+    if prev_status == TDEFLStatus::Done {
         return if flush == MZFlush::Finish {
             StreamResult {
                 bytes_written: 0,
@@ -51,7 +90,7 @@ pub fn deflate(
         let in_bytes;
         let out_bytes;
         let defl_status = {
-            let res = compress(compressor, next_in, next_out, TDEFLFlush::from(flush));
+            let res = compress(next_in, next_out, flush);
             in_bytes = res.1;
             out_bytes = res.2;
             res.0
@@ -98,12 +137,16 @@ pub fn deflate(
 
 #[cfg(test)]
 mod test {
-    use super::deflate;
+    use super::{deflate, deflate_workspace};
+    use crate::deflate::core::{
+        create_comp_flags_from_zip_params, CompressorWorkspace, WorkspaceCompressor,
+    };
     use crate::deflate::CompressorOxide;
     use crate::inflate::decompress_to_vec_zlib;
     use crate::{MZFlush, MZStatus};
     use alloc::boxed::Box;
     use alloc::vec;
+    use alloc::vec::Vec;
 
     #[test]
     fn test_state() {
@@ -118,4 +161,39 @@ mod test {
         assert_eq!(decomp[..], data[..]);
         assert_eq!(res.bytes_consumed, data.len());
     }
+
+    #[test]
+    fn workspace_copy() {
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 97 + i / 1000) as u8).collect();
+        let mut workspace = Box::new(CompressorWorkspace::new());
+        let mut copy_workspace = Box::new(CompressorWorkspace::new());
+        let flags = create_comp_flags_from_zip_params(6, 15, 0);
+        let mut compressor = WorkspaceCompressor::new(flags, &mut workspace);
+
+        // Copy the compressor in the middle of the data, with output left to write.
+        let mut compressed = vec![0; 100];
+        let res = deflate_workspace(
+            &mut compressor,
+            &data[..30_000],
+            &mut compressed,
+            MZFlush::Sync,
+        );
+        assert_eq!(res.status, Ok(MZStatus::Ok));
+        let mut copy = compressor.copy_into(&mut copy_workspace);
+        let (mut a, mut b) = (compressed.clone(), compressed);
+        for (c, out) in [(&mut compressor, &mut a), (&mut copy, &mut b)].iter_mut() {
+            let mut input = &data[res.bytes_consumed..];
+            loop {
+                let mut buf = vec![0; 1000];
+                let r = deflate_workspace(c, input, &mut buf, MZFlush::Finish);
+                input = &input[r.bytes_consumed..];
+                out.extend_from_slice(&buf[..r.bytes_written]);
+                if r.status == Ok(MZStatus::StreamEnd) {
+                    break;
+                }
+            }
+        }
+        assert_eq!(a, b);
+        assert_eq!(decompress_to_vec_zlib(&a).unwrap(), data);
+    }
 }
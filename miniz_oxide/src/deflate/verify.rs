@@ -0,0 +1,218 @@
+//! Checking of compressed data by decompressing it as it is produced.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp;
+
+use crate::inflate::core::inflate_flags::{
+    TINFL_FLAG_HAS_MORE_INPUT, TINFL_FLAG_PARSE_ZLIB_HEADER,
+};
+use crate::inflate::core::{decompress, DecompressorOxide, TINFL_LZ_DICT_SIZE};
+use crate::inflate::TINFLStatus;
+
+/// Decompresses the output of a compressor as it is produced and compares it against the input
+/// that was given to the compressor.
+///
+/// The output of each call to the compressor is compared against the input of that call where
+/// it is, so only the input that the compressor consumed but has not output yet has to be kept
+/// until the next call.
+#[derive(Clone)]
+pub(crate) struct Verifier {
+    decomp: Box<DecompressorOxide>,
+    /// Wrapping output buffer for the decompressor.
+    dict: Box<[u8]>,
+    dict_pos: usize,
+    /// Input consumed by earlier calls to the compressor that has not been compared against the
+    /// output yet, starting at `pending_pos`.
+    pending: Vec<u8>,
+    pending_pos: usize,
+    /// Amount of the input of the current call that has been compared against the output.
+    input_pos: usize,
+    /// Whether the decompressor has reached the end of the stream.
+    done: bool,
+    /// Whether the output has been found to not match the input.
+    failed: bool,
+}
+
+impl Verifier {
+    pub(crate) fn new() -> Verifier {
+        Verifier {
+            decomp: Box::default(),
+            dict: vec![0; TINFL_LZ_DICT_SIZE].into_boxed_slice(),
+            dict_pos: 0,
+            pending: Vec::new(),
+            pending_pos: 0,
+            input_pos: 0,
+            done: false,
+            failed: false,
+        }
+    }
+
+    /// Reset the verifier to the start of a new stream.
+    pub(crate) fn reset(&mut self) {
+        self.decomp.init();
+        self.dict_pos = 0;
+        self.pending.clear();
+        self.pending_pos = 0;
+        self.input_pos = 0;
+        self.done = false;
+        self.failed = false;
+    }
+
+    /// Decompress a chunk of compressed data produced by a call to the compressor with `input`,
+    /// and compare it against the input.
+    ///
+    /// Returns false if the data is not a valid continuation of the stream, or if it decompresses
+    /// to something other than the input.
+    pub(crate) fn check_output(&mut self, input: &[u8], data: &[u8], zlib: bool) -> bool {
+        if self.failed {
+            return false;
+        }
+        if data.is_empty() {
+            return true;
+        }
+        if self.done {
+            // There should be nothing after the end of the stream.
+            self.failed = true;
+            return false;
+        }
+
+        let flags = if zlib {
+            TINFL_FLAG_HAS_MORE_INPUT | TINFL_FLAG_PARSE_ZLIB_HEADER
+        } else {
+            TINFL_FLAG_HAS_MORE_INPUT
+        };
+
+        let mut in_pos = 0;
+        loop {
+            let (status, in_read, out_written) = decompress(
+                &mut self.decomp,
+                &data[in_pos..],
+                &mut self.dict,
+                self.dict_pos,
+                flags,
+            );
+            in_pos += in_read;
+
+            if !self.compare(input, out_written) {
+                self.failed = true;
+                return false;
+            }
+            self.dict_pos = (self.dict_pos + out_written) & (TINFL_LZ_DICT_SIZE - 1);
+
+            match status {
+                TINFLStatus::HasMoreOutput => (),
+                TINFLStatus::NeedsMoreInput => break,
+                TINFLStatus::Done => {
+                    self.done = true;
+                    self.failed = in_pos != data.len();
+                    break;
+                }
+                _ => {
+                    self.failed = true;
+                    break;
+                }
+            }
+        }
+
+        !self.failed
+    }
+
+    /// Compare the `len` bytes of output at `dict_pos` against the pending input and then the
+    /// input of the current call, and mark it as checked if it matches.
+    fn compare(&mut self, input: &[u8], len: usize) -> bool {
+        let mut out = &self.dict[self.dict_pos..self.dict_pos + len];
+        let pending = &self.pending[self.pending_pos..];
+        let from_pending = cmp::min(pending.len(), out.len());
+        if pending[..from_pending] != out[..from_pending] {
+            return false;
+        }
+        self.pending_pos += from_pending;
+        out = &out[from_pending..];
+
+        let end = self.input_pos + out.len();
+        if input.get(self.input_pos..end) != Some(out) {
+            return false;
+        }
+        self.input_pos = end;
+        true
+    }
+
+    /// End a call to the compressor, which consumed the first `consumed` bytes of `input`.
+    ///
+    /// Returns false if the output contained input that was not consumed.
+    pub(crate) fn end_call(&mut self, input: &[u8], consumed: usize) -> bool {
+        if self.input_pos > consumed {
+            self.failed = true;
+        }
+        if !self.failed {
+            // Keep the consumed input that has not been output yet for the next call.
+            self.pending.drain(..self.pending_pos);
+            self.pending_pos = 0;
+            self.pending
+                .extend_from_slice(&input[self.input_pos..consumed]);
+        }
+        self.input_pos = 0;
+        !self.failed
+    }
+
+    /// Mark the output as not matching the input.
+    pub(crate) fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    /// Returns true if the output has been found to not match the input.
+    pub(crate) fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Returns true if the end of the stream has been reached, and all the input was present in
+    /// the output.
+    pub(crate) fn finished(&self) -> bool {
+        !self.failed && self.done && self.pending_pos == self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Verifier;
+    use crate::deflate::compress_to_vec_zlib;
+
+    #[test]
+    fn detects_mismatch() {
+        let data = b"Some data that is repeated, some data that is repeated, and more.";
+        let compressed = compress_to_vec_zlib(data, 6);
+
+        let mut verifier = Verifier::new();
+        assert!(verifier.check_output(data, &compressed, true));
+        assert!(verifier.end_call(data, data.len()));
+        assert!(verifier.finished());
+
+        // Trailing garbage after the end of the stream.
+        assert!(!verifier.check_output(&[], &[0], true));
+
+        // Output that doesn't match the input.
+        verifier.reset();
+        assert!(!verifier.check_output(b"Some data that is different", &compressed, true));
+        assert!(!verifier.finished());
+
+        // Corrupted checksum.
+        let mut corrupted = compressed.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        verifier.reset();
+        assert!(!verifier.check_output(data, &corrupted, true));
+
+        // Output that contains input that was said to not be consumed.
+        verifier.reset();
+        assert!(verifier.check_output(data, &compressed[..compressed.len() - 4], true));
+        assert!(!verifier.end_call(data, data.len() - 10));
+
+        // Input consumed by one call and output by the next.
+        verifier.reset();
+        assert!(verifier.end_call(&data[..20], 20));
+        assert!(verifier.check_output(&data[20..], &compressed, true));
+        assert!(verifier.end_call(&data[20..], data.len() - 20));
+        assert!(verifier.finished());
+    }
+}
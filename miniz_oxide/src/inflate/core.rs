@@ -11,6 +11,7 @@ use self::output_buffer::OutputBuffer;
 pub const TINFL_LZ_DICT_SIZE: usize = 32_768;
 
 /// A struct containing huffman code lengths and the huffman code tree used by the decompressor.
+#[derive(Clone)]
 struct HuffmanTable {
     /// Length of the code at each index.
     pub code_size: [u8; MAX_HUFF_SYMBOLS_0],
@@ -146,6 +147,35 @@ pub mod inflate_flags {
     /// this will result in checksum failure (outside the unlikely event where the checksum happens
     /// to match anyway).
     pub const TINFL_FLAG_IGNORE_ADLER32: u32 = 64;
+
+    /// Accept zlib streams that use a preset dictionary.
+    ///
+    /// Without this flag, a zlib header with the FDICT flag set is rejected. With it, the
+    /// decompressor reads the id of the dictionary following the header and then stops, with
+    /// [`DecompressorOxide::dictionary_id`][super::DecompressorOxide::dictionary_id] returning
+    /// the id, until the dictionary has been placed in the output buffer before the current
+    /// position and [`DecompressorOxide::dictionary_added`][super::DecompressorOxide::dictionary_added]
+    /// has been called. Calls to [`decompress()`][super::decompress] in the meantime consume no
+    /// input and return [`TINFLStatus::NeedsMoreInput`][super::TINFLStatus::NeedsMoreInput].
+    ///
+    /// As the dictionary has to be in the output buffer, this can't be used together with
+    /// [`TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF`].
+    ///
+    /// NOTE: This flag does not exist in miniz as it does not support this and is a
+    /// custom addition for miniz_oxide.
+    pub const TINFL_FLAG_ALLOW_PRESET_DICT: u32 = 128;
+
+    /// Fail on matches that refer further back than the output written to the wrapping output
+    /// buffer since the decompressor was initialized, or further back than the size of the
+    /// buffer, instead of copying whatever the buffer holds there.
+    ///
+    /// Matches are always checked this way with [`TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF`].
+    /// This can't be used with [`TINFL_FLAG_ALLOW_PRESET_DICT`], as a dictionary placed in the
+    /// buffer is not counted as output.
+    ///
+    /// NOTE: This flag does not exist in miniz as it does not support this and is a
+    /// custom addition for miniz_oxide.
+    pub const TINFL_FLAG_CHECK_DISTANCE: u32 = 256;
 }
 
 use self::inflate_flags::*;
@@ -160,6 +190,7 @@ type BitBuffer = u32;
 
 /// Main decompression struct.
 ///
+#[derive(Clone)]
 pub struct DecompressorOxide {
     /// Current state of the decompressor.
     state: core::State,
@@ -171,6 +202,8 @@ pub struct DecompressorOxide {
     z_header1: u32,
     /// Adler32 checksum from the zlib header.
     z_adler32: u32,
+    /// Id of the preset dictionary from the zlib header.
+    z_dict_id: u32,
     /// 1 if the current block is the last block, 0 otherwise.
     finish: u32,
     /// The type of the current block.
@@ -193,6 +226,9 @@ pub struct DecompressorOxide {
     raw_header: [u8; 4],
     /// Huffman length codes.
     len_codes: [u8; MAX_HUFF_SYMBOLS_0 + MAX_HUFF_SYMBOLS_1 + 137],
+    /// Amount of output in the wrapping output buffer that matches can refer to, tracked with
+    /// [`TINFL_FLAG_CHECK_DISTANCE`].
+    out_history: usize,
 }
 
 impl DecompressorOxide {
@@ -206,6 +242,7 @@ impl DecompressorOxide {
     pub fn init(&mut self) {
         // The rest of the data is reset or overwritten when used.
         self.state = core::State::Start;
+        self.out_history = 0;
     }
 
     /// Returns the adler32 checksum of the currently decompressed data.
@@ -219,6 +256,50 @@ impl DecompressorOxide {
         }
     }
 
+    /// Returns the id of the preset dictionary if decompression has stopped to wait for it.
+    ///
+    /// See [`TINFL_FLAG_ALLOW_PRESET_DICT`].
+    #[inline]
+    pub fn dictionary_id(&self) -> Option<u32> {
+        if self.state == State::WaitDictionary {
+            Some(self.z_dict_id)
+        } else {
+            None
+        }
+    }
+
+    /// Continue decompressing after the preset dictionary has been placed in the output buffer.
+    ///
+    /// Does nothing if the decompressor is not waiting for a dictionary.
+    #[inline]
+    pub fn dictionary_added(&mut self) {
+        if self.state == State::WaitDictionary {
+            self.state = State::ReadBlockHeader;
+        }
+    }
+
+    /// Returns why decompression failed, or `None` if it has not failed.
+    ///
+    /// A mismatching adler32 checksum is not included, as it is only detected once the rest of
+    /// the data has been decompressed, and reported with [`TINFLStatus::Adler32Mismatch`].
+    #[inline]
+    pub fn failure_reason(&self) -> Option<FailureReason> {
+        let reason = match self.state {
+            BadZlibHeader => FailureReason::BadZlibHeader,
+            BlockTypeUnexpected => FailureReason::BadBlockType,
+            BadRawLength => FailureReason::BadStoredLength,
+            BadDistOrLiteralTableLength => FailureReason::BadTableSizes,
+            BadTotalSymbols => FailureReason::BadCodeLengths,
+            BadCodeSizeDistPrevLookup | BadCodeSizeSum => FailureReason::BadLengthRepeat,
+            InvalidCodeLen => FailureReason::InvalidCode,
+            InvalidLitlen => FailureReason::InvalidLiteralLength,
+            InvalidDist => FailureReason::InvalidDistance,
+            DistanceOutOfBounds => FailureReason::DistanceTooFarBack,
+            _ => return None,
+        };
+        Some(reason)
+    }
+
     /// Returns the adler32 that was read from the zlib header if it exists.
     #[inline]
     pub fn adler32_header(&self) -> Option<u32> {
@@ -240,6 +321,7 @@ impl Default for DecompressorOxide {
             z_header0: 0,
             z_header1: 0,
             z_adler32: 0,
+            z_dict_id: 0,
             finish: 0,
             block_type: 0,
             check_adler32: 0,
@@ -256,16 +338,46 @@ impl Default for DecompressorOxide {
             ],
             raw_header: [0; 4],
             len_codes: [0; MAX_HUFF_SYMBOLS_0 + MAX_HUFF_SYMBOLS_1 + 137],
+            out_history: 0,
         }
     }
 }
 
+/// The reason decompression failed, returned by [`DecompressorOxide::failure_reason`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum FailureReason {
+    /// The zlib header is invalid or uses an unsupported compression method or window size.
+    BadZlibHeader,
+    /// A block uses the reserved block type.
+    BadBlockType,
+    /// The length of a stored block does not match its one's complement.
+    BadStoredLength,
+    /// A dynamic block has too many literal/length or distance codes.
+    BadTableSizes,
+    /// The code lengths of a dynamic block do not make up a valid huffman code.
+    BadCodeLengths,
+    /// The code lengths of a dynamic block repeat a previous length where there is none, or
+    /// do not add up to the number of codes.
+    BadLengthRepeat,
+    /// The data contains a code that is not part of the huffman code.
+    InvalidCode,
+    /// The data contains one of the unused literal/length codes.
+    InvalidLiteralLength,
+    /// The data contains one of the unused distance codes.
+    InvalidDistance,
+    /// A match refers back to before the start of the data.
+    DistanceTooFarBack,
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 #[non_exhaustive]
 enum State {
     Start = 0,
     ReadZlibCmf,
     ReadZlibFlg,
+    ReadZlibDictId,
+    WaitDictionary,
     ReadBlockHeader,
     BlockTypeNoCompression,
     RawHeader,
@@ -440,12 +552,13 @@ fn fill_bit_buffer(l: &mut LocalVars, in_iter: &mut slice::Iter<u8>) {
 /// See https://tools.ietf.org/html/rfc1950
 #[inline]
 fn validate_zlib_header(cmf: u32, flg: u32, flags: u32, mask: usize) -> Action {
+    // If this flag is set, a dictionary was used for this zlib compressed data.
+    // This is not supported by miniz, and only accepted by miniz-oxide if requested.
+    let preset_dict = (flg & 0b0010_0000) != 0;
     let mut failed =
     // cmf + flg should be divisible by 31.
         (((cmf * 256) + flg) % 31 != 0) ||
-    // If this flag is set, a dictionary was used for this zlib compressed data.
-    // This is currently not supported by miniz or miniz-oxide
-        ((flg & 0b0010_0000) != 0) ||
+        (preset_dict && (flags & TINFL_FLAG_ALLOW_PRESET_DICT) == 0) ||
     // Compression method. Only 8(DEFLATE) is defined by the standard.
         ((cmf & 15) != 8);
 
@@ -460,6 +573,8 @@ fn validate_zlib_header(cmf: u32, flg: u32, flags: u32, mask: usize) -> Action {
 
     if failed {
         Action::Jump(BadZlibHeader)
+    } else if preset_dict {
+        Action::Jump(ReadZlibDictId)
     } else {
         Action::Jump(ReadBlockHeader)
     }
@@ -911,6 +1026,17 @@ fn apply_match(
     }
 }
 
+/// Returns true if a match with distance `dist` at `position` refers further back than the
+/// output that is available, if that is checked.
+///
+/// `history` is the amount of earlier output before the start of the output buffer, which is
+/// only there if the buffer wraps around.
+#[inline]
+fn dist_too_far(dist: u32, position: usize, history: usize, out_len: usize, flags: u32) -> bool {
+    flags & (TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF | TINFL_FLAG_CHECK_DISTANCE) != 0
+        && dist as usize > cmp::min(position + history, out_len)
+}
+
 /// Fast inner decompression loop which is run  while there is at least
 /// 259 bytes left in the output buffer, and at least 6 bytes left in the input buffer
 /// (The maximum one match would need + 1).
@@ -927,6 +1053,7 @@ fn decompress_fast(
     flags: u32,
     local_vars: &mut LocalVars,
     out_buf_size_mask: usize,
+    history: usize,
 ) -> (TINFLStatus, State) {
     // Make a local copy of the most used variables, to avoid having to update and read from values
     // in a random memory location and to encourage more register use.
@@ -1050,9 +1177,7 @@ fn decompress_fast(
             }
 
             let position = out_buf.position();
-            if l.dist as usize > out_buf.position()
-                && (flags & TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF != 0)
-            {
+            if dist_too_far(l.dist, position, history, out_buf.get_ref().len(), flags) {
                 // We encountered a distance that refers a position before
                 // the start of the decoded data, so we can't continue.
                 state.begin(DistanceOutOfBounds);
@@ -1135,6 +1260,14 @@ pub fn decompress(
         return (TINFLStatus::BadParam, 0, 0);
     }
 
+    // Amount of output before the start of the output buffer that matches can refer to when
+    // they are checked.
+    let history = if flags & TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF != 0 {
+        0
+    } else {
+        r.out_history.saturating_sub(out_pos)
+    };
+
     let mut in_iter = in_buf.iter();
 
     let mut state = r.state;
@@ -1183,6 +1316,21 @@ pub fn decompress(
                 })
             }),
 
+            ReadZlibDictId => generate_state!(state, 'state_machine, {
+                if l.counter < 4 {
+                    read_byte(&mut in_iter, flags, |byte| {
+                        r.z_dict_id = (r.z_dict_id << 8) | u32::from(byte);
+                        l.counter += 1;
+                        Action::None
+                    })
+                } else {
+                    Action::Jump(WaitDictionary)
+                }
+            }),
+
+            // Stop until the dictionary has been added to the output buffer.
+            WaitDictionary => break TINFLStatus::NeedsMoreInput,
+
             // Read the block header and jump to the relevant section depending on the block type.
             ReadBlockHeader => generate_state!(state, 'state_machine, {
                 read_bits(&mut l, 3, &mut in_iter, flags, |l, bits| {
@@ -1446,6 +1594,7 @@ pub fn decompress(
                         flags,
                         &mut l,
                         out_buf_size_mask,
+                        history,
                     );
 
                     state = new_state;
@@ -1580,9 +1729,9 @@ pub fn decompress(
             }),
 
             HuffDecodeOuterLoop2 => generate_state!(state, 'state_machine, {
-                if l.dist as usize > out_buf.position() &&
-                    (flags & TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF != 0)
-                {
+                if dist_too_far(
+                    l.dist, out_buf.position(), history, out_buf.get_ref().len(), flags
+                ) {
                     // We encountered a distance that refers a position before
                     // the start of the decoded data, so we can't continue.
                     Action::Jump(DistanceOutOfBounds)
@@ -1709,11 +1858,13 @@ pub fn decompress(
     };
 
     // Make sure HasMoreOutput overrides NeedsMoreInput if the output buffer is full.
-    // (Unless the missing input is the adler32 value in which case we don't need to write anything.)
+    // (Unless the missing input is the adler32 value or we are waiting for a dictionary, in which
+    // case we don't need to write anything.)
     // TODO: May want to see if we can do this in a better way.
     if status == TINFLStatus::NeedsMoreInput
         && out_buf.bytes_left() == 0
         && state != State::ReadAdler32
+        && state != State::WaitDictionary
     {
         status = TINFLStatus::HasMoreOutput
     }
@@ -1726,6 +1877,7 @@ pub fn decompress(
     r.num_extra = l.num_extra;
 
     r.bit_buf &= ((1 as BitBuffer) << r.num_bits) - 1;
+    r.out_history = cmp::min(history + out_buf.position(), out_buf.get_ref().len());
 
     // If this is a zlib stream, and update the adler32 checksum with the decompressed bytes if
     // requested.
@@ -1881,6 +2033,10 @@ mod test {
             decompress(&mut r, input, &mut output_buf, 0, flags);
         assert_eq!(expected_status, d_status);
         assert_eq!(expected_state, r.state);
+        assert_eq!(
+            d_status == TINFLStatus::Failed,
+            r.failure_reason().is_some()
+        );
     }
 
     #[test]
@@ -0,0 +1,277 @@
+//! A reusable decompressor with a builder for its settings.
+
+use crate::alloc::boxed::Box;
+use crate::alloc::vec::Vec;
+
+use crate::inflate::core::{decompress, inflate_flags};
+use crate::inflate::stream::{inflate, InflateState, MinReset};
+use crate::inflate::TINFLStatus;
+use crate::{DataFormat, MZFlush, StreamResult};
+
+/// Builder for a [`Decompressor`].
+///
+/// By default, the decompressor expects a zlib wrapper, checks the adler32 checksum and has no
+/// output limit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct DecompressorBuilder {
+    format: DataFormat,
+    output_limit: usize,
+    ignore_checksum: bool,
+}
+
+impl DecompressorBuilder {
+    /// Create a builder using the default settings.
+    pub fn new() -> DecompressorBuilder {
+        DecompressorBuilder {
+            format: DataFormat::Zlib,
+            output_limit: usize::max_value(),
+            ignore_checksum: false,
+        }
+    }
+
+    /// Set how the compressed data is wrapped.
+    pub fn format(mut self, format: DataFormat) -> DecompressorBuilder {
+        self.format = format;
+        self
+    }
+
+    /// Set the maximum number of bytes [`Decompressor::decompress_into`] may output.
+    pub fn output_limit(mut self, output_limit: usize) -> DecompressorBuilder {
+        self.output_limit = output_limit;
+        self
+    }
+
+    /// Don't compute and check the adler32 checksum of zlib wrapped data.
+    pub fn ignore_checksum(mut self, ignore_checksum: bool) -> DecompressorBuilder {
+        self.ignore_checksum = ignore_checksum;
+        self
+    }
+
+    /// Create the decompressor.
+    pub fn build(self) -> Decompressor {
+        let format = if self.ignore_checksum && self.format == DataFormat::Zlib {
+            DataFormat::ZLibIgnoreChecksum
+        } else {
+            self.format
+        };
+
+        Decompressor {
+            state: InflateState::new_boxed(format),
+            format,
+            output_limit: self.output_limit,
+            finished: false,
+        }
+    }
+}
+
+impl Default for DecompressorBuilder {
+    fn default() -> DecompressorBuilder {
+        DecompressorBuilder::new()
+    }
+}
+
+/// A decompressor that can be reused for many inputs without allocating new buffers.
+///
+/// ```
+/// use miniz_oxide::deflate::compress_to_vec_zlib;
+/// use miniz_oxide::inflate::Decompressor;
+///
+/// let mut decompressor = Decompressor::builder().output_limit(1024).build();
+/// let mut output = Vec::new();
+/// for input in [&b"first input"[..], &b"second input"[..]].iter() {
+///     let compressed = compress_to_vec_zlib(input, 6);
+///     output.clear();
+///     decompressor.decompress_into(&compressed, &mut output).unwrap();
+///     assert_eq!(&output[..], &input[..]);
+/// }
+/// ```
+pub struct Decompressor {
+    state: Box<InflateState>,
+    format: DataFormat,
+    output_limit: usize,
+    /// Whether the last use was a call to `decompress_into`, which ends the stream.
+    finished: bool,
+}
+
+impl Decompressor {
+    /// Create a builder for a decompressor.
+    pub fn builder() -> DecompressorBuilder {
+        DecompressorBuilder::new()
+    }
+
+    /// Decompress all of `input` as a separate stream, appending the output to `output`.
+    ///
+    /// Any stream in progress is discarded first, and the decompressor is ready to start a new
+    /// stream afterwards. Data following the end of the compressed stream is ignored.
+    ///
+    /// Returns the number of bytes appended to `output`. On failure, nothing is appended. If the
+    /// output would be larger than the output limit, [`TINFLStatus::HasMoreOutput`] is returned.
+    pub fn decompress_into(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<usize, TINFLStatus> {
+        self.state.reset_as(MinReset);
+        self.finished = true;
+
+        let mut flags = inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        match self.format {
+            DataFormat::Zlib => {
+                flags |= inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+                    | inflate_flags::TINFL_FLAG_COMPUTE_ADLER32
+            }
+            DataFormat::ZLibIgnoreChecksum => {
+                flags |= inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+                    | inflate_flags::TINFL_FLAG_IGNORE_ADLER32
+            }
+            DataFormat::Raw => flags |= inflate_flags::TINFL_FLAG_IGNORE_ADLER32,
+        }
+
+        let start = output.len();
+        let limit = self.output_limit;
+        let mut out_len = input.len().saturating_mul(2).min(limit);
+        output.resize(start + out_len, 0);
+
+        let mut in_pos = 0;
+        let mut out_pos = 0;
+        let status = loop {
+            let (status, in_consumed, out_consumed) = decompress(
+                self.state.decompressor(),
+                &input[in_pos..],
+                &mut output[start..],
+                out_pos,
+                flags,
+            );
+            in_pos += in_consumed;
+            out_pos += out_consumed;
+
+            match status {
+                TINFLStatus::HasMoreOutput if out_len < limit => {
+                    out_len = out_len.saturating_mul(2).max(64).min(limit);
+                    output.resize(start + out_len, 0);
+                }
+                _ => break status,
+            }
+        };
+
+        if status == TINFLStatus::Done {
+            output.truncate(start + out_pos);
+            Ok(out_pos)
+        } else {
+            output.truncate(start);
+            Err(status)
+        }
+    }
+
+    /// Decompress from `input` to `output`, as part of a stream that may be split over several
+    /// calls.
+    ///
+    /// See [`inflate`](crate::inflate::stream::inflate) for details. The output limit does not
+    /// apply here.
+    pub fn decompress(&mut self, input: &[u8], output: &mut [u8], flush: MZFlush) -> StreamResult {
+        if self.finished {
+            self.reset();
+        }
+        inflate(&mut self.state, input, output, flush)
+    }
+
+    /// Reset the decompressor to start a new stream, keeping the settings and buffers.
+    ///
+    /// The window buffer is not cleared.
+    pub fn reset(&mut self) {
+        self.state.reset_as(MinReset);
+        self.finished = false;
+    }
+
+    /// Access the inner stream state.
+    pub fn inner(&mut self) -> &mut InflateState {
+        &mut self.state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Decompressor;
+    use crate::deflate::{compress_to_vec, compress_to_vec_zlib};
+    use crate::inflate::TINFLStatus;
+    use crate::{DataFormat, MZFlush, MZStatus};
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn decompress_into() {
+        let data = include_bytes!("../../tests/test_data/numbers.txt");
+        let compressed = compress_to_vec_zlib(data, 6);
+
+        let mut decompressor = Decompressor::builder().build();
+        let mut output = vec![1, 2, 3];
+        for _ in 0..3 {
+            output.truncate(3);
+            let len = decompressor
+                .decompress_into(&compressed, &mut output)
+                .unwrap();
+            assert_eq!(len, data.len());
+            assert_eq!(&output[..3], &[1, 2, 3]);
+            assert!(output[3..] == data[..]);
+        }
+
+        let mut corrupted = compressed.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            decompressor.decompress_into(&corrupted, &mut output),
+            Err(TINFLStatus::Adler32Mismatch)
+        );
+        assert_eq!(output.len(), 3 + data.len());
+
+        let mut ignoring = Decompressor::builder().ignore_checksum(true).build();
+        assert_eq!(
+            ignoring.decompress_into(&corrupted, &mut Vec::new()),
+            Ok(data.len())
+        );
+
+        let mut limited = Decompressor::builder()
+            .format(DataFormat::Raw)
+            .output_limit(data.len() - 1)
+            .build();
+        let raw = compress_to_vec(data, 6);
+        let mut output = Vec::new();
+        assert_eq!(
+            limited.decompress_into(&raw, &mut output),
+            Err(TINFLStatus::HasMoreOutput)
+        );
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn streaming() {
+        let data = include_bytes!("../../tests/test_data/numbers.txt");
+        let compressed = compress_to_vec_zlib(data, 6);
+        let mut decompressor = Decompressor::builder().build();
+        decompressor
+            .decompress_into(&compressed, &mut Vec::new())
+            .unwrap();
+
+        for _ in 0..2 {
+            let mut output = vec![0; data.len()];
+            let mut in_pos = 0;
+            let mut out_pos = 0;
+            loop {
+                let in_end = core::cmp::min(in_pos + 100, compressed.len());
+                let out_end = core::cmp::min(out_pos + 1000, output.len());
+                let res = decompressor.decompress(
+                    &compressed[in_pos..in_end],
+                    &mut output[out_pos..out_end],
+                    MZFlush::None,
+                );
+                in_pos += res.bytes_consumed;
+                out_pos += res.bytes_written;
+                if res.status == Ok(MZStatus::StreamEnd) {
+                    break;
+                }
+                res.status.unwrap();
+            }
+            assert!(output[..out_pos] == data[..]);
+            decompressor.reset();
+        }
+    }
+}
@@ -3,13 +3,17 @@
 #[cfg(feature = "with-alloc")]
 use crate::alloc::{boxed::Box, vec, vec::Vec};
 use ::core::usize;
-#[cfg(all(feature = "std", feature = "with-alloc"))]
+#[cfg(feature = "std")]
 use std::error::Error;
 
 pub mod core;
+#[cfg(feature = "with-alloc")]
+mod decompressor;
 mod output_buffer;
 pub mod stream;
 use self::core::*;
+#[cfg(feature = "with-alloc")]
+pub use self::decompressor::{Decompressor, DecompressorBuilder};
 
 const TINFL_STATUS_FAILED_CANNOT_MAKE_PROGRESS: i32 = -4;
 const TINFL_STATUS_BAD_PARAM: i32 = -3;
@@ -222,6 +226,111 @@ fn decompress_to_vec_inner(
     }
 }
 
+/// Error returned by the exact-size decompression functions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ExactSizeError {
+    /// The stream ended after producing fewer bytes than expected. Contains the number of bytes
+    /// that were produced.
+    TooShort(usize),
+    /// The stream produces more bytes than expected.
+    TooLong,
+    /// Decompression failed. See [TINFLStatus] for details.
+    Failed(TINFLStatus),
+}
+
+impl ::core::fmt::Display for ExactSizeError {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        match *self {
+            ExactSizeError::TooShort(size) => write!(
+                f,
+                "Decompressed data is shorter than expected ({} bytes)",
+                size
+            ),
+            ExactSizeError::TooLong => f.write_str("Decompressed data is longer than expected"),
+            ExactSizeError::Failed(TINFLStatus::Adler32Mismatch) => {
+                f.write_str("Adler32 checksum mismatch")
+            }
+            ExactSizeError::Failed(TINFLStatus::FailedCannotMakeProgress)
+            | ExactSizeError::Failed(TINFLStatus::NeedsMoreInput) => {
+                f.write_str("Truncated input stream")
+            }
+            ExactSizeError::Failed(_) => f.write_str("Invalid input data"),
+        }
+    }
+}
+
+/// Implement Error trait only if std feature is requested as it requires std.
+#[cfg(feature = "std")]
+impl Error for ExactSizeError {}
+
+/// Decompress the deflate-encoded data in `input` into `output`, which has to be exactly the size
+/// of the decompressed data.
+///
+/// This is meant for formats that store the size of the uncompressed data, like ZIP or PNG. As
+/// the whole output is written to `output` directly, no additional memory is allocated.
+///
+/// Returns [`ExactSizeError::TooShort`] if the stream ends before `output` is filled,
+/// [`ExactSizeError::TooLong`] if the stream contains more data than fits in `output`, and
+/// [`ExactSizeError::Failed`] if decompression fails for any other reason.
+#[inline]
+pub fn decompress_exact(input: &[u8], output: &mut [u8]) -> Result<(), ExactSizeError> {
+    decompress_exact_inner(input, output, 0)
+}
+
+/// Decompress the deflate-encoded data (with a zlib wrapper) in `input` into `output`, which has
+/// to be exactly the size of the decompressed data.
+///
+/// See [`decompress_exact`] for details.
+#[inline]
+pub fn decompress_zlib_exact(input: &[u8], output: &mut [u8]) -> Result<(), ExactSizeError> {
+    decompress_exact_inner(input, output, inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER)
+}
+
+/// Decompress the deflate-encoded data in `input` to a vector of exactly `size` bytes.
+///
+/// Unlike [`decompress_to_vec_with_limit`], the vector is allocated with the final size up front
+/// rather than being grown as needed. As `size` usually comes from the container the data is
+/// stored in, it should be checked to be reasonable before calling this.
+///
+/// See [`decompress_exact`] for the errors returned.
+#[inline]
+#[cfg(feature = "with-alloc")]
+pub fn decompress_to_vec_exact(input: &[u8], size: usize) -> Result<Vec<u8>, ExactSizeError> {
+    let mut ret = vec![0; size];
+    decompress_exact_inner(input, &mut ret, 0)?;
+    Ok(ret)
+}
+
+/// Decompress the deflate-encoded data (with a zlib wrapper) in `input` to a vector of exactly
+/// `size` bytes.
+///
+/// See [`decompress_to_vec_exact`] for details.
+#[inline]
+#[cfg(feature = "with-alloc")]
+pub fn decompress_to_vec_zlib_exact(input: &[u8], size: usize) -> Result<Vec<u8>, ExactSizeError> {
+    let mut ret = vec![0; size];
+    decompress_exact_inner(input, &mut ret, inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER)?;
+    Ok(ret)
+}
+
+/// Backend of the exact-size decompression functions.
+fn decompress_exact_inner(
+    input: &[u8],
+    output: &mut [u8],
+    flags: u32,
+) -> Result<(), ExactSizeError> {
+    let flags = flags | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    let mut decomp = DecompressorOxide::new();
+
+    let (status, _, out_pos) = decompress(&mut decomp, input, output, 0, flags);
+    match status {
+        TINFLStatus::Done if out_pos == output.len() => Ok(()),
+        TINFLStatus::Done => Err(ExactSizeError::TooShort(out_pos)),
+        TINFLStatus::HasMoreOutput => Err(ExactSizeError::TooLong),
+        _ => Err(ExactSizeError::Failed(status)),
+    }
+}
+
 /// Decompress one or more source slices from an iterator into the output slice.
 ///
 /// * On success, returns the number of bytes that were written.
@@ -274,11 +383,106 @@ pub fn decompress_slice_iter_to_slice<'out, 'inp>(
     Err(TINFLStatus::FailedCannotMakeProgress)
 }
 
+/// Error returned by [`decompress_with_callbacks`] when it stops before the end of the stream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CallbackError {
+    /// The input callback had no more data.
+    InputEnded,
+    /// The output callback returned `false`.
+    OutputFailed,
+    /// Decompression failed. See [TINFLStatus] for details.
+    Failed(TINFLStatus),
+}
+
+impl ::core::fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        match *self {
+            CallbackError::InputEnded => f.write_str("Truncated input stream"),
+            CallbackError::OutputFailed => f.write_str("Writing the output failed"),
+            CallbackError::Failed(TINFLStatus::Adler32Mismatch) => {
+                f.write_str("Adler32 checksum mismatch")
+            }
+            CallbackError::Failed(_) => f.write_str("Invalid input data"),
+        }
+    }
+}
+
+/// Implement Error trait only if std feature is requested as it requires std.
+#[cfg(feature = "std")]
+impl Error for CallbackError {}
+
+/// Decompress a stream read through a callback, using `window` as the only output buffer and
+/// passing the output to another callback.
+///
+/// The data in `input` is decompressed first, then `read` is called for more each time it has
+/// all been used. `read` returns an empty slice at the end of the input. The output is passed to
+/// `write` each time `window` is full and when decompression stops, which returns `false` to stop
+/// decompression. When this returns, `input` holds the input that wasn't used.
+///
+/// `window` has to have a power of two size, at least as large as the window used to compress
+/// the data, which is 32 KiB for data compressed by this crate. Data that refers further back
+/// than that fails with [`TINFLStatus::Failed`]. `flags` are the
+/// [inflate flags](core::inflate_flags) to use, except for the ones for the output buffer, the
+/// amount of input and preset dictionaries. Any stream in progress in `decomp` is discarded
+/// first.
+///
+/// This avoids copying the data into a separate dictionary when the caller needs to buffer the
+/// output anyway, like when writing it to a file.
+pub fn decompress_with_callbacks<'inp>(
+    decomp: &mut DecompressorOxide,
+    window: &mut [u8],
+    input: &mut &'inp [u8],
+    mut read: impl FnMut() -> &'inp [u8],
+    mut write: impl FnMut(&[u8]) -> bool,
+    flags: u32,
+) -> Result<(), CallbackError> {
+    let flags = (flags
+        & !(inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF
+            | inflate_flags::TINFL_FLAG_ALLOW_PRESET_DICT))
+        | inflate_flags::TINFL_FLAG_HAS_MORE_INPUT
+        | inflate_flags::TINFL_FLAG_CHECK_DISTANCE;
+    decomp.init();
+
+    let mut out_pos = 0;
+    let result = loop {
+        let (status, in_consumed, out_consumed) = decompress(decomp, input, window, out_pos, flags);
+        *input = &input[in_consumed..];
+        out_pos += out_consumed;
+
+        if out_pos == window.len() {
+            if !write(window) {
+                return Err(CallbackError::OutputFailed);
+            }
+            out_pos = 0;
+        }
+
+        match status {
+            TINFLStatus::Done => break Ok(()),
+            TINFLStatus::HasMoreOutput => (),
+            TINFLStatus::NeedsMoreInput => {
+                *input = read();
+                if input.is_empty() {
+                    break Err(CallbackError::InputEnded);
+                }
+            }
+            _ => break Err(CallbackError::Failed(status)),
+        }
+    };
+
+    if out_pos > 0 && !write(&window[..out_pos]) {
+        return Err(CallbackError::OutputFailed);
+    }
+    result
+}
+
 #[cfg(test)]
 mod test {
     use super::{
-        decompress_slice_iter_to_slice, decompress_to_vec_zlib, decompress_to_vec_zlib_with_limit,
-        DecompressError, TINFLStatus,
+        decompress_slice_iter_to_slice, decompress_to_vec_exact, decompress_to_vec_zlib,
+        decompress_to_vec_zlib_exact, decompress_to_vec_zlib_with_limit, decompress_with_callbacks,
+        decompress_zlib_exact, CallbackError, DecompressError, DecompressorOxide, ExactSizeError,
+        FailureReason, TINFLStatus,
     };
     const ENCODED: [u8; 20] = [
         120, 156, 243, 72, 205, 201, 201, 215, 81, 168, 202, 201, 76, 82, 4, 0, 27, 101, 4, 19,
@@ -308,6 +512,40 @@ mod test {
         }
     }
 
+    #[test]
+    fn decompress_exact_size() {
+        let res = decompress_to_vec_zlib_exact(&ENCODED[..], 12).unwrap();
+        assert_eq!(res.as_slice(), &b"Hello, zlib!"[..]);
+
+        let mut out = [0_u8; 12];
+        assert_eq!(decompress_zlib_exact(&ENCODED[..], &mut out), Ok(()));
+        assert_eq!(&out[..], &b"Hello, zlib!"[..]);
+
+        assert_eq!(
+            decompress_to_vec_zlib_exact(&ENCODED[..], 11),
+            Err(ExactSizeError::TooLong)
+        );
+        assert_eq!(
+            decompress_to_vec_zlib_exact(&ENCODED[..], 13),
+            Err(ExactSizeError::TooShort(12))
+        );
+        assert_eq!(
+            decompress_to_vec_zlib_exact(&ENCODED[..ENCODED.len() - 1], 12),
+            Err(ExactSizeError::Failed(
+                TINFLStatus::FailedCannotMakeProgress
+            ))
+        );
+        // Raw deflate data without the zlib header and adler32 checksum.
+        assert_eq!(
+            decompress_to_vec_exact(&ENCODED[2..ENCODED.len() - 4], 12).unwrap(),
+            &b"Hello, zlib!"[..]
+        );
+        assert_eq!(
+            decompress_to_vec_exact(&ENCODED[2..ENCODED.len() - 4], 0),
+            Err(ExactSizeError::TooLong)
+        );
+    }
+
     #[test]
     fn test_decompress_slice_iter_to_slice() {
         // one slice
@@ -334,4 +572,120 @@ mod test {
         let r = decompress_slice_iter_to_slice(&mut out, ENCODED.chunks(7), true, false);
         assert!(r.is_err());
     }
+
+    #[test]
+    fn with_callbacks() {
+        use crate::alloc::vec::Vec;
+        use crate::deflate::compress_to_vec;
+
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251 ^ i / 997) as u8).collect();
+        let encoded = compress_to_vec(&data, 6);
+        let mut decomp = DecompressorOxide::new();
+        let mut window = [0u8; 32 * 1024];
+
+        for &chunk_size in &[1, 1000, encoded.len()] {
+            let mut chunks = encoded.chunks(chunk_size);
+            let mut input = &[][..];
+            let mut output = Vec::new();
+            let mut writes = 0;
+            let res = decompress_with_callbacks(
+                &mut decomp,
+                &mut window,
+                &mut input,
+                || chunks.next().unwrap_or(&[]),
+                |out| {
+                    output.extend_from_slice(out);
+                    writes += 1;
+                    true
+                },
+                0,
+            );
+            assert_eq!(res, Ok(()));
+            assert!(output == data);
+            assert_eq!(writes, 4);
+            assert!(input.is_empty() && chunks.next().is_none());
+        }
+
+        // Data after the stream is left in the input.
+        let mut with_trailer = encoded.clone();
+        with_trailer.extend_from_slice(b"trailer");
+        let mut input = &with_trailer[..];
+        let res =
+            decompress_with_callbacks(&mut decomp, &mut window, &mut input, || &[], |_| true, 0);
+        assert_eq!(res, Ok(()));
+        assert_eq!(input, b"trailer");
+
+        let mut input = &encoded[..encoded.len() / 2];
+        let mut written = 0;
+        let res = decompress_with_callbacks(
+            &mut decomp,
+            &mut window,
+            &mut input,
+            || &[],
+            |out| {
+                written += out.len();
+                true
+            },
+            0,
+        );
+        assert_eq!(res, Err(CallbackError::InputEnded));
+        assert!(written > 0);
+
+        let mut input = &encoded[..];
+        let res =
+            decompress_with_callbacks(&mut decomp, &mut window, &mut input, || &[], |_| false, 0);
+        assert_eq!(res, Err(CallbackError::OutputFailed));
+
+        let mut input = &[0xff; 10][..];
+        let res =
+            decompress_with_callbacks(&mut decomp, &mut window, &mut input, || &[], |_| true, 0);
+        assert_eq!(res, Err(CallbackError::Failed(TINFLStatus::Failed)));
+    }
+
+    #[test]
+    fn with_callbacks_small_window() {
+        use crate::alloc::vec::Vec;
+        use crate::deflate::compress_to_vec;
+
+        // Matches in the repeats refer 1000 bytes back.
+        let block: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 253) as u8).collect();
+        let data = [&block[..], &block, &block].concat();
+        let encoded = compress_to_vec(&data, 6);
+        let mut decomp = DecompressorOxide::new();
+
+        let mut input = &encoded[..];
+        let mut output = Vec::new();
+        let mut window = [0u8; 1024];
+        let res = decompress_with_callbacks(
+            &mut decomp,
+            &mut window,
+            &mut input,
+            || &[],
+            |out| {
+                output.extend_from_slice(out);
+                true
+            },
+            0,
+        );
+        assert_eq!(res, Ok(()));
+        assert!(output == data);
+
+        let mut input = &encoded[..];
+        let mut window = [0u8; 256];
+        let res =
+            decompress_with_callbacks(&mut decomp, &mut window, &mut input, || &[], |_| true, 0);
+        assert_eq!(res, Err(CallbackError::Failed(TINFLStatus::Failed)));
+        assert_eq!(
+            decomp.failure_reason(),
+            Some(FailureReason::DistanceTooFarBack)
+        );
+
+        // A match referring to before the start of the output fails even if the window is large
+        // enough: a fixed block with the literal 0 followed by a match of length 3, distance 2.
+        let mut input = &[0x63, 0x00, 0x42, 0x00][..];
+        let mut window = [0u8; 256];
+        let res =
+            decompress_with_callbacks(&mut decomp, &mut window, &mut input, || &[], |_| true, 0);
+        assert_eq!(res, Err(CallbackError::Failed(TINFLStatus::Failed)));
+    }
 }
@@ -7,6 +7,7 @@ use core::{cmp, mem};
 
 use crate::inflate::core::{decompress, inflate_flags, DecompressorOxide, TINFL_LZ_DICT_SIZE};
 use crate::inflate::TINFLStatus;
+use crate::shared::{update_adler32, MZ_ADLER32_INIT};
 use crate::{DataFormat, MZError, MZFlush, MZResult, MZStatus, StreamResult};
 
 /// Tag that determines reset policy of [InflateState](struct.InflateState.html)
@@ -25,6 +26,7 @@ impl ResetPolicy for MinReset {
         state.decompressor().init();
         state.dict_ofs = 0;
         state.dict_avail = 0;
+        state.history = 0;
         state.first_call = true;
         state.has_flushed = false;
         state.last_status = TINFLStatus::NeedsMoreInput;
@@ -57,6 +59,7 @@ impl ResetPolicy for FullReset {
 
 /// A struct that compbines a decompressor with extra data for streaming decompression.
 ///
+#[derive(Clone)]
 pub struct InflateState {
     /// Inner decompressor struct
     decomp: DecompressorOxide,
@@ -71,6 +74,9 @@ pub struct InflateState {
     dict_ofs: usize,
     /// How many bytes of data to be flushed is there currently in the buffer?
     dict_avail: usize,
+    /// How many bytes before `dict_ofs` have been flushed or set as a preset dictionary, up to
+    /// the size of the buffer. This is the data later matches can refer back to.
+    history: usize,
 
     first_call: bool,
     has_flushed: bool,
@@ -88,6 +94,7 @@ impl Default for InflateState {
             dict: [0; TINFL_LZ_DICT_SIZE],
             dict_ofs: 0,
             dict_avail: 0,
+            history: 0,
             first_call: true,
             has_flushed: false,
             data_format: DataFormat::Raw,
@@ -145,6 +152,73 @@ impl InflateState {
         b
     }
 
+    /// Set a preset dictionary, data that the compressed data can refer back to as if it had
+    /// been decompressed right before it.
+    ///
+    /// For zlib streams, this has to be done after [`inflate`] has returned
+    /// [`MZStatus::NeedDict`], and the adler32 checksum of the dictionary has to match the id
+    /// in the stream, which is available through
+    /// [`DecompressorOxide::dictionary_id`](crate::inflate::core::DecompressorOxide::dictionary_id).
+    /// For raw deflate streams, it can be done whenever there is no decompressed data waiting
+    /// to be output.
+    ///
+    /// Only the last 32 KiB of the dictionary are used.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MZError::Stream`] if a dictionary can't be set at this point, and
+    /// [`MZError::Data`] if the dictionary is not the one the zlib stream was compressed with.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> MZResult {
+        match self.decomp.dictionary_id() {
+            Some(id) => {
+                if update_adler32(MZ_ADLER32_INIT, dictionary) != id {
+                    return Err(MZError::Data);
+                }
+            }
+            None => {
+                if self.data_format != DataFormat::Raw || self.dict_avail != 0 {
+                    return Err(MZError::Stream);
+                }
+            }
+        }
+
+        // Place the dictionary right before the current position in the wrapping buffer.
+        let dictionary = &dictionary[dictionary.len().saturating_sub(TINFL_LZ_DICT_SIZE)..];
+        let start = self.dict_ofs.wrapping_sub(dictionary.len()) & (TINFL_LZ_DICT_SIZE - 1);
+        let first = cmp::min(dictionary.len(), TINFL_LZ_DICT_SIZE - start);
+        self.dict[start..start + first].copy_from_slice(&dictionary[..first]);
+        self.dict[..dictionary.len() - first].copy_from_slice(&dictionary[first..]);
+        self.history = cmp::min(self.history + dictionary.len(), TINFL_LZ_DICT_SIZE);
+
+        // The output buffer doesn't contain the dictionary, so it can't be used directly.
+        self.first_call = false;
+        self.decomp.dictionary_added();
+        Ok(MZStatus::Ok)
+    }
+
+    /// Get the length of the data later compressed data can refer back to, which is the
+    /// last up to 32 KiB of the output and any preset dictionary.
+    ///
+    /// The output of a call to [`inflate`] that decompressed the whole stream directly to the
+    /// output buffer with [`MZFlush::Finish`] is not included.
+    pub fn dictionary_len(&self) -> usize {
+        self.history
+    }
+
+    /// Copy the data later compressed data can refer back to, as described in
+    /// [`dictionary_len`](InflateState::dictionary_len), to the start of `dictionary`.
+    ///
+    /// If `dictionary` is too small, only the most recent data that fits is copied.
+    /// Returns the number of bytes copied.
+    pub fn get_dictionary(&self, dictionary: &mut [u8]) -> usize {
+        let len = cmp::min(self.history, dictionary.len());
+        let start = self.dict_ofs.wrapping_sub(len) & (TINFL_LZ_DICT_SIZE - 1);
+        let first = cmp::min(len, TINFL_LZ_DICT_SIZE - start);
+        dictionary[..first].copy_from_slice(&self.dict[start..start + first]);
+        dictionary[first..len].copy_from_slice(&self.dict[..len - first]);
+        len
+    }
+
     #[inline]
     /// Reset the decompressor without re-allocating memory, using the given
     /// data format.
@@ -170,6 +244,11 @@ impl InflateState {
 /// [`MZStatus::StreamEnd`] anyway.  Other values either have no effect or cause errors.  It's
 /// likely that you'll almost always just want to use [`MZFlush::None`].
 ///
+/// # Preset dictionaries
+///
+/// Returns [`MZStatus::NeedDict`] if the zlib stream was compressed with a preset dictionary,
+/// which then has to be set with [`InflateState::set_dictionary`] before continuing.
+///
 /// # Errors
 ///
 /// Returns [`MZError::Buf`] if the size of the `output` slice is empty or no progress was made due
@@ -206,7 +285,8 @@ pub fn inflate(
     if (state.data_format == DataFormat::Zlib)
         | (state.data_format == DataFormat::ZLibIgnoreChecksum)
     {
-        decomp_flags |= inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER;
+        decomp_flags |= inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+            | inflate_flags::TINFL_FLAG_ALLOW_PRESET_DICT;
     }
 
     let first_call = state.first_call;
@@ -236,6 +316,8 @@ pub fn inflate(
         let ret_status = {
             if (status as i32) < 0 {
                 Err(MZError::Data)
+            } else if state.decomp.dictionary_id().is_some() {
+                Ok(MZStatus::NeedDict)
             } else if status != TINFLStatus::Done {
                 state.last_status = TINFLStatus::Failed;
                 Err(MZError::Buf)
@@ -321,6 +403,11 @@ fn inflate_loop(
             return Err(MZError::Data);
         }
 
+        // The stream was compressed with a preset dictionary, which has to be set first.
+        if state.decomp.dictionary_id().is_some() {
+            return Ok(MZStatus::NeedDict);
+        }
+
         // The decompressor has flushed all it's data and is waiting for more input, but
         // there was no more input provided.
         if (status == TINFLStatus::NeedsMoreInput) && orig_in_len == 0 {
@@ -362,6 +449,7 @@ fn push_dict_out(state: &mut InflateState, next_out: &mut &mut [u8]) -> usize {
     *next_out = &mut mem::take(next_out)[n..];
     state.dict_avail -= n;
     state.dict_ofs = (state.dict_ofs + (n)) & (TINFL_LZ_DICT_SIZE - 1);
+    state.history = cmp::min(state.history + n, TINFL_LZ_DICT_SIZE);
     n
 }
 
@@ -415,4 +503,44 @@ mod test {
         // Should still have the checksum read from the header file.
         assert_eq!(state.decompressor().adler32_header(), Some(459605011))
     }
+
+    #[test]
+    fn dictionary() {
+        use crate::{MZError, MZStatus};
+
+        let encoded = [
+            120u8, 156, 243, 72, 205, 201, 201, 215, 81, 168, 202, 201, 76, 82, 4, 0, 27, 101, 4,
+            19,
+        ];
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        assert_eq!(state.set_dictionary(b"Hello"), Err(MZError::Stream));
+
+        // Decompress in two parts, copying the state in between.
+        let mut out = vec![0; 50];
+        let res = inflate(&mut state, &encoded[..10], &mut out, MZFlush::None);
+        assert_eq!(res.status, Ok(MZStatus::Ok));
+        let mut copy = state.clone();
+        let first = res.bytes_written;
+        let res = inflate(&mut copy, &encoded[10..], &mut out[first..], MZFlush::None);
+        assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+        assert_eq!(out[..first + res.bytes_written], b"Hello, zlib!"[..]);
+
+        let mut dictionary = vec![0; 32_768];
+        assert_eq!(copy.dictionary_len(), 12);
+        assert_eq!(copy.get_dictionary(&mut dictionary), 12);
+        assert_eq!(dictionary[..12], b"Hello, zlib!"[..]);
+        assert_eq!(copy.get_dictionary(&mut dictionary[..5]), 5);
+        assert_eq!(dictionary[..5], b"zlib!"[..]);
+
+        // A preset dictionary can be set at any point in a raw stream.
+        let mut state = InflateState::new_boxed(DataFormat::Raw);
+        assert_eq!(state.set_dictionary(b"Hello, zlib!"), Ok(MZStatus::Ok));
+        assert_eq!(state.dictionary_len(), 12);
+        // A single match of length 12 at distance 12 using the fixed huffman codes.
+        let encoded = [0x43, 0x66, 0x03, 0x00];
+        let res = inflate(&mut state, &encoded, &mut out, MZFlush::Finish);
+        assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+        assert_eq!(out[..res.bytes_written], b"Hello, zlib!"[..]);
+        assert_eq!(state.dictionary_len(), 24);
+    }
 }
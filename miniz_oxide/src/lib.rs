@@ -26,7 +26,6 @@
 #[cfg(feature = "with-alloc")]
 extern crate alloc;
 
-#[cfg(feature = "with-alloc")]
 pub mod deflate;
 pub mod inflate;
 mod shared;
@@ -44,7 +43,9 @@ pub enum MZFlush {
     /// Used when more input data is expected.
     None = 0,
     /// Zlib partial flush.
-    /// Currently treated as [`Sync`].
+    /// Finish compressing the currently buffered data, and output an empty static block without
+    /// aligning the output to a byte boundary.
+    /// Has no use in decompression.
     Partial = 1,
     /// Finish compressing the currently buffered data, and output an empty raw block.
     /// Has no use in decompression.
@@ -56,7 +57,9 @@ pub enum MZFlush {
     Full = 3,
     /// Attempt to flush the remaining data and end the stream.
     Finish = 4,
-    /// Not implemented.
+    /// Finish compressing the currently buffered data, ending the current block without
+    /// outputting an empty block or aligning the output to a byte boundary.
+    /// Has no use in decompression.
     Block = 5,
 }
 
@@ -67,9 +70,11 @@ impl MZFlush {
     pub fn new(flush: i32) -> Result<Self, MZError> {
         match flush {
             0 => Ok(MZFlush::None),
-            1 | 2 => Ok(MZFlush::Sync),
+            1 => Ok(MZFlush::Partial),
+            2 => Ok(MZFlush::Sync),
             3 => Ok(MZFlush::Full),
             4 => Ok(MZFlush::Finish),
+            5 => Ok(MZFlush::Block),
             _ => Err(MZError::Param),
         }
     }
@@ -95,7 +100,9 @@ pub enum MZStatus {
     /// respectively.
     StreamEnd = 1,
 
-    /// Unused
+    /// The zlib stream was compressed with a preset dictionary, which has to be set with
+    /// [`InflateState::set_dictionary`][inflate::stream::InflateState::set_dictionary] before
+    /// decompression can continue.
     NeedDict = 2,
 }
 
@@ -119,7 +126,8 @@ pub enum MZError {
 
     /// Error in inflation; see [`inflate::stream::inflate()`] for details.
     ///
-    /// Not returned from [`deflate::stream::deflate()`].
+    /// Only returned from [`deflate::stream::deflate()`] if verification of the output is enabled
+    /// and fails.
     Data = -3,
 
     /// Unused
@@ -208,6 +208,34 @@ fn issue_119_inflate_with_exact_limit() {
     );
 }
 
+#[test]
+fn decompress_exact_size() {
+    use miniz_oxide::inflate::{
+        decompress_to_vec_exact, decompress_to_vec_zlib_exact, ExactSizeError,
+    };
+
+    let data = get_test_data();
+    for level in [0, 1, 6, 10].iter().cloned() {
+        let enc = compress_to_vec(&data, level);
+        assert!(decompress_to_vec_exact(&enc, data.len()).unwrap() == data);
+        assert_eq!(
+            decompress_to_vec_exact(&enc, data.len() - 1),
+            Err(ExactSizeError::TooLong)
+        );
+        assert_eq!(
+            decompress_to_vec_exact(&enc, data.len() + 1),
+            Err(ExactSizeError::TooShort(data.len()))
+        );
+
+        let enc = compress_to_vec_zlib(&data, level);
+        assert!(decompress_to_vec_zlib_exact(&enc, data.len()).unwrap() == data);
+        assert_eq!(
+            decompress_to_vec_zlib_exact(&enc, data.len() - 1),
+            Err(ExactSizeError::TooLong)
+        );
+    }
+}
+
 #[test]
 fn issue_130_reject_invalid_table_sizes() {
     let input = get_test_file_data("tests/test_data/issue_130_table_size.bin");
@@ -152,11 +152,6 @@ extern "C" {
 //}
 //#endif
 
-void mz_free(void *p)
-{
-    MZ_FREE(p);
-}
-
 //void *miniz_def_alloc_func(void *opaque, size_t items, size_t size)
 //{
 //    (void)opaque, (void)items, (void)size;
@@ -173,11 +168,6 @@ void mz_free(void *p)
 //    return MZ_REALLOC(address, items * size);
 //}
 
-const char *mz_version(void)
-{
-    return MZ_VERSION;
-}
-
 #ifndef MINIZ_NO_ZLIB_APIS
 
 //int mz_deflateInit(mz_streamp pStream, int level)
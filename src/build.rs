@@ -9,7 +9,6 @@ fn main() {
     cc::Build::new()
         .files(&[
             "miniz_stub/miniz.c",
-            "miniz_stub/miniz_zip.c",
             "miniz_stub/miniz_tinfl.c",
             "miniz_stub/miniz_tdef.c",
         ])
@@ -3,19 +3,26 @@ use std::marker::PhantomData;
 use std::{ptr, slice};
 
 pub use crate::tinfl::{
-    tinfl_decompress, tinfl_decompress_mem_to_heap, tinfl_decompress_mem_to_mem,
-    tinfl_decompressor, tinfl_status,
+    tinfl_decompress, tinfl_decompress_mem_to_callback, tinfl_decompress_mem_to_heap,
+    tinfl_decompress_mem_to_mem, tinfl_decompressor, tinfl_put_buf_func_ptr, tinfl_status,
+    TINFL_DECOMPRESSOR_SIZE,
 };
 
 pub use crate::tdef::{
     tdefl_allocate, tdefl_compress, tdefl_compress_buffer, tdefl_compress_mem_to_heap,
-    tdefl_compress_mem_to_mem, tdefl_compress_mem_to_output,
-    tdefl_create_comp_flags_from_zip_params, tdefl_deallocate, tdefl_flush, tdefl_get_adler32,
-    tdefl_get_prev_return_status, tdefl_init,
+    tdefl_compress_mem_to_mem, tdefl_compress_mem_to_output, tdefl_compressor_alloc,
+    tdefl_compressor_free, tdefl_create_comp_flags_from_zip_params, tdefl_deallocate, tdefl_flush,
+    tdefl_get_adler32, tdefl_get_prev_return_status, tdefl_init,
+    tdefl_write_image_to_png_file_in_memory, tdefl_write_image_to_png_file_in_memory_ex,
+    TDEFL_COMPRESSOR_SIZE,
 };
+pub use crate::zip::c_api::*;
+
 use libc::*;
 
-use crate::lib_oxide::{InternalState, StateType, StateTypeEnum, StreamOxide, MZ_ADLER32_INIT};
+use crate::lib_oxide::{
+    Allocator, InternalState, StateBox, StateType, StateTypeEnum, StreamOxide, MZ_ADLER32_INIT,
+};
 
 use miniz_oxide::{mz_adler32_oxide, MZError};
 
@@ -23,7 +30,9 @@ use miniz_oxide::{mz_adler32_oxide, MZError};
 mod mz_typedefs {
     use libc::*;
 
+    pub type mz_uint16 = u16;
     pub type mz_uint32 = c_uint;
+    pub type mz_uint64 = u64;
     pub type mz_uint = c_uint;
     pub type mz_bool = c_int;
 }
@@ -108,6 +117,15 @@ pub type mz_alloc_callback =
 #[allow(bad_style)]
 pub type mz_free_callback = Option<unsafe extern "C" fn(*mut c_void, *mut c_void)>;
 
+/// Input function for `mz_inflateBack`. Sets `*buf` to the next input and returns its length,
+/// or 0 at the end of the input.
+#[allow(bad_style)]
+pub type mz_in_func = Option<unsafe extern "C" fn(*mut c_void, *mut *const u8) -> c_uint>;
+
+/// Output function for `mz_inflateBack`. Returns non-zero to stop decompression.
+#[allow(bad_style)]
+pub type mz_out_func = Option<unsafe extern "C" fn(*mut c_void, *mut u8, c_uint) -> c_int>;
+
 /// Inner stream state containing pointers to the used buffers and internal state.
 #[repr(C)]
 #[allow(bad_style)]
@@ -129,14 +147,14 @@ pub struct mz_stream {
 
     pub msg: *const c_char,
     /// Compressor or decompressor, if it exists.
-    /// This is boxed to work with the current C API.
-    pub state: Option<Box<InternalState>>,
+    /// This is allocated with `zalloc` to work with the current C API.
+    pub state: Option<StateBox<InternalState>>,
 
     /// Allocation function to use for allocating the internal compressor/decompressor.
-    /// Uses `mz_default_alloc_func` if set to `None`.
+    /// Uses `miniz_def_alloc_func` if set to `None`, and is set to it when the stream is used.
     pub zalloc: mz_alloc_callback,
-    /// Free function to use for allocating the internal compressor/decompressor.
-    /// Uses `mz_default_free_func` if `None`.
+    /// Free function to use for freeing the internal compressor/decompressor.
+    /// Uses `miniz_def_free_func` if set to `None`, and is set to it when the stream is used.
     pub zfree: mz_free_callback,
     /// Extra data to provide the allocation/deallocation functions.
     /// (Not used for the default ones)
@@ -194,11 +212,11 @@ impl<'io, ST: StateType> StreamOxide<'io, ST> {
                 .map_or(0, |out_slice| out_slice.len() as c_uint),
             total_out: self.total_out,
 
-            msg: ptr::null(),
+            msg: self.msg.map_or(ptr::null(), |msg| msg.as_ptr()),
 
-            zalloc: None,
-            zfree: None,
-            opaque: ptr::null_mut(),
+            zalloc: Some(self.alloc.alloc),
+            zfree: Some(self.alloc.free),
+            opaque: self.alloc.opaque,
             state: self.state.take(),
 
             data_type: ST::STATE_TYPE,
@@ -208,26 +226,21 @@ impl<'io, ST: StateType> StreamOxide<'io, ST> {
     }
 
     /// Create a new StreamOxide wrapper from a [mz_stream] object.
-    /// Custom allocation functions are not supported, supplying an mz_stream with allocation
-    /// function will cause creation to fail.
     ///
     /// Unsafe as the mz_stream object is not guaranteed to be valid. It is up to the
     /// caller to ensure it is.
     pub unsafe fn new(stream: &mut mz_stream) -> Self {
-        Self::try_new(stream).expect(
-            "Failed to create StreamOxide, wrong state type or tried to specify allocators.",
-        )
+        Self::try_new(stream).expect("Failed to create StreamOxide, wrong state type.")
     }
 
     /// Try to create a new StreamOxide wrapper from a [mz_stream] object.
-    /// Custom allocation functions are not supported, supplying an mz_stream with allocation
-    /// functions will cause creation to fail.
+    /// The default allocation functions are used for the ones that are not set.
     ///
     /// Unsafe as the mz_stream object is not guaranteed to be valid. It is up to the
     /// caller to ensure it is.
     pub unsafe fn try_new(stream: &mut mz_stream) -> Result<Self, MZError> {
         // Make sure we don't make an inflate stream from a deflate stream and vice versa.
-        if stream.data_type != ST::STATE_TYPE || stream.zalloc.is_some() || stream.zfree.is_some() {
+        if stream.data_type != ST::STATE_TYPE {
             return Err(MZError::Param);
         }
 
@@ -248,6 +261,12 @@ impl<'io, ST: StateType> StreamOxide<'io, ST> {
             total_out: stream.total_out,
             state: stream.state.take(),
             adler: stream.adler as u32,
+            msg: None,
+            alloc: Allocator {
+                alloc: stream.zalloc.unwrap_or(miniz_def_alloc_func),
+                free: stream.zfree.unwrap_or(miniz_def_free_func),
+                opaque: stream.opaque,
+            },
             state_type: PhantomData,
         })
     }
@@ -278,6 +297,12 @@ unmangle!(
         libc::realloc(address, items * size)
     }
 
+    /// Free memory allocated by the library and returned to the caller, such as the PNG image
+    /// from `tdefl_write_image_to_png_file_in_memory`, using `free`.
+    pub unsafe extern "C" fn mz_free(p: *mut c_void) {
+        libc::free(p)
+    }
+
     /// Calculate adler32 checksum of the provided buffer with the initial adler32 checksum of `adler`.
     /// If c_ulong is wider than 32 bits, only the lower 32 bits will be used.
     ///
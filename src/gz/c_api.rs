@@ -0,0 +1,619 @@
+//! The `gz*` file functions of `zlib.h`, implemented on top of [`GzReader`] and [`GzWriter`].
+//!
+//! A `gzFile` points to a [`gzFile_s`] owning the file. Its first fields have the layout zlib
+//! uses for its `gzgetc` macro, which programs built against zlib's header may have inlined.
+//! They always describe an empty buffer, so the macro falls back to calling `gzgetc`.
+//! Transparent writing (mode `T`) and `gzprintf` are not supported.
+
+use std::ffi::{CStr, CString};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::{cmp, ptr, slice};
+
+use libc::{c_char, c_int, c_long, c_uint, c_void};
+use miniz_oxide::deflate::core::CompressionStrategy;
+use miniz_oxide::deflate::CompressionLevel;
+use miniz_oxide::{MZError, MZFlush, MZStatus};
+
+use super::*;
+
+const SEEK_SET: c_int = 0;
+const SEEK_CUR: c_int = 1;
+
+/// How the file is opened.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+    Append,
+}
+
+/// The options given in the mode string of `gzopen` and `gzdopen`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Mode {
+    access: Access,
+    level: u8,
+    strategy: CompressionStrategy,
+    /// Fail if the file already exists.
+    exclusive: bool,
+}
+
+impl Mode {
+    /// Parse a mode string such as `"rb"` or `"wb9"`. Unknown characters are ignored, as in
+    /// zlib.
+    fn parse(mode: &[u8]) -> Option<Mode> {
+        let mut access = None;
+        let mut parsed = Mode {
+            access: Access::Read,
+            level: CompressionLevel::DefaultLevel as u8,
+            strategy: CompressionStrategy::Default,
+            exclusive: false,
+        };
+        for &c in mode {
+            match c {
+                b'r' => access = Some(Access::Read),
+                b'w' => access = Some(Access::Write),
+                b'a' => access = Some(Access::Append),
+                b'0'..=b'9' => parsed.level = c - b'0',
+                b'f' => parsed.strategy = CompressionStrategy::Filtered,
+                b'h' => parsed.strategy = CompressionStrategy::HuffmanOnly,
+                b'R' => parsed.strategy = CompressionStrategy::RLE,
+                b'F' => parsed.strategy = CompressionStrategy::Fixed,
+                b'x' => parsed.exclusive = true,
+                // Reading and writing at the same time, and transparent writing.
+                b'+' | b'T' => return None,
+                _ => (),
+            }
+        }
+        parsed.access = access?;
+        Some(parsed)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<File> {
+        let mut options = OpenOptions::new();
+        match self.access {
+            Access::Read => options.read(true),
+            Access::Write if self.exclusive => options.write(true).create_new(true),
+            Access::Write => options.write(true).create(true).truncate(true),
+            Access::Append => options.append(true).create(true),
+        };
+        options.open(path)
+    }
+}
+
+enum GzStream {
+    Read(BufReader<GzReader<File>>),
+    Write(GzWriter<File>),
+}
+
+/// State of an open gzip file.
+#[allow(bad_style)]
+#[repr(C)]
+pub struct gzFile_s {
+    /// Number of bytes buffered at `next` for zlib's `gzgetc` macro, always 0.
+    have: c_uint,
+    next: *mut u8,
+    /// Position in the uncompressed data.
+    pos: i64,
+    stream: GzStream,
+    /// Path or file descriptor, used in error messages.
+    path: String,
+    err: c_int,
+    msg: Option<CString>,
+    /// Whether a read was cut short by the end of the file.
+    eof: bool,
+}
+
+#[allow(bad_style)]
+pub type gzFile = *mut gzFile_s;
+
+impl gzFile_s {
+    fn new(file: File, path: String, mode: Mode) -> gzFile {
+        let stream = if mode.access == Access::Read {
+            GzStream::Read(BufReader::new(GzReader::new(file)))
+        } else {
+            GzStream::Write(GzWriter::with_strategy(file, mode.level, mode.strategy))
+        };
+        Box::into_raw(Box::new(gzFile_s {
+            have: 0,
+            next: ptr::null_mut(),
+            pos: 0,
+            stream,
+            path,
+            err: MZStatus::Ok as c_int,
+            msg: None,
+            eof: false,
+        }))
+    }
+
+    fn reader(&mut self) -> io::Result<&mut BufReader<GzReader<File>>> {
+        match self.stream {
+            GzStream::Read(ref mut reader) => Ok(reader),
+            GzStream::Write(_) => Err(wrong_mode()),
+        }
+    }
+
+    fn writer(&mut self) -> io::Result<&mut GzWriter<File>> {
+        match self.stream {
+            GzStream::Write(ref mut writer) => Ok(writer),
+            GzStream::Read(_) => Err(wrong_mode()),
+        }
+    }
+
+    /// Update `pos` after reading.
+    fn update_read_pos(&mut self) {
+        if let GzStream::Read(ref reader) = self.stream {
+            let position = reader.get_ref().position() - reader.buffer().len() as u64;
+            self.pos = position as i64;
+        }
+    }
+
+    fn set_error(&mut self, err: c_int, msg: &str) {
+        self.err = err;
+        self.msg = CString::new(format!("{}: {}", self.path, msg)).ok();
+    }
+
+    /// Record `err` to be returned by `gzerror`.
+    fn set_io_error(&mut self, err: &io::Error) {
+        let code = match err.kind() {
+            io::ErrorKind::InvalidData => MZError::Data,
+            io::ErrorKind::UnexpectedEof => MZError::Buf,
+            io::ErrorKind::InvalidInput => MZError::Stream,
+            _ => MZError::ErrNo,
+        };
+        self.set_error(code as c_int, &err.to_string());
+    }
+}
+
+fn wrong_mode() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "operation not allowed in this mode",
+    )
+}
+
+/// Run `f` on `file`, catching panics as this is called from C. On failure, the error is
+/// recorded in the file and `failed` is returned.
+unsafe fn with_file<T, F>(file: gzFile, failed: T, f: F) -> T
+where
+    F: FnOnce(&mut gzFile_s) -> io::Result<T>,
+{
+    let file = match file.as_mut() {
+        Some(file) => file,
+        None => return failed,
+    };
+    match catch_unwind(AssertUnwindSafe(|| f(file))) {
+        Ok(Ok(value)) => value,
+        Ok(Err(err)) => {
+            file.set_io_error(&err);
+            failed
+        }
+        Err(_) => {
+            file.set_error(MZError::Stream as c_int, "internal error");
+            failed
+        }
+    }
+}
+
+/// Read into `out` until it is full or the end of the file is reached, returning the number
+/// of bytes read along with the error that stopped it early, if any.
+fn read_full(file: &mut gzFile_s, out: &mut [u8]) -> (usize, Option<io::Error>) {
+    let mut n = 0;
+    let mut error = None;
+    while n < out.len() {
+        match file.reader().and_then(|reader| reader.read(&mut out[n..])) {
+            Ok(0) => {
+                file.eof = true;
+                break;
+            }
+            Ok(read) => n += read,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => {
+                error = Some(err);
+                break;
+            }
+        }
+    }
+    file.update_read_pos();
+    (n, error)
+}
+
+unsafe fn parse_mode(mode: *const c_char) -> Option<Mode> {
+    if mode.is_null() {
+        None
+    } else {
+        Mode::parse(CStr::from_ptr(mode).to_bytes())
+    }
+}
+
+unsafe fn open(path: &Path, name: String, mode: *const c_char) -> gzFile {
+    let mode = match parse_mode(mode) {
+        Some(mode) => mode,
+        None => return ptr::null_mut(),
+    };
+    match mode.open(path) {
+        Ok(file) => gzFile_s::new(file, name, mode),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+unmangle!(
+    /// Open the gzip file at `path` for reading or writing, as given by `mode`.
+    pub unsafe extern "C" fn gzopen(path: *const c_char, mode: *const c_char) -> gzFile {
+        if path.is_null() {
+            return ptr::null_mut();
+        }
+        let name = CStr::from_ptr(path);
+        #[cfg(unix)]
+        let path = {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+            Path::new(OsStr::from_bytes(name.to_bytes()))
+        };
+        #[cfg(not(unix))]
+        let path = match name.to_str() {
+            Ok(path) => Path::new(path),
+            Err(_) => return ptr::null_mut(),
+        };
+        open(path, name.to_string_lossy().into_owned(), mode)
+    }
+
+    /// Open the file descriptor `fd` as a gzip file, which takes ownership of it. Only
+    /// supported on unix platforms, returning null elsewhere.
+    pub unsafe extern "C" fn gzdopen(fd: c_int, mode: *const c_char) -> gzFile {
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::FromRawFd;
+            let mode = match parse_mode(mode) {
+                Some(mode) if fd >= 0 => mode,
+                _ => return ptr::null_mut(),
+            };
+            gzFile_s::new(File::from_raw_fd(fd), format!("<fd:{}>", fd), mode)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (fd, mode);
+            ptr::null_mut()
+        }
+    }
+
+    /// Read up to `len` bytes of data into `buf`, returning the number of bytes read, or -1 if
+    /// nothing could be read because of an error. Reading a truncated file is not an error
+    /// here, but is reported by `gzerror` and `gzclose`.
+    pub unsafe extern "C" fn gzread(file: gzFile, buf: *mut c_void, len: c_uint) -> c_int {
+        with_file(file, -1, |file| {
+            file.reader()?;
+            if len > c_int::max_value() as c_uint || (buf.is_null() && len > 0) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "request does not fit in an int",
+                ));
+            }
+            if len == 0 {
+                return Ok(0);
+            }
+            let out = slice::from_raw_parts_mut(buf as *mut u8, len as usize);
+            match read_full(file, out) {
+                (n, None) => Ok(n as c_int),
+                (n, Some(err)) => {
+                    file.set_io_error(&err);
+                    if n == 0 && file.err != MZError::Buf as c_int {
+                        Ok(-1)
+                    } else {
+                        Ok(n as c_int)
+                    }
+                }
+            }
+        })
+    }
+
+    /// Read a byte, returning -1 at the end of the file or on error.
+    pub unsafe extern "C" fn gzgetc(file: gzFile) -> c_int {
+        with_file(file, -1, |file| {
+            let mut byte = [0];
+            match read_full(file, &mut byte) {
+                (1, _) => Ok(c_int::from(byte[0])),
+                (_, Some(err)) => Err(err),
+                _ => Ok(-1),
+            }
+        })
+    }
+
+    /// Read a line of at most `len - 1` bytes into `buf`, including the newline, and
+    /// terminate it with a zero. Returns `buf`, or null if nothing could be read.
+    pub unsafe extern "C" fn gzgets(file: gzFile, buf: *mut c_char, len: c_int) -> *mut c_char {
+        with_file(file, ptr::null_mut(), |file| {
+            if buf.is_null() || len < 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid buffer",
+                ));
+            }
+            let out = slice::from_raw_parts_mut(buf as *mut u8, len as usize - 1);
+            let mut n = 0;
+            let mut result = Ok(());
+            while n < out.len() {
+                let available = match file.reader().and_then(|reader| reader.fill_buf()) {
+                    Ok(available) => available,
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                };
+                if available.is_empty() {
+                    file.eof = true;
+                    break;
+                }
+                let count = cmp::min(available.len(), out.len() - n);
+                let line_end = available[..count].iter().position(|&b| b == b'\n');
+                let count = line_end.map_or(count, |pos| pos + 1);
+                out[n..n + count].copy_from_slice(&available[..count]);
+                file.reader()?.consume(count);
+                n += count;
+                if line_end.is_some() {
+                    break;
+                }
+            }
+            file.update_read_pos();
+            result?;
+            *buf.add(n) = 0;
+            Ok(if n == 0 { ptr::null_mut() } else { buf })
+        })
+    }
+
+    /// Compress and write `len` bytes from `buf`, returning the number of bytes written, or 0
+    /// on error.
+    pub unsafe extern "C" fn gzwrite(file: gzFile, buf: *const c_void, len: c_uint) -> c_int {
+        with_file(file, 0, |file| {
+            if len > c_int::max_value() as c_uint || buf.is_null() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "request does not fit in an int",
+                ));
+            }
+            let data = slice::from_raw_parts(buf as *const u8, len as usize);
+            file.writer()?.write_all(data)?;
+            file.pos += i64::from(len);
+            Ok(len as c_int)
+        })
+    }
+
+    /// Write the zero-terminated string `s`, without the terminator. Returns the number of
+    /// bytes written, or -1 on error.
+    pub unsafe extern "C" fn gzputs(file: gzFile, s: *const c_char) -> c_int {
+        if s.is_null() {
+            return -1;
+        }
+        let s = CStr::from_ptr(s).to_bytes();
+        match gzwrite(file, s.as_ptr() as *const c_void, s.len() as c_uint) {
+            0 if !s.is_empty() => -1,
+            written => written,
+        }
+    }
+
+    /// Write the byte `c`, returning it, or -1 on error.
+    pub unsafe extern "C" fn gzputc(file: gzFile, c: c_int) -> c_int {
+        let byte = c as u8;
+        match gzwrite(file, &byte as *const u8 as *const c_void, 1) {
+            1 => c_int::from(byte),
+            _ => -1,
+        }
+    }
+
+    /// Flush the data written so far to the file. `flush` is one of the deflate flush modes.
+    /// With `Z_FINISH`, the current gzip member is ended, and data written after this goes
+    /// in a new one.
+    pub unsafe extern "C" fn gzflush(file: gzFile, flush: c_int) -> c_int {
+        with_file(file, MZError::Stream as c_int, |file| {
+            let writer = file.writer()?;
+            match MZFlush::new(flush) {
+                Ok(MZFlush::None) => (),
+                Ok(MZFlush::Partial) | Ok(MZFlush::Sync) | Ok(MZFlush::Block) => {
+                    writer.flush_data(false)?
+                }
+                Ok(MZFlush::Full) => writer.flush_data(true)?,
+                Ok(MZFlush::Finish) => writer.finish_member()?,
+                Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid flush")),
+            }
+            writer.get_mut().flush()?;
+            Ok(MZStatus::Ok as c_int)
+        })
+    }
+
+    /// Move to `offset` in the uncompressed data, relative to the start if `whence` is
+    /// `SEEK_SET` or to the current position if it is `SEEK_CUR`. Seeking backwards is only
+    /// supported when reading. When writing, the skipped data is filled with zeros. Returns
+    /// the new position, or -1 on error.
+    pub unsafe extern "C" fn gzseek(file: gzFile, offset: c_long, whence: c_int) -> c_long {
+        with_file(file, -1, |file| {
+            // `c_long` is 32 bits on some platforms.
+            #[allow(clippy::unnecessary_cast)]
+            let offset = offset as i64;
+            let target = match whence {
+                SEEK_SET => offset,
+                SEEK_CUR => file.pos.saturating_add(offset),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "invalid whence",
+                    ))
+                }
+            };
+            if target < 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "negative offset",
+                ));
+            }
+            if let GzStream::Write(ref mut writer) = file.stream {
+                if target < file.pos {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "can not seek backwards when writing",
+                    ));
+                }
+                let zeros = [0; 1024];
+                while file.pos < target {
+                    let n = cmp::min(zeros.len() as i64, target - file.pos);
+                    writer.write_all(&zeros[..n as usize])?;
+                    file.pos += n;
+                }
+            } else {
+                file.reader()?.seek(SeekFrom::Start(target as u64))?;
+                file.eof = false;
+                file.update_read_pos();
+            }
+            Ok(file.pos as c_long)
+        })
+    }
+
+    /// Go back to the start of a file opened for reading. Returns 0, or -1 on error.
+    pub unsafe extern "C" fn gzrewind(file: gzFile) -> c_int {
+        match gzseek(file, 0, SEEK_SET) {
+            0 => 0,
+            _ => -1,
+        }
+    }
+
+    /// Get the current position in the uncompressed data.
+    pub unsafe extern "C" fn gztell(file: gzFile) -> c_long {
+        file.as_ref().map_or(-1, |file| file.pos as c_long)
+    }
+
+    /// Whether a read was cut short by the end of the file.
+    pub unsafe extern "C" fn gzeof(file: gzFile) -> c_int {
+        file.as_ref().map_or(0, |file| file.eof as c_int)
+    }
+
+    /// Get a message describing the last error, and its error code if `errnum` isn't null.
+    pub unsafe extern "C" fn gzerror(file: gzFile, errnum: *mut c_int) -> *const c_char {
+        let file = match file.as_ref() {
+            Some(file) => file,
+            None => return ptr::null(),
+        };
+        if let Some(errnum) = errnum.as_mut() {
+            *errnum = file.err;
+        }
+        match file.msg {
+            Some(ref msg) if file.err != MZStatus::Ok as c_int => msg.as_ptr(),
+            _ => b"\0".as_ptr() as *const c_char,
+        }
+    }
+
+    /// Clear the error and end of file flags.
+    pub unsafe extern "C" fn gzclearerr(file: gzFile) {
+        if let Some(file) = file.as_mut() {
+            file.err = MZStatus::Ok as c_int;
+            file.msg = None;
+            file.eof = false;
+        }
+    }
+
+    /// Close the file, first finishing the gzip data when writing. Returns `Z_ERRNO` if
+    /// writing the end of the file failed, and `Z_BUF_ERROR` if reading ended in the middle of
+    /// the compressed data.
+    pub unsafe extern "C" fn gzclose(file: gzFile) -> c_int {
+        if file.is_null() {
+            return MZError::Stream as c_int;
+        }
+        let file = Box::from_raw(file);
+        match catch_unwind(AssertUnwindSafe(|| match file.stream {
+            GzStream::Write(writer) => match writer.finish() {
+                Ok(_) => MZStatus::Ok as c_int,
+                Err(_) => MZError::ErrNo as c_int,
+            },
+            GzStream::Read(_) if file.err == MZError::Buf as c_int => MZError::Buf as c_int,
+            GzStream::Read(_) => MZStatus::Ok as c_int,
+        })) {
+            Ok(status) => status,
+            Err(_) => MZError::Stream as c_int,
+        }
+    }
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn mode() {
+        let mode = Mode::parse(b"wb9h").unwrap();
+        assert_eq!(mode.access, Access::Write);
+        assert_eq!(mode.level, 9);
+        assert_eq!(mode.strategy, CompressionStrategy::HuffmanOnly);
+        assert_eq!(Mode::parse(b"rb").unwrap().access, Access::Read);
+        assert_eq!(Mode::parse(b"ax").unwrap().access, Access::Append);
+        assert!(Mode::parse(b"b").is_none());
+        assert!(Mode::parse(b"r+").is_none());
+        assert!(Mode::parse(b"wT").is_none());
+    }
+
+    #[test]
+    fn write_and_read() {
+        let path = env::temp_dir().join(format!("miniz_oxide_gz_{}.gz", std::process::id()));
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            let file = gzopen(c_path.as_ptr(), b"wb9\0".as_ptr() as *const c_char);
+            assert!(!file.is_null());
+            let line = b"first line\n";
+            assert_eq!(gzwrite(file, line.as_ptr() as *const c_void, 11), 11);
+            assert_eq!(
+                gzputs(file, b"second line\n\0".as_ptr() as *const c_char),
+                12
+            );
+            assert_eq!(gzputc(file, c_int::from(b'x')), c_int::from(b'x'));
+            assert_eq!(gzseek(file, 30, SEEK_SET), 30);
+            assert_eq!(gzseek(file, 10, SEEK_SET), -1);
+            assert_eq!(gzflush(file, 4), 0);
+            assert_eq!(gzputs(file, b"last\0".as_ptr() as *const c_char), 4);
+            assert_eq!(gztell(file), 34);
+            assert_eq!(gzread(file, ptr::null_mut(), 0), -1);
+            let mut errnum = 0;
+            assert!(!gzerror(file, &mut errnum).is_null());
+            assert_eq!(errnum, MZError::Stream as c_int);
+            assert_eq!(gzclose(file), 0);
+
+            // Append a member and read the whole file back.
+            let file = gzopen(c_path.as_ptr(), b"a\0".as_ptr() as *const c_char);
+            assert_eq!(gzputs(file, b"!\0".as_ptr() as *const c_char), 1);
+            assert_eq!(gzclose(file), 0);
+
+            let file = gzopen(c_path.as_ptr(), b"rb\0".as_ptr() as *const c_char);
+            let mut buf = [0 as c_char; 64];
+            assert_eq!(gzgets(file, buf.as_mut_ptr(), 64), buf.as_mut_ptr());
+            assert_eq!(CStr::from_ptr(buf.as_ptr()).to_bytes(), b"first line\n");
+            assert_eq!(gzgets(file, buf.as_mut_ptr(), 5), buf.as_mut_ptr());
+            assert_eq!(CStr::from_ptr(buf.as_ptr()).to_bytes(), b"seco");
+            assert_eq!(gztell(file), 15);
+            assert_eq!(gzgetc(file), c_int::from(b'n'));
+            assert_eq!(gzseek(file, 7, SEEK_CUR), 23);
+            assert_eq!(gzgetc(file), c_int::from(b'x'));
+            assert_eq!(gzrewind(file), 0);
+            assert_eq!(gzgetc(file), c_int::from(b'f'));
+            assert_eq!(gzseek(file, 30, SEEK_SET), 30);
+            let mut rest = [0u8; 16];
+            assert_eq!(gzread(file, rest.as_mut_ptr() as *mut c_void, 16), 5);
+            assert_eq!(&rest[..5], b"last!");
+            assert_eq!(gzeof(file), 1);
+            assert_eq!(gzgets(file, buf.as_mut_ptr(), 64), ptr::null_mut());
+            assert_eq!(gzclose(file), 0);
+
+            let file = gzopen(c_path.as_ptr(), b"wx\0".as_ptr() as *const c_char);
+            assert!(file.is_null());
+
+            // Truncated files give a buffer error.
+            let data = fs::read(&path).unwrap();
+            fs::write(&path, &data[..data.len() - 4]).unwrap();
+            let file = gzopen(c_path.as_ptr(), b"r\0".as_ptr() as *const c_char);
+            let mut all = [0u8; 64];
+            assert_eq!(gzread(file, all.as_mut_ptr() as *mut c_void, 64), 34);
+            assert_eq!(gzread(file, all.as_mut_ptr() as *mut c_void, 64), 0);
+            assert_eq!(gzclose(file), MZError::Buf as c_int);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+//! Reading and writing gzip files, replacing zlib's `gz*` file functions.
+//!
+//! [`GzReader`] decompresses files made of one or more gzip members, and passes other files
+//! through unchanged as zlib does. [`GzWriter`] compresses to a single member that can be ended
+//! early to start a new one. With the `zlib_compat` feature the `gz*` functions of `zlib.h` are
+//! exported on top of them.
+
+#[cfg(feature = "zlib_compat")]
+pub(crate) mod c_api;
+mod read;
+pub(crate) mod stream;
+mod write;
+
+pub use self::read::GzReader;
+pub use self::write::GzWriter;
+
+/// The two bytes every gzip member starts with.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Compression method value for deflate, the only one defined.
+const METHOD_DEFLATE: u8 = 8;
+
+/// Header flag set if the header is followed by a CRC-16 of it.
+const FLAG_HEADER_CRC: u8 = 1 << 1;
+/// Header flag set if there is an extra field.
+const FLAG_EXTRA: u8 = 1 << 2;
+/// Header flag set if there is a zero-terminated file name.
+const FLAG_NAME: u8 = 1 << 3;
+/// Header flag set if there is a zero-terminated comment.
+const FLAG_COMMENT: u8 = 1 << 4;
+/// Flags that are reserved and must not be set.
+const FLAG_RESERVED: u8 = 0xe0;
+
+/// Operating system value for an unknown system.
+const OS_UNKNOWN: u8 = 255;
+
+/// Size of the header written by [`GzWriter`], which has no optional fields.
+const HEADER_SIZE: usize = 10;
+/// Size of the CRC-32 and size that end each member.
+const TRAILER_SIZE: usize = 8;
+
+/// Size of the buffers data is read into or compressed into.
+const BUF_SIZE: usize = 32 * 1024;
+
+/// Extra flags stored in the header, telling whether the fastest or best compression was used.
+fn extra_flags(level: u8) -> u8 {
+    match level {
+        1 => 4,
+        9 | 10 => 2,
+        _ => 0,
+    }
+}
+
+/// Create a header with no optional fields.
+fn header(extra_flags: u8) -> [u8; HEADER_SIZE] {
+    let mut header = [0; HEADER_SIZE];
+    header[..2].copy_from_slice(&GZIP_MAGIC);
+    header[2] = METHOD_DEFLATE;
+    header[8] = extra_flags;
+    header[9] = OS_UNKNOWN;
+    header
+}
+
+/// Create the trailer of a member with the given CRC-32 and size of its data.
+fn trailer(crc: u32, size: u32) -> [u8; TRAILER_SIZE] {
+    let mut trailer = [0; TRAILER_SIZE];
+    trailer[..4].copy_from_slice(&crc.to_le_bytes());
+    trailer[4..].copy_from_slice(&size.to_le_bytes());
+    trailer
+}
@@ -0,0 +1,409 @@
+//! Reading gzip files.
+
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+
+use super::*;
+
+/// What the reader is in the middle of.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    /// Nothing has been read yet, so it's not known whether the file is compressed.
+    Start,
+    /// Decompressing the deflate stream of a member.
+    Inflate,
+    /// Passing the data through unchanged as the file isn't compressed.
+    Copy,
+    /// All the data has been read.
+    Done,
+}
+
+/// Decompresses a gzip file read from `R`.
+///
+/// Consecutive members are decompressed as one stream, and the CRC-32 and size of each is
+/// checked. Anything following a member that isn't another member is ignored. A file that
+/// doesn't start with a gzip header is read as is.
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use miniz_oxide_c_api::gz::{GzReader, GzWriter};
+///
+/// let mut writer = GzWriter::new(Vec::new(), 6);
+/// writer.write_all(b"Hello, world!").unwrap();
+/// let file = writer.finish().unwrap();
+///
+/// let mut text = String::new();
+/// GzReader::new(&file[..]).read_to_string(&mut text).unwrap();
+/// assert_eq!(text, "Hello, world!");
+/// ```
+pub struct GzReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    buf_pos: usize,
+    buf_len: usize,
+    /// Number of bytes read from `reader`.
+    read: u64,
+    state: Box<InflateState>,
+    mode: Mode,
+    crc: crc32fast::Hasher,
+    /// Size of the current member's data modulo 2^32, as stored in the trailer.
+    member_size: u32,
+    /// Number of bytes of data output.
+    position: u64,
+}
+
+impl<R: Read> GzReader<R> {
+    /// Read a gzip file from the current position of `reader`.
+    pub fn new(reader: R) -> GzReader<R> {
+        GzReader {
+            reader,
+            buf: vec![0; BUF_SIZE],
+            buf_pos: 0,
+            buf_len: 0,
+            read: 0,
+            state: InflateState::new_boxed(DataFormat::Raw),
+            mode: Mode::Start,
+            crc: crc32fast::Hasher::new(),
+            member_size: 0,
+            position: 0,
+        }
+    }
+
+    /// Number of bytes of data read so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Whether the file is read as is, as it isn't compressed. Only known once something has
+    /// been read.
+    pub fn is_transparent(&self) -> bool {
+        self.mode == Mode::Copy
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Make at least `n` bytes of input available in the buffer if possible, returning the
+    /// number of bytes available.
+    fn fill(&mut self, n: usize) -> io::Result<usize> {
+        if self.buf_len - self.buf_pos < n {
+            self.buf.copy_within(self.buf_pos..self.buf_len, 0);
+            self.buf_len -= self.buf_pos;
+            self.buf_pos = 0;
+            while self.buf_len < n {
+                let read = match self.reader.read(&mut self.buf[self.buf_len..]) {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err),
+                };
+                self.buf_len += read;
+                self.read += read as u64;
+            }
+        }
+        Ok(self.buf_len - self.buf_pos)
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        if self.fill(1)? == 0 {
+            return Err(unexpected_eof());
+        }
+        self.buf_pos += 1;
+        Ok(self.buf[self.buf_pos - 1])
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from(self.read_byte()?) | (u16::from(self.read_byte()?) << 8))
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from(self.read_u16()?) | (u32::from(self.read_u16()?) << 16))
+    }
+
+    /// Whether the input continues with a gzip header.
+    fn at_member(&mut self) -> io::Result<bool> {
+        Ok(self.fill(2)? >= 2 && self.buf[self.buf_pos..self.buf_pos + 2] == GZIP_MAGIC)
+    }
+
+    /// Read the header of a member, skipping the optional fields.
+    fn read_header(&mut self) -> io::Result<()> {
+        self.buf_pos += GZIP_MAGIC.len();
+        if self.read_byte()? != METHOD_DEFLATE {
+            return Err(invalid_data("unknown compression method"));
+        }
+        let flags = self.read_byte()?;
+        if flags & FLAG_RESERVED != 0 {
+            return Err(invalid_data("unknown header flags set"));
+        }
+        // Modification time, extra flags and operating system.
+        for _ in 0..6 {
+            self.read_byte()?;
+        }
+        if flags & FLAG_EXTRA != 0 {
+            for _ in 0..self.read_u16()? {
+                self.read_byte()?;
+            }
+        }
+        if flags & FLAG_NAME != 0 {
+            while self.read_byte()? != 0 {}
+        }
+        if flags & FLAG_COMMENT != 0 {
+            while self.read_byte()? != 0 {}
+        }
+        if flags & FLAG_HEADER_CRC != 0 {
+            self.read_u16()?;
+        }
+
+        self.state.reset(DataFormat::Raw);
+        self.crc = crc32fast::Hasher::new();
+        self.member_size = 0;
+        self.mode = Mode::Inflate;
+        Ok(())
+    }
+
+    /// Check the trailer of a member, and start the next one if there is one.
+    fn end_member(&mut self) -> io::Result<()> {
+        let crc = self.read_u32()?;
+        let size = self.read_u32()?;
+        if crc != self.crc.clone().finalize() {
+            return Err(invalid_data("incorrect data check"));
+        }
+        if size != self.member_size {
+            return Err(invalid_data("incorrect length check"));
+        }
+        if self.at_member()? {
+            self.read_header()
+        } else {
+            self.mode = Mode::Done;
+            Ok(())
+        }
+    }
+
+    /// Decompress into `out`, returning the number of bytes written.
+    fn inflate(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let available = self.fill(1)?;
+            let res = inflate(
+                &mut self.state,
+                &self.buf[self.buf_pos..self.buf_len],
+                out,
+                MZFlush::None,
+            );
+            self.buf_pos += res.bytes_consumed;
+            self.crc.update(&out[..res.bytes_written]);
+            self.member_size = self.member_size.wrapping_add(res.bytes_written as u32);
+            match res.status {
+                Ok(MZStatus::StreamEnd) => {
+                    self.end_member()?;
+                    if res.bytes_written > 0 || self.mode == Mode::Done {
+                        return Ok(res.bytes_written);
+                    }
+                }
+                Ok(_) | Err(MZError::Buf) => {
+                    if res.bytes_written > 0 {
+                        return Ok(res.bytes_written);
+                    }
+                    if available == 0 {
+                        return Err(unexpected_eof());
+                    }
+                }
+                Err(_) => return Err(invalid_data("invalid compressed data")),
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for GzReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        if self.mode == Mode::Start {
+            self.mode = if self.at_member()? {
+                self.read_header()?;
+                Mode::Inflate
+            } else {
+                Mode::Copy
+            };
+        }
+        let n = match self.mode {
+            Mode::Inflate => self.inflate(out)?,
+            Mode::Copy if self.buf_pos < self.buf_len => {
+                let n = cmp::min(out.len(), self.buf_len - self.buf_pos);
+                out[..n].copy_from_slice(&self.buf[self.buf_pos..self.buf_pos + n]);
+                self.buf_pos += n;
+                n
+            }
+            Mode::Copy => {
+                let n = self.reader.read(out)?;
+                self.read += n as u64;
+                n
+            }
+            Mode::Start | Mode::Done => 0,
+        };
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> GzReader<R> {
+    /// Go back to the start of the file, which is where `reader` was when the `GzReader` was
+    /// created.
+    pub fn rewind(&mut self) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Current(-(self.read as i64)))?;
+        self.buf_pos = 0;
+        self.buf_len = 0;
+        self.read = 0;
+        self.mode = Mode::Start;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+/// Seeking in the decompressed data. Seeking backwards rewinds and decompresses the data
+/// again up to the new position, so it can be slow. Seeking from the end isn't supported.
+impl<R: Read + Seek> Seek for GzReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(target) => Some(target),
+            SeekFrom::Current(offset) if offset < 0 => {
+                self.position.checked_sub(offset.wrapping_neg() as u64)
+            }
+            SeekFrom::Current(offset) => self.position.checked_add(offset as u64),
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "seeking from the end is not supported",
+                ))
+            }
+        };
+        let target = target
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position"))?;
+
+        if target < self.position {
+            self.rewind()?;
+        }
+        let mut scratch = [0; 1024];
+        while self.position < target {
+            let n = cmp::min(scratch.len() as u64, target - self.position) as usize;
+            if self.read(&mut scratch[..n])? == 0 {
+                break;
+            }
+        }
+        Ok(self.position)
+    }
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected end of file")
+}
+
+fn invalid_data(what: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::GzWriter;
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    fn gzip(data: &[u8], level: u8) -> Vec<u8> {
+        let mut writer = GzWriter::new(Vec::new(), level);
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn read_all(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        GzReader::new(data).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn members_and_transparent() {
+        let mut file = gzip(b"first member, ", 6);
+        file.extend_from_slice(&gzip(b"", 1));
+        file.extend_from_slice(&gzip(b"second member", 0));
+        assert_eq!(read_all(&file).unwrap(), b"first member, second member");
+
+        // Trailing garbage after a member is ignored.
+        file.extend_from_slice(b"garbage");
+        assert_eq!(read_all(&file).unwrap(), b"first member, second member");
+
+        assert_eq!(read_all(b"not compressed").unwrap(), b"not compressed");
+        assert_eq!(read_all(b"").unwrap(), b"");
+    }
+
+    #[test]
+    fn optional_header_fields() {
+        let compressed = miniz_oxide::deflate::compress_to_vec(b"data", 6);
+        let mut file = vec![
+            0x1f,
+            0x8b,
+            8,
+            FLAG_EXTRA | FLAG_NAME | FLAG_COMMENT | FLAG_HEADER_CRC,
+        ];
+        file.extend_from_slice(&[0, 0, 0, 0, 0, 3, 2, 0, 0xaa, 0xbb]);
+        file.extend_from_slice(b"name\0comment\0\x12\x34");
+        file.extend_from_slice(&compressed);
+        file.extend_from_slice(&crc32fast::hash(b"data").to_le_bytes());
+        file.extend_from_slice(&4u32.to_le_bytes());
+        assert_eq!(read_all(&file).unwrap(), b"data");
+    }
+
+    #[test]
+    fn corrupt() {
+        let file = gzip(b"some data to check", 6);
+        let mut bad_crc = file.clone();
+        let crc_pos = file.len() - TRAILER_SIZE;
+        bad_crc[crc_pos] ^= 1;
+        let err = read_all(&bad_crc).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut bad_size = file.clone();
+        bad_size[file.len() - 1] ^= 1;
+        assert_eq!(
+            read_all(&bad_size).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let err = read_all(&file[..file.len() - 3]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = read_all(&file[..HEADER_SIZE + 2]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn seek() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let mut file = b"prefix".to_vec();
+        file.extend_from_slice(&gzip(&data, 6));
+        let mut cursor = Cursor::new(file);
+        cursor.set_position(6);
+        let mut reader = GzReader::new(cursor);
+
+        let mut buf = [0; 10];
+        assert_eq!(reader.seek(SeekFrom::Start(70_000)).unwrap(), 70_000);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], data[70_000..70_010]);
+        assert_eq!(reader.seek(SeekFrom::Current(-70_005)).unwrap(), 5);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], data[5..15]);
+        assert!(reader.seek(SeekFrom::Current(-16)).is_err());
+        assert_eq!(reader.seek(SeekFrom::Start(200_000)).unwrap(), 100_000);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+}
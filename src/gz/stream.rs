@@ -0,0 +1,482 @@
+//! The gzip wrapper of deflate streams set up with gzip window bits, which is written and read
+//! a few bytes at a time as the header and trailer may be split across calls.
+
+use std::cmp;
+
+use super::*;
+
+/// Writes the header and trailer of a gzip member around the deflate data of a stream.
+#[derive(Clone)]
+pub(crate) struct GzipEncoder {
+    extra_flags: u8,
+    crc: crc32fast::Hasher,
+    /// Size of the data modulo 2^32, as stored in the trailer.
+    size: u32,
+    /// The header or trailer, which is written from `pos` up to `len`.
+    buf: [u8; HEADER_SIZE],
+    pos: usize,
+    len: usize,
+    /// Whether the trailer has been added.
+    finished: bool,
+}
+
+impl GzipEncoder {
+    /// Start a member compressed with the given level, which is noted in the header.
+    pub fn new(level: u8) -> GzipEncoder {
+        GzipEncoder::with_extra_flags(extra_flags(level))
+    }
+
+    fn with_extra_flags(extra_flags: u8) -> GzipEncoder {
+        GzipEncoder {
+            extra_flags,
+            crc: crc32fast::Hasher::new(),
+            size: 0,
+            buf: header(extra_flags),
+            pos: 0,
+            len: HEADER_SIZE,
+            finished: false,
+        }
+    }
+
+    /// Start over with a new member.
+    pub fn reset(&mut self) {
+        *self = GzipEncoder::with_extra_flags(self.extra_flags);
+    }
+
+    /// Whether the header has not been completely written yet.
+    pub fn in_header(&self) -> bool {
+        !self.finished && self.pos < self.len
+    }
+
+    /// Whether the trailer has been added, so the member is done once it's written.
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Number of bytes of the header or trailer that have not been written yet.
+    pub fn pending(&self) -> usize {
+        self.len - self.pos
+    }
+
+    /// Copy as much as possible of the header or trailer to `out`, returning the number of
+    /// bytes copied.
+    pub fn write_pending(&mut self, out: &mut [u8]) -> usize {
+        let n = cmp::min(out.len(), self.pending());
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        n
+    }
+
+    /// Add data that has been compressed to the checksum and size.
+    pub fn update(&mut self, data: &[u8]) {
+        self.crc.update(data);
+        self.size = self.size.wrapping_add(data.len() as u32);
+    }
+
+    /// The CRC-32 of the data so far.
+    pub fn crc(&self) -> u32 {
+        self.crc.clone().finalize()
+    }
+
+    /// Add the trailer after the end of the deflate data.
+    pub fn finish(&mut self) {
+        if !self.finished {
+            let trailer = trailer(self.crc(), self.size);
+            self.buf[..TRAILER_SIZE].copy_from_slice(&trailer);
+            self.pos = 0;
+            self.len = TRAILER_SIZE;
+            self.finished = true;
+        }
+    }
+}
+
+/// Reasons a gzip wrapper is invalid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum GzipError {
+    /// The data doesn't start with the gzip magic bytes.
+    HeaderCheck,
+    /// The compression method is not deflate.
+    Method,
+    /// Reserved header flags are set.
+    Flags,
+    /// The CRC-16 of the header doesn't match.
+    HeaderCrc,
+    /// The CRC-32 of the data doesn't match the trailer.
+    DataCheck,
+    /// The size of the data doesn't match the trailer.
+    LengthCheck,
+}
+
+/// What a [`GzipDecoder`] is reading.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Stage {
+    /// The fixed part of the header.
+    Header,
+    /// The length of the extra field.
+    ExtraLength,
+    /// The extra field.
+    Extra,
+    /// The zero-terminated file name.
+    Name,
+    /// The zero-terminated comment.
+    Comment,
+    /// The CRC-16 of the header.
+    HeaderCrc,
+    /// The deflate data, which is read by the decompressor.
+    Data,
+    /// The CRC-32 and size of the data.
+    Trailer,
+    /// The end of the member has been reached.
+    Done,
+    /// The wrapper is invalid.
+    Failed(GzipError),
+}
+
+/// Result of reading the header with [`GzipDecoder::read_header`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum HeaderStatus {
+    /// More input is needed.
+    Incomplete,
+    /// The header has been read, and the deflate data comes next.
+    Complete,
+    /// The data doesn't start with a gzip header, and is read as a zlib stream instead.
+    NotGzip,
+}
+
+/// Reads the header and trailer of a gzip member around the deflate data of a stream, checking
+/// the CRC-32 and size of the data.
+#[derive(Clone)]
+pub(crate) struct GzipDecoder {
+    stage: Stage,
+    /// Whether the data may also be a zlib stream, which is detected from its first byte.
+    detect: bool,
+    flags: u8,
+    /// Bytes of the current fixed size field read so far.
+    buf: [u8; HEADER_SIZE],
+    buf_len: usize,
+    /// Remaining length of the extra field.
+    remaining: usize,
+    header_crc: crc32fast::Hasher,
+    crc: crc32fast::Hasher,
+    size: u32,
+}
+
+impl GzipDecoder {
+    /// Create a decoder for a gzip member, or with `detect` set, for either a gzip member or
+    /// a zlib stream.
+    pub fn new(detect: bool) -> GzipDecoder {
+        GzipDecoder {
+            stage: Stage::Header,
+            detect,
+            flags: 0,
+            buf: [0; HEADER_SIZE],
+            buf_len: 0,
+            remaining: 0,
+            header_crc: crc32fast::Hasher::new(),
+            crc: crc32fast::Hasher::new(),
+            size: 0,
+        }
+    }
+
+    /// Whether the header has not been completely read yet.
+    pub fn in_header(&self) -> bool {
+        match self.stage {
+            Stage::Header
+            | Stage::ExtraLength
+            | Stage::Extra
+            | Stage::Name
+            | Stage::Comment
+            | Stage::HeaderCrc => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the header has been read and the end of the member has not been reached.
+    pub fn in_data(&self) -> bool {
+        self.stage == Stage::Data
+    }
+
+    /// The CRC-32 of the data so far.
+    pub fn crc(&self) -> u32 {
+        self.crc.clone().finalize()
+    }
+
+    /// Add decompressed data to the checksum and size.
+    pub fn update(&mut self, data: &[u8]) {
+        self.crc.update(data);
+        self.size = self.size.wrapping_add(data.len() as u32);
+    }
+
+    /// Collect the bytes of a field of `len` bytes from `input` into the buffer, returning
+    /// whether all of it is there.
+    fn fill(&mut self, input: &mut &[u8], len: usize) -> bool {
+        let n = cmp::min(len - self.buf_len, input.len());
+        self.buf[self.buf_len..self.buf_len + n].copy_from_slice(&input[..n]);
+        self.buf_len += n;
+        *input = &input[n..];
+        self.buf_len == len
+    }
+
+    /// Move on to the next field of the header that is present according to the flags.
+    fn next_field(&mut self) {
+        self.buf_len = 0;
+        loop {
+            let (stage, flag) = match self.stage {
+                Stage::Header => (Stage::ExtraLength, FLAG_EXTRA),
+                Stage::ExtraLength | Stage::Extra => (Stage::Name, FLAG_NAME),
+                Stage::Name => (Stage::Comment, FLAG_COMMENT),
+                Stage::Comment => (Stage::HeaderCrc, FLAG_HEADER_CRC),
+                _ => (Stage::Data, 0),
+            };
+            self.stage = stage;
+            if stage == Stage::Data || self.flags & flag != 0 {
+                return;
+            }
+        }
+    }
+
+    fn fail(&mut self, err: GzipError) -> Result<HeaderStatus, GzipError> {
+        self.stage = Stage::Failed(err);
+        Err(err)
+    }
+
+    /// Read as much of the header as possible from `input`, advancing it past the bytes used.
+    pub fn read_header(&mut self, input: &mut &[u8]) -> Result<HeaderStatus, GzipError> {
+        if self.detect && self.stage == Stage::Header && self.buf_len == 0 {
+            match input.first() {
+                Some(&byte) if byte != GZIP_MAGIC[0] => return Ok(HeaderStatus::NotGzip),
+                _ => (),
+            }
+        }
+
+        loop {
+            let before = *input;
+            let stage = self.stage;
+            let res = self.read_field(input);
+            // The CRC-16 covers the header up to where it's stored.
+            if stage != Stage::HeaderCrc {
+                self.header_crc
+                    .update(&before[..before.len() - input.len()]);
+            }
+            if let Some(res) = res {
+                return res;
+            }
+        }
+    }
+
+    /// Read the current field of the header, returning `None` if it has been read and the next
+    /// one should be read.
+    fn read_field(&mut self, input: &mut &[u8]) -> Option<Result<HeaderStatus, GzipError>> {
+        match self.stage {
+            Stage::Header => {
+                if self.buf_len < GZIP_MAGIC.len() {
+                    if !self.fill(input, GZIP_MAGIC.len()) {
+                        return Some(Ok(HeaderStatus::Incomplete));
+                    }
+                    if self.buf[..2] != GZIP_MAGIC {
+                        return Some(self.fail(GzipError::HeaderCheck));
+                    }
+                }
+                if !self.fill(input, HEADER_SIZE) {
+                    return Some(Ok(HeaderStatus::Incomplete));
+                }
+                if self.buf[2] != METHOD_DEFLATE {
+                    return Some(self.fail(GzipError::Method));
+                }
+                self.flags = self.buf[3];
+                if self.flags & FLAG_RESERVED != 0 {
+                    return Some(self.fail(GzipError::Flags));
+                }
+                self.next_field();
+            }
+            Stage::ExtraLength => {
+                if !self.fill(input, 2) {
+                    return Some(Ok(HeaderStatus::Incomplete));
+                }
+                self.remaining = usize::from(u16::from_le_bytes([self.buf[0], self.buf[1]]));
+                self.buf_len = 0;
+                self.stage = Stage::Extra;
+            }
+            Stage::Extra => {
+                let n = cmp::min(self.remaining, input.len());
+                *input = &input[n..];
+                self.remaining -= n;
+                if self.remaining > 0 {
+                    return Some(Ok(HeaderStatus::Incomplete));
+                }
+                self.next_field();
+            }
+            Stage::Name | Stage::Comment => match input.iter().position(|&b| b == 0) {
+                Some(end) => {
+                    *input = &input[end + 1..];
+                    self.next_field();
+                }
+                None => {
+                    *input = &[];
+                    return Some(Ok(HeaderStatus::Incomplete));
+                }
+            },
+            Stage::HeaderCrc => {
+                if !self.fill(input, 2) {
+                    return Some(Ok(HeaderStatus::Incomplete));
+                }
+                let expected = u16::from_le_bytes([self.buf[0], self.buf[1]]);
+                if expected != self.header_crc.clone().finalize() as u16 {
+                    return Some(self.fail(GzipError::HeaderCrc));
+                }
+                self.next_field();
+            }
+            Stage::Failed(err) => return Some(Err(err)),
+            _ => return Some(Ok(HeaderStatus::Complete)),
+        }
+        None
+    }
+
+    /// Note that the deflate data has ended, so the trailer comes next.
+    pub fn end_data(&mut self) {
+        if self.stage == Stage::Data {
+            self.stage = Stage::Trailer;
+            self.buf_len = 0;
+        }
+    }
+
+    /// Read as much of the trailer as possible from `input`, advancing it past the bytes used,
+    /// and check it once it has been read. Returns whether the whole trailer has been read.
+    pub fn read_trailer(&mut self, input: &mut &[u8]) -> Result<bool, GzipError> {
+        match self.stage {
+            Stage::Trailer => (),
+            Stage::Failed(err) => return Err(err),
+            stage => return Ok(stage == Stage::Done),
+        }
+        if !self.fill(input, TRAILER_SIZE) {
+            return Ok(false);
+        }
+        let field = |i: usize| {
+            u32::from_le_bytes([
+                self.buf[i],
+                self.buf[i + 1],
+                self.buf[i + 2],
+                self.buf[i + 3],
+            ])
+        };
+        let err = if field(0) != self.crc() {
+            GzipError::DataCheck
+        } else if field(4) != self.size {
+            GzipError::LengthCheck
+        } else {
+            self.stage = Stage::Done;
+            return Ok(true);
+        };
+        self.stage = Stage::Failed(err);
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Read the header of `member` a byte at a time.
+    fn read_header_bytewise(member: &[u8]) -> (Result<HeaderStatus, GzipError>, usize) {
+        let mut decoder = GzipDecoder::new(false);
+        for (i, byte) in member.iter().enumerate() {
+            let mut input = &[*byte][..];
+            match decoder.read_header(&mut input) {
+                Ok(HeaderStatus::Incomplete) => assert!(input.is_empty()),
+                res => return (res, i + 1),
+            }
+        }
+        (Ok(HeaderStatus::Incomplete), member.len())
+    }
+
+    #[test]
+    fn optional_fields() {
+        let mut member = header(0).to_vec();
+        member[3] = FLAG_EXTRA | FLAG_NAME | FLAG_COMMENT | FLAG_HEADER_CRC;
+        member.extend_from_slice(&[3, 0, 1, 2, 3]);
+        member.extend_from_slice(b"name\0comment\0");
+        let crc = crc32fast::hash(&member) as u16;
+        member.extend_from_slice(&crc.to_le_bytes());
+        let header_len = member.len();
+        member.push(0x42);
+
+        let mut input = &member[..];
+        let mut decoder = GzipDecoder::new(true);
+        assert_eq!(decoder.read_header(&mut input), Ok(HeaderStatus::Complete));
+        assert_eq!(input, [0x42]);
+        assert!(decoder.in_data());
+        assert_eq!(
+            read_header_bytewise(&member),
+            (Ok(HeaderStatus::Complete), header_len)
+        );
+
+        member[header_len - 1] ^= 1;
+        assert_eq!(
+            read_header_bytewise(&member),
+            (Err(GzipError::HeaderCrc), header_len)
+        );
+        assert_eq!(
+            GzipDecoder::new(false).read_header(&mut &member[..]),
+            Err(GzipError::HeaderCrc)
+        );
+    }
+
+    #[test]
+    fn invalid_header() {
+        let mut input = &[0x78, 0x9c][..];
+        assert_eq!(
+            GzipDecoder::new(true).read_header(&mut input),
+            Ok(HeaderStatus::NotGzip)
+        );
+        assert_eq!(input.len(), 2);
+        assert_eq!(
+            GzipDecoder::new(false).read_header(&mut input),
+            Err(GzipError::HeaderCheck)
+        );
+
+        let mut member = header(0);
+        member[2] = 7;
+        assert_eq!(read_header_bytewise(&member).0, Err(GzipError::Method));
+        member[2] = METHOD_DEFLATE;
+        member[3] = 0x20;
+        assert_eq!(read_header_bytewise(&member).0, Err(GzipError::Flags));
+    }
+
+    #[test]
+    fn encode_and_check_trailer() {
+        let data = b"some data";
+        let mut encoder = GzipEncoder::new(9);
+        let mut out = [0; HEADER_SIZE + TRAILER_SIZE];
+        assert!(encoder.in_header());
+        assert_eq!(encoder.write_pending(&mut out[..4]), 4);
+        assert_eq!(encoder.write_pending(&mut out[4..]), HEADER_SIZE - 4);
+        assert!(!encoder.in_header());
+        encoder.update(data);
+        encoder.finish();
+        assert_eq!(encoder.pending(), TRAILER_SIZE);
+        assert_eq!(encoder.write_pending(&mut out[HEADER_SIZE..]), TRAILER_SIZE);
+        assert_eq!(out[..HEADER_SIZE], header(2));
+
+        let mut decoder = GzipDecoder::new(false);
+        assert_eq!(
+            decoder.read_header(&mut &out[..HEADER_SIZE]),
+            Ok(HeaderStatus::Complete)
+        );
+        decoder.update(data);
+        decoder.end_data();
+        let mut trailer = &out[HEADER_SIZE..HEADER_SIZE + 3];
+        assert_eq!(decoder.read_trailer(&mut trailer), Ok(false));
+        let mut trailer = &out[HEADER_SIZE + 3..];
+        assert_eq!(decoder.read_trailer(&mut trailer), Ok(true));
+        assert_eq!(decoder.read_trailer(&mut &[][..]), Ok(true));
+
+        let mut decoder = GzipDecoder::new(false);
+        decoder.read_header(&mut &out[..HEADER_SIZE]).unwrap();
+        decoder.update(&data[1..]);
+        decoder.end_data();
+        assert_eq!(
+            decoder.read_trailer(&mut &out[HEADER_SIZE..]),
+            Err(GzipError::DataCheck)
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Writing gzip files.
+
+use std::io::{self, Write};
+
+use miniz_oxide::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressionParams, CompressionStrategy,
+    CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+use miniz_oxide::DataFormat;
+
+use super::*;
+
+/// Compresses data to a gzip file written to `W`.
+///
+/// The header is written along with the first data, and [`finish`](GzWriter::finish) has to be
+/// called to write the end of the file. [`finish_member`](GzWriter::finish_member) ends the
+/// current member, so that data written after it goes in a new one.
+pub struct GzWriter<W: Write> {
+    writer: W,
+    compressor: Box<CompressorOxide>,
+    /// Extra flags stored in the header, telling whether the fastest or best compression was
+    /// used.
+    extra_flags: u8,
+    crc: crc32fast::Hasher,
+    /// Size of the current member's data modulo 2^32, as stored in the trailer.
+    member_size: u32,
+    /// Whether the header of the current member has been written.
+    in_member: bool,
+    /// Whether a member has been finished.
+    finished_member: bool,
+}
+
+impl<W: Write> GzWriter<W> {
+    /// Compress with the given compression level, from 0 to 10.
+    pub fn new(writer: W, level: u8) -> GzWriter<W> {
+        GzWriter::with_strategy(writer, level, CompressionStrategy::Default)
+    }
+
+    /// Compress with the given compression level and strategy.
+    pub fn with_strategy(writer: W, level: u8, strategy: CompressionStrategy) -> GzWriter<W> {
+        let flags = create_comp_flags_from_zip_params(
+            level.into(),
+            DataFormat::Raw.to_window_bits(),
+            strategy as i32,
+        );
+        GzWriter {
+            writer,
+            compressor: Box::new(CompressorOxide::new(flags)),
+            extra_flags: extra_flags(level),
+            crc: crc32fast::Hasher::new(),
+            member_size: 0,
+            in_member: false,
+            finished_member: false,
+        }
+    }
+
+    /// Change the compression parameters. Data that has already been written is compressed
+    /// with the old ones. The format is always raw deflate.
+    pub fn set_params(&mut self, mut params: CompressionParams) {
+        params.format = DataFormat::Raw;
+        self.compressor.set_params(params);
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Write the header if the current member hasn't been started.
+    fn start_member(&mut self) -> io::Result<()> {
+        if !self.in_member {
+            self.writer.write_all(&header(self.extra_flags))?;
+            self.in_member = true;
+        }
+        Ok(())
+    }
+
+    /// Compress `data`, writing the output to the file.
+    fn compress(&mut self, data: &[u8], flush: TDEFLFlush) -> io::Result<()> {
+        self.start_member()?;
+        let (compressor, writer) = (&mut self.compressor, &mut self.writer);
+        let mut write_error = None;
+        let (status, _) =
+            compress_to_output(compressor, data, flush, |out| match writer.write_all(out) {
+                Ok(()) => true,
+                Err(err) => {
+                    write_error = Some(err);
+                    false
+                }
+            });
+        if let Some(err) = write_error {
+            return Err(err);
+        }
+        match status {
+            TDEFLStatus::Okay | TDEFLStatus::Done => {
+                self.crc.update(data);
+                self.member_size = self.member_size.wrapping_add(data.len() as u32);
+                Ok(())
+            }
+            _ => Err(io::Error::new(io::ErrorKind::Other, "compression failed")),
+        }
+    }
+
+    /// Flush the compressed data written so far so that it can all be decompressed, aligning
+    /// the output to a byte boundary. With `full` set, the data after this point can be
+    /// decompressed without the data before it.
+    pub fn flush_data(&mut self, full: bool) -> io::Result<()> {
+        let flush = if full {
+            TDEFLFlush::Full
+        } else {
+            TDEFLFlush::Sync
+        };
+        self.compress(&[], flush)
+    }
+
+    /// End the current member, writing its trailer. Data written after this goes in a new
+    /// member. If nothing has been written to the file yet, an empty member is written.
+    pub fn finish_member(&mut self) -> io::Result<()> {
+        self.compress(&[], TDEFLFlush::Finish)?;
+        let trailer = trailer(self.crc.clone().finalize(), self.member_size);
+        self.writer.write_all(&trailer)?;
+
+        self.compressor.reset();
+        self.crc = crc32fast::Hasher::new();
+        self.member_size = 0;
+        self.in_member = false;
+        self.finished_member = true;
+        Ok(())
+    }
+
+    /// End the file, returning the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.in_member || !self.finished_member {
+            self.finish_member()?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for GzWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        self.compress(data, TDEFLFlush::None)?;
+        Ok(data.len())
+    }
+
+    /// Do a sync flush of the compressed data, and flush the writer.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.in_member {
+            self.flush_data(false)?;
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::GzReader;
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn members_and_flushes() {
+        let mut writer = GzWriter::new(Vec::new(), 9);
+        writer.write_all(b"one ").unwrap();
+        writer.flush().unwrap();
+        // A sync flush ends with an empty stored block.
+        assert!(writer.get_ref().ends_with(&[0, 0, 0xff, 0xff]));
+        writer.write_all(b"two ").unwrap();
+        writer.finish_member().unwrap();
+        let first_member_len = writer.get_ref().len();
+        writer.write_all(b"three").unwrap();
+        writer.flush_data(true).unwrap();
+        let mut params = CompressionParams::from_level(1);
+        params.strategy = CompressionStrategy::HuffmanOnly;
+        writer.set_params(params);
+        writer.write_all(b" four").unwrap();
+        let file = writer.finish().unwrap();
+
+        assert_eq!(file[..4], [0x1f, 0x8b, 8, 0]);
+        assert_eq!(file[8], 2);
+        assert_eq!(file[first_member_len..first_member_len + 2], GZIP_MAGIC);
+        let mut data = Vec::new();
+        GzReader::new(&file[..]).read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"one two three four");
+    }
+
+    #[test]
+    fn empty() {
+        let file = GzWriter::new(Vec::new(), 6).finish().unwrap();
+        assert_eq!(file.len(), HEADER_SIZE + 2 + TRAILER_SIZE);
+
+        let mut writer = GzWriter::new(Vec::new(), 6);
+        writer.write_all(b"data").unwrap();
+        writer.finish_member().unwrap();
+        let len = writer.get_ref().len();
+        assert_eq!(writer.finish().unwrap().len(), len);
+    }
+}
@@ -24,10 +24,12 @@ mod libc {
 
     pub type c_void = u8;
     pub type c_int = i32;
+    pub type c_long = i64;
     pub type c_uint = u32;
     pub type c_ulong = u64;
     pub type c_char = i8;
     pub type size_t = usize;
+    pub type time_t = i64;
 
     pub unsafe fn malloc(a: size_t) -> *mut c_void {
         let size = a + mem::size_of::<size_t>();
@@ -61,19 +63,33 @@ mod libc {
 extern crate miniz_oxide;
 
 use std::panic::{catch_unwind, AssertUnwindSafe};
-use std::{cmp, ptr};
+use std::ptr::{self, NonNull};
+use std::{cmp, slice};
 
-use libc::{c_int, c_uint, c_ulong};
+use libc::{c_char, c_int, c_uint, c_ulong, c_void};
 
 use miniz_oxide::deflate::core::CompressionStrategy;
 use miniz_oxide::deflate::CompressionLevel;
 pub use miniz_oxide::{MZError, MZFlush, MZResult, MZStatus};
 
+#[macro_use]
+mod unmangle;
+
+/// Fail to compile if the constant expression `$cond` is false.
+macro_rules! static_assert {
+    ($cond:expr) => {
+        const _: [(); 0] = [(); !$cond as usize];
+    };
+}
+
+pub mod gz;
 pub mod lib_oxide;
+pub mod png;
+pub mod zip;
+#[cfg(feature = "zlib_compat")]
+pub mod zlib;
 use crate::lib_oxide::*;
 
-#[macro_use]
-mod unmangle;
 mod tdef;
 mod tinfl;
 
@@ -91,35 +107,111 @@ fn as_c_return_code(r: MZResult) -> c_int {
     }
 }
 
-macro_rules! oxidize {
-    ($mz_func:ident, $mz_func_oxide:ident; $($arg_name:ident: $type_name:ident),*) => {
-        unmangle!(
-        pub unsafe extern "C" fn $mz_func(stream: *mut mz_stream, $($arg_name: $type_name),*)
-                                          -> c_int {
-            match stream.as_mut() {
-                None => MZError::Stream as c_int,
-                Some(stream) => {
-                    // Make sure we catch a potential panic, as
-                    // this is called from C.
-                    match catch_unwind(AssertUnwindSafe(|| {
-                        // Do some checks to see if the stream object has the right type.
-                        match StreamOxide::try_new(stream) {
-                            Ok(mut stream_oxide) => {
-                                let status = $mz_func_oxide(&mut stream_oxide, $($arg_name),*);
-                                *stream = stream_oxide.into_mz_stream();
-                                as_c_return_code(status) }
-                            Err(e) => {
-                                e as c_int
+/// Run `f` on the stream wrapped in a `StreamOxide`, returning the result as a C return code.
+///
+/// If `f` fails without setting a message, `msg` is set to the description of the error code.
+/// On success a previous message is left in place.
+unsafe fn with_stream_oxide<ST, F>(stream: *mut mz_stream, f: F) -> c_int
+where
+    ST: StateType,
+    F: FnOnce(&mut StreamOxide<ST>) -> MZResult,
+{
+    match stream.as_mut() {
+        None => MZError::Stream as c_int,
+        Some(stream) => {
+            // Make sure we catch a potential panic, as
+            // this is called from C.
+            let prev_msg = stream.msg;
+            match catch_unwind(AssertUnwindSafe(|| {
+                // Do some checks to see if the stream object has the right type.
+                match StreamOxide::try_new(stream) {
+                    Ok(mut stream_oxide) => {
+                        let status = f(&mut stream_oxide);
+                        if let Err(e) = status {
+                            if stream_oxide.msg.is_none() {
+                                stream_oxide.msg = return_code_message(e as c_int);
                             }
                         }
-                    })) {
-                        Ok(res) => res,
-                        Err(_) => {
-                            println!("FATAL ERROR: Caught panic!");
-                            MZError::Stream as c_int},
+                        *stream = stream_oxide.into_mz_stream();
+                        if stream.msg.is_null() {
+                            stream.msg = prev_msg;
+                        }
+                        as_c_return_code(status)
                     }
+                    Err(e) => e as c_int,
+                }
+            })) {
+                Ok(res) => res,
+                Err(_) => {
+                    stream.msg = panic_message().as_ptr();
+                    MZError::Stream as c_int
                 }
             }
+        }
+    }
+}
+
+/// Copy `source` to the uninitialized `dest`, using `copy_state` to copy the inner state.
+unsafe fn copy_stream<ST, F>(dest: *mut mz_stream, source: *mut mz_stream, copy_state: F) -> c_int
+where
+    ST: StateType,
+    F: FnOnce(&mut StreamOxide<ST>) -> Result<StateBox<InternalState>, MZError>,
+{
+    if dest.is_null() {
+        return MZError::Stream as c_int;
+    }
+
+    let mut state = None;
+    let status = with_stream_oxide(source, |stream_oxide| {
+        state = Some(copy_state(stream_oxide)?);
+        Ok(MZStatus::Ok)
+    });
+
+    if let Some(state) = state {
+        let source = &*source;
+        // The destination is not initialized, so it must not be dropped.
+        ptr::write(
+            dest,
+            mz_stream {
+                next_in: source.next_in,
+                avail_in: source.avail_in,
+                total_in: source.total_in,
+                next_out: source.next_out,
+                avail_out: source.avail_out,
+                total_out: source.total_out,
+                msg: source.msg,
+                state: Some(state),
+                zalloc: source.zalloc,
+                zfree: source.zfree,
+                opaque: source.opaque,
+                data_type: source.data_type,
+                adler: source.adler,
+                reserved: source.reserved,
+            },
+        );
+    }
+    status
+}
+
+/// Get the `len` bytes at `data`, which may only be null if `len` is 0.
+unsafe fn slice_or_empty<'a>(data: *const u8, len: c_uint) -> Option<&'a [u8]> {
+    if data.is_null() {
+        if len == 0 {
+            Some(&[])
+        } else {
+            None
+        }
+    } else {
+        Some(slice::from_raw_parts(data, len as usize))
+    }
+}
+
+macro_rules! oxidize {
+    ($mz_func:ident, $mz_func_oxide:ident; $($arg_name:ident: $type_name:ident),*) => {
+        unmangle!(
+        pub unsafe extern "C" fn $mz_func(stream: *mut mz_stream, $($arg_name: $type_name),*)
+                                          -> c_int {
+            with_stream_oxide(stream, |stream_oxide| $mz_func_oxide(stream_oxide, $($arg_name),*))
         });
     };
 }
@@ -128,10 +220,25 @@ oxidize!(mz_deflate, mz_deflate_oxide;
          flush: c_int);
 oxidize!(mz_deflateEnd, mz_deflate_end_oxide;);
 oxidize!(mz_deflateReset, mz_deflate_reset_oxide;);
+oxidize!(mz_deflateParams, mz_deflate_params_oxide;
+         level: c_int,
+         strategy: c_int);
+oxidize!(mz_deflatePrime, mz_deflate_prime_oxide;
+         bits: c_int,
+         value: c_int);
+oxidize!(mz_deflateTune, mz_deflate_tune_oxide;
+         good_length: c_int,
+         max_lazy: c_int,
+         nice_length: c_int,
+         max_chain: c_int);
 
 oxidize!(mz_inflate, mz_inflate_oxide;
          flush: c_int);
 oxidize!(mz_inflateEnd, mz_inflate_end_oxide;);
+oxidize!(mz_inflateReset, mz_inflate_reset_oxide;);
+oxidize!(mz_inflateReset2, mz_inflate_reset2_oxide;
+         window_bits: c_int);
+oxidize!(mz_inflateBackEnd, mz_inflate_back_end_oxide;);
 
 unmangle!(
     pub unsafe extern "C" fn mz_deflateInit(stream: *mut mz_stream, level: c_int) -> c_int {
@@ -157,29 +264,17 @@ unmangle!(
             None => MZError::Stream as c_int,
             Some(stream) => {
                 stream.data_type = StateTypeEnum::DeflateType;
-                // Make sure we catch a potential panic, as
-                // this is called from C.
-                match catch_unwind(AssertUnwindSafe(|| match StreamOxide::try_new(stream) {
-                    Ok(mut stream_oxide) => {
-                        let status = mz_deflate_init2_oxide(
-                            &mut stream_oxide,
-                            level,
-                            method,
-                            window_bits,
-                            mem_level,
-                            strategy,
-                        );
-                        *stream = stream_oxide.into_mz_stream();
-                        as_c_return_code(status)
-                    }
-                    Err(e) => e as c_int,
-                })) {
-                    Ok(res) => res,
-                    Err(_) => {
-                        println!("FATAL ERROR: Caught panic!");
-                        MZError::Stream as c_int
-                    }
-                }
+                stream.msg = ptr::null();
+                with_stream_oxide(stream, |stream_oxide| {
+                    mz_deflate_init2_oxide(
+                        stream_oxide,
+                        level,
+                        method,
+                        window_bits,
+                        mem_level,
+                        strategy,
+                    )
+                })
             }
         }
     }
@@ -189,22 +284,10 @@ unmangle!(
             None => MZError::Stream as c_int,
             Some(stream) => {
                 stream.data_type = StateTypeEnum::InflateType;
-                // Make sure we catch a potential panic, as
-                // this is called from C.
-                match catch_unwind(AssertUnwindSafe(|| match StreamOxide::try_new(stream) {
-                    Ok(mut stream_oxide) => {
-                        let status = mz_inflate_init2_oxide(&mut stream_oxide, window_bits);
-                        *stream = stream_oxide.into_mz_stream();
-                        as_c_return_code(status)
-                    }
-                    Err(e) => e as c_int,
-                })) {
-                    Ok(res) => res,
-                    Err(_) => {
-                        println!("FATAL ERROR: Caught panic!");
-                        MZError::Stream as c_int
-                    }
-                }
+                stream.msg = ptr::null();
+                with_stream_oxide(stream, |stream_oxide| {
+                    mz_inflate_init2_oxide(stream_oxide, window_bits)
+                })
             }
         }
     }
@@ -294,8 +377,165 @@ unmangle!(
     pub extern "C" fn mz_compressBound(source_len: c_ulong) -> c_ulong {
         mz_deflateBound(ptr::null_mut(), source_len)
     }
+
+    pub unsafe extern "C" fn mz_deflateSetDictionary(
+        stream: *mut mz_stream,
+        dictionary: *const u8,
+        dict_length: c_uint,
+    ) -> c_int {
+        with_stream_oxide(stream, |stream_oxide| {
+            let dictionary = slice_or_empty(dictionary, dict_length).ok_or(MZError::Stream)?;
+            mz_deflate_set_dictionary_oxide(stream_oxide, dictionary)
+        })
+    }
+
+    pub unsafe extern "C" fn mz_deflateCopy(dest: *mut mz_stream, source: *mut mz_stream) -> c_int {
+        copy_stream(dest, source, mz_deflate_copy_oxide)
+    }
+
+    /// Get the number of bytes and bits of output that did not fit in the output buffer.
+    /// Either of `pending` and `bits` may be null.
+    pub unsafe extern "C" fn mz_deflatePending(
+        stream: *mut mz_stream,
+        pending: *mut c_uint,
+        bits: *mut c_int,
+    ) -> c_int {
+        with_stream_oxide(stream, |stream_oxide| {
+            let (mut pending_bytes, mut pending_bits) = (0, 0);
+            mz_deflate_pending_oxide(stream_oxide, &mut pending_bytes, &mut pending_bits)?;
+            if let Some(pending) = pending.as_mut() {
+                *pending = pending_bytes;
+            }
+            if let Some(bits) = bits.as_mut() {
+                *bits = pending_bits;
+            }
+            Ok(MZStatus::Ok)
+        })
+    }
+
+    pub unsafe extern "C" fn mz_inflateSetDictionary(
+        stream: *mut mz_stream,
+        dictionary: *const u8,
+        dict_length: c_uint,
+    ) -> c_int {
+        with_stream_oxide(stream, |stream_oxide| {
+            let dictionary = slice_or_empty(dictionary, dict_length).ok_or(MZError::Stream)?;
+            mz_inflate_set_dictionary_oxide(stream_oxide, dictionary)
+        })
+    }
+
+    /// Copy the window of the decompressor to `dictionary` and set `dict_length` to its length,
+    /// which is at most 32768 bytes.
+    /// If `dictionary` is null, only the length is set. `dict_length` may be null.
+    pub unsafe extern "C" fn mz_inflateGetDictionary(
+        stream: *mut mz_stream,
+        dictionary: *mut u8,
+        dict_length: *mut c_uint,
+    ) -> c_int {
+        with_stream_oxide(stream, |stream_oxide| {
+            let mut len = 0;
+            mz_inflate_get_dictionary_oxide(stream_oxide, &mut [], &mut len)?;
+            if !dictionary.is_null() {
+                let dictionary = slice::from_raw_parts_mut(dictionary, len as usize);
+                mz_inflate_get_dictionary_oxide(stream_oxide, dictionary, &mut len)?;
+            }
+            if let Some(dict_length) = dict_length.as_mut() {
+                *dict_length = len;
+            }
+            Ok(MZStatus::Ok)
+        })
+    }
+
+    pub unsafe extern "C" fn mz_inflateCopy(dest: *mut mz_stream, source: *mut mz_stream) -> c_int {
+        copy_stream(dest, source, mz_inflate_copy_oxide)
+    }
+
+    /// Set up a stream for `mz_inflateBack`, which decompresses raw deflate data using the
+    /// `1 << window_bits` bytes at `window` as its only buffer.
+    ///
+    /// `window_bits` has to be from 8 to 15.
+    ///
+    /// # Safety
+    ///
+    /// `stream` has to be null or point to a valid `mz_stream`. `window` has to be null or point
+    /// to `1 << window_bits` bytes that stay valid, and are not used by the caller, until
+    /// `mz_inflateBackEnd` is called.
+    pub unsafe extern "C" fn mz_inflateBackInit(
+        stream: *mut mz_stream,
+        window_bits: c_int,
+        window: *mut u8,
+    ) -> c_int {
+        let window = match NonNull::new(window) {
+            Some(window) if (8..=15).contains(&window_bits) => window,
+            _ => return MZError::Stream as c_int,
+        };
+        match stream.as_mut() {
+            None => MZError::Stream as c_int,
+            Some(stream) => {
+                stream.data_type = StateTypeEnum::InflateBackType;
+                stream.msg = ptr::null();
+                with_stream_oxide(stream, move |stream_oxide| {
+                    mz_inflate_back_init_oxide(stream_oxide, window, 1 << window_bits)
+                })
+            }
+        }
+    }
+
+    /// Decompress a whole raw deflate stream, starting with the input in `next_in` and calling
+    /// `in_func` for more, and passing the output to `out_func` each time the window is full.
+    ///
+    /// Returns `MZ_STREAM_END` when done, with `next_in` and `avail_in` set to the unused
+    /// input. Returns `MZ_BUF_ERROR` if `in_func` returned no input, in which case `next_in` is
+    /// null, or if `out_func` returned non-zero.
+    ///
+    /// # Safety
+    ///
+    /// `stream` has to be null or point to a valid `mz_stream` set up with `mz_inflateBackInit`,
+    /// with `next_in` pointing to `avail_in` readable bytes. `in_func` has to set its pointer
+    /// argument to null or to as many readable bytes as it returns, which stay valid until it is
+    /// called again or `mz_inflateBack` returns.
+    pub unsafe extern "C" fn mz_inflateBack(
+        stream: *mut mz_stream,
+        in_func: mz_in_func,
+        in_desc: *mut c_void,
+        out_func: mz_out_func,
+        out_desc: *mut c_void,
+    ) -> c_int {
+        let (in_func, out_func) = match (in_func, out_func) {
+            (Some(in_func), Some(out_func)) => (in_func, out_func),
+            _ => return MZError::Stream as c_int,
+        };
+        with_stream_oxide(stream, |stream_oxide| {
+            mz_inflate_back_oxide(
+                stream_oxide,
+                || {
+                    let mut buf = ptr::null();
+                    let len = in_func(in_desc, &mut buf);
+                    if buf.is_null() {
+                        &[]
+                    } else {
+                        slice::from_raw_parts(buf, len as usize)
+                    }
+                },
+                |out| out_func(out_desc, out.as_ptr() as *mut u8, out.len() as c_uint) == 0,
+            )
+        })
+    }
+
+    /// Get a description of the return code `err`, or null if it isn't one.
+    pub extern "C" fn mz_error(err: c_int) -> *const c_char {
+        return_code_message(err).map_or(ptr::null(), |msg| msg.as_ptr())
+    }
+
+    /// Get the version of the miniz API that is implemented.
+    pub extern "C" fn mz_version() -> *const c_char {
+        MZ_VERSION.as_ptr() as *const c_char
+    }
 );
 
+/// Version string returned by `mz_version`, matching `MZ_VERSION` in `miniz_extra_defs.h`.
+const MZ_VERSION: &[u8] = b"0.0\0";
+
 #[cfg(target_bit_width = "64")]
 #[inline]
 fn buffer_too_large(source_len: c_ulong, dest_len: c_ulong) -> bool {
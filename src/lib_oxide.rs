@@ -1,16 +1,23 @@
 //! This module mainly contains functionality replicating the miniz higher level API.
 
 use std::default::Default;
-use std::{fmt, mem};
+use std::ffi::CStr;
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+use std::{cmp, fmt, mem, slice};
 
-use libc::c_ulong;
+use libc::{c_int, c_uint, c_ulong, c_void};
 
-use crate::tdef::Compressor;
+use crate::gz::stream::{GzipDecoder, GzipEncoder, GzipError, HeaderStatus};
+use crate::{miniz_def_alloc_func, miniz_def_free_func, mz_alloc_func, mz_free_func};
 use miniz_oxide::deflate::core::{
-    create_comp_flags_from_zip_params, deflate_flags, CompressionStrategy, CompressorOxide,
+    create_comp_flags_from_zip_params, deflate_flags, BufferSizes, CompressionParams,
+    CompressionStrategy, CompressorWorkspace, WorkspaceCompressor,
 };
-use miniz_oxide::deflate::stream::deflate;
-use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::deflate::stream::deflate_workspace;
+use miniz_oxide::inflate::core::{DecompressorOxide, FailureReason};
+use miniz_oxide::inflate::stream::{inflate, InflateState, MinReset};
+use miniz_oxide::inflate::{decompress_with_callbacks, CallbackError, TINFLStatus};
 
 use miniz_oxide::*;
 
@@ -19,8 +26,9 @@ const MZ_DEFLATED: i32 = 8;
 pub use miniz_oxide::MZ_ADLER32_INIT;
 
 pub enum InternalState {
-    Inflate(Box<InflateState>),
-    Deflate(Box<Compressor>),
+    Inflate(StateBox<InflateStreamState>),
+    Deflate(StateBox<DeflateState>),
+    InflateBack(StateBox<InflateBackState>),
 }
 
 impl fmt::Debug for InternalState {
@@ -28,6 +36,7 @@ impl fmt::Debug for InternalState {
         let name = match &self {
             InternalState::Inflate(_) => "Decompressor",
             InternalState::Deflate(_) => "Compressor",
+            InternalState::InflateBack(_) => "Callback decompressor",
         };
         write!(f, "{}", name)
     }
@@ -35,6 +44,238 @@ impl fmt::Debug for InternalState {
 
 pub type MZResult = Result<MZStatus, MZError>;
 
+/// The allocation functions of a stream, along with the opaque pointer passed to them.
+#[derive(Debug, Copy, Clone)]
+pub struct Allocator {
+    pub alloc: mz_alloc_func,
+    pub free: mz_free_func,
+    pub opaque: *mut c_void,
+}
+
+impl Default for Allocator {
+    /// Use `miniz_def_alloc_func` and `miniz_def_free_func`.
+    fn default() -> Allocator {
+        Allocator {
+            alloc: miniz_def_alloc_func,
+            free: miniz_def_free_func,
+            opaque: ptr::null_mut(),
+        }
+    }
+}
+
+/// A value allocated with an [`Allocator`], along with the allocator used to free it.
+struct Allocation<T> {
+    alloc: Allocator,
+    value: T,
+}
+
+/// Pointer to a value in memory allocated with the allocation functions of a stream, which is
+/// freed with the same functions when dropped.
+///
+/// This is used instead of `Box` so that the memory of the internal state of a stream comes from
+/// the `zalloc` function of the stream.
+pub struct StateBox<T> {
+    ptr: NonNull<Allocation<T>>,
+}
+
+impl<T> StateBox<T> {
+    /// Move `value` into memory allocated with `alloc`.
+    ///
+    /// Returns `MZError::Mem` if the allocation fails.
+    pub fn new(alloc: Allocator, value: T) -> Result<StateBox<T>, MZError> {
+        unsafe {
+            let ptr = StateBox::allocate(alloc)?;
+            ptr::write(ptr.as_ptr(), Allocation { alloc, value });
+            Ok(StateBox { ptr })
+        }
+    }
+
+    /// Allocate memory with `alloc` for a value with all bytes set to zero, avoiding
+    /// constructing large values on the stack.
+    ///
+    /// Returns `MZError::Mem` if the allocation fails.
+    ///
+    /// # Safety
+    ///
+    /// All bytes set to zero has to be a valid value of `T`.
+    pub unsafe fn new_zeroed(alloc: Allocator) -> Result<StateBox<T>, MZError> {
+        let ptr = StateBox::<T>::allocate(alloc)?;
+        ptr::write_bytes(ptr.as_ptr(), 0, 1);
+        ptr::write(&mut (*ptr.as_ptr()).alloc, alloc);
+        Ok(StateBox { ptr })
+    }
+
+    unsafe fn allocate(alloc: Allocator) -> Result<NonNull<Allocation<T>>, MZError> {
+        let ptr = (alloc.alloc)(alloc.opaque, 1, mem::size_of::<Allocation<T>>());
+        let ptr = NonNull::new(ptr as *mut Allocation<T>).ok_or(MZError::Mem)?;
+        // Allocation functions return memory aligned for any type, like `malloc`, but check it
+        // rather than risk unaligned accesses.
+        if ptr.as_ptr() as usize % mem::align_of::<Allocation<T>>() != 0 {
+            (alloc.free)(alloc.opaque, ptr.as_ptr() as *mut c_void);
+            return Err(MZError::Mem);
+        }
+        Ok(ptr)
+    }
+
+    /// The allocation functions the value was allocated with.
+    pub fn allocator(&self) -> Allocator {
+        unsafe { self.ptr.as_ref().alloc }
+    }
+}
+
+impl<T> Deref for StateBox<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &self.ptr.as_ref().value }
+    }
+}
+
+impl<T> DerefMut for StateBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut self.ptr.as_mut().value }
+    }
+}
+
+impl<T> AsRef<T> for StateBox<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T> AsMut<T> for StateBox<T> {
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+impl<T> Drop for StateBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let alloc = self.allocator();
+            ptr::drop_in_place(self.ptr.as_ptr());
+            (alloc.free)(alloc.opaque, self.ptr.as_ptr() as *mut c_void);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for StateBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// The compressor of a deflate stream.
+///
+/// The buffers of the compressor are kept in a workspace allocated with the allocation functions
+/// of the stream.
+pub struct DeflateState {
+    // The compressor borrows the workspace, so it has to be dropped first.
+    compressor: WorkspaceCompressor<'static>,
+    _workspace: StateBox<CompressorWorkspace>,
+    /// The gzip wrapper written around the deflate data, if any.
+    gzip: Option<GzipEncoder>,
+}
+
+impl DeflateState {
+    /// Create a compressor with the given flags and buffer sizes, allocating its workspace with
+    /// `alloc`.
+    fn new(
+        alloc: Allocator,
+        flags: u32,
+        sizes: BufferSizes,
+        gzip: Option<GzipEncoder>,
+    ) -> Result<DeflateState, MZError> {
+        DeflateState::with_workspace(alloc, gzip, |workspace| {
+            WorkspaceCompressor::with_buffers(flags, sizes, workspace.buffers())
+                .expect("the workspace has room for any buffer sizes")
+        })
+    }
+
+    /// Create a copy of the compressor, allocating its workspace with `alloc`.
+    fn copy(&self, alloc: Allocator) -> Result<DeflateState, MZError> {
+        DeflateState::with_workspace(alloc, self.gzip.clone(), |workspace| {
+            self.compressor.copy_into(workspace)
+        })
+    }
+
+    fn with_workspace<F>(
+        alloc: Allocator,
+        gzip: Option<GzipEncoder>,
+        create: F,
+    ) -> Result<DeflateState, MZError>
+    where
+        F: FnOnce(&'static mut CompressorWorkspace) -> WorkspaceCompressor<'static>,
+    {
+        // The workspace is only made of integers, so zeroed memory is a valid workspace.
+        let mut workspace = unsafe { StateBox::<CompressorWorkspace>::new_zeroed(alloc)? };
+        // The workspace stays at the same place when the box is moved, and the compressor
+        // borrowing it is dropped before it. It is not accessed through the box otherwise.
+        let borrowed = unsafe { &mut *(&mut *workspace as *mut CompressorWorkspace) };
+        Ok(DeflateState {
+            compressor: create(borrowed),
+            _workspace: workspace,
+            gzip,
+        })
+    }
+}
+
+/// The decompressor of a stream, along with the gzip wrapper if the stream may have one.
+#[derive(Clone)]
+pub struct InflateStreamState {
+    inflate: InflateState,
+    wrapper: Wrapper,
+    gzip: Option<GzipDecoder>,
+}
+
+impl InflateStreamState {
+    fn new(wrapper: Wrapper) -> InflateStreamState {
+        InflateStreamState {
+            inflate: InflateState::new(wrapper.inflate_format()),
+            wrapper,
+            gzip: wrapper.gzip_decoder(),
+        }
+    }
+
+    /// Start decompressing a new stream with the given wrapper.
+    fn reset(&mut self, wrapper: Wrapper) {
+        self.inflate.reset(wrapper.inflate_format());
+        self.wrapper = wrapper;
+        self.gzip = wrapper.gzip_decoder();
+    }
+}
+
+/// The decompressor of a stream used with `mz_inflateBack`, which uses a window provided by
+/// the caller as its only buffer.
+pub struct InflateBackState {
+    decomp: DecompressorOxide,
+    window: NonNull<u8>,
+    window_len: usize,
+}
+
+impl InflateBackState {
+    /// Create the state for a window of `window_len` bytes at `window` given by the caller.
+    ///
+    /// # Safety
+    ///
+    /// `window` has to be valid for reads and writes of `window_len` bytes, and not be accessed
+    /// by anything else, until the state is dropped.
+    pub unsafe fn new(window: NonNull<u8>, window_len: usize) -> InflateBackState {
+        InflateBackState {
+            decomp: DecompressorOxide::new(),
+            window,
+            window_len,
+        }
+    }
+
+    /// The decompressor and the window.
+    fn parts(&mut self) -> (&mut DecompressorOxide, &mut [u8]) {
+        // Safe as `new` requires the window to stay valid and unused elsewhere while the state
+        // exists, and the slice borrows the state.
+        let window = unsafe { slice::from_raw_parts_mut(self.window.as_ptr(), self.window_len) };
+        (&mut self.decomp, window)
+    }
+}
+
 /// Enum to keep track of what type the internal state is when moving over the C API boundary.
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -42,6 +283,7 @@ pub enum StateTypeEnum {
     None = 0,
     InflateType,
     DeflateType,
+    InflateBackType,
 }
 
 /// Trait used for states that can be carried by BoxedState.
@@ -50,7 +292,7 @@ pub trait StateType {
     fn from_enum(value: &mut InternalState) -> Option<&mut Self>;
 }
 
-impl StateType for InflateState {
+impl StateType for InflateStreamState {
     const STATE_TYPE: StateTypeEnum = StateTypeEnum::InflateType;
     fn from_enum(value: &mut InternalState) -> Option<&mut Self> {
         if let InternalState::Inflate(state) = value {
@@ -61,7 +303,7 @@ impl StateType for InflateState {
     }
 }
 
-impl StateType for Compressor {
+impl StateType for DeflateState {
     const STATE_TYPE: StateTypeEnum = StateTypeEnum::DeflateType;
     fn from_enum(value: &mut InternalState) -> Option<&mut Self> {
         if let InternalState::Deflate(state) = value {
@@ -72,6 +314,17 @@ impl StateType for Compressor {
     }
 }
 
+impl StateType for InflateBackState {
+    const STATE_TYPE: StateTypeEnum = StateTypeEnum::InflateBackType;
+    fn from_enum(value: &mut InternalState) -> Option<&mut Self> {
+        if let InternalState::InflateBack(state) = value {
+            Some(state.as_mut())
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct StreamOxide<'io, ST: StateType> {
     pub next_in: Option<&'io [u8]>,
@@ -80,9 +333,13 @@ pub struct StreamOxide<'io, ST: StateType> {
     pub next_out: Option<&'io mut [u8]>,
     pub total_out: c_ulong,
 
-    pub(crate) state: Option<Box<InternalState>>,
+    pub(crate) state: Option<StateBox<InternalState>>,
 
     pub adler: u32,
+    /// Description of the last error, stored in the `msg` field of the C stream.
+    pub msg: Option<&'static CStr>,
+    /// The allocation functions used for the internal state.
+    pub alloc: Allocator,
     pub(crate) state_type: std::marker::PhantomData<ST>,
 }
 
@@ -92,9 +349,115 @@ impl<'io, ST: StateType> StreamOxide<'io, ST> {
     }
 }
 
-/// Returns true if the window_bits parameter is valid.
-fn invalid_window_bits(window_bits: i32) -> bool {
-    (window_bits != MZ_DEFAULT_WINDOW_BITS) && (-window_bits != MZ_DEFAULT_WINDOW_BITS)
+/// Create a `CStr` from a static nul-terminated byte string.
+fn static_c_str(bytes: &'static [u8]) -> &'static CStr {
+    CStr::from_bytes_with_nul(bytes).expect("message is not nul-terminated")
+}
+
+/// Get the description of the return code `code`, as returned by `mz_error`, or `None` if it is
+/// not a valid return code.
+pub fn return_code_message(code: c_int) -> Option<&'static CStr> {
+    let message: &'static [u8] = match code {
+        2 => b"need dictionary\0",
+        1 => b"stream end\0",
+        0 => b"\0",
+        -1 => b"file error\0",
+        -2 => b"stream error\0",
+        -3 => b"data error\0",
+        -4 => b"out of memory\0",
+        -5 => b"buf error\0",
+        -6 => b"version error\0",
+        -10000 => b"parameter error\0",
+        _ => return None,
+    };
+    Some(static_c_str(message))
+}
+
+/// Description used when a panic is caught in the C API.
+pub fn panic_message() -> &'static CStr {
+    static_c_str(b"internal error: caught panic\0")
+}
+
+/// Get the description of why decompression failed with `status`, using the messages zlib uses.
+fn inflate_error_message(status: TINFLStatus, decomp: &DecompressorOxide) -> Option<&'static CStr> {
+    if status == TINFLStatus::Adler32Mismatch {
+        return Some(static_c_str(b"incorrect data check\0"));
+    }
+    let message: &'static [u8] = match decomp.failure_reason()? {
+        FailureReason::BadZlibHeader => b"incorrect header check\0",
+        FailureReason::BadBlockType => b"invalid block type\0",
+        FailureReason::BadStoredLength => b"invalid stored block lengths\0",
+        FailureReason::BadTableSizes => b"too many length or distance symbols\0",
+        FailureReason::BadCodeLengths => b"invalid code lengths set\0",
+        FailureReason::BadLengthRepeat => b"invalid bit length repeat\0",
+        FailureReason::InvalidCode => b"invalid code\0",
+        FailureReason::InvalidLiteralLength => b"invalid literal/length code\0",
+        FailureReason::InvalidDistance => b"invalid distance code\0",
+        FailureReason::DistanceTooFarBack => b"invalid distance too far back\0",
+        _ => b"invalid data\0",
+    };
+    Some(static_c_str(message))
+}
+
+/// Get the message zlib uses for an invalid gzip wrapper.
+fn gzip_error_message(err: GzipError) -> &'static CStr {
+    let message: &'static [u8] = match err {
+        GzipError::HeaderCheck => b"incorrect header check\0",
+        GzipError::Method => b"unknown compression method\0",
+        GzipError::Flags => b"unknown header flags set\0",
+        GzipError::HeaderCrc => b"header crc mismatch\0",
+        GzipError::DataCheck => b"incorrect data check\0",
+        GzipError::LengthCheck => b"incorrect length check\0",
+    };
+    static_c_str(message)
+}
+
+/// The wrapper around the deflate data of a stream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Wrapper {
+    Raw,
+    Zlib,
+    Gzip,
+    /// Either zlib or gzip, detected from the start of the data when decompressing.
+    Detect,
+}
+
+impl Wrapper {
+    /// The format the decompressor starts with.
+    fn inflate_format(self) -> DataFormat {
+        match self {
+            Wrapper::Raw | Wrapper::Gzip => DataFormat::Raw,
+            Wrapper::Zlib | Wrapper::Detect => DataFormat::Zlib,
+        }
+    }
+
+    fn gzip_decoder(self) -> Option<GzipDecoder> {
+        match self {
+            Wrapper::Gzip => Some(GzipDecoder::new(false)),
+            Wrapper::Detect => Some(GzipDecoder::new(true)),
+            Wrapper::Raw | Wrapper::Zlib => None,
+        }
+    }
+}
+
+/// Split `window_bits` into the wrapper and the size of the window in bits, as zlib does: the
+/// window bits are negated for raw deflate, 16 is added for gzip, and 32 is added to detect
+/// whether the data is zlib or gzip when decompressing.
+///
+/// Returns `None` if the size of the window is not from 8 to 15 bits.
+fn parse_window_bits(window_bits: i32) -> Option<(Wrapper, i32)> {
+    let (wrapper, bits) = match window_bits {
+        _ if window_bits < 0 => (Wrapper::Raw, window_bits.checked_neg()?),
+        0..=15 => (Wrapper::Zlib, window_bits),
+        16..=31 => (Wrapper::Gzip, window_bits - 16),
+        32..=47 => (Wrapper::Detect, window_bits - 32),
+        _ => return None,
+    };
+    if (8..=MZ_DEFAULT_WINDOW_BITS).contains(&bits) {
+        Some((wrapper, bits))
+    } else {
+        None
+    }
 }
 
 /// Try to fully decompress the data provided in the stream struct, with the specified
@@ -102,7 +465,7 @@ fn invalid_window_bits(window_bits: i32) -> bool {
 ///
 /// Returns MZResult::Ok on success.
 pub fn mz_compress2_oxide(
-    stream_oxide: &mut StreamOxide<Compressor>,
+    stream_oxide: &mut StreamOxide<DeflateState>,
     level: i32,
     dest_len: &mut c_ulong,
 ) -> MZResult {
@@ -123,7 +486,7 @@ pub fn mz_compress2_oxide(
 /// Initialize the wrapped compressor with the requested level (0-10) and default settings.
 ///
 /// The compression level will be set to 6 (default) if the requested level is not available.
-pub fn mz_deflate_init_oxide(stream_oxide: &mut StreamOxide<Compressor>, level: i32) -> MZResult {
+pub fn mz_deflate_init_oxide(stream_oxide: &mut StreamOxide<DeflateState>, level: i32) -> MZResult {
     mz_deflate_init2_oxide(
         stream_oxide,
         level,
@@ -140,42 +503,64 @@ pub fn mz_deflate_init_oxide(stream_oxide: &mut StreamOxide<Compressor>, level:
 /// stream_oxide: The stream to be initialized.
 /// level: Compression level (0-10).
 /// method: Compression method. Only `MZ_DEFLATED` is accepted.
-/// window_bits: Number of bits used to represent the compression sliding window.
-///              Only `MZ_DEFAULT_WINDOW_BITS` is currently supported.
+/// window_bits: Number of bits used to represent the compression sliding window, from 9 to 15,
+///              or 8 with a zlib wrapper. Windows smaller than 1 KiB are not supported, so
+///              10 bits are used instead, which the zlib header states as zlib does when
+///              asked for 8 bits.
 ///              A negative value, i.e `-MZ_DEFAULT_WINDOW_BITS` indicates that the stream
-///              should be not be wrapped in a zlib wrapper.
+///              should be not be wrapped in a zlib wrapper. Adding 16 writes a gzip wrapper
+///              instead.
 /// mem_level: Currently unused. Only values from 1 to and including 9 are accepted.
 /// strategy: Compression strategy. See `deflate::CompressionStrategy` for accepted options.
-///           The default, which is used in most cases, is 0.
+///           The default, which is used in most cases, is 0. Other values are rejected.
 pub fn mz_deflate_init2_oxide(
-    stream_oxide: &mut StreamOxide<Compressor>,
+    stream_oxide: &mut StreamOxide<DeflateState>,
     level: i32,
     method: i32,
     window_bits: i32,
     mem_level: i32,
     strategy: i32,
 ) -> MZResult {
+    let (wrapper, bits) = match parse_window_bits(window_bits) {
+        Some((Wrapper::Detect, _)) | None => return Err(MZError::Param),
+        Some((wrapper, bits)) if bits > 8 || wrapper == Wrapper::Zlib => (wrapper, bits),
+        Some(_) => return Err(MZError::Param),
+    };
+    let format_bits = if wrapper == Wrapper::Zlib {
+        bits
+    } else {
+        -bits
+    };
     let comp_flags = deflate_flags::TDEFL_COMPUTE_ADLER32
-        | create_comp_flags_from_zip_params(level, window_bits, strategy);
+        | create_comp_flags_from_zip_params(level, format_bits, strategy);
 
     let invalid_level = !(1..=9).contains(&mem_level);
-    if (method != MZ_DEFLATED) || invalid_level || invalid_window_bits(window_bits) {
+    let invalid_strategy = !(0..=CompressionStrategy::Fixed as i32).contains(&strategy);
+    if (method != MZ_DEFLATED) || invalid_level || invalid_strategy {
         return Err(MZError::Param);
     }
 
-    stream_oxide.adler = MZ_ADLER32_INIT;
+    let gzip = if wrapper == Wrapper::Gzip {
+        let level = if (0..=10).contains(&level) { level } else { 6 };
+        Some(GzipEncoder::new(level as u8))
+    } else {
+        None
+    };
+    stream_oxide.adler = if gzip.is_some() { 0 } else { MZ_ADLER32_INIT };
     stream_oxide.total_in = 0;
     stream_oxide.total_out = 0;
 
-    let mut compr: Box<Compressor> = Box::default();
-    compr.inner = Some(CompressorOxide::new(comp_flags));
-    stream_oxide.state = Some(Box::new(InternalState::Deflate(compr)));
+    // The buffer sizes clamp the window to the smallest one supported.
+    let sizes = BufferSizes::new(bits as u8, BufferSizes::MAX_HASH_BITS, usize::max_value());
+    let alloc = stream_oxide.alloc;
+    let compressor = StateBox::new(alloc, DeflateState::new(alloc, comp_flags, sizes, gzip)?)?;
+    stream_oxide.state = Some(StateBox::new(alloc, InternalState::Deflate(compressor))?);
 
     Ok(MZStatus::Ok)
 }
 
-pub fn mz_deflate_oxide(stream_oxide: &mut StreamOxide<Compressor>, flush: i32) -> MZResult {
-    let state: &mut Compressor = {
+pub fn mz_deflate_oxide(stream_oxide: &mut StreamOxide<DeflateState>, flush: i32) -> MZResult {
+    let state: &mut DeflateState = {
         let enum_ref = stream_oxide.state.as_mut().ok_or(MZError::Stream)?;
         StateType::from_enum(enum_ref)
     }
@@ -185,14 +570,13 @@ pub fn mz_deflate_oxide(stream_oxide: &mut StreamOxide<Compressor>, flush: i32)
 
     let flush = MZFlush::new(flush)?;
 
-    let ret = if let Some(compressor) = state.inner.as_mut() {
-        deflate(compressor, next_in, next_out, flush)
-    } else {
-        return Err(MZError::Param);
+    let ret = match state.gzip.as_mut() {
+        Some(gzip) => deflate_gzip(&mut state.compressor, gzip, next_in, next_out, flush),
+        None => deflate_workspace(&mut state.compressor, next_in, next_out, flush),
     };
 
-    *next_in = &next_in[ret.bytes_consumed as usize..];
-    *next_out = &mut mem::replace(next_out, &mut [])[ret.bytes_written as usize..];
+    *next_in = &next_in[ret.bytes_consumed..];
+    *next_out = &mut mem::replace(next_out, &mut [])[ret.bytes_written..];
     // Wrapping add to emulate miniz_behaviour, will wrap around >4 GiB on 32-bit.
     stream_oxide.total_in = stream_oxide
         .total_in
@@ -200,14 +584,108 @@ pub fn mz_deflate_oxide(stream_oxide: &mut StreamOxide<Compressor>, flush: i32)
     stream_oxide.total_out = stream_oxide
         .total_out
         .wrapping_add(ret.bytes_written as c_ulong);
-    stream_oxide.adler = state.adler32();
+    stream_oxide.adler = match state.gzip.as_ref() {
+        Some(gzip) => gzip.crc(),
+        None => state.compressor.adler32(),
+    };
     ret.into()
 }
 
+/// Compress with a gzip wrapper, writing the header before the deflate data and the trailer
+/// after it.
+fn deflate_gzip(
+    compressor: &mut WorkspaceCompressor,
+    gzip: &mut GzipEncoder,
+    input: &[u8],
+    output: &mut [u8],
+    flush: MZFlush,
+) -> StreamResult {
+    let header_len = if gzip.in_header() {
+        gzip.write_pending(output)
+    } else {
+        0
+    };
+    let mut ret = if gzip.in_header() {
+        StreamResult::error(MZError::Buf)
+    } else if gzip.finished() {
+        StreamResult {
+            bytes_consumed: 0,
+            bytes_written: 0,
+            status: Ok(MZStatus::StreamEnd),
+        }
+    } else {
+        deflate_workspace(compressor, input, &mut output[header_len..], flush)
+    };
+    gzip.update(&input[..ret.bytes_consumed]);
+    ret.bytes_written += header_len;
+
+    if ret.status == Ok(MZStatus::StreamEnd) {
+        gzip.finish();
+        ret.bytes_written += gzip.write_pending(&mut output[ret.bytes_written..]);
+        if gzip.pending() > 0 {
+            ret.status = Ok(MZStatus::Ok);
+        }
+    } else if ret.status == Err(MZError::Buf) && ret.bytes_written > 0 {
+        ret.status = Ok(MZStatus::Ok);
+    }
+    ret
+}
+
+/// Change the compression level and strategy of the compressor.
+///
+/// As in zlib, if compression has started and the strategy changes, or the level changes between
+/// level 0, levels 1 to 3 and the higher levels, the input given so far is first compressed with
+/// the old settings, ending the current block as `mz_deflate` with `MZFlush::Block` does. Other
+/// changes take effect right away.
+///
+/// Returns `MZError::Param` if `level` is not -1 (the default level) or 0 to 10, or `strategy`
+/// is not one of the values of `CompressionStrategy`, `MZError::Buf` if there was not enough
+/// output space to compress the input given so far, in which case the settings are not changed,
+/// and `MZError::Stream` if the inner stream is missing, otherwise `MZStatus::Ok`.
+pub fn mz_deflate_params_oxide(
+    stream_oxide: &mut StreamOxide<DeflateState>,
+    level: i32,
+    strategy: i32,
+) -> MZResult {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    if !(-1..=10).contains(&level) || !(0..=CompressionStrategy::Fixed as i32).contains(&strategy) {
+        return Err(MZError::Param);
+    }
+    let window_bits = state.compressor.data_format().to_window_bits();
+    let flags = create_comp_flags_from_zip_params(level, window_bits, strategy);
+    let params = CompressionParams::from_flags(flags);
+
+    // Like zlib, flush when switching between stored, greedy and lazy compression or changing
+    // the strategy.
+    let current = state.compressor.params();
+    let switch = current.strategy != params.strategy
+        || current.raw_blocks != params.raw_blocks
+        || current.greedy != params.greedy;
+    if switch && stream_oxide.total_in != 0 {
+        match mz_deflate_oxide(stream_oxide, MZFlush::Block as i32) {
+            Ok(_) | Err(MZError::Buf) => (),
+            Err(e) => return Err(e),
+        }
+        let no_input = stream_oxide
+            .next_in
+            .as_ref()
+            .map_or(true, |next_in| next_in.is_empty());
+        let state = stream_oxide.state().ok_or(MZError::Stream)?;
+        let compressor = &state.compressor;
+        if !no_input || compressor.pending_input() != 0 || compressor.pending_output().0 != 0 {
+            return Err(MZError::Buf);
+        }
+    }
+
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    state.compressor.set_params(params);
+    Ok(MZStatus::Ok)
+}
+
 /// Free the inner compression state.
 ///
 /// Currently always returns `MZStatus::Ok`.
-pub fn mz_deflate_end_oxide(stream_oxide: &mut StreamOxide<Compressor>) -> MZResult {
+pub fn mz_deflate_end_oxide(stream_oxide: &mut StreamOxide<DeflateState>) -> MZResult {
     stream_oxide.state = None;
     Ok(MZStatus::Ok)
 }
@@ -216,42 +694,162 @@ pub fn mz_deflate_end_oxide(stream_oxide: &mut StreamOxide<Compressor>) -> MZRes
 ///
 /// Returns `MZError::Stream` if the inner stream is missing, otherwise `MZStatus::Ok`.
 // TODO: probably not covered by tests
-pub fn mz_deflate_reset_oxide(stream_oxide: &mut StreamOxide<Compressor>) -> MZResult {
+pub fn mz_deflate_reset_oxide(stream_oxide: &mut StreamOxide<DeflateState>) -> MZResult {
     stream_oxide.total_in = 0;
     stream_oxide.total_out = 0;
     stream_oxide.adler = 0;
     stream_oxide.next_in = None;
     stream_oxide.next_out = None;
     let state = stream_oxide.state().ok_or(MZError::Stream)?;
-    state.reset();
+    state.compressor.reset();
+    if let Some(gzip) = state.gzip.as_mut() {
+        gzip.reset();
+    }
+    Ok(MZStatus::Ok)
+}
+
+/// Set a preset dictionary for the compressor.
+///
+/// This has to be done before compression starts. With a zlib wrapper, `adler` is set to the
+/// adler32 checksum of the dictionary, which is stored in the header.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, has a gzip wrapper or compression
+/// has started.
+pub fn mz_deflate_set_dictionary_oxide(
+    stream_oxide: &mut StreamOxide<DeflateState>,
+    dictionary: &[u8],
+) -> MZResult {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let compressor = &mut state.compressor;
+    if state.gzip.is_some() || !compressor.set_dictionary(dictionary) {
+        return Err(MZError::Stream);
+    }
+
+    if compressor.data_format() == DataFormat::Zlib {
+        stream_oxide.adler = mz_adler32_oxide(MZ_ADLER32_INIT, dictionary);
+    }
+    Ok(MZStatus::Ok)
+}
+
+/// Create a copy of the compression state of the stream, including any input and output that
+/// is waiting to be processed.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, and `MZError::Mem` if allocating
+/// the copy fails.
+pub fn mz_deflate_copy_oxide(
+    stream_oxide: &mut StreamOxide<DeflateState>,
+) -> Result<StateBox<InternalState>, MZError> {
+    let alloc = stream_oxide.alloc;
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let copy = StateBox::new(alloc, state.copy(alloc)?)?;
+    StateBox::new(alloc, InternalState::Deflate(copy))
+}
+
+/// Get the number of bytes and bits of compressed output that did not fit in the output
+/// buffer yet.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, otherwise `MZStatus::Ok`.
+pub fn mz_deflate_pending_oxide(
+    stream_oxide: &mut StreamOxide<DeflateState>,
+    pending: &mut c_uint,
+    bits: &mut c_int,
+) -> MZResult {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let (pending_bytes, pending_bits) = state.compressor.pending_output();
+    let gzip_bytes = state.gzip.as_ref().map_or(0, |gzip| gzip.pending());
+    *pending = (pending_bytes + gzip_bytes) as c_uint;
+    *bits = pending_bits as c_int;
+    Ok(MZStatus::Ok)
+}
+
+/// Insert the lowest `bits` bits of `value` into the output before the next deflate block.
+///
+/// Returns `MZError::Stream` if the inner stream is missing or `bits` is not in the range 0 to
+/// 16, and `MZError::Buf` if there is no room for the bits.
+pub fn mz_deflate_prime_oxide(
+    stream_oxide: &mut StreamOxide<DeflateState>,
+    bits: i32,
+    value: i32,
+) -> MZResult {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let compressor = &mut state.compressor;
+    if !(0..=16).contains(&bits) {
+        return Err(MZError::Stream);
+    }
+
+    if compressor.prime(bits as u32, value as u32) {
+        Ok(MZStatus::Ok)
+    } else {
+        Err(MZError::Buf)
+    }
+}
+
+/// Fine-tune the match search of the compressor, replacing the values set by the compression
+/// level.
+///
+/// # Params
+/// good_length: Matches at least this long reduce the search for a longer match at the next
+///              position.
+/// max_lazy: Matches at least this long are used without looking for a longer one at the next
+///           position.
+/// nice_length: Stop searching once a match at least this long has been found.
+/// max_chain: Number of positions to check for a match.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, otherwise `MZStatus::Ok`.
+pub fn mz_deflate_tune_oxide(
+    stream_oxide: &mut StreamOxide<DeflateState>,
+    good_length: i32,
+    max_lazy: i32,
+    nice_length: i32,
+    max_chain: i32,
+) -> MZResult {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let compressor = &mut state.compressor;
+
+    let clamp = |value: i32| cmp::min(cmp::max(value, 0), i32::from(u16::max_value())) as u16;
+    let mut params = compressor.params();
+    params.good_length = clamp(good_length);
+    params.max_lazy = clamp(max_lazy);
+    params.nice_length = clamp(nice_length);
+    // About a third of the probes value is the number of positions checked.
+    params.probes = clamp(max_chain.saturating_sub(1).saturating_mul(3));
+    compressor.set_params(params);
     Ok(MZStatus::Ok)
 }
 
-pub fn mz_inflate_init_oxide(stream_oxide: &mut StreamOxide<InflateState>) -> MZResult {
+pub fn mz_inflate_init_oxide(stream_oxide: &mut StreamOxide<InflateStreamState>) -> MZResult {
     mz_inflate_init2_oxide(stream_oxide, MZ_DEFAULT_WINDOW_BITS)
 }
 
+/// Initialize the decompressor for the format given by `window_bits`.
+///
+/// A negative value is for raw deflate, and a positive one for zlib, with 16 added for gzip or
+/// 32 to detect whether the data is zlib or gzip. The window is always 32 KiB, so the size of
+/// the window given by the value, from 8 to 15 bits, doesn't matter.
+///
+/// Returns `MZError::Param` if `window_bits` is invalid.
 pub fn mz_inflate_init2_oxide(
-    stream_oxide: &mut StreamOxide<InflateState>,
+    stream_oxide: &mut StreamOxide<InflateStreamState>,
     window_bits: i32,
 ) -> MZResult {
-    if invalid_window_bits(window_bits) {
-        return Err(MZError::Param);
-    }
+    let (wrapper, _) = parse_window_bits(window_bits).ok_or(MZError::Param)?;
 
     stream_oxide.adler = 0;
     stream_oxide.total_in = 0;
     stream_oxide.total_out = 0;
 
-    stream_oxide.state = Some(Box::new(InternalState::Inflate(
-        InflateState::new_boxed_with_window_bits(window_bits),
-    )));
+    let alloc = stream_oxide.alloc;
+    let decompressor = StateBox::new(alloc, InflateStreamState::new(wrapper))?;
+    stream_oxide.state = Some(StateBox::new(alloc, InternalState::Inflate(decompressor))?);
 
     Ok(MZStatus::Ok)
 }
 
-pub fn mz_inflate_oxide(stream_oxide: &mut StreamOxide<InflateState>, flush: i32) -> MZResult {
-    let state: &mut InflateState = {
+pub fn mz_inflate_oxide(
+    stream_oxide: &mut StreamOxide<InflateStreamState>,
+    flush: i32,
+) -> MZResult {
+    let state: &mut InflateStreamState = {
         let enum_ref = stream_oxide.state.as_mut().ok_or(MZError::Stream)?;
         StateType::from_enum(enum_ref)
     }
@@ -260,10 +858,20 @@ pub fn mz_inflate_oxide(stream_oxide: &mut StreamOxide<InflateState>, flush: i32
     let next_in = stream_oxide.next_in.as_mut().ok_or(MZError::Stream)?;
     let next_out = stream_oxide.next_out.as_mut().ok_or(MZError::Stream)?;
 
-    let flush = MZFlush::new(flush)?;
-    let ret = inflate(state, next_in, next_out, flush);
-    *next_in = &next_in[ret.bytes_consumed as usize..];
-    *next_out = &mut mem::replace(next_out, &mut [])[ret.bytes_written as usize..];
+    // The partial and block flushes only apply to compression.
+    let flush = match MZFlush::new(flush)? {
+        MZFlush::Partial | MZFlush::Block => return Err(MZError::Param),
+        flush => flush,
+    };
+    let (ret, gzip_error) = inflate_gzip(state, next_in, next_out, flush);
+    if let Some(err) = gzip_error {
+        stream_oxide.msg = Some(gzip_error_message(err));
+    } else if ret.status == Err(MZError::Data) {
+        let inflate = &mut state.inflate;
+        stream_oxide.msg = inflate_error_message(inflate.last_status(), inflate.decompressor());
+    }
+    *next_in = &next_in[ret.bytes_consumed..];
+    *next_out = &mut mem::replace(next_out, &mut [])[ret.bytes_written..];
     // Wrapping add to emulate miniz_behaviour, will wrap around >4 GiB on 32-bit.
     stream_oxide.total_in = stream_oxide
         .total_in
@@ -271,12 +879,87 @@ pub fn mz_inflate_oxide(stream_oxide: &mut StreamOxide<InflateState>, flush: i32
     stream_oxide.total_out = stream_oxide
         .total_out
         .wrapping_add(ret.bytes_written as c_ulong);
-    stream_oxide.adler = state.decompressor().adler32().unwrap_or(0);
+    // While waiting for a preset dictionary, report its id as zlib does.
+    let decomp = state.inflate.decompressor();
+    stream_oxide.adler = match state.gzip.as_ref() {
+        Some(gzip) => gzip.crc(),
+        None => decomp
+            .dictionary_id()
+            .unwrap_or_else(|| decomp.adler32().unwrap_or(0)),
+    };
     ret.into()
 }
 
+/// Decompress a stream that may have a gzip wrapper, reading the header before the deflate
+/// data and checking the trailer after it. Streams without one are decompressed as is.
+///
+/// Returns the error if the gzip wrapper is invalid.
+fn inflate_gzip(
+    state: &mut InflateStreamState,
+    input: &[u8],
+    output: &mut [u8],
+    flush: MZFlush,
+) -> (StreamResult, Option<GzipError>) {
+    let gzip = match state.gzip.as_mut() {
+        Some(gzip) => gzip,
+        None => return (inflate(&mut state.inflate, input, output, flush), None),
+    };
+
+    let mut next_in = input;
+    let mut ret = StreamResult {
+        bytes_consumed: 0,
+        bytes_written: 0,
+        status: Ok(MZStatus::Ok),
+    };
+    let mut error = None;
+    if gzip.in_header() {
+        match gzip.read_header(&mut next_in) {
+            Ok(HeaderStatus::Complete) => state.inflate.reset(DataFormat::Raw),
+            Ok(HeaderStatus::Incomplete) => (),
+            Ok(HeaderStatus::NotGzip) => {
+                state.gzip = None;
+                return (inflate(&mut state.inflate, input, output, flush), None);
+            }
+            Err(err) => error = Some(err),
+        }
+    }
+    if gzip.in_data() {
+        let res = inflate(&mut state.inflate, next_in, output, flush);
+        gzip.update(&output[..res.bytes_written]);
+        next_in = &next_in[res.bytes_consumed..];
+        ret.bytes_written = res.bytes_written;
+        if res.status == Ok(MZStatus::StreamEnd) {
+            gzip.end_data();
+        } else {
+            ret.status = res.status;
+        }
+    }
+    if !gzip.in_header() && !gzip.in_data() {
+        match gzip.read_trailer(&mut next_in) {
+            Ok(true) => ret.status = Ok(MZStatus::StreamEnd),
+            Ok(false) => (),
+            Err(err) => error = Some(err),
+        }
+    }
+
+    ret.bytes_consumed = input.len() - next_in.len();
+    if error.is_some() {
+        ret.status = Err(MZError::Data);
+    } else if ret.status == Ok(MZStatus::Ok) || ret.status == Err(MZError::Buf) {
+        // As with the deflate data, getting stuck in the wrapper is only an error if no
+        // progress was made, or if the stream should have been finished.
+        let progress = ret.bytes_consumed > 0 || ret.bytes_written > 0;
+        ret.status = if progress && flush != MZFlush::Finish {
+            Ok(MZStatus::Ok)
+        } else {
+            Err(MZError::Buf)
+        };
+    }
+    (ret, error)
+}
+
 pub fn mz_uncompress2_oxide(
-    stream_oxide: &mut StreamOxide<InflateState>,
+    stream_oxide: &mut StreamOxide<InflateStreamState>,
     dest_len: &mut c_ulong,
 ) -> MZResult {
     mz_inflate_init_oxide(stream_oxide)?;
@@ -296,11 +979,172 @@ pub fn mz_uncompress2_oxide(
     }
 }
 
-pub fn mz_inflate_end_oxide(stream_oxide: &mut StreamOxide<InflateState>) -> MZResult {
+pub fn mz_inflate_end_oxide(stream_oxide: &mut StreamOxide<InflateStreamState>) -> MZResult {
+    stream_oxide.state = None;
+    Ok(MZStatus::Ok)
+}
+
+/// Set up a stream for `mz_inflate_back_oxide`, which decompresses raw deflate data using
+/// `window` as its only buffer.
+///
+/// Returns `MZError::Stream` if `window_len` is not a power of two from 256 bytes to 32 KiB.
+///
+/// # Safety
+///
+/// `window` has to be valid for reads and writes of `window_len` bytes, and not be accessed by
+/// anything else, until the state is freed with `mz_inflate_back_end_oxide` or replaced.
+pub unsafe fn mz_inflate_back_init_oxide(
+    stream_oxide: &mut StreamOxide<InflateBackState>,
+    window: NonNull<u8>,
+    window_len: usize,
+) -> MZResult {
+    if !window_len.is_power_of_two() || !(1 << 8..=1 << 15).contains(&window_len) {
+        return Err(MZError::Stream);
+    }
+
+    let alloc = stream_oxide.alloc;
+    let decompressor = StateBox::new(alloc, InflateBackState::new(window, window_len))?;
+    stream_oxide.state = Some(StateBox::new(
+        alloc,
+        InternalState::InflateBack(decompressor),
+    )?);
+    Ok(MZStatus::Ok)
+}
+
+/// Decompress a whole raw deflate stream, starting with the data in `next_in`, getting more
+/// input with `read` and passing the output to `write`.
+///
+/// `read` returns an empty slice at the end of the input, and `write` returns false to stop.
+/// When done, `next_in` is set to the unused input, or to `None` if `read` ran out of input.
+///
+/// Returns `MZStatus::StreamEnd` when the end of the stream is reached, `MZError::Buf` if
+/// `read` ran out of input or `write` failed, and `MZError::Data` if the stream is invalid.
+pub fn mz_inflate_back_oxide<'io, R, W>(
+    stream_oxide: &mut StreamOxide<'io, InflateBackState>,
+    read: R,
+    write: W,
+) -> MZResult
+where
+    R: FnMut() -> &'io [u8],
+    W: FnMut(&[u8]) -> bool,
+{
+    let mut input = stream_oxide.next_in.unwrap_or(&[]);
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let (decomp, window) = state.parts();
+    let res = decompress_with_callbacks(decomp, window, &mut input, read, write, 0);
+    let msg = match res {
+        Err(CallbackError::Failed(status)) => inflate_error_message(status, decomp),
+        _ => None,
+    };
+
+    stream_oxide.next_in = Some(input);
+    match res {
+        Ok(()) => Ok(MZStatus::StreamEnd),
+        Err(CallbackError::InputEnded) => {
+            stream_oxide.next_in = None;
+            Err(MZError::Buf)
+        }
+        Err(CallbackError::Failed(_)) => {
+            stream_oxide.msg = msg;
+            Err(MZError::Data)
+        }
+        Err(_) => Err(MZError::Buf),
+    }
+}
+
+/// Free the inner state of a stream set up with `mz_inflate_back_init_oxide`.
+pub fn mz_inflate_back_end_oxide(stream_oxide: &mut StreamOxide<InflateBackState>) -> MZResult {
     stream_oxide.state = None;
     Ok(MZStatus::Ok)
 }
 
+/// Reset the decompressor, so it can be used to decompress a new stream of the same format.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, otherwise `MZStatus::Ok`.
+pub fn mz_inflate_reset_oxide(stream_oxide: &mut StreamOxide<InflateStreamState>) -> MZResult {
+    stream_oxide.total_in = 0;
+    stream_oxide.total_out = 0;
+    stream_oxide.adler = 0;
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    if state.wrapper == Wrapper::Gzip || state.wrapper == Wrapper::Detect {
+        let wrapper = state.wrapper;
+        state.reset(wrapper);
+    } else {
+        state.inflate.reset_as(MinReset);
+    }
+    Ok(MZStatus::Ok)
+}
+
+/// Reset the decompressor, so it can be used to decompress a new stream, using the format
+/// given by `window_bits` as in `mz_inflate_init2_oxide`.
+///
+/// Returns `MZError::Param` if `window_bits` is invalid and `MZError::Stream` if the inner stream
+/// is missing, otherwise `MZStatus::Ok`.
+pub fn mz_inflate_reset2_oxide(
+    stream_oxide: &mut StreamOxide<InflateStreamState>,
+    window_bits: i32,
+) -> MZResult {
+    let (wrapper, _) = parse_window_bits(window_bits).ok_or(MZError::Param)?;
+
+    stream_oxide.total_in = 0;
+    stream_oxide.total_out = 0;
+    stream_oxide.adler = 0;
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    state.reset(wrapper);
+    Ok(MZStatus::Ok)
+}
+
+/// Set a preset dictionary for the decompressor.
+///
+/// For zlib streams, this has to be done after `mz_inflate_oxide` returned `MZStatus::NeedDict`.
+/// For raw deflate streams, it can be done when there is no decompressed data waiting to be
+/// output.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, may have a gzip wrapper or a
+/// dictionary can't be set at this point, and `MZError::Data` if the dictionary doesn't match
+/// the one the zlib stream was compressed with.
+pub fn mz_inflate_set_dictionary_oxide(
+    stream_oxide: &mut StreamOxide<InflateStreamState>,
+    dictionary: &[u8],
+) -> MZResult {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    if state.gzip.is_some() {
+        return Err(MZError::Stream);
+    }
+    state.inflate.set_dictionary(dictionary)
+}
+
+/// Copy the window of the decompressor, the last up to 32 KiB of output that later data can
+/// refer back to, into `dictionary`, and set `dict_length` to its length.
+///
+/// If `dictionary` is shorter than the window, only the end of the window is copied.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, otherwise `MZStatus::Ok`.
+pub fn mz_inflate_get_dictionary_oxide(
+    stream_oxide: &mut StreamOxide<InflateStreamState>,
+    dictionary: &mut [u8],
+    dict_length: &mut c_uint,
+) -> MZResult {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    state.inflate.get_dictionary(dictionary);
+    *dict_length = state.inflate.dictionary_len() as c_uint;
+    Ok(MZStatus::Ok)
+}
+
+/// Create a copy of the decompression state of the stream, including any output that is
+/// waiting to be written.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, and `MZError::Mem` if allocating
+/// the copy fails.
+pub fn mz_inflate_copy_oxide(
+    stream_oxide: &mut StreamOxide<InflateStreamState>,
+) -> Result<StateBox<InternalState>, MZError> {
+    let alloc = stream_oxide.alloc;
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let copy = StateBox::new(alloc, state.clone())?;
+    StateBox::new(alloc, InternalState::Inflate(copy))
+}
+
 /*
 #[test]
 fn roundtrip_oxide() {
@@ -0,0 +1,482 @@
+//! Simple PNG encoder, ported from `tdefl_write_image_to_png_file_in_memory` in miniz.
+//!
+//! Unlike the miniz version, which writes every scanline unfiltered, a filter is picked for
+//! each scanline using the minimum sum of absolute differences heuristic from the PNG
+//! specification. The C API keeps writing unfiltered scanlines like miniz.
+
+use std::error::Error;
+use std::fmt;
+
+use miniz_oxide::deflate::core::{compress_to_output, CompressionParams, CompressorOxide};
+use miniz_oxide::deflate::core::{TDEFLFlush, TDEFLStatus};
+use miniz_oxide::DataFormat;
+
+/// The eight byte signature every PNG file starts with.
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Color layout of the image data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorType<'a> {
+    /// One gray value per pixel.
+    Grayscale,
+    /// A gray value followed by an alpha value.
+    GrayscaleAlpha,
+    /// Red, green and blue values.
+    Rgb,
+    /// Red, green, blue and alpha values.
+    Rgba,
+    /// One index into the given palette of RGB colors per pixel, only valid with 8 bit depth.
+    Palette(&'a [[u8; 3]]),
+}
+
+impl<'a> ColorType<'a> {
+    /// Create a color type from the number of channels as used by the C API.
+    pub fn from_channels(channels: u32) -> Option<ColorType<'static>> {
+        match channels {
+            1 => Some(ColorType::Grayscale),
+            2 => Some(ColorType::GrayscaleAlpha),
+            3 => Some(ColorType::Rgb),
+            4 => Some(ColorType::Rgba),
+            _ => None,
+        }
+    }
+
+    /// Number of values per pixel.
+    pub fn channels(&self) -> usize {
+        match *self {
+            ColorType::Grayscale | ColorType::Palette(_) => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// The color type code stored in the IHDR chunk.
+    fn code(&self) -> u8 {
+        match *self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Palette(_) => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+}
+
+/// Number of bits used for each value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BitDepth {
+    Eight = 8,
+    /// Values are stored in big-endian byte order.
+    Sixteen = 16,
+}
+
+/// Errors that can occur when writing a PNG image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PngError {
+    /// The width or height is zero or larger than allowed by the PNG format.
+    BadDimensions,
+    /// The image data doesn't have the length expected from the dimensions and color type.
+    BadImageSize,
+    /// The palette is empty or has more than 256 entries.
+    BadPalette,
+    /// Palette images can only use 8 bit depth.
+    BadBitDepth,
+    /// The compressor failed.
+    Compression(TDEFLStatus),
+}
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PngError::BadDimensions => f.write_str("invalid image dimensions"),
+            PngError::BadImageSize => f.write_str("image data size doesn't match dimensions"),
+            PngError::BadPalette => f.write_str("palette must have 1 to 256 entries"),
+            PngError::BadBitDepth => f.write_str("palette images must use 8 bit depth"),
+            PngError::Compression(status) => write!(f, "compression failed: {:?}", status),
+        }
+    }
+}
+
+impl Error for PngError {}
+
+/// Filter types, in the order of their codes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Filter {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+const FILTERS: [Filter; 5] = [
+    Filter::None,
+    Filter::Sub,
+    Filter::Up,
+    Filter::Average,
+    Filter::Paeth,
+];
+
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let pa = (p - i16::from(a)).abs();
+    let pb = (p - i16::from(b)).abs();
+    let pc = (p - i16::from(c)).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Filter `row` into `out` using `prev` as the previous scanline, which is all zeroes for the
+/// first one. `bpp` is the number of bytes per complete pixel, rounded up to one.
+fn filter_row(filter: Filter, bpp: usize, prev: &[u8], row: &[u8], out: &mut [u8]) {
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let up = prev[i];
+        let up_left = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predicted = match filter {
+            Filter::None => 0,
+            Filter::Sub => left,
+            Filter::Up => up,
+            Filter::Average => ((u16::from(left) + u16::from(up)) / 2) as u8,
+            Filter::Paeth => paeth_predictor(left, up, up_left),
+        };
+        out[i] = row[i].wrapping_sub(predicted);
+    }
+}
+
+/// Sum of the filtered bytes taken as signed values, used to guess which filter will compress
+/// best.
+fn filter_cost(filtered: &[u8]) -> u64 {
+    filtered
+        .iter()
+        .map(|&b| i16::from(b as i8).abs() as u64)
+        .sum()
+}
+
+/// Append a chunk with its length and CRC to `out`.
+fn write_chunk(out: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(chunk_type);
+    out.extend_from_slice(data);
+    let crc = crc32fast::hash(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Encode an image as a PNG file.
+///
+/// `image` holds `height` rows of `width` pixels each, with no padding between rows. The values
+/// of each pixel are laid out as described by `color_type`, and are big-endian for 16 bit
+/// depth. If `flip` is true, the rows are written in reverse order, which turns an image stored
+/// bottom-up (as with OpenGL) into the top-down order used by PNG.
+///
+/// `level` is the compression level (0-10) used for the image data.
+pub fn write_image_to_png(
+    image: &[u8],
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    level: u8,
+    flip: bool,
+) -> Result<Vec<u8>, PngError> {
+    encode_png(
+        image, width, height, color_type, bit_depth, level, flip, true,
+    )
+}
+
+/// Encode an image as a PNG file like [`write_image_to_png`], but with every scanline
+/// unfiltered like miniz does.
+pub(crate) fn write_image_to_png_unfiltered(
+    image: &[u8],
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    level: u8,
+    flip: bool,
+) -> Result<Vec<u8>, PngError> {
+    encode_png(
+        image, width, height, color_type, bit_depth, level, flip, false,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn encode_png(
+    image: &[u8],
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    level: u8,
+    flip: bool,
+    filter: bool,
+) -> Result<Vec<u8>, PngError> {
+    // The PNG format limits the dimensions to 2^31 - 1.
+    if width == 0
+        || height == 0
+        || width > i32::max_value() as u32
+        || height > i32::max_value() as u32
+    {
+        return Err(PngError::BadDimensions);
+    }
+    if let ColorType::Palette(palette) = color_type {
+        if palette.is_empty() || palette.len() > 256 {
+            return Err(PngError::BadPalette);
+        }
+        if bit_depth != BitDepth::Eight {
+            return Err(PngError::BadBitDepth);
+        }
+    }
+
+    let bpp = color_type.channels() * bit_depth as usize / 8;
+    let row_len = (width as usize)
+        .checked_mul(bpp)
+        .ok_or(PngError::BadImageSize)?;
+    match row_len.checked_mul(height as usize) {
+        Some(len) if len == image.len() => (),
+        _ => return Err(PngError::BadImageSize),
+    }
+
+    let mut params = CompressionParams::from_level(level);
+    params.format = DataFormat::Zlib;
+    let mut compressor = CompressorOxide::new(params.to_flags());
+    compressor.set_params(params);
+
+    let mut idat = Vec::new();
+    let mut compress_row = |data: &[u8], flush: TDEFLFlush| {
+        let (status, _) = compress_to_output(&mut compressor, data, flush, |out| {
+            idat.extend_from_slice(out);
+            true
+        });
+        match status {
+            TDEFLStatus::Okay | TDEFLStatus::Done => Ok(()),
+            _ => Err(PngError::Compression(status)),
+        }
+    };
+
+    // Filtering doesn't help palette indices, and is pointless if nothing is compressed.
+    let filters: &[Filter] = match color_type {
+        _ if !filter => &FILTERS[..1],
+        ColorType::Palette(_) => &FILTERS[..1],
+        _ if level == 0 => &FILTERS[..1],
+        _ => &FILTERS[..],
+    };
+
+    let zero_row = vec![0; row_len];
+    let mut best = vec![0; row_len + 1];
+    let mut candidate = vec![0; row_len + 1];
+    let mut prev: &[u8] = &zero_row;
+    for y in 0..height as usize {
+        let row_index = if flip { height as usize - 1 - y } else { y };
+        let row = &image[row_index * row_len..(row_index + 1) * row_len];
+
+        let mut best_cost = u64::max_value();
+        for &filter in filters {
+            candidate[0] = filter as u8;
+            filter_row(filter, bpp, prev, row, &mut candidate[1..]);
+            let cost = filter_cost(&candidate[1..]);
+            if cost < best_cost {
+                best_cost = cost;
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+
+        compress_row(&best, TDEFLFlush::None)?;
+        prev = row;
+    }
+    compress_row(&[], TDEFLFlush::Finish)?;
+
+    let mut ihdr = [0; 13];
+    ihdr[0..4].copy_from_slice(&width.to_be_bytes());
+    ihdr[4..8].copy_from_slice(&height.to_be_bytes());
+    ihdr[8] = bit_depth as u8;
+    ihdr[9] = color_type.code();
+    // Compression method, filter method and interlace method are all 0.
+
+    let mut png = Vec::with_capacity(idat.len() + 57);
+    png.extend_from_slice(&PNG_SIGNATURE);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    if let ColorType::Palette(palette) = color_type {
+        let plte: Vec<u8> = palette.iter().flat_map(|c| c.iter().cloned()).collect();
+        write_chunk(&mut png, b"PLTE", &plte);
+    }
+    write_chunk(&mut png, b"IDAT", &idat);
+    write_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+    /// Split a PNG file into its chunks, checking the signature and CRCs.
+    fn read_chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(&png[..8], &PNG_SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < png.len() {
+            let mut len = [0; 4];
+            len.copy_from_slice(&png[pos..pos + 4]);
+            let len = u32::from_be_bytes(len) as usize;
+            let body = &png[pos + 4..pos + 8 + len];
+            let mut crc = [0; 4];
+            crc.copy_from_slice(&png[pos + 8 + len..pos + 12 + len]);
+            assert_eq!(u32::from_be_bytes(crc), crc32fast::hash(body));
+            let mut chunk_type = [0; 4];
+            chunk_type.copy_from_slice(&body[..4]);
+            chunks.push((chunk_type, body[4..].to_vec()));
+            pos += 12 + len;
+        }
+        chunks
+    }
+
+    /// Undo the filtering of the decompressed image data.
+    fn unfilter(data: &[u8], row_len: usize, bpp: usize) -> Vec<u8> {
+        let mut image: Vec<u8> = Vec::new();
+        for (y, line) in data.chunks(row_len + 1).enumerate() {
+            let start = image.len();
+            for (i, &b) in line[1..].iter().enumerate() {
+                let left = if i >= bpp { image[start + i - bpp] } else { 0 };
+                let up = if y > 0 { image[start + i - row_len] } else { 0 };
+                let up_left = if y > 0 && i >= bpp {
+                    image[start + i - row_len - bpp]
+                } else {
+                    0
+                };
+                let predicted = match line[0] {
+                    0 => 0,
+                    1 => left,
+                    2 => up,
+                    3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                    4 => paeth_predictor(left, up, up_left),
+                    f => panic!("invalid filter {}", f),
+                };
+                image.push(b.wrapping_add(predicted));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn roundtrip() {
+        let (width, height) = (37, 23);
+        for &(color_type, bit_depth) in &[
+            (ColorType::Grayscale, BitDepth::Eight),
+            (ColorType::GrayscaleAlpha, BitDepth::Sixteen),
+            (ColorType::Rgb, BitDepth::Eight),
+            (ColorType::Rgba, BitDepth::Sixteen),
+            (
+                ColorType::Palette(&[[0, 0, 0], [255, 0, 0], [0, 0, 255]]),
+                BitDepth::Eight,
+            ),
+        ] {
+            let bpp = color_type.channels() * bit_depth as usize / 8;
+            let row_len = width * bpp;
+            let image: Vec<u8> = (0..row_len * height)
+                .map(|i| match color_type {
+                    ColorType::Palette(_) => (i / 5 % 3) as u8,
+                    _ => ((i % row_len) * 3 + (i / row_len) * 7) as u8,
+                })
+                .collect();
+
+            for &flip in &[false, true] {
+                let png = write_image_to_png(
+                    &image,
+                    width as u32,
+                    height as u32,
+                    color_type,
+                    bit_depth,
+                    6,
+                    flip,
+                )
+                .unwrap();
+                let chunks = read_chunks(&png);
+                let types: Vec<&[u8]> = chunks.iter().map(|c| &c.0[..]).collect();
+                if let ColorType::Palette(palette) = color_type {
+                    assert_eq!(types, [&b"IHDR"[..], b"PLTE", b"IDAT", b"IEND"]);
+                    assert_eq!(chunks[1].1.len(), palette.len() * 3);
+                } else {
+                    assert_eq!(types, [&b"IHDR"[..], b"IDAT", b"IEND"]);
+                }
+                let ihdr = &chunks[0].1;
+                assert_eq!(&ihdr[..8], &[0, 0, 0, 37, 0, 0, 0, 23]);
+                assert_eq!(&ihdr[8..], &[bit_depth as u8, color_type.code(), 0, 0, 0]);
+
+                let data = decompress_to_vec_zlib(&chunks[chunks.len() - 2].1).unwrap();
+                let mut decoded = unfilter(&data, row_len, bpp);
+                if flip {
+                    decoded = decoded.chunks(row_len).rev().flatten().cloned().collect();
+                }
+                assert!(decoded == image);
+            }
+        }
+    }
+
+    #[test]
+    fn filters_used() {
+        // A horizontal gradient is best filtered with sub, repeated rows with up.
+        let row: Vec<u8> = (0..64).collect();
+        let image: Vec<u8> = row.iter().cycle().take(64 * 4).cloned().collect();
+        let png = write_image_to_png(
+            &image,
+            64,
+            4,
+            ColorType::Grayscale,
+            BitDepth::Eight,
+            6,
+            false,
+        )
+        .unwrap();
+        let chunks = read_chunks(&png);
+        let data = decompress_to_vec_zlib(&chunks[1].1).unwrap();
+        let filters: Vec<u8> = data.chunks(65).map(|line| line[0]).collect();
+        assert_eq!(filters, [1, 2, 2, 2]);
+
+        let png = write_image_to_png_unfiltered(
+            &image,
+            64,
+            4,
+            ColorType::Grayscale,
+            BitDepth::Eight,
+            6,
+            false,
+        )
+        .unwrap();
+        let chunks = read_chunks(&png);
+        let data = decompress_to_vec_zlib(&chunks[1].1).unwrap();
+        let rows: Vec<&[u8]> = data.chunks(65).collect();
+        assert!(rows.iter().all(|line| line[0] == 0 && line[1..] == row[..]));
+    }
+
+    #[test]
+    fn invalid_input() {
+        let image = [0; 12];
+        let write = |w, h, c, d| write_image_to_png(&image, w, h, c, d, 6, false);
+        assert_eq!(
+            write(0, 3, ColorType::Rgba, BitDepth::Eight),
+            Err(PngError::BadDimensions)
+        );
+        assert_eq!(
+            write(3, 2, ColorType::Rgb, BitDepth::Eight),
+            Err(PngError::BadImageSize)
+        );
+        assert_eq!(
+            write(12, 1, ColorType::Palette(&[]), BitDepth::Eight),
+            Err(PngError::BadPalette)
+        );
+        assert_eq!(
+            write(6, 1, ColorType::Palette(&[[0; 3]]), BitDepth::Sixteen),
+            Err(PngError::BadBitDepth)
+        );
+        assert!(write(1, 1, ColorType::Rgba, BitDepth::Sixteen).is_err());
+        assert!(write(4, 1, ColorType::Rgb, BitDepth::Eight).is_ok());
+    }
+}
@@ -1,10 +1,13 @@
 use libc::*;
+use std::alloc::{self, Layout};
+use std::cell::UnsafeCell;
 use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::{cmp, mem, ptr, slice};
 
+use crate::png::{write_image_to_png_unfiltered, BitDepth, ColorType};
 use miniz_oxide::deflate::core::{
-    compress, compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush,
-    TDEFLStatus,
+    create_comp_flags_from_zip_params, CompressorWorkspace, TDEFLFlush, TDEFLStatus,
+    WorkspaceCompressor,
 };
 
 /// Compression callback function type.
@@ -21,19 +24,54 @@ pub struct CallbackFunc {
     pub put_buf_user: *mut c_void,
 }
 
+/// Size in bytes of `tdefl_compressor`, as declared in the `miniz.h` generated for this crate.
+///
+/// This is larger than the 319352 bytes of the struct in the original `miniz.h`, so C code that
+/// allocates the struct itself has to be compiled against the generated header.
+pub const TDEFL_COMPRESSOR_SIZE: usize = 319488;
+
+/// State of an initialized compressor.
+struct CompressorInner {
+    /// Compressor using the workspace stored after this in the same `Compressor`.
+    compressor: WorkspaceCompressor<'static>,
+    callback: Option<CallbackFunc>,
+}
+
+/// Offset of the workspace in the storage of a `Compressor`.
+const WORKSPACE_OFFSET: usize =
+    (mem::size_of::<Option<CompressorInner>>() + mem::align_of::<CompressorWorkspace>() - 1)
+        / mem::align_of::<CompressorWorkspace>()
+        * mem::align_of::<CompressorWorkspace>();
+
 /// Main compression struct. Not the same as `CompressorOxide`
-/// #[repr(C)]
+///
+/// The compressor and the buffers it uses are stored inline so C code can place the struct
+/// wherever it wants, like the one in miniz. `tdefl_init` has to be called before it is used,
+/// after which it must not be moved, as the compressor refers to the buffers. It holds no other
+/// resources, so it doesn't need to be freed.
+///
+/// As the compressor borrows the buffers, the storage is only accessed through raw pointers
+/// derived from the `UnsafeCell` once initialized, and never through a mutable reference to the
+/// whole struct, which would invalidate that borrow.
+#[repr(C)]
 pub struct Compressor {
-    pub(crate) inner: Option<CompressorOxide>,
-    pub(crate) callback: Option<CallbackFunc>,
+    m_storage: UnsafeCell<[u64; TDEFL_COMPRESSOR_SIZE / 8]>,
 }
 
+static_assert!(TDEFL_COMPRESSOR_SIZE % 8 == 0);
+static_assert!(WORKSPACE_OFFSET + mem::size_of::<CompressorWorkspace>() <= TDEFL_COMPRESSOR_SIZE);
+static_assert!(mem::align_of::<Option<CompressorInner>>() <= mem::align_of::<Compressor>());
+static_assert!(mem::align_of::<CompressorWorkspace>() <= mem::align_of::<Compressor>());
+static_assert!(!mem::needs_drop::<Option<CompressorInner>>());
+static_assert!(!mem::needs_drop::<CompressorWorkspace>());
+
 impl Default for Compressor {
     fn default() -> Self {
-        Compressor {
-            inner: None,
-            callback: None,
-        }
+        let compressor = Compressor {
+            m_storage: UnsafeCell::new([0; TDEFL_COMPRESSOR_SIZE / 8]),
+        };
+        compressor.drop_inner();
+        compressor
     }
 }
 
@@ -103,47 +141,75 @@ impl From<tdefl_flush> for TDEFLFlush {
 // }
 
 impl Compressor {
-    pub(crate) fn new_with_callback(flags: u32, func: CallbackFunc) -> Self {
-        Compressor {
-            inner: Some(CompressorOxide::new(flags)),
-            callback: Some(func),
+    /// Initialize the compressor with the given flags and callback, using the buffers in the
+    /// storage.
+    ///
+    /// The storage doesn't have to be initialized before this is called.
+    pub(crate) fn init(&self, flags: u32, callback: Option<CallbackFunc>) {
+        // Safety: The storage is large and aligned enough for both the workspace and the inner
+        // state as checked above, and neither has to be dropped. The compressor only uses the
+        // workspace, which lives as long as the `Compressor` as long as it's not moved, and is
+        // not accessed in any other way until the compressor is replaced.
+        unsafe {
+            let workspace = &mut *((self.m_storage.get() as *mut u8).add(WORKSPACE_OFFSET)
+                as *mut CompressorWorkspace);
+            let compressor = WorkspaceCompressor::new(flags, workspace);
+            ptr::write(
+                self.inner_ptr(),
+                Some(CompressorInner {
+                    compressor,
+                    callback,
+                }),
+            );
         }
     }
 
-    /// Sets the inner state to `None` and thus drops it.
-    pub fn drop_inner(&mut self) {
-        self.inner = None;
+    /// Pointer to the inner state, which only covers the start of the storage so references
+    /// made from it don't overlap the workspace.
+    fn inner_ptr(&self) -> *mut Option<CompressorInner> {
+        self.m_storage.get() as *mut Option<CompressorInner>
+    }
+
+    fn inner(&self) -> Option<&CompressorInner> {
+        // Safety: The inner state is always written when a `Compressor` is created.
+        unsafe { (*self.inner_ptr()).as_ref() }
+    }
+
+    /// Sets the inner state to `None`.
+    pub fn drop_inner(&self) {
+        // Safety: The inner state doesn't have to be dropped.
+        unsafe { ptr::write(self.inner_ptr(), None) }
     }
 
     /// Reset the inner compressor if any.
-    pub fn reset(&mut self) {
-        if let Some(c) = self.inner.as_mut() {
-            c.reset();
+    pub fn reset(&self) {
+        // Safety: No other reference to the inner state exists while this one is used.
+        if let Some(i) = unsafe { (*self.inner_ptr()).as_mut() } {
+            i.compressor.reset();
         }
     }
 
     pub fn adler32(&self) -> u32 {
-        self.inner.as_ref().map(|i| i.adler32()).unwrap_or(0)
+        self.inner().map(|i| i.compressor.adler32()).unwrap_or(0)
     }
 
     pub fn prev_return_status(&self) -> TDEFLStatus {
         // Not sure we should return on inner not existing, but that shouldn't happen
         // anyway.
-        self.inner
-            .as_ref()
-            .map(|i| i.prev_return_status())
+        self.inner()
+            .map(|i| i.compressor.prev_return_status())
             .unwrap_or(TDEFLStatus::BadParam)
     }
 
     /// Return the compressor flags of the inner compressor.
     pub fn flags(&self) -> i32 {
-        self.inner.as_ref().map(|i| i.flags()).unwrap_or(0)
+        self.inner().map(|i| i.compressor.flags()).unwrap_or(0)
     }
 }
 
 unmangle!(
     pub unsafe extern "C" fn tdefl_compress(
-        d: Option<&mut Compressor>,
+        d: Option<&Compressor>,
         in_buf: *const c_void,
         in_size: Option<&mut usize>,
         out_buf: *mut c_void,
@@ -164,7 +230,7 @@ unmangle!(
                 tdefl_status::TDEFL_STATUS_BAD_PARAM
             }
             Some(compressor_wrap) => {
-                if let Some(ref mut compressor) = compressor_wrap.inner {
+                if let Some(inner) = (*compressor_wrap.inner_ptr()).as_mut() {
                     let in_buf_size = in_size.as_ref().map_or(0, |size| **size);
                     let out_buf_size = out_size.as_ref().map_or(0, |size| **size);
 
@@ -182,10 +248,10 @@ unmangle!(
                         .as_ref()
                         .map_or(&[][..], |in_buf| slice::from_raw_parts(in_buf, in_buf_size));
 
-                    let res = match compressor_wrap.callback {
+                    let compressor = &mut inner.compressor;
+                    let res = match inner.callback {
                         None => match (out_buf as *mut u8).as_mut() {
-                            Some(out_buf) => compress(
-                                compressor,
+                            Some(out_buf) => compressor.compress(
                                 in_slice,
                                 slice::from_raw_parts_mut(out_buf, out_buf_size),
                                 flush,
@@ -211,7 +277,7 @@ unmangle!(
                                 return tdefl_status::TDEFL_STATUS_BAD_PARAM;
                             }
                             let res =
-                                compress_to_output(compressor, in_slice, flush, |out: &[u8]| {
+                                compressor.compress_to_output(in_slice, flush, |out: &[u8]| {
                                     (func.put_buf_func)(
                                         &(out[0]) as *const u8 as *const c_void,
                                         out.len() as i32,
@@ -237,7 +303,7 @@ unmangle!(
     }
 
     pub unsafe extern "C" fn tdefl_compress_buffer(
-        d: Option<&mut Compressor>,
+        d: Option<&Compressor>,
         in_buf: *const c_void,
         mut in_size: usize,
         flush: tdefl_flush,
@@ -249,67 +315,67 @@ unmangle!(
     ///
     /// This does initialize the struct, but not the inner constructor,
     /// tdefl_init has to be called before doing anything with it.
+    /// Returns null if allocation fails.
     pub unsafe extern "C" fn tdefl_allocate() -> *mut Compressor {
-        Box::into_raw(Box::<Compressor>::new(Compressor {
-            inner: None,
-            callback: None,
-        }))
+        // Allocate directly as the struct is too large to be created on the stack first.
+        let c = alloc::alloc(Layout::new::<Compressor>()) as *mut Compressor;
+        if let Some(c) = c.as_ref() {
+            c.drop_inner();
+        }
+        c
     }
 
-    /// Deallocate the compressor. (Does nothing if the argument is null).
-    ///
-    /// This also calls the compressor's destructor, freeing the internal memory
-    /// allocated by it.
+    /// Deallocate a compressor allocated with `tdefl_allocate`. (Does nothing if the argument
+    /// is null).
     pub unsafe extern "C" fn tdefl_deallocate(c: *mut Compressor) {
         if !c.is_null() {
-            drop(Box::from_raw(c));
+            alloc::dealloc(c as *mut u8, Layout::new::<Compressor>());
         }
     }
 
-    /// Initialize the compressor struct in the space pointed to by `d`.
+    /// Allocate a compressor, the same as `tdefl_allocate`.
+    pub unsafe extern "C" fn tdefl_compressor_alloc() -> *mut Compressor {
+        tdefl_allocate()
+    }
+
+    /// Deallocate a compressor allocated with `tdefl_compressor_alloc`, the same as
+    /// `tdefl_deallocate`.
+    pub unsafe extern "C" fn tdefl_compressor_free(c: *mut Compressor) {
+        tdefl_deallocate(c)
+    }
+
+    /// Initialize the compressor struct in the space pointed to by `d`, which does not have to
+    /// be initialized already.
     /// if d is null, an error is returned.
-    ///
-    /// Deinitialization is handled by tdefl_deallocate, and thus
-    /// Compressor should not be allocated or freed manually, but only through
-    /// tdefl_allocate and tdefl_deallocate
     pub unsafe extern "C" fn tdefl_init(
-        d: Option<&mut Compressor>,
+        d: Option<&Compressor>,
         put_buf_func: tdefl_put_buf_func_ptr,
         put_buf_user: *mut c_void,
         flags: c_int,
     ) -> tdefl_status {
         if let Some(d) = d {
             match catch_unwind(AssertUnwindSafe(|| {
-                d.inner = Some(CompressorOxide::new(flags as u32));
-                if let Some(f) = put_buf_func {
-                    d.callback = Some(CallbackFunc {
-                        put_buf_func: f,
-                        put_buf_user,
-                    })
-                } else {
-                    d.callback = None;
-                };
+                let callback = put_buf_func.map(|f| CallbackFunc {
+                    put_buf_func: f,
+                    put_buf_user,
+                });
+                d.init(flags as u32, callback);
             })) {
                 Ok(_) => tdefl_status::TDEFL_STATUS_OKAY,
-                Err(_) => {
-                    eprintln!("FATAL ERROR: Caught panic when initializing the compressor!");
-                    tdefl_status::TDEFL_STATUS_BAD_PARAM
-                }
+                Err(_) => tdefl_status::TDEFL_STATUS_BAD_PARAM,
             }
         } else {
             tdefl_status::TDEFL_STATUS_BAD_PARAM
         }
     }
 
-    pub unsafe extern "C" fn tdefl_get_prev_return_status(
-        d: Option<&mut Compressor>,
-    ) -> tdefl_status {
+    pub unsafe extern "C" fn tdefl_get_prev_return_status(d: Option<&Compressor>) -> tdefl_status {
         d.map_or(tdefl_status::TDEFL_STATUS_OKAY, |d| {
             d.prev_return_status().into()
         })
     }
 
-    pub unsafe extern "C" fn tdefl_get_adler32(d: Option<&mut Compressor>) -> c_uint {
+    pub unsafe extern "C" fn tdefl_get_adler32(d: Option<&Compressor>) -> c_uint {
         d.map_or(crate::MZ_ADLER32_INIT as u32, |d| d.adler32())
     }
 
@@ -321,28 +387,23 @@ unmangle!(
         flags: c_int,
     ) -> c_int {
         if let Some(put_buf_func) = put_buf_func {
-            let compressor =
-                crate::miniz_def_alloc_func(ptr::null_mut(), 1, mem::size_of::<Compressor>())
-                    as *mut Compressor;
-
-            ptr::write(
-                compressor,
-                Compressor::new_with_callback(
-                    flags as u32,
-                    CallbackFunc {
-                        put_buf_func,
-                        put_buf_user,
-                    },
-                ),
+            let ptr = tdefl_allocate();
+            let compressor = match ptr.as_ref() {
+                Some(compressor) => compressor,
+                None => return false.into(),
+            };
+            compressor.init(
+                flags as u32,
+                Some(CallbackFunc {
+                    put_buf_func,
+                    put_buf_user,
+                }),
             );
 
             let res =
-                tdefl_compress_buffer(compressor.as_mut(), buf, buf_len, tdefl_flush::TDEFL_FINISH)
+                tdefl_compress_buffer(Some(compressor), buf, buf_len, tdefl_flush::TDEFL_FINISH)
                     == tdefl_status::TDEFL_STATUS_DONE;
-            if let Some(c) = compressor.as_mut() {
-                c.drop_inner();
-            }
-            crate::miniz_def_free_func(ptr::null_mut(), compressor as *mut c_void);
+            tdefl_deallocate(ptr);
             res.into()
         } else {
             false.into()
@@ -472,6 +533,79 @@ unmangle!(
     ) -> c_uint {
         create_comp_flags_from_zip_params(level, window_bits, strategy)
     }
+
+    /// Write an image with `num_chans` 8 bit channels (1 to 4, gray, gray+alpha, RGB or RGBA)
+    /// to a PNG file in a newly allocated buffer, which should be freed with `mz_free`. The
+    /// scanlines are written unfiltered, like miniz does.
+    ///
+    /// If `flip` is non-zero the image is flipped vertically. Returns null and sets
+    /// `*len_out` to 0 on failure.
+    pub unsafe extern "C" fn tdefl_write_image_to_png_file_in_memory_ex(
+        image: *const c_void,
+        w: c_int,
+        h: c_int,
+        num_chans: c_int,
+        len_out: *mut usize,
+        level: c_uint,
+        flip: c_int,
+    ) -> *mut c_void {
+        let len_out = match len_out.as_mut() {
+            Some(len_out) => len_out,
+            None => return ptr::null_mut(),
+        };
+        *len_out = 0;
+        let color_type = match ColorType::from_channels(num_chans as u32) {
+            Some(color_type) => color_type,
+            None => return ptr::null_mut(),
+        };
+        if image.is_null() || w <= 0 || h <= 0 {
+            return ptr::null_mut();
+        }
+
+        // The image has to fit in a slice, which can't be longer than `isize::MAX` bytes.
+        let len = match (w as usize)
+            .checked_mul(h as usize)
+            .and_then(|len| len.checked_mul(num_chans as usize))
+        {
+            Some(len) if len <= isize::max_value() as usize => len,
+            _ => return ptr::null_mut(),
+        };
+        let image = slice::from_raw_parts(image as *const u8, len);
+        let level = cmp::min(level, 10) as u8;
+        let png = match catch_unwind(|| {
+            write_image_to_png_unfiltered(
+                image,
+                w as u32,
+                h as u32,
+                color_type,
+                BitDepth::Eight,
+                level,
+                flip != 0,
+            )
+        }) {
+            Ok(Ok(png)) => png,
+            _ => return ptr::null_mut(),
+        };
+
+        let out = crate::miniz_def_alloc_func(ptr::null_mut(), 1, png.len()) as *mut u8;
+        if !out.is_null() {
+            ptr::copy_nonoverlapping(png.as_ptr(), out, png.len());
+            *len_out = png.len();
+        }
+        out as *mut c_void
+    }
+
+    /// Write an image to a PNG file in memory using the default compression level and no
+    /// flipping. See `tdefl_write_image_to_png_file_in_memory_ex`.
+    pub unsafe extern "C" fn tdefl_write_image_to_png_file_in_memory(
+        image: *const c_void,
+        w: c_int,
+        h: c_int,
+        num_chans: c_int,
+        len_out: *mut usize,
+    ) -> *mut c_void {
+        tdefl_write_image_to_png_file_in_memory_ex(image, w, h, num_chans, len_out, 6, 0)
+    }
 );
 
 #[cfg(test)]
@@ -500,4 +634,86 @@ mod test {
             assert!(dec.as_slice() == &data[..]);
         }
     }
+
+    #[test]
+    fn compressor_in_place() {
+        let data = b"Hello, zlib! Hello, zlib! Hello, zlib!";
+        let mut out = [0u8; 128];
+        unsafe {
+            // Start from garbage, as with memory allocated in C.
+            let layout = Layout::new::<Compressor>();
+            let c = alloc::alloc(layout) as *mut Compressor;
+            assert!(!c.is_null());
+            ptr::write_bytes(c as *mut u8, 0xaa, layout.size());
+
+            let flags = tdefl_create_comp_flags_from_zip_params(6, 15, 0);
+            assert!(
+                tdefl_init(c.as_ref(), None, ptr::null_mut(), flags as c_int)
+                    == tdefl_status::TDEFL_STATUS_OKAY
+            );
+            let mut in_size = data.len();
+            let mut out_size = out.len();
+            assert!(
+                tdefl_compress(
+                    c.as_ref(),
+                    data.as_ptr() as *const c_void,
+                    Some(&mut in_size),
+                    out.as_mut_ptr() as *mut c_void,
+                    Some(&mut out_size),
+                    tdefl_flush::TDEFL_FINISH,
+                ) == tdefl_status::TDEFL_STATUS_DONE
+            );
+            assert_eq!(in_size, data.len());
+            let adler = miniz_oxide::mz_adler32_oxide(1, data);
+            assert_eq!(tdefl_get_adler32(c.as_ref()), adler);
+            alloc::dealloc(c as *mut u8, layout);
+
+            let dec = miniz_oxide::inflate::decompress_to_vec_zlib(&out[..out_size]).unwrap();
+            assert_eq!(dec, &data[..]);
+        }
+    }
+
+    #[test]
+    fn png_in_memory() {
+        let image: Vec<u8> = (0..4 * 3 * 5).map(|i| i as u8).collect();
+        let mut len = 0;
+        unsafe {
+            let png = tdefl_write_image_to_png_file_in_memory(
+                image.as_ptr() as *const c_void,
+                4,
+                5,
+                3,
+                &mut len,
+            );
+            assert!(!png.is_null());
+            let png_slice = slice::from_raw_parts(png as *const u8, len);
+            assert_eq!(&png_slice[1..4], b"PNG");
+            assert_eq!(&png_slice[len - 8..len - 4], b"IEND");
+            crate::miniz_def_free_func(ptr::null_mut(), png);
+
+            let png = tdefl_write_image_to_png_file_in_memory_ex(
+                image.as_ptr() as *const c_void,
+                4,
+                5,
+                5,
+                &mut len,
+                6,
+                1,
+            );
+            assert!(png.is_null());
+            assert_eq!(len, 0);
+
+            // Sizes that don't fit in a slice are rejected before the image is read.
+            let max = c_int::max_value();
+            let png = tdefl_write_image_to_png_file_in_memory(
+                image.as_ptr() as *const c_void,
+                max,
+                max,
+                4,
+                &mut len,
+            );
+            assert!(png.is_null());
+            assert_eq!(len, 0);
+        }
+    }
 }
@@ -1,14 +1,21 @@
 #![allow(dead_code)]
 
 use libc::*;
-use miniz_oxide::inflate::core::DecompressorOxide;
+use miniz_oxide::inflate::core::{DecompressorOxide, TINFL_LZ_DICT_SIZE};
 // pub use miniz_oxide::inflate::core::DecompressorOxide as tinfl_decompressor;
 pub use miniz_oxide::inflate::core::{decompress, inflate_flags};
 use miniz_oxide::inflate::TINFLStatus;
-use std::{ptr, slice, usize};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::{mem, ptr, slice, usize};
 
 pub const TINFL_DECOMPRESS_MEM_TO_MEM_FAILED: size_t = usize::MAX;
 
+/// Decompression callback function type, called with each chunk of decompressed data.
+/// Returning 0 stops decompression.
+#[allow(bad_style)]
+pub type tinfl_put_buf_func_ptr =
+    Option<unsafe extern "C" fn(*const c_void, c_int, *mut c_void) -> c_int>;
+
 #[allow(bad_style)]
 #[repr(C)]
 pub enum tinfl_status {
@@ -59,17 +66,54 @@ impl From<TINFLStatus> for tinfl_status {
     }
 }
 
+/// Size in bytes of `tinfl_decompressor`, the same as in the original `miniz.h`.
+pub const TINFL_DECOMPRESSOR_SIZE: usize = 11000;
+
+/// Main decompression struct.
+///
+/// The decompressor is stored inline so C code can place the struct wherever it wants, like
+/// the one in miniz, as long as `tinfl_init` is called before it is used. It holds no other
+/// resources, so it doesn't need to be freed.
 #[allow(bad_style)]
 #[repr(C)]
 pub struct tinfl_decompressor {
-    inner: Option<Box<DecompressorOxide>>,
+    m_storage: [u64; TINFL_DECOMPRESSOR_SIZE / 8],
+}
+
+static_assert!(TINFL_DECOMPRESSOR_SIZE % 8 == 0);
+static_assert!(mem::size_of::<DecompressorOxide>() <= TINFL_DECOMPRESSOR_SIZE);
+static_assert!(mem::align_of::<DecompressorOxide>() <= mem::align_of::<tinfl_decompressor>());
+static_assert!(!mem::needs_drop::<DecompressorOxide>());
+
+impl tinfl_decompressor {
+    /// Write a new decompressor into the storage.
+    pub fn init(&mut self) {
+        // Safety: The storage is large and aligned enough for the decompressor as checked above,
+        // and any previous one doesn't need to be dropped.
+        unsafe {
+            ptr::write(
+                self.m_storage.as_mut_ptr() as *mut DecompressorOxide,
+                DecompressorOxide::new(),
+            )
+        }
+    }
+
+    /// Get the decompressor.
+    ///
+    /// # Safety
+    /// `init` has to have been called first.
+    unsafe fn inner(&mut self) -> &mut DecompressorOxide {
+        &mut *(self.m_storage.as_mut_ptr() as *mut DecompressorOxide)
+    }
 }
 
 impl Default for tinfl_decompressor {
     fn default() -> tinfl_decompressor {
-        tinfl_decompressor {
-            inner: Some(Box::<DecompressorOxide>::default()),
-        }
+        let mut decompressor = tinfl_decompressor {
+            m_storage: [0; TINFL_DECOMPRESSOR_SIZE / 8],
+        };
+        decompressor.init();
+        decompressor
     }
 }
 
@@ -86,21 +130,17 @@ unmangle!(
         let next_pos = out_buf_next as usize - out_buf_start as usize;
         let out_size = *out_buf_size + next_pos;
         let r_ref = r.as_mut().expect("bad decompressor pointer");
-        if let Some(decompressor) = r_ref.inner.as_mut() {
-            let (status, in_consumed, out_consumed) = decompress(
-                decompressor.as_mut(),
-                slice::from_raw_parts(in_buf, *in_buf_size),
-                slice::from_raw_parts_mut(out_buf_start, out_size),
-                next_pos,
-                flags,
-            );
+        let (status, in_consumed, out_consumed) = decompress(
+            r_ref.inner(),
+            slice::from_raw_parts(in_buf, *in_buf_size),
+            slice::from_raw_parts_mut(out_buf_start, out_size),
+            next_pos,
+            flags,
+        );
 
-            *in_buf_size = in_consumed;
-            *out_buf_size = out_consumed;
-            status as i32
-        } else {
-            TINFLStatus::BadParam as i32
-        }
+        *in_buf_size = in_consumed;
+        *out_buf_size = out_consumed;
+        status as i32
     }
 
     pub unsafe extern "C" fn tinfl_decompress_mem_to_mem(
@@ -209,40 +249,99 @@ unmangle!(
         p_buf
     }
 
-    /// Allocate a compressor.
+    /// Decompress data from `p_in_buf` into a 32 KiB dictionary, passing the output to
+    /// `put_buf_func` one chunk at a time.
     ///
-    /// This does initialize the struct, but not the inner constructor,
-    /// tdefl_init has to be called before doing anything with it.
-    pub unsafe extern "C" fn tinfl_decompressor_alloc() -> *mut tinfl_decompressor {
-        Box::into_raw(Box::<tinfl_decompressor>::new(tinfl_decompressor::default()))
+    /// Sets `p_in_buf_size` to the number of input bytes consumed.
+    /// Returns 1 if the end of the stream was reached, and 0 if decompression failed or the
+    /// callback returned 0.
+    pub unsafe extern "C" fn tinfl_decompress_mem_to_callback(
+        p_in_buf: *const c_void,
+        p_in_buf_size: *mut size_t,
+        put_buf_func: tinfl_put_buf_func_ptr,
+        put_buf_user: *mut c_void,
+        flags: c_int,
+    ) -> c_int {
+        let in_buf_size = match p_in_buf_size.as_mut() {
+            Some(in_buf_size) => in_buf_size,
+            None => return 0,
+        };
+        let put_buf_func = match put_buf_func {
+            Some(put_buf_func) if !p_in_buf.is_null() || *in_buf_size == 0 => put_buf_func,
+            _ => return 0,
+        };
+        let flags = flags as u32
+            & !(inflate_flags::TINFL_FLAG_HAS_MORE_INPUT
+                | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF);
+        let input = if p_in_buf.is_null() {
+            &[]
+        } else {
+            slice::from_raw_parts(p_in_buf as *const u8, *in_buf_size)
+        };
+
+        let mut in_buf_ofs = 0;
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let mut decomp = Box::<DecompressorOxide>::default();
+            let mut dict = vec![0; TINFL_LZ_DICT_SIZE];
+            let mut dict_ofs = 0;
+            let mut result = 0;
+            loop {
+                let (status, in_consumed, out_consumed) = decompress(
+                    &mut decomp,
+                    &input[in_buf_ofs..],
+                    &mut dict,
+                    dict_ofs,
+                    flags,
+                );
+                in_buf_ofs += in_consumed;
+
+                if out_consumed > 0
+                    && put_buf_func(
+                        dict[dict_ofs..].as_ptr() as *const c_void,
+                        out_consumed as c_int,
+                        put_buf_user,
+                    ) == 0
+                {
+                    break;
+                }
+
+                if status != TINFLStatus::HasMoreOutput {
+                    result = (status == TINFLStatus::Done) as c_int;
+                    break;
+                }
+                dict_ofs = (dict_ofs + out_consumed) & (TINFL_LZ_DICT_SIZE - 1);
+            }
+            result
+        }));
+
+        *in_buf_size = in_buf_ofs;
+        result.unwrap_or(0)
     }
-    /// Deallocate the compressor. (Does nothing if the argument is null).
+
+    /// Allocate a decompressor.
     ///
-    /// This also calls the compressor's destructor, freeing the internal memory
-    /// allocated by it.
+    /// The decompressor is initialized, so `tinfl_init` only has to be called to reuse it.
+    pub unsafe extern "C" fn tinfl_decompressor_alloc() -> *mut tinfl_decompressor {
+        Box::into_raw(Box::<tinfl_decompressor>::default())
+    }
+    /// Deallocate a decompressor allocated with `tinfl_decompressor_alloc`. (Does nothing if
+    /// the argument is null).
     pub unsafe extern "C" fn tinfl_decompressor_free(c: *mut tinfl_decompressor) {
         if !c.is_null() {
             drop(Box::from_raw(c));
         }
     }
 
+    /// Initialize the decompressor in the space pointed to by `c`, which does not have to be
+    /// initialized already.
     pub unsafe extern "C" fn tinfl_init(c: *mut tinfl_decompressor) {
-        let wrapped = c.as_mut().unwrap();
-        if let Some(decomp) = wrapped.inner.as_mut() {
-            decomp.init();
-        } else {
-            wrapped.inner.replace(Box::default());
-        }
+        c.as_mut().unwrap().init();
     }
 
     pub unsafe extern "C" fn tinfl_get_adler32(c: *mut tinfl_decompressor) -> c_int {
         let wrapped = c.as_mut().unwrap();
-        if let Some(decomp) = wrapped.inner.as_mut() {
-            // TODO: Need to test if conversion is ok.
-            decomp.adler32().unwrap_or(0) as c_int
-        } else {
-            0
-        }
+        // TODO: Need to test if conversion is ok.
+        wrapped.inner().adler32().unwrap_or(0) as c_int
     }
 );
 
@@ -350,4 +449,97 @@ mod test {
         let out_buf = tinfl_decompress_mem_to_heap_wrapper(&mut encoded[..], flags as i32).unwrap();
         assert_eq!(out_buf.as_slice(), &b"Hello, zlib!"[..]);
     }
+
+    unsafe extern "C" fn put_buf_to_vec(
+        buf: *const c_void,
+        len: c_int,
+        user: *mut c_void,
+    ) -> c_int {
+        let out = &mut *(user as *mut Vec<u8>);
+        out.extend_from_slice(slice::from_raw_parts(buf as *const u8, len as usize));
+        // Stop after the first chunk once the output is large enough.
+        (out.len() < 100_000) as c_int
+    }
+
+    #[test]
+    fn mem_to_callback() {
+        let data: Vec<u8> = (0..90_000u64).map(|i| (i * i % 251) as u8).collect();
+        let encoded = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        let flags = (TINFL_FLAG_COMPUTE_ADLER32 | TINFL_FLAG_PARSE_ZLIB_HEADER) as c_int;
+
+        let mut out = Vec::new();
+        let mut in_size = encoded.len() + 4;
+        let mut input = encoded.clone();
+        input.extend_from_slice(&[1, 2, 3, 4]);
+        let result = unsafe {
+            tinfl_decompress_mem_to_callback(
+                input.as_ptr() as *const c_void,
+                &mut in_size,
+                Some(put_buf_to_vec),
+                &mut out as *mut Vec<u8> as *mut c_void,
+                flags,
+            )
+        };
+        assert_eq!(result, 1);
+        assert_eq!(in_size, encoded.len());
+        assert_eq!(out, data);
+
+        // The callback returning 0 stops decompression.
+        let mut out = vec![0u8; 99_000];
+        let mut in_size = encoded.len();
+        let result = unsafe {
+            tinfl_decompress_mem_to_callback(
+                encoded.as_ptr() as *const c_void,
+                &mut in_size,
+                Some(put_buf_to_vec),
+                &mut out as *mut Vec<u8> as *mut c_void,
+                flags,
+            )
+        };
+        assert_eq!(result, 0);
+        assert!(out.len() >= 100_000 && out.len() < 99_000 + data.len());
+
+        let mut in_size = encoded.len() - 1;
+        let result = unsafe {
+            tinfl_decompress_mem_to_callback(
+                encoded.as_ptr() as *const c_void,
+                &mut in_size,
+                Some(put_buf_to_vec),
+                &mut Vec::<u8>::new() as *mut Vec<u8> as *mut c_void,
+                flags,
+            )
+        };
+        assert_eq!(result, 0);
+    }
+
+    #[test]
+    fn decompressor_in_place() {
+        let data = b"Hello, zlib! Hello, zlib! Hello, zlib!";
+        let encoded = miniz_oxide::deflate::compress_to_vec_zlib(data, 6);
+        let mut out = [0u8; 64];
+        // Start from garbage, as with a decompressor placed on the stack in C.
+        let mut decompressor = tinfl_decompressor {
+            m_storage: [!0; TINFL_DECOMPRESSOR_SIZE / 8],
+        };
+        unsafe {
+            tinfl_init(&mut decompressor);
+            let mut in_size = encoded.len();
+            let mut out_size = out.len();
+            let status = tinfl_decompress(
+                &mut decompressor,
+                encoded.as_ptr(),
+                &mut in_size,
+                out.as_mut_ptr(),
+                out.as_mut_ptr(),
+                &mut out_size,
+                TINFL_FLAG_PARSE_ZLIB_HEADER
+                    | TINFL_FLAG_COMPUTE_ADLER32
+                    | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+            );
+            assert_eq!(status, TINFLStatus::Done as i32);
+            assert_eq!(&out[..out_size], &data[..]);
+            let adler = miniz_oxide::mz_adler32_oxide(1, data);
+            assert_eq!(tinfl_get_adler32(&mut decompressor), adler as c_int);
+        }
+    }
 }
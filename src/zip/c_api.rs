@@ -0,0 +1,3415 @@
+//! The `mz_zip_*` functions of `miniz_zip.h`, implemented on top of [`ZipArchive`] and the
+//! helpers used by [`ZipWriter`].
+//!
+//! An initialized `mz_zip_archive` owns a `ZipArchive` reading through the archive's I/O
+//! callbacks, which is also used to keep track of the entries while writing. As in miniz, the
+//! memory, heap and file variants install their own callbacks. Unlike miniz, entries added
+//! from a file are streamed and followed by a data descriptor. As in miniz, the times stored in
+//! the archive are local times, except on non-unix platforms where they are taken to be UTC.
+//!
+//! # Safety
+//!
+//! A valid archive passed as `pZip` is either zeroed, for the functions initializing it, or was
+//! initialized by them and not ended since. It must not be used from several threads at once,
+//! and any callbacks and allocation functions set in it must be safe to call with their opaque
+//! pointers.
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use std::{cmp, mem, ptr, slice};
+
+use libc::{size_t, time_t};
+use miniz_oxide::deflate::core::CompressorOxide;
+use miniz_oxide::deflate::CompressionLevel;
+
+use super::read::EntryReader;
+use super::write::{
+    central_directory, data_descriptor, local_header, read_chunk, start_entry,
+    write_central_header, write_deflated, write_stored, CHUNK_SIZE, ZIP64_LIMIT,
+};
+use super::*;
+use crate::c_export::{
+    miniz_def_alloc_func, miniz_def_free_func, miniz_def_realloc_func, mz_alloc_func, mz_bool,
+    mz_free_func, mz_realloc_func, mz_uint, mz_uint16, mz_uint32, mz_uint64,
+};
+use crate::tinfl::tinfl_status::TINFL_STATUS_FAILED;
+
+use self::mz_zip_error::*;
+use self::mz_zip_mode::*;
+use self::mz_zip_type::*;
+
+const MZ_FALSE: mz_bool = 0;
+const MZ_TRUE: mz_bool = 1;
+
+pub const MZ_ZIP_MAX_IO_BUF_SIZE: usize = 64 * 1024;
+pub const MZ_ZIP_MAX_ARCHIVE_FILENAME_SIZE: usize = 512;
+pub const MZ_ZIP_MAX_ARCHIVE_FILE_COMMENT_SIZE: usize = 512;
+
+pub const MZ_ZIP_FLAG_CASE_SENSITIVE: mz_uint = 0x0100;
+pub const MZ_ZIP_FLAG_IGNORE_PATH: mz_uint = 0x0200;
+pub const MZ_ZIP_FLAG_COMPRESSED_DATA: mz_uint = 0x0400;
+/// Has no effect, the central directory is never sorted.
+pub const MZ_ZIP_FLAG_DO_NOT_SORT_CENTRAL_DIRECTORY: mz_uint = 0x0800;
+pub const MZ_ZIP_FLAG_VALIDATE_LOCATE_FILE_FLAG: mz_uint = 0x1000;
+pub const MZ_ZIP_FLAG_VALIDATE_HEADERS_ONLY: mz_uint = 0x2000;
+pub const MZ_ZIP_FLAG_WRITE_ZIP64: mz_uint = 0x4000;
+/// Has no effect, archives can always be read while writing.
+pub const MZ_ZIP_FLAG_WRITE_ALLOW_READING: mz_uint = 0x8000;
+pub const MZ_ZIP_FLAG_ASCII_FILENAME: mz_uint = 0x10000;
+
+/// General purpose flag set for patch data, which is not supported.
+const FLAG_COMPRESSED_PATCH: u16 = 1 << 5;
+
+#[allow(bad_style)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum mz_zip_mode {
+    MZ_ZIP_MODE_INVALID = 0,
+    MZ_ZIP_MODE_READING = 1,
+    MZ_ZIP_MODE_WRITING = 2,
+    MZ_ZIP_MODE_WRITING_HAS_BEEN_FINALIZED = 3,
+}
+
+#[allow(bad_style)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum mz_zip_type {
+    MZ_ZIP_TYPE_INVALID = 0,
+    MZ_ZIP_TYPE_USER,
+    MZ_ZIP_TYPE_MEMORY,
+    MZ_ZIP_TYPE_HEAP,
+    MZ_ZIP_TYPE_FILE,
+    MZ_ZIP_TYPE_CFILE,
+    MZ_ZIP_TOTAL_TYPES,
+}
+
+#[allow(bad_style)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum mz_zip_error {
+    MZ_ZIP_NO_ERROR = 0,
+    MZ_ZIP_UNDEFINED_ERROR,
+    MZ_ZIP_TOO_MANY_FILES,
+    MZ_ZIP_FILE_TOO_LARGE,
+    MZ_ZIP_UNSUPPORTED_METHOD,
+    MZ_ZIP_UNSUPPORTED_ENCRYPTION,
+    MZ_ZIP_UNSUPPORTED_FEATURE,
+    MZ_ZIP_FAILED_FINDING_CENTRAL_DIR,
+    MZ_ZIP_NOT_AN_ARCHIVE,
+    MZ_ZIP_INVALID_HEADER_OR_CORRUPTED,
+    MZ_ZIP_UNSUPPORTED_MULTIDISK,
+    MZ_ZIP_DECOMPRESSION_FAILED,
+    MZ_ZIP_COMPRESSION_FAILED,
+    MZ_ZIP_UNEXPECTED_DECOMPRESSED_SIZE,
+    MZ_ZIP_CRC_CHECK_FAILED,
+    MZ_ZIP_UNSUPPORTED_CDIR_SIZE,
+    MZ_ZIP_ALLOC_FAILED,
+    MZ_ZIP_FILE_OPEN_FAILED,
+    MZ_ZIP_FILE_CREATE_FAILED,
+    MZ_ZIP_FILE_WRITE_FAILED,
+    MZ_ZIP_FILE_READ_FAILED,
+    MZ_ZIP_FILE_CLOSE_FAILED,
+    MZ_ZIP_FILE_SEEK_FAILED,
+    MZ_ZIP_FILE_STAT_FAILED,
+    MZ_ZIP_INVALID_PARAMETER,
+    MZ_ZIP_INVALID_FILENAME,
+    MZ_ZIP_BUF_TOO_SMALL,
+    MZ_ZIP_INTERNAL_ERROR,
+    MZ_ZIP_FILE_NOT_FOUND,
+    MZ_ZIP_ARCHIVE_TOO_LARGE,
+    MZ_ZIP_VALIDATION_FAILED,
+    MZ_ZIP_WRITE_CALLBACK_FAILED,
+    MZ_ZIP_TOTAL_ERRORS,
+}
+
+impl From<ZipError> for mz_zip_error {
+    fn from(err: ZipError) -> mz_zip_error {
+        match err {
+            ZipError::Io(err) => err.into(),
+            ZipError::NotAnArchive => MZ_ZIP_NOT_AN_ARCHIVE,
+            ZipError::InvalidArchive(_) => MZ_ZIP_INVALID_HEADER_OR_CORRUPTED,
+            ZipError::UnsupportedMultiDisk => MZ_ZIP_UNSUPPORTED_MULTIDISK,
+            ZipError::UnsupportedMethod(_) => MZ_ZIP_UNSUPPORTED_METHOD,
+            ZipError::UnsupportedEncryption => MZ_ZIP_UNSUPPORTED_ENCRYPTION,
+            ZipError::DecompressionFailed => MZ_ZIP_DECOMPRESSION_FAILED,
+            ZipError::CompressionFailed => MZ_ZIP_COMPRESSION_FAILED,
+            ZipError::UnexpectedSize => MZ_ZIP_UNEXPECTED_DECOMPRESSED_SIZE,
+            ZipError::CrcMismatch => MZ_ZIP_CRC_CHECK_FAILED,
+            ZipError::FileNotFound => MZ_ZIP_FILE_NOT_FOUND,
+            ZipError::InvalidName => MZ_ZIP_INVALID_FILENAME,
+            ZipError::FileTooLarge => MZ_ZIP_FILE_TOO_LARGE,
+        }
+    }
+}
+
+impl From<io::Error> for mz_zip_error {
+    fn from(err: io::Error) -> mz_zip_error {
+        match err.kind() {
+            io::ErrorKind::WriteZero => MZ_ZIP_FILE_WRITE_FAILED,
+            io::ErrorKind::InvalidInput => MZ_ZIP_FILE_SEEK_FAILED,
+            _ => MZ_ZIP_FILE_READ_FAILED,
+        }
+    }
+}
+
+#[allow(bad_style)]
+pub type mz_file_read_func =
+    unsafe extern "C" fn(*mut c_void, mz_uint64, *mut c_void, size_t) -> size_t;
+#[allow(bad_style)]
+pub type mz_file_write_func =
+    unsafe extern "C" fn(*mut c_void, mz_uint64, *const c_void, size_t) -> size_t;
+#[allow(bad_style)]
+pub type mz_file_needs_keepalive = unsafe extern "C" fn(*mut c_void) -> mz_bool;
+
+/// Metadata of an entry, as returned by `mz_zip_reader_file_stat`.
+#[allow(bad_style)]
+#[repr(C)]
+pub struct mz_zip_archive_file_stat {
+    pub m_file_index: mz_uint32,
+    pub m_central_dir_ofs: mz_uint64,
+    pub m_version_made_by: mz_uint16,
+    pub m_version_needed: mz_uint16,
+    pub m_bit_flag: mz_uint16,
+    pub m_method: mz_uint16,
+    pub m_time: time_t,
+    pub m_crc32: mz_uint32,
+    pub m_comp_size: mz_uint64,
+    pub m_uncomp_size: mz_uint64,
+    pub m_internal_attr: mz_uint16,
+    pub m_external_attr: mz_uint32,
+    pub m_local_header_ofs: mz_uint64,
+    pub m_comment_size: mz_uint32,
+    pub m_is_directory: mz_bool,
+    pub m_is_encrypted: mz_bool,
+    pub m_is_supported: mz_bool,
+    /// The name, truncated to fit and zero terminated.
+    pub m_filename: [c_char; MZ_ZIP_MAX_ARCHIVE_FILENAME_SIZE],
+    /// The comment, truncated to fit and zero terminated.
+    pub m_comment: [c_char; MZ_ZIP_MAX_ARCHIVE_FILE_COMMENT_SIZE],
+}
+
+/// An archive being read or written. Must be zeroed before initializing it.
+#[allow(bad_style)]
+#[repr(C)]
+pub struct mz_zip_archive {
+    pub m_archive_size: mz_uint64,
+    pub m_central_directory_file_ofs: mz_uint64,
+    pub m_total_files: mz_uint32,
+    pub m_zip_mode: mz_zip_mode,
+    pub m_zip_type: mz_zip_type,
+    pub m_last_error: mz_zip_error,
+    /// Local headers of added entries are aligned to this if it's non-zero, which must be a
+    /// power of two.
+    pub m_file_offset_alignment: mz_uint64,
+    pub m_pAlloc: Option<mz_alloc_func>,
+    pub m_pFree: Option<mz_free_func>,
+    pub m_pRealloc: Option<mz_realloc_func>,
+    pub m_pAlloc_opaque: *mut c_void,
+    pub m_pRead: Option<mz_file_read_func>,
+    pub m_pWrite: Option<mz_file_write_func>,
+    /// Not used.
+    pub m_pNeeds_keepalive: Option<mz_file_needs_keepalive>,
+    pub m_pIO_opaque: *mut c_void,
+    pub m_pState: *mut mz_zip_internal_state,
+}
+
+/// State of an iterator over the data of an entry.
+///
+/// Only `pZip`, `flags`, `status`, `file_crc32`, `comp_remaining`, `out_buf_ofs` and
+/// `file_stat` are kept up to date.
+#[allow(bad_style)]
+#[repr(C)]
+pub struct mz_zip_reader_extract_iter_state {
+    pub pZip: *mut mz_zip_archive,
+    pub flags: mz_uint,
+    pub status: c_int,
+    pub file_crc32: mz_uint,
+    pub read_buf_size: mz_uint64,
+    pub read_buf_ofs: mz_uint64,
+    pub read_buf_avail: mz_uint64,
+    pub comp_remaining: mz_uint64,
+    pub out_buf_ofs: mz_uint64,
+    pub cur_file_ofs: mz_uint64,
+    pub file_stat: mz_zip_archive_file_stat,
+    pub pRead_buf: *mut c_void,
+    pub pWrite_buf: *mut c_void,
+    pub out_blk_remain: size_t,
+    /// Takes the place of the decompressor in miniz.
+    reader: EntryReader<ZipIo>,
+}
+
+/// Internal state of an initialized archive.
+#[allow(bad_style)]
+pub struct mz_zip_internal_state {
+    archive: ZipArchive<ZipIo>,
+    /// Storage behind the I/O callbacks for the memory, heap and file variants, null for
+    /// user callbacks.
+    backing: *mut Backing,
+    /// The `FILE` passed in for the `cfile` variants.
+    cfile: *mut c_void,
+    compressor: Option<CompressorOxide>,
+    /// Whether to always use the zip64 extensions for new entries.
+    zip64: bool,
+}
+
+impl mz_zip_internal_state {
+    /// Create the state for `zip` with an empty archive of `size` bytes. The archive is read
+    /// through `backing` if it's given, otherwise through the callbacks in `zip`.
+    fn new(
+        zip: &mz_zip_archive,
+        backing: Option<Backing>,
+        size: u64,
+    ) -> Box<mz_zip_internal_state> {
+        let backing = backing.map_or(ptr::null_mut(), |backing| Box::into_raw(Box::new(backing)));
+        let io = if backing.is_null() {
+            ZipIo {
+                read: zip.m_pRead,
+                write: zip.m_pWrite,
+                opaque: zip.m_pIO_opaque,
+                pos: 0,
+                size,
+            }
+        } else {
+            ZipIo {
+                read: Some(backing_read),
+                write: Some(backing_write),
+                opaque: backing as *mut c_void,
+                pos: 0,
+                size,
+            }
+        };
+        Box::new(mz_zip_internal_state {
+            archive: ZipArchive::empty(io),
+            backing,
+            cfile: ptr::null_mut(),
+            compressor: None,
+            zip64: false,
+        })
+    }
+}
+
+impl Drop for mz_zip_internal_state {
+    fn drop(&mut self) {
+        if !self.backing.is_null() {
+            unsafe { drop(Box::from_raw(self.backing)) };
+        }
+    }
+}
+
+/// Reads and writes the archive through a pair of callbacks, which are given absolute
+/// offsets.
+#[derive(Copy, Clone)]
+struct ZipIo {
+    read: Option<mz_file_read_func>,
+    write: Option<mz_file_write_func>,
+    opaque: *mut c_void,
+    pos: u64,
+    size: u64,
+}
+
+impl Read for ZipIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self.read {
+            Some(read) => read,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "no read callback")),
+        };
+        let n = cmp::min(buf.len() as u64, self.size.saturating_sub(self.pos)) as usize;
+        if n == 0 {
+            return Ok(0);
+        }
+        if unsafe { read(self.opaque, self.pos, buf.as_mut_ptr() as *mut c_void, n) } != n {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "read callback failed",
+            ));
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for ZipIo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let write = match self.write {
+            Some(write) => write,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "no write callback",
+                ))
+            }
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = buf.len();
+        if unsafe { write(self.opaque, self.pos, buf.as_ptr() as *const c_void, n) } != n {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "write callback failed",
+            ));
+        }
+        self.pos += n as u64;
+        self.size = cmp::max(self.size, self.pos);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for ZipIo {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => add_signed(self.size, delta),
+            SeekFrom::Current(delta) => add_signed(self.pos, delta),
+        };
+        match new_pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            )),
+        }
+    }
+}
+
+fn add_signed(base: u64, delta: i64) -> Option<u64> {
+    if delta >= 0 {
+        base.checked_add(delta as u64)
+    } else {
+        base.checked_sub(delta.wrapping_neg() as u64)
+    }
+}
+
+/// A file the archive is stored in.
+trait Storage: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> Storage for T {}
+
+/// Storage behind the internal I/O callbacks.
+enum Backing {
+    /// A read-only block of memory owned by the caller.
+    Mem {
+        data: *const u8,
+        len: usize,
+    },
+    Heap(HeapBuf),
+    /// A file, with the archive starting `start` bytes in.
+    File {
+        file: Box<dyn Storage>,
+        start: u64,
+    },
+}
+
+impl Backing {
+    fn read_at(&mut self, ofs: u64, buf: &mut [u8]) -> usize {
+        match *self {
+            Backing::Mem { data, len } => unsafe { copy_from(data, len, ofs, buf) },
+            Backing::Heap(ref heap) => unsafe { copy_from(heap.data, heap.len, ofs, buf) },
+            Backing::File {
+                ref mut file,
+                start,
+            } => {
+                if file.seek(SeekFrom::Start(start + ofs)).is_err() {
+                    return 0;
+                }
+                let mut n = 0;
+                while n < buf.len() {
+                    match read_chunk(file, &mut buf[n..]) {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => n += read,
+                    }
+                }
+                n
+            }
+        }
+    }
+
+    fn write_at(&mut self, ofs: u64, buf: &[u8]) -> usize {
+        match *self {
+            Backing::Mem { .. } => 0,
+            Backing::Heap(ref mut heap) => unsafe { heap.write_at(ofs, buf) },
+            Backing::File {
+                ref mut file,
+                start,
+            } => match file
+                .seek(SeekFrom::Start(start + ofs))
+                .and_then(|_| file.write_all(buf))
+            {
+                Ok(()) => buf.len(),
+                Err(_) => 0,
+            },
+        }
+    }
+}
+
+/// Copy from `len` bytes at `data`, starting at `ofs`, into `buf`.
+unsafe fn copy_from(data: *const u8, len: usize, ofs: u64, buf: &mut [u8]) -> usize {
+    if ofs >= len as u64 {
+        return 0;
+    }
+    let n = cmp::min(buf.len(), len - ofs as usize);
+    ptr::copy_nonoverlapping(data.add(ofs as usize), buf.as_mut_ptr(), n);
+    n
+}
+
+unsafe extern "C" fn backing_read(
+    opaque: *mut c_void,
+    file_ofs: mz_uint64,
+    buf: *mut c_void,
+    n: size_t,
+) -> size_t {
+    let backing = &mut *(opaque as *mut Backing);
+    backing.read_at(file_ofs, slice::from_raw_parts_mut(buf as *mut u8, n))
+}
+
+unsafe extern "C" fn backing_write(
+    opaque: *mut c_void,
+    file_ofs: mz_uint64,
+    buf: *const c_void,
+    n: size_t,
+) -> size_t {
+    let backing = &mut *(opaque as *mut Backing);
+    backing.write_at(file_ofs, slice::from_raw_parts(buf as *const u8, n))
+}
+
+/// The allocation functions of an archive.
+struct Allocator {
+    alloc: mz_alloc_func,
+    realloc: mz_realloc_func,
+    free: mz_free_func,
+    opaque: *mut c_void,
+}
+
+impl Allocator {
+    fn of(zip: &mz_zip_archive) -> Allocator {
+        Allocator {
+            alloc: zip.m_pAlloc.unwrap_or(miniz_def_alloc_func),
+            realloc: zip.m_pRealloc.unwrap_or(miniz_def_realloc_func),
+            free: zip.m_pFree.unwrap_or(miniz_def_free_func),
+            opaque: zip.m_pAlloc_opaque,
+        }
+    }
+}
+
+/// A growable block of memory allocated with the archive's allocation functions.
+struct HeapBuf {
+    data: *mut u8,
+    len: usize,
+    capacity: usize,
+    alloc: Allocator,
+}
+
+impl HeapBuf {
+    /// Make sure the block can hold at least `size` bytes.
+    unsafe fn reserve(&mut self, size: usize) -> bool {
+        if size <= self.capacity {
+            return true;
+        }
+        let capacity = cmp::max(cmp::max(size, self.capacity.saturating_mul(2)), 64);
+        let data = if self.data.is_null() {
+            (self.alloc.alloc)(self.alloc.opaque, 1, capacity)
+        } else {
+            (self.alloc.realloc)(self.alloc.opaque, self.data as *mut c_void, 1, capacity)
+        };
+        if data.is_null() {
+            return false;
+        }
+        self.data = data as *mut u8;
+        self.capacity = capacity;
+        true
+    }
+
+    /// Write `buf` at `ofs`, filling any gap after the current end with zeros.
+    unsafe fn write_at(&mut self, ofs: u64, buf: &[u8]) -> usize {
+        let end = match ofs.checked_add(buf.len() as u64) {
+            Some(end) if end <= usize::max_value() as u64 => end as usize,
+            _ => return 0,
+        };
+        if buf.is_empty() || !self.reserve(end) {
+            return 0;
+        }
+        let ofs = ofs as usize;
+        if ofs > self.len {
+            ptr::write_bytes(self.data.add(self.len), 0, ofs - self.len);
+        }
+        ptr::copy_nonoverlapping(buf.as_ptr(), self.data.add(ofs), buf.len());
+        self.len = cmp::max(self.len, end);
+        buf.len()
+    }
+}
+
+impl Drop for HeapBuf {
+    fn drop(&mut self) {
+        if !self.data.is_null() {
+            unsafe { (self.alloc.free)(self.alloc.opaque, self.data as *mut c_void) };
+        }
+    }
+}
+
+/// Run `f` on `zip`, catching panics as this is called from C. On failure, the error is
+/// stored in `m_last_error` and `failed` is returned.
+unsafe fn with_zip<T, F>(zip: *mut mz_zip_archive, failed: T, f: F) -> T
+where
+    F: FnOnce(&mut mz_zip_archive) -> Result<T, mz_zip_error>,
+{
+    let zip = match zip.as_mut() {
+        Some(zip) => zip,
+        None => return failed,
+    };
+    let err = match catch_unwind(AssertUnwindSafe(|| f(zip))) {
+        Ok(Ok(value)) => return value,
+        Ok(Err(err)) => err,
+        Err(_) => MZ_ZIP_INTERNAL_ERROR,
+    };
+    zip.m_last_error = err;
+    failed
+}
+
+/// Get the state of an initialized archive.
+unsafe fn state<'a>(zip: &mz_zip_archive) -> Result<&'a mut mz_zip_internal_state, mz_zip_error> {
+    zip.m_pState.as_mut().ok_or(MZ_ZIP_INVALID_PARAMETER)
+}
+
+fn entry(state: &mz_zip_internal_state, index: mz_uint) -> Result<&ZipEntry, mz_zip_error> {
+    state
+        .archive
+        .entry(index as usize)
+        .ok_or(MZ_ZIP_INVALID_PARAMETER)
+}
+
+/// `len` bytes at `data`, which may be null if `len` is 0.
+unsafe fn bytes<'a>(data: *const c_void, len: usize) -> Result<&'a [u8], mz_zip_error> {
+    if len == 0 {
+        Ok(&[])
+    } else if data.is_null() {
+        Err(MZ_ZIP_INVALID_PARAMETER)
+    } else {
+        Ok(slice::from_raw_parts(data as *const u8, len))
+    }
+}
+
+unsafe fn c_str<'a>(s: *const c_char) -> Result<&'a [u8], mz_zip_error> {
+    if s.is_null() {
+        Err(MZ_ZIP_INVALID_PARAMETER)
+    } else {
+        Ok(CStr::from_ptr(s as *const _).to_bytes())
+    }
+}
+
+/// The path in the C string `name`.
+unsafe fn c_path<'a>(name: *const c_char) -> Result<&'a Path, mz_zip_error> {
+    let name = c_str(name)?;
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        Ok(Path::new(OsStr::from_bytes(name)))
+    }
+    #[cfg(not(unix))]
+    {
+        std::str::from_utf8(name)
+            .map(Path::new)
+            .map_err(|_| MZ_ZIP_INVALID_FILENAME)
+    }
+}
+
+fn set_default_allocators(zip: &mut mz_zip_archive) {
+    if zip.m_pAlloc.is_none() {
+        zip.m_pAlloc = Some(miniz_def_alloc_func);
+    }
+    if zip.m_pFree.is_none() {
+        zip.m_pFree = Some(miniz_def_free_func);
+    }
+    if zip.m_pRealloc.is_none() {
+        zip.m_pRealloc = Some(miniz_def_realloc_func);
+    }
+}
+
+fn check_uninitialized(zip: &mz_zip_archive) -> Result<(), mz_zip_error> {
+    if zip.m_zip_mode != MZ_ZIP_MODE_INVALID || !zip.m_pState.is_null() {
+        Err(MZ_ZIP_INVALID_PARAMETER)
+    } else {
+        Ok(())
+    }
+}
+
+/// Store `state` in `zip`, pointing the I/O callbacks at its backing if it has one.
+fn install(zip: &mut mz_zip_archive, state: Box<mz_zip_internal_state>) {
+    if !state.backing.is_null() {
+        zip.m_pRead = Some(backing_read);
+        zip.m_pWrite = Some(backing_write);
+        zip.m_pIO_opaque = state.backing as *mut c_void;
+    }
+    zip.m_pState = Box::into_raw(state);
+}
+
+/// Free the state of `zip`, leaving it uninitialized.
+unsafe fn end(zip: &mut mz_zip_archive) {
+    let state = Box::from_raw(zip.m_pState);
+    zip.m_pState = ptr::null_mut();
+    if !state.backing.is_null() {
+        zip.m_pRead = None;
+        zip.m_pWrite = None;
+        zip.m_pIO_opaque = ptr::null_mut();
+    }
+    zip.m_zip_mode = MZ_ZIP_MODE_INVALID;
+}
+
+/// Read the central directory of the `m_archive_size` byte archive, through `backing` or
+/// the user callbacks.
+fn init_reader(
+    zip: &mut mz_zip_archive,
+    zip_type: mz_zip_type,
+    backing: Option<Backing>,
+) -> Result<(), mz_zip_error> {
+    check_uninitialized(zip)?;
+    set_default_allocators(zip);
+    let mut state = mz_zip_internal_state::new(zip, backing, zip.m_archive_size);
+    if state.archive.get_ref().read.is_none() {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    }
+    state.archive = ZipArchive::new(*state.archive.get_ref())?;
+    if state.archive.len() > u32::max_value() as usize {
+        return Err(MZ_ZIP_TOO_MANY_FILES);
+    }
+    zip.m_total_files = state.archive.len() as u32;
+    zip.m_central_directory_file_ofs = state.archive.offset() + state.archive.central_dir_offset();
+    zip.m_zip_mode = MZ_ZIP_MODE_READING;
+    zip.m_zip_type = zip_type;
+    install(zip, state);
+    Ok(())
+}
+
+/// Start writing an archive after `existing_size` bytes, through `backing` or the user
+/// callbacks.
+fn init_writer(
+    zip: &mut mz_zip_archive,
+    zip_type: mz_zip_type,
+    backing: Option<Backing>,
+    existing_size: u64,
+    flags: mz_uint,
+) -> Result<(), mz_zip_error> {
+    check_uninitialized(zip)?;
+    let alignment = zip.m_file_offset_alignment;
+    if alignment & alignment.wrapping_sub(1) != 0 {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    }
+    set_default_allocators(zip);
+    let mut state = mz_zip_internal_state::new(zip, backing, existing_size);
+    if state.archive.get_ref().write.is_none() {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    }
+    state.archive.get_mut().pos = existing_size;
+    state.zip64 = flags & MZ_ZIP_FLAG_WRITE_ZIP64 != 0;
+    zip.m_archive_size = existing_size;
+    zip.m_central_directory_file_ofs = 0;
+    zip.m_total_files = 0;
+    zip.m_zip_mode = MZ_ZIP_MODE_WRITING;
+    zip.m_zip_type = zip_type;
+    install(zip, state);
+    Ok(())
+}
+
+/// Find the first entry named `name`, and with the comment `comment` if it's given and not
+/// empty. Names and comments are compared ignoring ASCII case unless `flags` contains
+/// `MZ_ZIP_FLAG_CASE_SENSITIVE`. With `MZ_ZIP_FLAG_IGNORE_PATH`, only the part of entry names
+/// after the last path separator is compared.
+fn locate(
+    entries: &[ZipEntry],
+    name: &[u8],
+    comment: Option<&[u8]>,
+    flags: mz_uint,
+) -> Option<usize> {
+    let equal = |a: &[u8], b: &[u8]| {
+        if flags & MZ_ZIP_FLAG_CASE_SENSITIVE != 0 {
+            a == b
+        } else {
+            a.eq_ignore_ascii_case(b)
+        }
+    };
+    entries.iter().position(|entry| {
+        if let Some(comment) = comment {
+            if !comment.is_empty() && !equal(comment, entry.comment()) {
+                return false;
+            }
+        }
+        let mut entry_name = entry.name_raw();
+        if flags & MZ_ZIP_FLAG_IGNORE_PATH != 0 {
+            if let Some(pos) = entry_name
+                .iter()
+                .rposition(|&c| c == b'/' || c == b'\\' || c == b':')
+            {
+                entry_name = &entry_name[pos + 1..];
+            }
+        }
+        equal(name, entry_name)
+    })
+}
+
+unsafe fn locate_c(
+    state: &mz_zip_internal_state,
+    name: *const c_char,
+    comment: *const c_char,
+    flags: mz_uint,
+) -> Result<mz_uint, mz_zip_error> {
+    let name = c_str(name)?;
+    let comment = if comment.is_null() {
+        None
+    } else {
+        Some(c_str(comment)?)
+    };
+    match locate(state.archive.entries(), name, comment, flags) {
+        Some(index) => Ok(index as mz_uint),
+        None => Err(MZ_ZIP_FILE_NOT_FOUND),
+    }
+}
+
+/// Decompress the entry at `index`, or copy its compressed data with
+/// `MZ_ZIP_FLAG_COMPRESSED_DATA`, passing each chunk to `out` with its offset in the data.
+fn extract<F>(
+    state: &mut mz_zip_internal_state,
+    index: mz_uint,
+    flags: mz_uint,
+    mut out: F,
+) -> Result<(), mz_zip_error>
+where
+    F: FnMut(u64, &[u8]) -> Result<(), mz_zip_error>,
+{
+    let raw = flags & MZ_ZIP_FLAG_COMPRESSED_DATA != 0;
+    let size = extracted_size(entry(state, index)?, flags);
+    let mut reader = state.archive.entry_reader(index as usize, raw)?;
+    let mut buf = vec![0; cmp::min(CHUNK_SIZE as u64, size + 1) as usize];
+    while !reader.is_done() {
+        let n = reader.read_data(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        out(reader.written() - n as u64, &buf[..n])?;
+    }
+    Ok(())
+}
+
+fn extracted_size(entry: &ZipEntry, flags: mz_uint) -> u64 {
+    if flags & MZ_ZIP_FLAG_COMPRESSED_DATA != 0 {
+        entry.compressed_size()
+    } else {
+        entry.size()
+    }
+}
+
+unsafe fn extract_to_mem(
+    zip: &mut mz_zip_archive,
+    index: mz_uint,
+    buf: *mut c_void,
+    buf_size: size_t,
+    flags: mz_uint,
+) -> Result<(), mz_zip_error> {
+    let state = state(zip)?;
+    let size = extracted_size(entry(state, index)?, flags);
+    if size > buf_size as u64 {
+        return Err(MZ_ZIP_BUF_TOO_SMALL);
+    }
+    if size == 0 {
+        return extract(state, index, flags, |_, _| Ok(()));
+    }
+    if buf.is_null() {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    }
+    let out = slice::from_raw_parts_mut(buf as *mut u8, size as usize);
+    extract(state, index, flags, |ofs, data| {
+        out[ofs as usize..ofs as usize + data.len()].copy_from_slice(data);
+        Ok(())
+    })
+}
+
+unsafe fn extract_to_heap(
+    zip: &mut mz_zip_archive,
+    index: mz_uint,
+    size_out: *mut size_t,
+    flags: mz_uint,
+) -> Result<*mut c_void, mz_zip_error> {
+    let state = state(zip)?;
+    let size = extracted_size(entry(state, index)?, flags);
+    if size > usize::max_value() as u64 {
+        return Err(MZ_ZIP_ARCHIVE_TOO_LARGE);
+    }
+    let size = size as usize;
+    let alloc = Allocator::of(zip);
+    let buf = (alloc.alloc)(alloc.opaque, 1, cmp::max(size, 1));
+    if buf.is_null() {
+        return Err(MZ_ZIP_ALLOC_FAILED);
+    }
+    let out = slice::from_raw_parts_mut(buf as *mut u8, size);
+    let result = extract(state, index, flags, |ofs, data| {
+        out[ofs as usize..ofs as usize + data.len()].copy_from_slice(data);
+        Ok(())
+    });
+    if let Err(err) = result {
+        (alloc.free)(alloc.opaque, buf);
+        return Err(err);
+    }
+    if !size_out.is_null() {
+        *size_out = size;
+    }
+    Ok(buf)
+}
+
+unsafe fn extract_to_callback(
+    zip: &mut mz_zip_archive,
+    index: mz_uint,
+    callback: Option<mz_file_write_func>,
+    opaque: *mut c_void,
+    flags: mz_uint,
+) -> Result<(), mz_zip_error> {
+    let state = state(zip)?;
+    let callback = callback.ok_or(MZ_ZIP_INVALID_PARAMETER)?;
+    extract(state, index, flags, |ofs, data| {
+        if callback(opaque, ofs, data.as_ptr() as *const c_void, data.len()) == data.len() {
+            Ok(())
+        } else {
+            Err(MZ_ZIP_WRITE_CALLBACK_FAILED)
+        }
+    })
+}
+
+unsafe fn extract_to_file(
+    zip: &mut mz_zip_archive,
+    index: mz_uint,
+    dst_filename: *const c_char,
+    flags: mz_uint,
+) -> Result<(), mz_zip_error> {
+    let state = state(zip)?;
+    let entry = entry(state, index)?;
+    if entry.is_dir() || !entry.is_supported() {
+        return Err(MZ_ZIP_UNSUPPORTED_FEATURE);
+    }
+    let time = local_unix_time(entry.last_modified());
+    let path = c_path(dst_filename)?;
+    let mut file = File::create(path).map_err(|_| MZ_ZIP_FILE_OPEN_FAILED)?;
+    extract(state, index, flags, |_, data| {
+        file.write_all(data).map_err(|_| MZ_ZIP_FILE_WRITE_FAILED)
+    })?;
+    drop(file);
+    set_file_time(dst_filename, time);
+    Ok(())
+}
+
+#[cfg(all(unix, not(feature = "libc_stub")))]
+unsafe fn set_file_time(filename: *const c_char, time: i64) {
+    let times = libc::utimbuf {
+        actime: time as time_t,
+        modtime: time as time_t,
+    };
+    libc::utime(filename, &times);
+}
+
+#[cfg(not(all(unix, not(feature = "libc_stub"))))]
+unsafe fn set_file_time(_filename: *const c_char, _time: i64) {}
+
+fn fill_stat(entry: &ZipEntry, stat: &mut mz_zip_archive_file_stat) {
+    stat.m_file_index = entry.index() as mz_uint32;
+    stat.m_central_dir_ofs = entry.central_dir_offset();
+    stat.m_version_made_by = entry.version_made_by();
+    stat.m_version_needed = entry.version_needed();
+    stat.m_bit_flag = entry.flags();
+    stat.m_method = entry.method().to_u16();
+    stat.m_time = local_unix_time(entry.last_modified()) as time_t;
+    stat.m_crc32 = entry.crc32();
+    stat.m_comp_size = entry.compressed_size();
+    stat.m_uncomp_size = entry.size();
+    stat.m_internal_attr = entry.internal_attributes();
+    stat.m_external_attr = entry.external_attributes();
+    stat.m_local_header_ofs = entry.local_header_offset();
+    stat.m_comment_size = entry.comment().len() as mz_uint32;
+    stat.m_is_directory = entry.is_dir() as mz_bool;
+    stat.m_is_encrypted = entry.is_encrypted() as mz_bool;
+    stat.m_is_supported = is_supported(entry) as mz_bool;
+    copy_c_str(&mut stat.m_filename, entry.name_raw());
+    copy_c_str(&mut stat.m_comment, entry.comment());
+}
+
+fn is_supported(entry: &ZipEntry) -> bool {
+    entry.is_supported() && entry.flags() & FLAG_COMPRESSED_PATCH == 0
+}
+
+/// Copy as much of `src` as fits into `dst`, followed by a zero.
+fn copy_c_str(dst: &mut [c_char], src: &[u8]) {
+    let n = cmp::min(src.len(), dst.len() - 1);
+    for (d, &s) in dst.iter_mut().zip(&src[..n]) {
+        *d = s as c_char;
+    }
+    dst[n] = 0;
+}
+
+/// Check the local header of the entry at `index` against the central directory, and unless
+/// `flags` contains `MZ_ZIP_FLAG_VALIDATE_HEADERS_ONLY`, that it can be extracted.
+fn validate(
+    state: &mut mz_zip_internal_state,
+    index: mz_uint,
+    flags: mz_uint,
+) -> Result<(), mz_zip_error> {
+    let entry = entry(state, index)?.clone();
+    if entry.is_encrypted() {
+        return Err(MZ_ZIP_UNSUPPORTED_ENCRYPTION);
+    }
+    if !is_supported(&entry) {
+        return Err(MZ_ZIP_UNSUPPORTED_METHOD);
+    }
+
+    let mut io = *state.archive.get_ref();
+    let header_pos = state
+        .archive
+        .offset()
+        .checked_add(entry.local_header_offset())
+        .ok_or(MZ_ZIP_INVALID_HEADER_OR_CORRUPTED)?;
+    io.seek(SeekFrom::Start(header_pos))?;
+    let mut header = [0; LOCAL_HEADER_SIZE];
+    io.read_exact(&mut header)?;
+    if read_u32(&header, 0) != LOCAL_HEADER_SIGNATURE {
+        return Err(MZ_ZIP_INVALID_HEADER_OR_CORRUPTED);
+    }
+    let mut name = vec![0; usize::from(read_u16(&header, 26))];
+    io.read_exact(&mut name)?;
+    if name != entry.name_raw() || read_u16(&header, 8) != entry.method().to_u16() {
+        return Err(MZ_ZIP_VALIDATION_FAILED);
+    }
+    if read_u16(&header, 6) & FLAG_DATA_DESCRIPTOR == 0 {
+        let compressed_size = read_u32(&header, 18);
+        let size = read_u32(&header, 22);
+        if read_u32(&header, 14) != entry.crc32()
+            || (compressed_size != ZIP64_LIMIT as u32
+                && u64::from(compressed_size) != entry.compressed_size())
+            || (size != ZIP64_LIMIT as u32 && u64::from(size) != entry.size())
+        {
+            return Err(MZ_ZIP_VALIDATION_FAILED);
+        }
+    }
+
+    if flags & MZ_ZIP_FLAG_VALIDATE_LOCATE_FILE_FLAG != 0
+        && locate(
+            state.archive.entries(),
+            entry.name_raw(),
+            None,
+            MZ_ZIP_FLAG_CASE_SENSITIVE,
+        ) != Some(index as usize)
+    {
+        return Err(MZ_ZIP_VALIDATION_FAILED);
+    }
+    if flags & MZ_ZIP_FLAG_VALIDATE_HEADERS_ONLY == 0 {
+        extract(state, index, 0, |_, _| Ok(()))?;
+    }
+    Ok(())
+}
+
+/// Where the data of an entry being added comes from.
+enum Source<R> {
+    /// Stored or already compressed data with the CRC-32 and sizes known in advance, followed
+    /// by a data descriptor if `descriptor` is set.
+    Raw {
+        reader: R,
+        crc32: u32,
+        size: u64,
+        compressed_size: u64,
+        descriptor: bool,
+    },
+    /// `size` bytes of data compressed at `level` while written, followed by a data
+    /// descriptor.
+    Data { reader: R, size: u64, level: u8 },
+}
+
+/// Add `entry` with data from `source` at the end of the archive being written, aligned to
+/// `m_file_offset_alignment`. `local_extra` is added to the extra field in the local header.
+unsafe fn add_entry<R: Read>(
+    zip: &mut mz_zip_archive,
+    mut entry: ZipEntry,
+    local_extra: &[u8],
+    source: Source<R>,
+) -> Result<(), mz_zip_error> {
+    if zip.m_zip_mode != MZ_ZIP_MODE_WRITING {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    }
+    let state = state(zip)?;
+    if state.archive.len() >= u32::max_value() as usize {
+        return Err(MZ_ZIP_TOO_MANY_FILES);
+    }
+    // Leave room for the zip64 extra field.
+    if local_extra.len() > 0xffff - 20 || entry.extra.len() > 0xffff - 28 {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    }
+
+    // Write through a copy of the I/O, so nothing changes if this fails.
+    let mut io = *state.archive.get_ref();
+    io.pos = zip.m_archive_size;
+    let alignment = zip.m_file_offset_alignment;
+    if alignment > 1 {
+        let padding = (alignment - io.pos % alignment) % alignment;
+        io.write_all(&vec![0; padding as usize])?;
+    }
+    entry.index = state.archive.len();
+    entry.local_header_offset = io.pos - state.archive.offset();
+    write_entry(
+        &mut io,
+        &mut state.compressor,
+        state.zip64,
+        &mut entry,
+        local_extra,
+        source,
+    )?;
+
+    *state.archive.get_mut() = io;
+    state.archive.entries_mut().push(entry);
+    zip.m_total_files = state.archive.len() as mz_uint32;
+    zip.m_archive_size = io.pos;
+    Ok(())
+}
+
+fn write_entry<R: Read>(
+    io: &mut ZipIo,
+    compressor: &mut Option<CompressorOxide>,
+    force_zip64: bool,
+    entry: &mut ZipEntry,
+    local_extra: &[u8],
+    source: Source<R>,
+) -> Result<(), ZipError> {
+    match source {
+        Source::Raw {
+            reader,
+            crc32,
+            size,
+            compressed_size,
+            descriptor,
+        } => {
+            let zip64 = force_zip64 || size >= ZIP64_LIMIT || compressed_size >= ZIP64_LIMIT;
+            if zip64 {
+                entry.version_needed = cmp::max(entry.version_needed, VERSION_ZIP64);
+            }
+            if descriptor {
+                entry.flags |= FLAG_DATA_DESCRIPTOR;
+            } else {
+                entry.flags &= !FLAG_DATA_DESCRIPTOR;
+            }
+            entry.crc32 = crc32;
+            entry.uncompressed_size = size;
+            entry.compressed_size = compressed_size;
+            io.write_all(&local_header_with_extra(entry, zip64, local_extra))?;
+            if io::copy(&mut reader.take(compressed_size), io)? != compressed_size {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            if descriptor {
+                io.write_all(&data_descriptor(entry, zip64))?;
+            }
+        }
+        Source::Data {
+            reader,
+            size,
+            level,
+        } => {
+            let zip64 = force_zip64 || size >= ZIP64_LIMIT;
+            if zip64 {
+                entry.version_needed = cmp::max(entry.version_needed, VERSION_ZIP64);
+            }
+            entry.flags |= FLAG_DATA_DESCRIPTOR;
+            io.write_all(&local_header_with_extra(entry, zip64, local_extra))?;
+            let reader = reader.take(size);
+            let (crc32, uncompressed_size, compressed_size) = match entry.method {
+                CompressionMethod::Deflated => write_deflated(compressor, reader, io, level)?,
+                _ => write_stored(reader, io)?,
+            };
+            if uncompressed_size != size {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            entry.crc32 = crc32;
+            entry.uncompressed_size = uncompressed_size;
+            entry.compressed_size = compressed_size;
+            io.write_all(&data_descriptor(entry, zip64))?;
+        }
+    }
+    Ok(())
+}
+
+/// The local header for `entry` with `extra` appended to the extra field.
+fn local_header_with_extra(entry: &ZipEntry, zip64: bool, extra: &[u8]) -> Vec<u8> {
+    let mut header = local_header(entry, zip64);
+    if !extra.is_empty() {
+        let extra_len = usize::from(read_u16(&header, 28)) + extra.len();
+        header[28..30].copy_from_slice(&(extra_len as u16).to_le_bytes());
+        header.extend_from_slice(extra);
+    }
+    header
+}
+
+/// Split `level_and_flags` into the compression level and the flags, using the default
+/// level for a negative value as miniz does.
+fn split_level(level_and_flags: mz_uint) -> Result<(u8, mz_uint), mz_zip_error> {
+    let level_and_flags = if (level_and_flags as c_int) < 0 {
+        CompressionLevel::DefaultLevel as mz_uint
+    } else {
+        level_and_flags
+    };
+    let level = level_and_flags & 0xf;
+    if level > 10 {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    }
+    Ok((level as u8, level_and_flags & !0xf))
+}
+
+/// Check the name of an entry to add.
+unsafe fn archive_name<'a>(name: *const c_char, flags: mz_uint) -> Result<&'a [u8], mz_zip_error> {
+    let name = c_str(name)?;
+    if name
+        .first()
+        .map_or(true, |&c| c == b'/' || c == b'\\' || c == b':')
+        || (flags & MZ_ZIP_FLAG_ASCII_FILENAME != 0 && !name.is_ascii())
+    {
+        return Err(MZ_ZIP_INVALID_FILENAME);
+    }
+    Ok(name)
+}
+
+/// Start an entry named `name` for data added with `add_entry`.
+fn new_entry(
+    name: &[u8],
+    level: u8,
+    last_modified: DateTime,
+    comment: &[u8],
+    central_extra: &[u8],
+    streamed: bool,
+) -> Result<ZipEntry, mz_zip_error> {
+    let directory = name.last() == Some(&b'/');
+    let options = FileOptions::new()
+        .level(level)
+        .last_modified(last_modified)
+        .comment(comment);
+    let mut entry = start_entry(0, name, &options, 0, false, directory, streamed)?;
+    entry.extra = central_extra.to_vec();
+    Ok(entry)
+}
+
+// `time_t` isn't 64 bits on every platform.
+#[allow(clippy::unnecessary_cast)]
+fn unix_time_or_now(time: *const time_t) -> DateTime {
+    match unsafe { time.as_ref() } {
+        Some(&time) => local_date_time(time as i64),
+        None => local_date_time(DateTime::now().to_unix_time()),
+    }
+}
+
+/// Convert a unix timestamp to the local time that is stored in the archive, like miniz.
+#[cfg(all(unix, not(feature = "libc_stub")))]
+#[allow(clippy::unnecessary_cast)]
+fn local_date_time(time: i64) -> DateTime {
+    let time = time as time_t;
+    // Safety: `tm` is plain data that `localtime_r` fills in.
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return DateTime::from_unix_time(time as i64);
+    }
+    let year = i64::from(tm.tm_year) + 1900;
+    if year < 1980 {
+        DateTime::from_unix_time(i64::min_value())
+    } else if year > 2107 {
+        DateTime::from_unix_time(i64::max_value())
+    } else {
+        DateTime {
+            year: year as u16,
+            month: (tm.tm_mon + 1) as u8,
+            day: tm.tm_mday as u8,
+            hour: tm.tm_hour as u8,
+            minute: tm.tm_min as u8,
+            second: (cmp::min(tm.tm_sec, 59) / 2 * 2) as u8,
+        }
+    }
+}
+
+/// Convert a local time stored in the archive to a unix timestamp, like miniz.
+#[cfg(all(unix, not(feature = "libc_stub")))]
+#[allow(clippy::unnecessary_cast)]
+fn local_unix_time(time: DateTime) -> i64 {
+    // Safety: `tm` is plain data, and `mktime` only reads and normalizes it.
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    tm.tm_year = c_int::from(time.year) - 1900;
+    tm.tm_mon = c_int::from(time.month) - 1;
+    tm.tm_mday = time.day.into();
+    tm.tm_hour = time.hour.into();
+    tm.tm_min = time.minute.into();
+    tm.tm_sec = time.second.into();
+    // Let `mktime` work out whether daylight saving time applies.
+    tm.tm_isdst = -1;
+    match unsafe { libc::mktime(&mut tm) } {
+        -1 => time.to_unix_time(),
+        unix_time => unix_time as i64,
+    }
+}
+
+#[cfg(not(all(unix, not(feature = "libc_stub"))))]
+fn local_date_time(time: i64) -> DateTime {
+    DateTime::from_unix_time(time)
+}
+
+#[cfg(not(all(unix, not(feature = "libc_stub"))))]
+fn local_unix_time(time: DateTime) -> i64 {
+    time.to_unix_time()
+}
+
+/// Add an entry with `size` bytes of data from `reader`.
+#[allow(clippy::too_many_arguments)]
+unsafe fn add_reader<R: Read>(
+    zip: &mut mz_zip_archive,
+    name: *const c_char,
+    reader: R,
+    size: u64,
+    last_modified: DateTime,
+    comment: &[u8],
+    level_and_flags: mz_uint,
+    local_extra: &[u8],
+    central_extra: &[u8],
+) -> Result<(), mz_zip_error> {
+    let (level, flags) = split_level(level_and_flags)?;
+    if flags & MZ_ZIP_FLAG_COMPRESSED_DATA != 0 {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    }
+    let name = archive_name(name, flags)?;
+    let level = if size <= 3 { 0 } else { level };
+    let entry = new_entry(name, level, last_modified, comment, central_extra, true)?;
+    add_entry(
+        zip,
+        entry,
+        local_extra,
+        Source::Data {
+            reader,
+            size,
+            level,
+        },
+    )
+}
+
+unsafe fn finalize(zip: &mut mz_zip_archive) -> Result<(), mz_zip_error> {
+    if zip.m_zip_mode != MZ_ZIP_MODE_WRITING {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    }
+    let state = state(zip)?;
+    let central_dir_pos = zip.m_archive_size;
+    let comment = state.archive.comment().to_vec();
+    let central_dir_offset = central_dir_pos - state.archive.offset();
+    let out = central_directory(state.archive.entries_mut(), &comment, central_dir_offset)?;
+    let io = state.archive.get_mut();
+    io.pos = central_dir_pos;
+    io.write_all(&out)?;
+    zip.m_central_directory_file_ofs = central_dir_pos;
+    zip.m_archive_size = io.pos;
+    zip.m_zip_mode = MZ_ZIP_MODE_WRITING_HAS_BEEN_FINALIZED;
+    Ok(())
+}
+
+unsafe fn reader_end(zip: &mut mz_zip_archive) -> Result<(), mz_zip_error> {
+    if zip.m_zip_mode != MZ_ZIP_MODE_READING || zip.m_pState.is_null() {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    }
+    end(zip);
+    Ok(())
+}
+
+unsafe fn writer_end(zip: &mut mz_zip_archive) -> Result<(), mz_zip_error> {
+    if (zip.m_zip_mode != MZ_ZIP_MODE_WRITING
+        && zip.m_zip_mode != MZ_ZIP_MODE_WRITING_HAS_BEEN_FINALIZED)
+        || zip.m_pState.is_null()
+    {
+        return Err(MZ_ZIP_INVALID_PARAMETER);
+    }
+    end(zip);
+    Ok(())
+}
+
+unmangle!(
+    /// Initialize `pZip` for reading an archive of `size` bytes through `m_pRead`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and the `m_pRead`
+    /// callback must be safe to call with `m_pIO_opaque` for the whole archive.
+    pub unsafe extern "C" fn mz_zip_reader_init(
+        pZip: *mut mz_zip_archive,
+        size: mz_uint64,
+        _flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            check_uninitialized(zip)?;
+            zip.m_archive_size = size;
+            init_reader(zip, MZ_ZIP_TYPE_USER, None)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Initialize `pZip` for reading the archive in the `size` bytes at `pMem`, which must
+    /// stay valid until the archive is ended.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pMem` must be
+    /// valid for reads of `size` bytes until the archive is ended.
+    pub unsafe extern "C" fn mz_zip_reader_init_mem(
+        pZip: *mut mz_zip_archive,
+        pMem: *const c_void,
+        size: size_t,
+        _flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            check_uninitialized(zip)?;
+            if pMem.is_null() {
+                return Err(MZ_ZIP_INVALID_PARAMETER);
+            }
+            zip.m_archive_size = size as u64;
+            let backing = Backing::Mem {
+                data: pMem as *const u8,
+                len: size,
+            };
+            init_reader(zip, MZ_ZIP_TYPE_MEMORY, Some(backing))?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Initialize `pZip` for reading the archive in the file `pFilename`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pFilename` must
+    /// be a zero-terminated string.
+    pub unsafe extern "C" fn mz_zip_reader_init_file(
+        pZip: *mut mz_zip_archive,
+        pFilename: *const c_char,
+        flags: mz_uint32,
+    ) -> mz_bool {
+        mz_zip_reader_init_file_v2(pZip, pFilename, flags, 0, 0)
+    }
+
+    /// Initialize `pZip` for reading the archive of `archive_size` bytes starting at
+    /// `file_start_ofs` in the file `pFilename`. A size of 0 means the rest of the file.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pFilename` must
+    /// be a zero-terminated string.
+    pub unsafe extern "C" fn mz_zip_reader_init_file_v2(
+        pZip: *mut mz_zip_archive,
+        pFilename: *const c_char,
+        _flags: mz_uint,
+        file_start_ofs: mz_uint64,
+        archive_size: mz_uint64,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            check_uninitialized(zip)?;
+            let file = File::open(c_path(pFilename)?).map_err(|_| MZ_ZIP_FILE_OPEN_FAILED)?;
+            zip.m_archive_size = if archive_size == 0 {
+                let len = file.metadata().map_err(|_| MZ_ZIP_FILE_STAT_FAILED)?.len();
+                len.checked_sub(file_start_ofs)
+                    .ok_or(MZ_ZIP_FILE_SEEK_FAILED)?
+            } else {
+                archive_size
+            };
+            let backing = Backing::File {
+                file: Box::new(file),
+                start: file_start_ofs,
+            };
+            init_reader(zip, MZ_ZIP_TYPE_FILE, Some(backing))?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// End reading, freeing the state and closing the file if one was opened.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_reader_end(pZip: *mut mz_zip_archive) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            reader_end(zip)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Zero `pZip`, without freeing anything it holds.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or valid for writing an `mz_zip_archive`.
+    pub unsafe extern "C" fn mz_zip_zero_struct(pZip: *mut mz_zip_archive) {
+        if !pZip.is_null() {
+            ptr::write_bytes(pZip, 0, 1);
+        }
+    }
+
+    /// Get the mode `pZip` is in, invalid if it is null.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_get_mode(pZip: *mut mz_zip_archive) -> mz_zip_mode {
+        pZip.as_ref()
+            .map_or(MZ_ZIP_MODE_INVALID, |zip| zip.m_zip_mode)
+    }
+
+    /// Get the type of archive `pZip` was initialized as, invalid if it is null.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_get_type(pZip: *mut mz_zip_archive) -> mz_zip_type {
+        pZip.as_ref()
+            .map_or(MZ_ZIP_TYPE_INVALID, |zip| zip.m_zip_type)
+    }
+
+    /// Get the number of entries in the archive.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_reader_get_num_files(pZip: *mut mz_zip_archive) -> mz_uint {
+        pZip.as_ref().map_or(0, |zip| zip.m_total_files)
+    }
+
+    /// Get the size of the archive in bytes.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_get_archive_size(pZip: *mut mz_zip_archive) -> mz_uint64 {
+        pZip.as_ref().map_or(0, |zip| zip.m_archive_size)
+    }
+
+    /// Offset of the archive in the file it was opened from, 0 for other archives.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_get_archive_file_start_offset(
+        pZip: *mut mz_zip_archive,
+    ) -> mz_uint64 {
+        match pZip.as_ref().and_then(|zip| zip.m_pState.as_ref()) {
+            Some(state) => match state.backing.as_ref() {
+                Some(&Backing::File { start, .. }) => start,
+                _ => 0,
+            },
+            None => 0,
+        }
+    }
+
+    /// Read `n` bytes of the archive at `file_ofs` into `pBuf`, returning the number of bytes
+    /// read.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pBuf` must be
+    /// valid for writes of `n` bytes.
+    pub unsafe extern "C" fn mz_zip_read_archive_data(
+        pZip: *mut mz_zip_archive,
+        file_ofs: mz_uint64,
+        pBuf: *mut c_void,
+        n: size_t,
+    ) -> size_t {
+        with_zip(pZip, 0, |zip| {
+            let mut io = *state(zip)?.archive.get_ref();
+            if file_ofs >= io.size || n as u64 > io.size - file_ofs || (n > 0 && pBuf.is_null()) {
+                return Err(MZ_ZIP_INVALID_PARAMETER);
+            }
+            if n == 0 {
+                return Ok(0);
+            }
+            io.pos = file_ofs;
+            io.read_exact(slice::from_raw_parts_mut(pBuf as *mut u8, n))
+                .map_err(|_| MZ_ZIP_FILE_READ_FAILED)?;
+            Ok(n)
+        })
+    }
+
+    /// Get the index of the entry named `pName`, with the comment `pComment` unless it's null.
+    ///
+    /// Returns -1 if there is no such entry.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pName` and
+    /// `pComment` must be null or zero-terminated strings.
+    pub unsafe extern "C" fn mz_zip_reader_locate_file(
+        pZip: *mut mz_zip_archive,
+        pName: *const c_char,
+        pComment: *const c_char,
+        flags: mz_uint,
+    ) -> c_int {
+        let mut index = 0;
+        if mz_zip_reader_locate_file_v2(pZip, pName, pComment, flags, &mut index) != 0 {
+            index as c_int
+        } else {
+            -1
+        }
+    }
+
+    /// Like `mz_zip_reader_locate_file`, but storing the index in `file_index`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pName` and
+    /// `pComment` must be null or zero-terminated strings, and `file_index` null or valid for
+    /// writes.
+    pub unsafe extern "C" fn mz_zip_reader_locate_file_v2(
+        pZip: *mut mz_zip_archive,
+        pName: *const c_char,
+        pComment: *const c_char,
+        flags: mz_uint,
+        file_index: *mut mz_uint32,
+    ) -> mz_bool {
+        if !file_index.is_null() {
+            *file_index = 0;
+        }
+        with_zip(pZip, MZ_FALSE, |zip| {
+            let index = locate_c(state(zip)?, pName, pComment, flags)?;
+            if !file_index.is_null() {
+                *file_index = index;
+            }
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Same as `mz_zip_reader_locate_file`.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `mz_zip_reader_locate_file`.
+    pub unsafe extern "C" fn mz_zip_locate_file(
+        pZip: *mut mz_zip_archive,
+        pName: *const c_char,
+        pComment: *const c_char,
+        flags: mz_uint,
+    ) -> c_int {
+        mz_zip_reader_locate_file(pZip, pName, pComment, flags)
+    }
+
+    /// Same as `mz_zip_reader_locate_file_v2`.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `mz_zip_reader_locate_file_v2`.
+    pub unsafe extern "C" fn mz_zip_locate_file_v2(
+        pZip: *mut mz_zip_archive,
+        pName: *const c_char,
+        pComment: *const c_char,
+        flags: mz_uint,
+        pIndex: *mut mz_uint32,
+    ) -> mz_bool {
+        mz_zip_reader_locate_file_v2(pZip, pName, pComment, flags, pIndex)
+    }
+
+    /// Set the last error, returning the previous one.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_set_last_error(
+        pZip: *mut mz_zip_archive,
+        err_num: mz_zip_error,
+    ) -> mz_zip_error {
+        match pZip.as_mut() {
+            Some(zip) => mem::replace(&mut zip.m_last_error, err_num),
+            None => MZ_ZIP_INVALID_PARAMETER,
+        }
+    }
+
+    /// Get the last error without clearing it.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_peek_last_error(pZip: *mut mz_zip_archive) -> mz_zip_error {
+        pZip.as_ref()
+            .map_or(MZ_ZIP_INVALID_PARAMETER, |zip| zip.m_last_error)
+    }
+
+    /// Clear the last error, returning it.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_clear_last_error(pZip: *mut mz_zip_archive) -> mz_zip_error {
+        mz_zip_set_last_error(pZip, MZ_ZIP_NO_ERROR)
+    }
+
+    /// Get the last error and clear it.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_get_last_error(pZip: *mut mz_zip_archive) -> mz_zip_error {
+        mz_zip_set_last_error(pZip, MZ_ZIP_NO_ERROR)
+    }
+
+    pub extern "C" fn mz_zip_get_error_string(mz_err: mz_zip_error) -> *const c_char {
+        let msg: &[u8] = match mz_err {
+            MZ_ZIP_NO_ERROR => b"no error\0",
+            MZ_ZIP_UNDEFINED_ERROR => b"undefined error\0",
+            MZ_ZIP_TOO_MANY_FILES => b"too many files\0",
+            MZ_ZIP_FILE_TOO_LARGE => b"file too large\0",
+            MZ_ZIP_UNSUPPORTED_METHOD => b"unsupported method\0",
+            MZ_ZIP_UNSUPPORTED_ENCRYPTION => b"unsupported encryption\0",
+            MZ_ZIP_UNSUPPORTED_FEATURE => b"unsupported feature\0",
+            MZ_ZIP_FAILED_FINDING_CENTRAL_DIR => b"failed finding central directory\0",
+            MZ_ZIP_NOT_AN_ARCHIVE => b"not a ZIP archive\0",
+            MZ_ZIP_INVALID_HEADER_OR_CORRUPTED => b"invalid header or archive is corrupted\0",
+            MZ_ZIP_UNSUPPORTED_MULTIDISK => b"unsupported multidisk archive\0",
+            MZ_ZIP_DECOMPRESSION_FAILED => b"decompression failed or archive is corrupted\0",
+            MZ_ZIP_COMPRESSION_FAILED => b"compression failed\0",
+            MZ_ZIP_UNEXPECTED_DECOMPRESSED_SIZE => b"unexpected decompressed size\0",
+            MZ_ZIP_CRC_CHECK_FAILED => b"CRC-32 check failed\0",
+            MZ_ZIP_UNSUPPORTED_CDIR_SIZE => b"unsupported central directory size\0",
+            MZ_ZIP_ALLOC_FAILED => b"allocation failed\0",
+            MZ_ZIP_FILE_OPEN_FAILED => b"file open failed\0",
+            MZ_ZIP_FILE_CREATE_FAILED => b"file create failed\0",
+            MZ_ZIP_FILE_WRITE_FAILED => b"file write failed\0",
+            MZ_ZIP_FILE_READ_FAILED => b"file read failed\0",
+            MZ_ZIP_FILE_CLOSE_FAILED => b"file close failed\0",
+            MZ_ZIP_FILE_SEEK_FAILED => b"file seek failed\0",
+            MZ_ZIP_FILE_STAT_FAILED => b"file stat failed\0",
+            MZ_ZIP_INVALID_PARAMETER => b"invalid parameter\0",
+            MZ_ZIP_INVALID_FILENAME => b"invalid filename\0",
+            MZ_ZIP_BUF_TOO_SMALL => b"buffer too small\0",
+            MZ_ZIP_INTERNAL_ERROR => b"internal error\0",
+            MZ_ZIP_FILE_NOT_FOUND => b"file not found\0",
+            MZ_ZIP_ARCHIVE_TOO_LARGE => b"archive is too large\0",
+            MZ_ZIP_VALIDATION_FAILED => b"validation failed\0",
+            MZ_ZIP_WRITE_CALLBACK_FAILED => b"write calledback failed\0",
+            MZ_ZIP_TOTAL_ERRORS => b"unknown error\0",
+        };
+        msg.as_ptr() as *const c_char
+    }
+
+    /// Whether the entry is a directory.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_reader_is_file_a_directory(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            Ok(entry(state(zip)?, file_index)?.is_dir() as mz_bool)
+        })
+    }
+
+    /// Whether the entry is encrypted.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_reader_is_file_encrypted(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            Ok(entry(state(zip)?, file_index)?.is_encrypted() as mz_bool)
+        })
+    }
+
+    /// Whether the entry can be extracted: it isn't encrypted or patch data, and it's stored
+    /// or deflated.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_reader_is_file_supported(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            Ok(is_supported(entry(state(zip)?, file_index)?) as mz_bool)
+        })
+    }
+
+    /// Copy the name of the entry into `pFilename`, truncated to fit and zero terminated.
+    ///
+    /// Returns the number of bytes written including the terminator, or if
+    /// `filename_buf_size` is 0, the size needed for the whole name.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pFilename` must
+    /// be null or valid for writes of `filename_buf_size` bytes.
+    pub unsafe extern "C" fn mz_zip_reader_get_filename(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+        pFilename: *mut c_char,
+        filename_buf_size: mz_uint,
+    ) -> mz_uint {
+        with_zip(pZip, 0, |zip| {
+            let entry = match entry(state(zip)?, file_index) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    if filename_buf_size > 0 && !pFilename.is_null() {
+                        *pFilename = 0;
+                    }
+                    return Err(err);
+                }
+            };
+            let name = entry.name_raw();
+            if filename_buf_size == 0 {
+                return Ok(name.len() as mz_uint + 1);
+            }
+            if pFilename.is_null() {
+                return Err(MZ_ZIP_INVALID_PARAMETER);
+            }
+            let out = slice::from_raw_parts_mut(pFilename, filename_buf_size as usize);
+            copy_c_str(out, name);
+            Ok(cmp::min(name.len(), out.len() - 1) as mz_uint + 1)
+        })
+    }
+
+    /// Fill in `pStat` with information about the entry.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pStat` must be
+    /// null or valid for writes.
+    pub unsafe extern "C" fn mz_zip_reader_file_stat(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+        pStat: *mut mz_zip_archive_file_stat,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            let entry = entry(state(zip)?, file_index)?;
+            fill_stat(entry, pStat.as_mut().ok_or(MZ_ZIP_INVALID_PARAMETER)?);
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Whether the archive uses or will use the zip64 extensions.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_is_zip64(pZip: *mut mz_zip_archive) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            let state = state(zip)?;
+            let large_entry = state.archive.entries().iter().any(|entry| {
+                entry.size() >= ZIP64_LIMIT
+                    || entry.compressed_size() >= ZIP64_LIMIT
+                    || entry.local_header_offset() >= ZIP64_LIMIT
+            });
+            Ok((state.archive.is_zip64() || state.zip64 || large_entry) as mz_bool)
+        })
+    }
+
+    /// Size of the central directory, not counting the end of central directory records.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_get_central_dir_size(pZip: *mut mz_zip_archive) -> size_t {
+        with_zip(pZip, 0, |zip| {
+            let state = state(zip)?;
+            if zip.m_zip_mode == MZ_ZIP_MODE_READING {
+                return Ok(state.archive.central_dir_size() as size_t);
+            }
+            let mut size = 0;
+            let mut header = Vec::new();
+            for entry in state.archive.entries() {
+                header.clear();
+                write_central_header(entry, &mut header)?;
+                size += header.len();
+            }
+            Ok(size)
+        })
+    }
+
+    /// Extract the entry into the `buf_size` bytes at `pBuf`. The read buffer is not used.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pBuf` must be
+    /// valid for writes of `buf_size` bytes.
+    pub unsafe extern "C" fn mz_zip_reader_extract_to_mem_no_alloc(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+        pBuf: *mut c_void,
+        buf_size: size_t,
+        flags: mz_uint,
+        _pUser_read_buf: *mut c_void,
+        _user_read_buf_size: size_t,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            extract_to_mem(zip, file_index, pBuf, buf_size, flags)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Like `mz_zip_reader_extract_to_mem_no_alloc`, for the entry named `pFilename`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pFilename` must
+    /// be a zero-terminated string and `pBuf` valid for writes of `buf_size` bytes.
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_to_mem_no_alloc(
+        pZip: *mut mz_zip_archive,
+        pFilename: *const c_char,
+        pBuf: *mut c_void,
+        buf_size: size_t,
+        flags: mz_uint,
+        _pUser_read_buf: *mut c_void,
+        _user_read_buf_size: size_t,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            let index = locate_c(state(zip)?, pFilename, ptr::null(), flags)?;
+            extract_to_mem(zip, index, pBuf, buf_size, flags)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Extract the entry into the `buf_size` bytes at `pBuf`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pBuf` must be
+    /// valid for writes of `buf_size` bytes.
+    pub unsafe extern "C" fn mz_zip_reader_extract_to_mem(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+        pBuf: *mut c_void,
+        buf_size: size_t,
+        flags: mz_uint,
+    ) -> mz_bool {
+        mz_zip_reader_extract_to_mem_no_alloc(
+            pZip,
+            file_index,
+            pBuf,
+            buf_size,
+            flags,
+            ptr::null_mut(),
+            0,
+        )
+    }
+
+    /// Extract the entry named `pFilename` into the `buf_size` bytes at `pBuf`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pFilename` must
+    /// be a zero-terminated string and `pBuf` valid for writes of `buf_size` bytes.
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_to_mem(
+        pZip: *mut mz_zip_archive,
+        pFilename: *const c_char,
+        pBuf: *mut c_void,
+        buf_size: size_t,
+        flags: mz_uint,
+    ) -> mz_bool {
+        mz_zip_reader_extract_file_to_mem_no_alloc(
+            pZip,
+            pFilename,
+            pBuf,
+            buf_size,
+            flags,
+            ptr::null_mut(),
+            0,
+        )
+    }
+
+    /// Extract the entry into a block allocated with `m_pAlloc`, storing its size in `pSize`.
+    ///
+    /// Returns null on failure.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pSize` must be
+    /// null or valid for writes.
+    pub unsafe extern "C" fn mz_zip_reader_extract_to_heap(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+        pSize: *mut size_t,
+        flags: mz_uint,
+    ) -> *mut c_void {
+        if !pSize.is_null() {
+            *pSize = 0;
+        }
+        with_zip(pZip, ptr::null_mut(), |zip| {
+            extract_to_heap(zip, file_index, pSize, flags)
+        })
+    }
+
+    /// Like `mz_zip_reader_extract_to_heap`, for the entry named `pFilename`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pFilename` must
+    /// be a zero-terminated string and `pSize` null or valid for writes.
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_to_heap(
+        pZip: *mut mz_zip_archive,
+        pFilename: *const c_char,
+        pSize: *mut size_t,
+        flags: mz_uint,
+    ) -> *mut c_void {
+        if !pSize.is_null() {
+            *pSize = 0;
+        }
+        with_zip(pZip, ptr::null_mut(), |zip| {
+            let index = locate_c(state(zip)?, pFilename, ptr::null(), flags)?;
+            extract_to_heap(zip, index, pSize, flags)
+        })
+    }
+
+    /// Extract the entry, passing the data to `pCallback` in order. The callback must
+    /// return the number of bytes it was given.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pCallback` must
+    /// be safe to call with `pOpaque`.
+    pub unsafe extern "C" fn mz_zip_reader_extract_to_callback(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+        pCallback: Option<mz_file_write_func>,
+        pOpaque: *mut c_void,
+        flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            extract_to_callback(zip, file_index, pCallback, pOpaque, flags)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Like `mz_zip_reader_extract_to_callback`, for the entry named `pFilename`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pFilename` must
+    /// be a zero-terminated string and `pCallback` safe to call with `pOpaque`.
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_to_callback(
+        pZip: *mut mz_zip_archive,
+        pFilename: *const c_char,
+        pCallback: Option<mz_file_write_func>,
+        pOpaque: *mut c_void,
+        flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            let index = locate_c(state(zip)?, pFilename, ptr::null(), flags)?;
+            extract_to_callback(zip, index, pCallback, pOpaque, flags)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Start extracting the entry piece by piece with `mz_zip_reader_extract_iter_read`.
+    ///
+    /// Returns null on failure. The state must be freed with `mz_zip_reader_extract_iter_free`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and the archive must
+    /// not be ended or changed while the state is in use.
+    pub unsafe extern "C" fn mz_zip_reader_extract_iter_new(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+        flags: mz_uint,
+    ) -> *mut mz_zip_reader_extract_iter_state {
+        with_zip(pZip, ptr::null_mut(), |zip| {
+            let state = state(zip)?;
+            let raw = flags & MZ_ZIP_FLAG_COMPRESSED_DATA != 0;
+            // Fail early like miniz rather than on the first read.
+            if !raw && !is_supported(entry(state, file_index)?) {
+                let entry = entry(state, file_index)?;
+                return Err(if entry.is_encrypted() {
+                    MZ_ZIP_UNSUPPORTED_ENCRYPTION
+                } else {
+                    MZ_ZIP_UNSUPPORTED_METHOD
+                });
+            }
+            let reader = state.archive.entry_reader(file_index as usize, raw)?;
+            let mut iter = Box::new(mz_zip_reader_extract_iter_state {
+                pZip,
+                flags,
+                status: 0,
+                file_crc32: 0,
+                read_buf_size: 0,
+                read_buf_ofs: 0,
+                read_buf_avail: 0,
+                comp_remaining: reader.remaining(),
+                out_buf_ofs: 0,
+                cur_file_ofs: 0,
+                file_stat: mem::zeroed(),
+                pRead_buf: ptr::null_mut(),
+                pWrite_buf: ptr::null_mut(),
+                out_blk_remain: 0,
+                reader,
+            });
+            fill_stat(entry(state, file_index)?, &mut iter.file_stat);
+            Ok(Box::into_raw(iter))
+        })
+    }
+
+    /// Like `mz_zip_reader_extract_iter_new`, for the entry named `pFilename`.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `mz_zip_reader_extract_iter_new`, and `pFilename` must be a zero-terminated
+    /// string.
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_iter_new(
+        pZip: *mut mz_zip_archive,
+        pFilename: *const c_char,
+        flags: mz_uint,
+    ) -> *mut mz_zip_reader_extract_iter_state {
+        let index = with_zip(pZip, None, |zip| {
+            locate_c(state(zip)?, pFilename, ptr::null(), flags).map(Some)
+        });
+        match index {
+            Some(index) => mz_zip_reader_extract_iter_new(pZip, index, flags),
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Read up to `buf_size` bytes of the entry into `pvBuf`, returning the number of bytes
+    /// read. Fewer bytes are only read at the end of the data or on failure.
+    ///
+    /// # Safety
+    ///
+    /// `pState` must be null or a state from `mz_zip_reader_extract_iter_new` that hasn't been
+    /// freed, and `pvBuf` valid for writes of `buf_size` bytes.
+    pub unsafe extern "C" fn mz_zip_reader_extract_iter_read(
+        pState: *mut mz_zip_reader_extract_iter_state,
+        pvBuf: *mut c_void,
+        buf_size: size_t,
+    ) -> size_t {
+        let iter = match pState.as_mut() {
+            Some(iter) => iter,
+            None => return 0,
+        };
+        if iter.status < 0 || buf_size == 0 || pvBuf.is_null() {
+            return 0;
+        }
+        let buf = slice::from_raw_parts_mut(pvBuf as *mut u8, buf_size);
+        let mut n = 0;
+        let result = catch_unwind(AssertUnwindSafe(|| -> Result<(), mz_zip_error> {
+            while n < buf.len() && !iter.reader.is_done() {
+                let read = iter.reader.read_data(&mut buf[n..])?;
+                if read == 0 {
+                    break;
+                }
+                let mut crc = crc32fast::Hasher::new_with_initial(iter.file_crc32);
+                crc.update(&buf[n..n + read]);
+                iter.file_crc32 = crc.finalize();
+                n += read;
+            }
+            Ok(())
+        }));
+        iter.comp_remaining = iter.reader.remaining();
+        iter.out_buf_ofs = iter.reader.written();
+        let err = match result {
+            Ok(Ok(())) => return n,
+            Ok(Err(err)) => err,
+            Err(_) => MZ_ZIP_INTERNAL_ERROR,
+        };
+        iter.status = TINFL_STATUS_FAILED as c_int;
+        if let Some(zip) = iter.pZip.as_mut() {
+            zip.m_last_error = err;
+        }
+        n
+    }
+
+    /// Free the iterator state, returning whether all reads succeeded.
+    ///
+    /// # Safety
+    ///
+    /// `pState` must be null or a state from `mz_zip_reader_extract_iter_new` that hasn't been
+    /// freed.
+    pub unsafe extern "C" fn mz_zip_reader_extract_iter_free(
+        pState: *mut mz_zip_reader_extract_iter_state,
+    ) -> mz_bool {
+        if pState.is_null() {
+            return MZ_FALSE;
+        }
+        let iter = Box::from_raw(pState);
+        (iter.status >= 0) as mz_bool
+    }
+
+    /// Extract the entry to a new file named `pDst_filename`, setting its modification time.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pDst_filename`
+    /// must be a zero-terminated string.
+    pub unsafe extern "C" fn mz_zip_reader_extract_to_file(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+        pDst_filename: *const c_char,
+        flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            extract_to_file(zip, file_index, pDst_filename, flags)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Extract the entry named `pArchive_filename` to a new file named `pDst_filename`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and
+    /// `pArchive_filename` and `pDst_filename` must be zero-terminated strings.
+    pub unsafe extern "C" fn mz_zip_reader_extract_file_to_file(
+        pZip: *mut mz_zip_archive,
+        pArchive_filename: *const c_char,
+        pDst_filename: *const c_char,
+        flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            let index = locate_c(state(zip)?, pArchive_filename, ptr::null(), flags)?;
+            extract_to_file(zip, index, pDst_filename, flags)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Check the local header of the entry against the central directory, and unless
+    /// `MZ_ZIP_FLAG_VALIDATE_HEADERS_ONLY` is given, that the entry can be extracted.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_validate_file(
+        pZip: *mut mz_zip_archive,
+        file_index: mz_uint,
+        flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            validate(state(zip)?, file_index, flags)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Validate every entry with `mz_zip_validate_file`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_validate_archive(
+        pZip: *mut mz_zip_archive,
+        flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            let state = state(zip)?;
+            for index in 0..state.archive.len() {
+                validate(state, index as mz_uint, flags)?;
+            }
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Validate the archive in the `size` bytes at `pMem`, storing any error in `pErr`.
+    ///
+    /// # Safety
+    ///
+    /// `pMem` must be valid for reads of `size` bytes, and `pErr` null or valid for writes.
+    pub unsafe extern "C" fn mz_zip_validate_mem_archive(
+        pMem: *const c_void,
+        size: size_t,
+        flags: mz_uint,
+        pErr: *mut mz_zip_error,
+    ) -> mz_bool {
+        let mut zip: mz_zip_archive = mem::zeroed();
+        let mut ok = mz_zip_reader_init_mem(&mut zip, pMem, size, flags) != 0;
+        if ok {
+            ok = mz_zip_validate_archive(&mut zip, flags) != 0;
+            mz_zip_reader_end(&mut zip);
+        }
+        if !pErr.is_null() {
+            *pErr = zip.m_last_error;
+        }
+        ok as mz_bool
+    }
+
+    /// Validate the archive in the file `pFilename`, storing any error in `pErr`.
+    ///
+    /// # Safety
+    ///
+    /// `pFilename` must be a zero-terminated string, and `pErr` null or valid for writes.
+    pub unsafe extern "C" fn mz_zip_validate_file_archive(
+        pFilename: *const c_char,
+        flags: mz_uint,
+        pErr: *mut mz_zip_error,
+    ) -> mz_bool {
+        let mut zip: mz_zip_archive = mem::zeroed();
+        let mut ok = mz_zip_reader_init_file_v2(&mut zip, pFilename, flags, 0, 0) != 0;
+        if ok {
+            ok = mz_zip_validate_archive(&mut zip, flags) != 0;
+            mz_zip_reader_end(&mut zip);
+        }
+        if !pErr.is_null() {
+            *pErr = zip.m_last_error;
+        }
+        ok as mz_bool
+    }
+
+    /// End reading or writing, whichever `pZip` is initialized for.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_end(pZip: *mut mz_zip_archive) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            match zip.m_zip_mode {
+                MZ_ZIP_MODE_READING => reader_end(zip)?,
+                MZ_ZIP_MODE_WRITING | MZ_ZIP_MODE_WRITING_HAS_BEEN_FINALIZED => writer_end(zip)?,
+                MZ_ZIP_MODE_INVALID => return Err(MZ_ZIP_INVALID_PARAMETER),
+            }
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Initialize `pZip` for writing an archive through `m_pWrite`, after `existing_size` bytes of
+    /// other data.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `mz_zip_writer_init_v2`.
+    pub unsafe extern "C" fn mz_zip_writer_init(
+        pZip: *mut mz_zip_archive,
+        existing_size: mz_uint64,
+    ) -> mz_bool {
+        mz_zip_writer_init_v2(pZip, existing_size, 0)
+    }
+
+    /// Initialize `pZip` for writing an archive through `m_pWrite`, after `existing_size`
+    /// bytes of other data. The archive is written sequentially.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and the `m_pWrite`
+    /// callback must be safe to call with `m_pIO_opaque` until the archive is ended.
+    pub unsafe extern "C" fn mz_zip_writer_init_v2(
+        pZip: *mut mz_zip_archive,
+        existing_size: mz_uint64,
+        flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            init_writer(zip, MZ_ZIP_TYPE_USER, None, existing_size, flags)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Initialize `pZip` for writing an archive to a block of memory.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_writer_init_heap(
+        pZip: *mut mz_zip_archive,
+        size_to_reserve_at_beginning: size_t,
+        initial_allocation_size: size_t,
+    ) -> mz_bool {
+        mz_zip_writer_init_heap_v2(
+            pZip,
+            size_to_reserve_at_beginning,
+            initial_allocation_size,
+            0,
+        )
+    }
+
+    /// Initialize `pZip` for writing an archive to a block of memory allocated with the
+    /// archive's allocation functions, after `size_to_reserve_at_beginning` zero bytes.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and any allocation
+    /// functions set must be safe to call with `m_pAlloc_opaque`.
+    pub unsafe extern "C" fn mz_zip_writer_init_heap_v2(
+        pZip: *mut mz_zip_archive,
+        size_to_reserve_at_beginning: size_t,
+        initial_allocation_size: size_t,
+        flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            check_uninitialized(zip)?;
+            set_default_allocators(zip);
+            let mut heap = HeapBuf {
+                data: ptr::null_mut(),
+                len: 0,
+                capacity: 0,
+                alloc: Allocator::of(zip),
+            };
+            if !heap.reserve(cmp::max(
+                initial_allocation_size,
+                size_to_reserve_at_beginning,
+            )) {
+                return Err(MZ_ZIP_ALLOC_FAILED);
+            }
+            init_writer(
+                zip,
+                MZ_ZIP_TYPE_HEAP,
+                Some(Backing::Heap(heap)),
+                size_to_reserve_at_beginning as u64,
+                flags,
+            )?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Initialize `pZip` for writing an archive to the file `pFilename`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pFilename` must
+    /// be a zero-terminated string.
+    pub unsafe extern "C" fn mz_zip_writer_init_file(
+        pZip: *mut mz_zip_archive,
+        pFilename: *const c_char,
+        size_to_reserve_at_beginning: mz_uint64,
+    ) -> mz_bool {
+        mz_zip_writer_init_file_v2(pZip, pFilename, size_to_reserve_at_beginning, 0)
+    }
+
+    /// Initialize `pZip` for writing an archive to the file `pFilename`, which is created or
+    /// truncated, after `size_to_reserve_at_beginning` zero bytes.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pFilename` must
+    /// be a zero-terminated string.
+    pub unsafe extern "C" fn mz_zip_writer_init_file_v2(
+        pZip: *mut mz_zip_archive,
+        pFilename: *const c_char,
+        size_to_reserve_at_beginning: mz_uint64,
+        flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            check_uninitialized(zip)?;
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(c_path(pFilename)?)
+                .map_err(|_| MZ_ZIP_FILE_CREATE_FAILED)?;
+            let backing = Backing::File {
+                file: Box::new(file),
+                start: 0,
+            };
+            init_writer(
+                zip,
+                MZ_ZIP_TYPE_FILE,
+                Some(backing),
+                size_to_reserve_at_beginning,
+                flags,
+            )?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Switch an archive from reading to appending entries in place.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `mz_zip_writer_init_from_reader_v2`.
+    pub unsafe extern "C" fn mz_zip_writer_init_from_reader(
+        pZip: *mut mz_zip_archive,
+        pFilename: *const c_char,
+    ) -> mz_bool {
+        mz_zip_writer_init_from_reader_v2(pZip, pFilename, 0)
+    }
+
+    /// Switch an archive from reading to appending entries in place, overwriting the central
+    /// directory.
+    ///
+    /// Archives read from a file are reopened for writing from `pFilename`. Archives read from
+    /// memory must be in a block that can be grown with `m_pRealloc`, which is taken over.
+    /// Archives read through `m_pRead` need `m_pWrite` to be set.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pFilename` must
+    /// be null or a zero-terminated string.
+    pub unsafe extern "C" fn mz_zip_writer_init_from_reader_v2(
+        pZip: *mut mz_zip_archive,
+        pFilename: *const c_char,
+        flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            if zip.m_zip_mode != MZ_ZIP_MODE_READING {
+                return Err(MZ_ZIP_INVALID_PARAMETER);
+            }
+            let state = state(zip)?;
+            if flags & MZ_ZIP_FLAG_WRITE_ZIP64 != 0 && !state.archive.is_zip64() {
+                return Err(MZ_ZIP_INVALID_PARAMETER);
+            }
+            match zip.m_zip_type {
+                MZ_ZIP_TYPE_FILE => {
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .open(c_path(pFilename)?)
+                        .map_err(|_| MZ_ZIP_FILE_OPEN_FAILED)?;
+                    if let Some(&mut Backing::File {
+                        file: ref mut old, ..
+                    }) = state.backing.as_mut()
+                    {
+                        *old = Box::new(file);
+                    }
+                }
+                MZ_ZIP_TYPE_MEMORY => {
+                    let backing = state.backing.as_mut().ok_or(MZ_ZIP_INTERNAL_ERROR)?;
+                    if let Backing::Mem { data, len } = *backing {
+                        *backing = Backing::Heap(HeapBuf {
+                            data: data as *mut u8,
+                            len,
+                            capacity: len,
+                            alloc: Allocator::of(zip),
+                        });
+                    }
+                    zip.m_zip_type = MZ_ZIP_TYPE_HEAP;
+                }
+                MZ_ZIP_TYPE_USER => {
+                    if zip.m_pWrite.is_none() {
+                        return Err(MZ_ZIP_INVALID_PARAMETER);
+                    }
+                    state.archive.get_mut().write = zip.m_pWrite;
+                }
+                _ => (),
+            }
+            state.zip64 = state.archive.is_zip64();
+            zip.m_archive_size = zip.m_central_directory_file_ofs;
+            zip.m_central_directory_file_ofs = 0;
+            zip.m_zip_mode = MZ_ZIP_MODE_WRITING;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Add an entry named `pArchive_name` with the `buf_size` bytes at `pBuf`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pArchive_name`
+    /// must be a zero-terminated string and `pBuf` valid for reads of `buf_size` bytes.
+    pub unsafe extern "C" fn mz_zip_writer_add_mem(
+        pZip: *mut mz_zip_archive,
+        pArchive_name: *const c_char,
+        pBuf: *const c_void,
+        buf_size: size_t,
+        level_and_flags: mz_uint,
+    ) -> mz_bool {
+        mz_zip_writer_add_mem_ex_v2(
+            pZip,
+            pArchive_name,
+            pBuf,
+            buf_size,
+            ptr::null(),
+            0,
+            level_and_flags,
+            0,
+            0,
+            ptr::null(),
+            ptr::null(),
+            0,
+            ptr::null(),
+            0,
+        )
+    }
+
+    /// Like `mz_zip_writer_add_mem_ex_v2`, with the current time and no extra data.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `mz_zip_writer_add_mem_ex_v2`.
+    pub unsafe extern "C" fn mz_zip_writer_add_mem_ex(
+        pZip: *mut mz_zip_archive,
+        pArchive_name: *const c_char,
+        pBuf: *const c_void,
+        buf_size: size_t,
+        pComment: *const c_void,
+        comment_size: mz_uint16,
+        level_and_flags: mz_uint,
+        uncomp_size: mz_uint64,
+        uncomp_crc32: mz_uint32,
+    ) -> mz_bool {
+        mz_zip_writer_add_mem_ex_v2(
+            pZip,
+            pArchive_name,
+            pBuf,
+            buf_size,
+            pComment,
+            comment_size,
+            level_and_flags,
+            uncomp_size,
+            uncomp_crc32,
+            ptr::null(),
+            ptr::null(),
+            0,
+            ptr::null(),
+            0,
+        )
+    }
+
+    /// Add an entry with the `buf_size` bytes at `pBuf`, compressed at the level in the low
+    /// four bits of `level_and_flags`. A name ending in a slash with no data adds a directory.
+    ///
+    /// With `MZ_ZIP_FLAG_COMPRESSED_DATA`, the data is already deflated and `uncomp_size` and
+    /// `uncomp_crc32` describe the uncompressed data. The time defaults to the current time.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pArchive_name`
+    /// must be a zero-terminated string, and `pBuf`, `pComment` and the extra data must be null or
+    /// valid for reads of their sizes. `last_modified` must be null or valid for reads.
+    pub unsafe extern "C" fn mz_zip_writer_add_mem_ex_v2(
+        pZip: *mut mz_zip_archive,
+        pArchive_name: *const c_char,
+        pBuf: *const c_void,
+        buf_size: size_t,
+        pComment: *const c_void,
+        comment_size: mz_uint16,
+        level_and_flags: mz_uint,
+        uncomp_size: mz_uint64,
+        uncomp_crc32: mz_uint32,
+        last_modified: *const time_t,
+        user_extra_data_local: *const c_char,
+        user_extra_data_local_len: mz_uint,
+        user_extra_data_central: *const c_char,
+        user_extra_data_central_len: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            let (level, flags) = split_level(level_and_flags)?;
+            let compressed = flags & MZ_ZIP_FLAG_COMPRESSED_DATA != 0;
+            if !compressed && uncomp_size != 0 {
+                return Err(MZ_ZIP_INVALID_PARAMETER);
+            }
+            let data = bytes(pBuf, buf_size)?;
+            let comment = bytes(pComment, comment_size.into())?;
+            let local_extra = bytes(
+                user_extra_data_local as *const c_void,
+                user_extra_data_local_len as usize,
+            )?;
+            let central_extra = bytes(
+                user_extra_data_central as *const c_void,
+                user_extra_data_central_len as usize,
+            )?;
+            let name = archive_name(pArchive_name, flags)?;
+            if name.last() == Some(&b'/') && (!data.is_empty() || uncomp_size != 0) {
+                return Err(MZ_ZIP_INVALID_PARAMETER);
+            }
+
+            let level = if compressed || data.len() > 3 {
+                level
+            } else {
+                0
+            };
+            let last_modified = unix_time_or_now(last_modified);
+            let mut entry = new_entry(name, level, last_modified, comment, central_extra, false)?;
+            let mut compressed_data = Vec::new();
+            let (crc32, size, data) = if compressed {
+                entry.method = CompressionMethod::Deflated;
+                entry.version_needed = cmp::max(entry.version_needed, VERSION_DEFLATE);
+                (uncomp_crc32, uncomp_size, data)
+            } else if entry.method == CompressionMethod::Deflated {
+                let state = state(zip)?;
+                let (crc32, size, _) =
+                    write_deflated(&mut state.compressor, data, &mut compressed_data, level)?;
+                (crc32, size, &compressed_data[..])
+            } else {
+                let mut crc = crc32fast::Hasher::new();
+                crc.update(data);
+                (crc.finalize(), data.len() as u64, data)
+            };
+            let source = Source::Raw {
+                reader: data,
+                crc32,
+                size,
+                compressed_size: data.len() as u64,
+                descriptor: false,
+            };
+            add_entry(zip, entry, local_extra, source)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Add an entry with the contents of the file `pSrc_filename`, using its modification
+    /// time.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pArchive_name`
+    /// and `pSrc_filename` must be zero-terminated strings, `pComment` null or valid for reads of
+    /// `comment_size` bytes, and `pFile_time` null or valid for reads.
+    pub unsafe extern "C" fn mz_zip_writer_add_file(
+        pZip: *mut mz_zip_archive,
+        pArchive_name: *const c_char,
+        pSrc_filename: *const c_char,
+        pComment: *const c_void,
+        comment_size: mz_uint16,
+        level_and_flags: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            let comment = bytes(pComment, comment_size.into())?;
+            let file = File::open(c_path(pSrc_filename)?).map_err(|_| MZ_ZIP_FILE_OPEN_FAILED)?;
+            let metadata = file.metadata().map_err(|_| MZ_ZIP_FILE_STAT_FAILED)?;
+            let last_modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or_else(
+                    || unix_time_or_now(ptr::null()),
+                    |time| local_date_time(time.as_secs() as i64),
+                );
+            add_reader(
+                zip,
+                pArchive_name,
+                file,
+                metadata.len(),
+                last_modified,
+                comment,
+                level_and_flags,
+                &[],
+                &[],
+            )?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Add an entry copying the raw data of the entry at `src_file_index` in the archive
+    /// being read in `pSource_zip`, without recompressing it.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `pSource_zip` must
+    /// be a valid archive being read.
+    pub unsafe extern "C" fn mz_zip_writer_add_from_zip_reader(
+        pZip: *mut mz_zip_archive,
+        pSource_zip: *mut mz_zip_archive,
+        src_file_index: mz_uint,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            if pSource_zip.is_null() || pSource_zip == pZip {
+                return Err(MZ_ZIP_INVALID_PARAMETER);
+            }
+            let source = state(&*pSource_zip)?;
+            let entry = entry(source, src_file_index)?.clone();
+            let reader = source.archive.by_index_raw(src_file_index as usize)?;
+            let source = Source::Raw {
+                reader,
+                crc32: entry.crc32(),
+                size: entry.size(),
+                compressed_size: entry.compressed_size(),
+                descriptor: entry.flags() & FLAG_DATA_DESCRIPTOR != 0,
+            };
+            add_entry(zip, entry, &[], source)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Write the central directory, completing the archive. Nothing can be added afterwards.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_writer_finalize_archive(pZip: *mut mz_zip_archive) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            finalize(zip)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Finalize a heap archive if needed, and hand over its memory block and size in `ppBuf`
+    /// and `pSize`. The block must be freed with `m_pFree`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation, and `ppBuf` and
+    /// `pSize` must be valid for writes.
+    pub unsafe extern "C" fn mz_zip_writer_finalize_heap_archive(
+        pZip: *mut mz_zip_archive,
+        ppBuf: *mut *mut c_void,
+        pSize: *mut size_t,
+    ) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            if ppBuf.is_null() || pSize.is_null() {
+                return Err(MZ_ZIP_INVALID_PARAMETER);
+            }
+            *ppBuf = ptr::null_mut();
+            *pSize = 0;
+            if zip.m_zip_type != MZ_ZIP_TYPE_HEAP {
+                return Err(MZ_ZIP_INVALID_PARAMETER);
+            }
+            if zip.m_zip_mode == MZ_ZIP_MODE_WRITING {
+                finalize(zip)?;
+            }
+            if zip.m_zip_mode != MZ_ZIP_MODE_WRITING_HAS_BEEN_FINALIZED {
+                return Err(MZ_ZIP_INVALID_PARAMETER);
+            }
+            match state(zip)?.backing.as_mut() {
+                Some(&mut Backing::Heap(ref mut heap)) => {
+                    *ppBuf = mem::replace(&mut heap.data, ptr::null_mut()) as *mut c_void;
+                    *pSize = mem::replace(&mut heap.len, 0);
+                    heap.capacity = 0;
+                    Ok(MZ_TRUE)
+                }
+                _ => Err(MZ_ZIP_INTERNAL_ERROR),
+            }
+        })
+    }
+
+    /// End writing, freeing the state and closing the file if one was opened. This doesn't
+    /// finalize the archive.
+    ///
+    /// # Safety
+    ///
+    /// `pZip` must be null or a valid archive, see the module documentation.
+    pub unsafe extern "C" fn mz_zip_writer_end(pZip: *mut mz_zip_archive) -> mz_bool {
+        with_zip(pZip, MZ_FALSE, |zip| {
+            writer_end(zip)?;
+            Ok(MZ_TRUE)
+        })
+    }
+
+    /// Add an entry to the archive file `pZip_filename` in place.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `mz_zip_add_mem_to_archive_file_in_place_v2`.
+    pub unsafe extern "C" fn mz_zip_add_mem_to_archive_file_in_place(
+        pZip_filename: *const c_char,
+        pArchive_name: *const c_char,
+        pBuf: *const c_void,
+        buf_size: size_t,
+        pComment: *const c_void,
+        comment_size: mz_uint16,
+        level_and_flags: mz_uint,
+    ) -> mz_bool {
+        mz_zip_add_mem_to_archive_file_in_place_v2(
+            pZip_filename,
+            pArchive_name,
+            pBuf,
+            buf_size,
+            pComment,
+            comment_size,
+            level_and_flags,
+            ptr::null_mut(),
+        )
+    }
+
+    /// Add an entry to the archive file `pZip_filename` in place, creating the archive if
+    /// the file doesn't exist.
+    ///
+    /// The central directory is rewritten even if adding the entry fails, but if anything
+    /// else goes wrong the archive may be left invalid.
+    ///
+    /// # Safety
+    ///
+    /// `pZip_filename` and `pArchive_name` must be zero-terminated strings, `pBuf` and `pComment`
+    /// null or valid for reads of their sizes, and `pErr` null or valid for writes.
+    pub unsafe extern "C" fn mz_zip_add_mem_to_archive_file_in_place_v2(
+        pZip_filename: *const c_char,
+        pArchive_name: *const c_char,
+        pBuf: *const c_void,
+        buf_size: size_t,
+        pComment: *const c_void,
+        comment_size: mz_uint16,
+        level_and_flags: mz_uint,
+        pErr: *mut mz_zip_error,
+    ) -> mz_bool {
+        let mut zip: mz_zip_archive = mem::zeroed();
+        let exists = c_path(pZip_filename)
+            .ok()
+            .map_or(false, |path| path.exists());
+        let mut ok = if exists {
+            mz_zip_reader_init_file_v2(&mut zip, pZip_filename, level_and_flags, 0, 0) != 0
+                && mz_zip_writer_init_from_reader_v2(&mut zip, pZip_filename, level_and_flags) != 0
+        } else {
+            mz_zip_writer_init_file_v2(&mut zip, pZip_filename, 0, level_and_flags) != 0
+        };
+        let mut err = zip.m_last_error;
+        if ok {
+            ok = mz_zip_writer_add_mem_ex(
+                &mut zip,
+                pArchive_name,
+                pBuf,
+                buf_size,
+                pComment,
+                comment_size,
+                level_and_flags,
+                0,
+                0,
+            ) != 0;
+            err = zip.m_last_error;
+            if mz_zip_writer_finalize_archive(&mut zip) == 0 && ok {
+                ok = false;
+                err = zip.m_last_error;
+            }
+        } else if pZip_filename.is_null() {
+            err = MZ_ZIP_INVALID_PARAMETER;
+        }
+        if zip.m_zip_mode != MZ_ZIP_MODE_INVALID {
+            mz_zip_end(&mut zip);
+        }
+        if !ok && !exists && !pZip_filename.is_null() {
+            let _ = std::fs::remove_file(c_path(pZip_filename).unwrap_or_else(|_| Path::new("")));
+        }
+        if !pErr.is_null() {
+            *pErr = err;
+        }
+        ok as mz_bool
+    }
+
+    /// Extract the entry named `pArchive_name` from the archive file `pZip_filename`.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `mz_zip_extract_archive_file_to_heap_v2`.
+    pub unsafe extern "C" fn mz_zip_extract_archive_file_to_heap(
+        pZip_filename: *const c_char,
+        pArchive_name: *const c_char,
+        pSize: *mut size_t,
+        flags: mz_uint,
+    ) -> *mut c_void {
+        mz_zip_extract_archive_file_to_heap_v2(
+            pZip_filename,
+            pArchive_name,
+            ptr::null(),
+            pSize,
+            flags,
+            ptr::null_mut(),
+        )
+    }
+
+    /// Extract the entry named `pArchive_name`, with the comment `pComment` unless it's null,
+    /// from the archive file `pZip_filename` into a block allocated with `malloc`.
+    ///
+    /// # Safety
+    ///
+    /// `pZip_filename` and `pArchive_name` must be zero-terminated strings, `pComment` null or a
+    /// zero-terminated string, and `pSize` and `pErr` null or valid for writes.
+    pub unsafe extern "C" fn mz_zip_extract_archive_file_to_heap_v2(
+        pZip_filename: *const c_char,
+        pArchive_name: *const c_char,
+        pComment: *const c_char,
+        pSize: *mut size_t,
+        flags: mz_uint,
+        pErr: *mut mz_zip_error,
+    ) -> *mut c_void {
+        if !pSize.is_null() {
+            *pSize = 0;
+        }
+        let mut zip: mz_zip_archive = mem::zeroed();
+        let mut data = ptr::null_mut();
+        if mz_zip_reader_init_file_v2(&mut zip, pZip_filename, flags, 0, 0) != 0 {
+            let mut index = 0;
+            if mz_zip_reader_locate_file_v2(&mut zip, pArchive_name, pComment, flags, &mut index)
+                != 0
+            {
+                data = mz_zip_reader_extract_to_heap(&mut zip, index, pSize, flags);
+            }
+            mz_zip_reader_end(&mut zip);
+        }
+        if !pErr.is_null() {
+            *pErr = zip.m_last_error;
+        }
+        data
+    }
+);
+
+#[cfg(not(any(
+    feature = "libc_stub",
+    all(target_arch = "wasm32", not(target_os = "emscripten"))
+)))]
+pub use self::cfile::*;
+
+/// The functions taking a C `FILE`, which is not available with the libc stub.
+#[cfg(not(any(
+    feature = "libc_stub",
+    all(target_arch = "wasm32", not(target_os = "emscripten"))
+)))]
+mod cfile {
+    use libc::{fflush, fread, fseek, ftell, fwrite, FILE, SEEK_CUR, SEEK_END, SEEK_SET};
+
+    use super::*;
+
+    /// Reads and writes through a `FILE` owned by the caller.
+    struct CFile(*mut FILE);
+
+    impl Read for CFile {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = unsafe { fread(buf.as_mut_ptr() as *mut c_void, 1, buf.len(), self.0) };
+            if n == 0 && !buf.is_empty() && unsafe { libc::ferror(self.0) } != 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "fread failed"));
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for CFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(unsafe { fwrite(buf.as_ptr() as *const c_void, 1, buf.len(), self.0) })
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if unsafe { fflush(self.0) } == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::new(io::ErrorKind::Other, "fflush failed"))
+            }
+        }
+    }
+
+    impl Seek for CFile {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            let (offset, whence) = match pos {
+                SeekFrom::Start(pos) => (pos as i64, SEEK_SET),
+                SeekFrom::End(delta) => (delta, SEEK_END),
+                SeekFrom::Current(delta) => (delta, SEEK_CUR),
+            };
+            if unsafe { fseek(self.0, offset as _, whence) } != 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "fseek failed"));
+            }
+            match unsafe { ftell(self.0) } {
+                pos if pos >= 0 => Ok(pos as u64),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "ftell failed")),
+            }
+        }
+    }
+
+    unmangle!(
+        /// Initialize `pZip` for reading the archive of `archive_size` bytes at the current
+        /// position in `pFile`. A size of 0 means the rest of the file.
+        ///
+        /// # Safety
+        ///
+        /// `pZip` must be null or a valid archive, see the module documentation, and `pFile` must
+        /// be an open `FILE` that stays open until the archive is ended.
+        pub unsafe extern "C" fn mz_zip_reader_init_cfile(
+            pZip: *mut mz_zip_archive,
+            pFile: *mut FILE,
+            archive_size: mz_uint64,
+            _flags: mz_uint,
+        ) -> mz_bool {
+            with_zip(pZip, MZ_FALSE, |zip| {
+                check_uninitialized(zip)?;
+                if pFile.is_null() {
+                    return Err(MZ_ZIP_FILE_OPEN_FAILED);
+                }
+                let mut file = CFile(pFile);
+                let start = file.seek(SeekFrom::Current(0))?;
+                zip.m_archive_size = if archive_size == 0 {
+                    file.seek(SeekFrom::End(0))? - start
+                } else {
+                    archive_size
+                };
+                let backing = Backing::File {
+                    file: Box::new(file),
+                    start,
+                };
+                init_reader(zip, MZ_ZIP_TYPE_CFILE, Some(backing))?;
+                state(zip)?.cfile = pFile as *mut c_void;
+                Ok(MZ_TRUE)
+            })
+        }
+
+        /// The `FILE` an archive was initialized with by `mz_zip_reader_init_cfile` or
+        /// `mz_zip_writer_init_cfile`, null otherwise.
+        ///
+        /// # Safety
+        ///
+        /// `pZip` must be null or a valid archive, see the module documentation.
+        pub unsafe extern "C" fn mz_zip_get_cfile(pZip: *mut mz_zip_archive) -> *mut FILE {
+            match pZip.as_ref().and_then(|zip| zip.m_pState.as_ref()) {
+                Some(state) => state.cfile as *mut FILE,
+                None => ptr::null_mut(),
+            }
+        }
+
+        /// Extract the entry, writing it to `pFile`.
+        ///
+        /// # Safety
+        ///
+        /// `pZip` must be null or a valid archive, see the module documentation, and `pFile` must
+        /// be an open `FILE`.
+        pub unsafe extern "C" fn mz_zip_reader_extract_to_cfile(
+            pZip: *mut mz_zip_archive,
+            file_index: mz_uint,
+            pFile: *mut FILE,
+            flags: mz_uint,
+        ) -> mz_bool {
+            with_zip(pZip, MZ_FALSE, |zip| {
+                extract_to_cfile(zip, file_index, pFile, flags)?;
+                Ok(MZ_TRUE)
+            })
+        }
+
+        /// Extract the entry named `pArchive_filename`, writing it to `pFile`.
+        ///
+        /// # Safety
+        ///
+        /// `pZip` must be null or a valid archive, see the module documentation, and
+        /// `pArchive_filename` must be a zero-terminated string and `pFile` an open `FILE`.
+        pub unsafe extern "C" fn mz_zip_reader_extract_file_to_cfile(
+            pZip: *mut mz_zip_archive,
+            pArchive_filename: *const c_char,
+            pFile: *mut FILE,
+            flags: mz_uint,
+        ) -> mz_bool {
+            with_zip(pZip, MZ_FALSE, |zip| {
+                let index = locate_c(state(zip)?, pArchive_filename, ptr::null(), flags)?;
+                extract_to_cfile(zip, index, pFile, flags)?;
+                Ok(MZ_TRUE)
+            })
+        }
+
+        /// Initialize `pZip` for writing an archive at the current position in `pFile`.
+        ///
+        /// # Safety
+        ///
+        /// `pZip` must be null or a valid archive, see the module documentation, and `pFile` must
+        /// be an open `FILE` that stays open until the archive is ended.
+        pub unsafe extern "C" fn mz_zip_writer_init_cfile(
+            pZip: *mut mz_zip_archive,
+            pFile: *mut FILE,
+            flags: mz_uint,
+        ) -> mz_bool {
+            with_zip(pZip, MZ_FALSE, |zip| {
+                check_uninitialized(zip)?;
+                if pFile.is_null() {
+                    return Err(MZ_ZIP_INVALID_PARAMETER);
+                }
+                let mut file = CFile(pFile);
+                let start = file.seek(SeekFrom::Current(0))?;
+                let backing = Backing::File {
+                    file: Box::new(file),
+                    start,
+                };
+                init_writer(zip, MZ_ZIP_TYPE_CFILE, Some(backing), 0, flags)?;
+                state(zip)?.cfile = pFile as *mut c_void;
+                Ok(MZ_TRUE)
+            })
+        }
+
+        /// Add an entry with `size_to_add` bytes read from `pSrc_file`. The time defaults to
+        /// the current time.
+        ///
+        /// # Safety
+        ///
+        /// `pZip` must be null or a valid archive, see the module documentation, and
+        /// `pArchive_name` must be a zero-terminated string and `pSrc_file` an open `FILE`.
+        /// `pComment` and the extra data must be null or valid for reads of their sizes, and
+        /// `pFile_time` null or valid for reads.
+        pub unsafe extern "C" fn mz_zip_writer_add_cfile(
+            pZip: *mut mz_zip_archive,
+            pArchive_name: *const c_char,
+            pSrc_file: *mut FILE,
+            size_to_add: mz_uint64,
+            pFile_time: *const time_t,
+            pComment: *const c_void,
+            comment_size: mz_uint16,
+            level_and_flags: mz_uint,
+            user_extra_data_local: *const c_char,
+            user_extra_data_local_len: mz_uint,
+            user_extra_data_central: *const c_char,
+            user_extra_data_central_len: mz_uint,
+        ) -> mz_bool {
+            with_zip(pZip, MZ_FALSE, |zip| {
+                if pSrc_file.is_null() {
+                    return Err(MZ_ZIP_INVALID_PARAMETER);
+                }
+                let comment = bytes(pComment, comment_size.into())?;
+                let local_extra = bytes(
+                    user_extra_data_local as *const c_void,
+                    user_extra_data_local_len as usize,
+                )?;
+                let central_extra = bytes(
+                    user_extra_data_central as *const c_void,
+                    user_extra_data_central_len as usize,
+                )?;
+                add_reader(
+                    zip,
+                    pArchive_name,
+                    CFile(pSrc_file),
+                    size_to_add,
+                    unix_time_or_now(pFile_time),
+                    comment,
+                    level_and_flags,
+                    local_extra,
+                    central_extra,
+                )?;
+                Ok(MZ_TRUE)
+            })
+        }
+    );
+
+    unsafe fn extract_to_cfile(
+        zip: &mut mz_zip_archive,
+        index: mz_uint,
+        file: *mut FILE,
+        flags: mz_uint,
+    ) -> Result<(), mz_zip_error> {
+        let state = state(zip)?;
+        if file.is_null() {
+            return Err(MZ_ZIP_INVALID_PARAMETER);
+        }
+        let entry = entry(state, index)?;
+        if entry.is_dir() || !entry.is_supported() {
+            return Err(MZ_ZIP_UNSUPPORTED_FEATURE);
+        }
+        let mut file = CFile(file);
+        extract(state, index, flags, |_, data| {
+            file.write_all(data).map_err(|_| MZ_ZIP_FILE_WRITE_FAILED)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn c(s: &[u8]) -> *const c_char {
+        assert_eq!(s.last(), Some(&0));
+        s.as_ptr() as *const c_char
+    }
+
+    #[test]
+    fn write_heap_and_read_mem() {
+        let data = include_bytes!("../../miniz_oxide/tests/test_data/numbers.txt");
+        unsafe {
+            let mut zip: mz_zip_archive = mem::zeroed();
+            zip.m_file_offset_alignment = 16;
+            assert_eq!(mz_zip_writer_init_heap(&mut zip, 0, 0), MZ_TRUE);
+            let time: time_t = 1_577_836_798;
+            assert_eq!(
+                mz_zip_writer_add_mem_ex_v2(
+                    &mut zip,
+                    c(b"numbers.txt\0"),
+                    data.as_ptr() as *const c_void,
+                    data.len(),
+                    b"Some Comment".as_ptr() as *const c_void,
+                    12,
+                    9,
+                    0,
+                    0,
+                    &time,
+                    ptr::null(),
+                    0,
+                    ptr::null(),
+                    0,
+                ),
+                MZ_TRUE
+            );
+            assert_eq!(
+                mz_zip_writer_add_mem(&mut zip, c(b"dir/\0"), ptr::null(), 0, 6),
+                MZ_TRUE
+            );
+            assert_eq!(
+                mz_zip_writer_add_mem(
+                    &mut zip,
+                    c(b"dir/stored\0"),
+                    data.as_ptr() as *const c_void,
+                    100,
+                    0
+                ),
+                MZ_TRUE
+            );
+            // Names can't start with a slash, and directories can't have data.
+            assert_eq!(
+                mz_zip_writer_add_mem(&mut zip, c(b"/abs\0"), ptr::null(), 0, 6),
+                MZ_FALSE
+            );
+            assert_eq!(mz_zip_get_last_error(&mut zip), MZ_ZIP_INVALID_FILENAME);
+            assert_eq!(
+                mz_zip_writer_add_mem(&mut zip, c(b"d/\0"), data.as_ptr() as *const c_void, 1, 6),
+                MZ_FALSE
+            );
+            assert_eq!(mz_zip_peek_last_error(&mut zip), MZ_ZIP_INVALID_PARAMETER);
+            assert_eq!(zip.m_total_files, 3);
+
+            let mut buf = ptr::null_mut();
+            let mut size = 0;
+            assert_eq!(
+                mz_zip_writer_finalize_heap_archive(&mut zip, &mut buf, &mut size),
+                MZ_TRUE
+            );
+            assert_eq!(mz_zip_writer_end(&mut zip), MZ_TRUE);
+            assert_eq!(zip.m_archive_size, size as u64);
+            let archive_data = slice::from_raw_parts(buf as *const u8, size).to_vec();
+
+            let mut archive = ZipArchive::new(Cursor::new(&archive_data[..])).unwrap();
+            assert_eq!(archive.len(), 3);
+            assert_eq!(archive.read_to_vec(0).unwrap(), &data[..]);
+            assert_eq!(archive.read_to_vec(2).unwrap(), &data[..100]);
+            assert_eq!(archive.entry(0).unwrap().comment(), b"Some Comment");
+            assert_eq!(archive.entry(1).unwrap().local_header_offset() % 16, 0);
+            assert!(archive.entry(1).unwrap().is_dir());
+
+            let mut zip: mz_zip_archive = mem::zeroed();
+            assert_eq!(mz_zip_reader_init_mem(&mut zip, buf, size, 0), MZ_TRUE);
+            assert_eq!(mz_zip_reader_get_num_files(&mut zip), 3);
+            assert_eq!(mz_zip_validate_archive(&mut zip, 0), MZ_TRUE);
+            assert_eq!(
+                mz_zip_reader_locate_file(&mut zip, c(b"NUMBERS.txt\0"), c(b"some comment\0"), 0),
+                0
+            );
+            assert_eq!(
+                mz_zip_reader_locate_file(&mut zip, c(b"numbers.txt\0"), c(b"other\0"), 0),
+                -1
+            );
+            assert_eq!(mz_zip_get_last_error(&mut zip), MZ_ZIP_FILE_NOT_FOUND);
+            assert_eq!(
+                mz_zip_reader_locate_file(
+                    &mut zip,
+                    c(b"STORED\0"),
+                    ptr::null(),
+                    MZ_ZIP_FLAG_IGNORE_PATH
+                ),
+                2
+            );
+            assert_eq!(
+                mz_zip_reader_locate_file(
+                    &mut zip,
+                    c(b"STORED\0"),
+                    ptr::null(),
+                    MZ_ZIP_FLAG_IGNORE_PATH | MZ_ZIP_FLAG_CASE_SENSITIVE
+                ),
+                -1
+            );
+            assert_eq!(mz_zip_reader_is_file_a_directory(&mut zip, 1), MZ_TRUE);
+
+            let mut stat: mz_zip_archive_file_stat = mem::zeroed();
+            assert_eq!(mz_zip_reader_file_stat(&mut zip, 0, &mut stat), MZ_TRUE);
+            assert_eq!(stat.m_uncomp_size, data.len() as u64);
+            assert_eq!(stat.m_method, 8);
+            assert_eq!(stat.m_time, time);
+            assert_eq!(
+                CStr::from_ptr(stat.m_filename.as_ptr()).to_bytes(),
+                b"numbers.txt"
+            );
+            assert_eq!(stat.m_is_supported, MZ_TRUE);
+
+            let mut name = [0 as c_char; 4];
+            assert_eq!(
+                mz_zip_reader_get_filename(&mut zip, 0, ptr::null_mut(), 0),
+                12
+            );
+            assert_eq!(
+                mz_zip_reader_get_filename(&mut zip, 0, name.as_mut_ptr(), 4),
+                4
+            );
+            assert_eq!(CStr::from_ptr(name.as_ptr()).to_bytes(), b"num");
+
+            let mut extracted_size = 0;
+            let extracted = mz_zip_reader_extract_to_heap(&mut zip, 0, &mut extracted_size, 0);
+            assert!(!extracted.is_null());
+            assert_eq!(
+                slice::from_raw_parts(extracted as *const u8, extracted_size),
+                &data[..]
+            );
+            miniz_def_free_func(ptr::null_mut(), extracted);
+
+            let mut small = [0u8; 99];
+            assert_eq!(
+                mz_zip_reader_extract_to_mem(
+                    &mut zip,
+                    2,
+                    small.as_mut_ptr() as *mut c_void,
+                    small.len(),
+                    0
+                ),
+                MZ_FALSE
+            );
+            assert_eq!(mz_zip_get_last_error(&mut zip), MZ_ZIP_BUF_TOO_SMALL);
+
+            let iter = mz_zip_reader_extract_file_iter_new(&mut zip, c(b"numbers.txt\0"), 0);
+            assert!(!iter.is_null());
+            let mut out = Vec::new();
+            let mut chunk = [0u8; 1000];
+            loop {
+                let n = mz_zip_reader_extract_iter_read(
+                    iter,
+                    chunk.as_mut_ptr() as *mut c_void,
+                    chunk.len(),
+                );
+                out.extend_from_slice(&chunk[..n]);
+                if n < chunk.len() {
+                    break;
+                }
+            }
+            assert_eq!((*iter).file_crc32, archive.entry(0).unwrap().crc32());
+            assert_eq!(mz_zip_reader_extract_iter_free(iter), MZ_TRUE);
+            assert_eq!(out, &data[..]);
+
+            assert_eq!(mz_zip_end(&mut zip), MZ_TRUE);
+            assert_eq!(mz_zip_get_mode(&mut zip), MZ_ZIP_MODE_INVALID);
+            miniz_def_free_func(ptr::null_mut(), buf);
+        }
+    }
+
+    #[test]
+    fn clone_entries_and_append_in_place() {
+        let data = include_bytes!("../../miniz_oxide/tests/test_data/numbers.txt");
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("miniz_oxide_c_zip_{}.zip", std::process::id()));
+        let path_c = format!("{}\0", path.to_str().unwrap());
+        let copy = dir.join(format!("miniz_oxide_c_zip_copy_{}.zip", std::process::id()));
+        let copy_c = format!("{}\0", copy.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+        unsafe {
+            for (i, name) in [&b"a\0"[..], b"b\0"].iter().enumerate() {
+                assert_eq!(
+                    mz_zip_add_mem_to_archive_file_in_place(
+                        c(path_c.as_bytes()),
+                        c(name),
+                        data.as_ptr() as *const c_void,
+                        data.len() / (i + 1),
+                        ptr::null(),
+                        0,
+                        6,
+                    ),
+                    MZ_TRUE
+                );
+            }
+
+            let mut src: mz_zip_archive = mem::zeroed();
+            assert_eq!(
+                mz_zip_reader_init_file(&mut src, c(path_c.as_bytes()), 0),
+                MZ_TRUE
+            );
+            let mut dst: mz_zip_archive = mem::zeroed();
+            assert_eq!(
+                mz_zip_writer_init_file(&mut dst, c(copy_c.as_bytes()), 0),
+                MZ_TRUE
+            );
+            for index in 0..mz_zip_reader_get_num_files(&mut src) {
+                assert_eq!(
+                    mz_zip_writer_add_from_zip_reader(&mut dst, &mut src, index),
+                    MZ_TRUE
+                );
+            }
+            assert_eq!(mz_zip_writer_finalize_archive(&mut dst), MZ_TRUE);
+            assert_eq!(mz_zip_writer_end(&mut dst), MZ_TRUE);
+            assert_eq!(mz_zip_reader_end(&mut src), MZ_TRUE);
+
+            let mut err = MZ_ZIP_UNDEFINED_ERROR;
+            assert_eq!(
+                mz_zip_validate_file_archive(c(copy_c.as_bytes()), 0, &mut err),
+                MZ_TRUE
+            );
+            assert_eq!(err, MZ_ZIP_NO_ERROR);
+            let mut size = 0;
+            let extracted =
+                mz_zip_extract_archive_file_to_heap(c(copy_c.as_bytes()), c(b"B\0"), &mut size, 0);
+            assert!(!extracted.is_null());
+            assert_eq!(
+                slice::from_raw_parts(extracted as *const u8, size),
+                &data[..data.len() / 2]
+            );
+            miniz_def_free_func(ptr::null_mut(), extracted);
+        }
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&copy).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+//! ZIP archive support, replacing the archive functions from `miniz_zip.c`.
+//!
+//! Only single-disk archives using the stored and deflate methods without encryption are
+//! supported, as in miniz. Zip64 archives can be read and written.
+
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{cmp, fmt, io};
+
+pub(crate) mod c_api;
+mod read;
+mod stream;
+mod write;
+
+pub use self::read::{ZipArchive, ZipEntry, ZipFile};
+pub use self::stream::ZipStreamWriter;
+pub use self::write::{add_to_archive_file_in_place, FileOptions, ZipWriter};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+
+/// Version of the ZIP specification needed for deflate and directories.
+const VERSION_DEFLATE: u16 = 20;
+/// Version of the ZIP specification needed for zip64 extensions.
+const VERSION_ZIP64: u16 = 45;
+
+const LOCAL_HEADER_SIZE: usize = 30;
+const CENTRAL_HEADER_SIZE: usize = 46;
+const END_OF_CENTRAL_DIR_SIZE: usize = 22;
+const ZIP64_END_OF_CENTRAL_DIR_SIZE: usize = 56;
+const ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIZE: usize = 20;
+
+/// Header id of the extra field holding the 64-bit sizes and offset of an entry.
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+
+/// General purpose flag set if the entry is encrypted.
+const FLAG_ENCRYPTED: u16 = 1;
+/// General purpose flag set if the CRC and sizes follow the data in a data descriptor.
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+/// General purpose flag set if the entry uses strong encryption.
+const FLAG_STRONG_ENCRYPTION: u16 = 1 << 6;
+/// General purpose flag set if the name and comment are UTF-8.
+const FLAG_UTF8: u16 = 1 << 11;
+
+/// How the data of an entry is compressed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CompressionMethod {
+    /// The data is stored without compression.
+    Stored,
+    /// The data is compressed with deflate.
+    Deflated,
+    /// Some other method, which is not supported.
+    Unsupported(u16),
+}
+
+impl CompressionMethod {
+    /// Get the method from the value stored in the headers.
+    pub fn from_u16(method: u16) -> CompressionMethod {
+        match method {
+            0 => CompressionMethod::Stored,
+            8 => CompressionMethod::Deflated,
+            other => CompressionMethod::Unsupported(other),
+        }
+    }
+
+    /// Get the value stored in the headers for this method.
+    pub fn to_u16(self) -> u16 {
+        match self {
+            CompressionMethod::Stored => 0,
+            CompressionMethod::Deflated => 8,
+            CompressionMethod::Unsupported(other) => other,
+        }
+    }
+}
+
+/// Modification time of an entry, stored in MS-DOS format with a resolution of two seconds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DateTime {
+    /// Year, from 1980 to 2107.
+    pub year: u16,
+    /// Month, from 1 to 12.
+    pub month: u8,
+    /// Day of the month, from 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    /// Seconds, always even.
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Decode the MS-DOS time and date fields.
+    pub fn from_dos(time: u16, date: u16) -> DateTime {
+        DateTime {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0xf) as u8,
+            day: (date & 0x1f) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3f) as u8,
+            second: ((time & 0x1f) * 2) as u8,
+        }
+    }
+
+    /// Encode as MS-DOS time and date fields.
+    pub fn to_dos(self) -> (u16, u16) {
+        let time = (u16::from(self.hour) << 11)
+            | (u16::from(self.minute) << 5)
+            | (u16::from(self.second) / 2);
+        let date = (self.year.saturating_sub(1980) << 9)
+            | (u16::from(self.month) << 5)
+            | u16::from(self.day);
+        (time, date)
+    }
+
+    /// Convert a unix timestamp to UTC time, clamped to the range that can be stored.
+    pub fn from_unix_time(time: i64) -> DateTime {
+        let min = days_from_civil(1980, 1, 1) * 86400;
+        let max = days_from_civil(2107, 12, 31) * 86400 + 86398;
+        let time = cmp::min(cmp::max(time, min), max);
+        let (year, month, day) = civil_from_days(time.div_euclid(86400));
+        let seconds = time.rem_euclid(86400);
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60 / 2 * 2) as u8,
+        }
+    }
+
+    /// Convert to a unix timestamp, taking the time to be UTC.
+    pub fn to_unix_time(self) -> i64 {
+        let days = days_from_civil(
+            i64::from(self.year),
+            i64::from(self.month),
+            i64::from(self.day),
+        );
+        days * 86400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second)
+    }
+
+    /// The current time in UTC.
+    pub fn now() -> DateTime {
+        let time = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch.as_secs() as i64,
+            Err(_) => 0,
+        };
+        DateTime::from_unix_time(time)
+    }
+}
+
+/// Number of days since 1970-01-01 of the given date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of `days_from_civil`, returning the year, month and day.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl Default for DateTime {
+    /// The earliest time that can be stored, 1980-01-01 00:00:00.
+    fn default() -> DateTime {
+        DateTime::from_dos(0, (1 << 5) | 1)
+    }
+}
+
+/// Errors that can occur when reading or writing an archive.
+#[derive(Debug)]
+pub enum ZipError {
+    /// Reading from or writing to the underlying storage failed.
+    Io(io::Error),
+    /// No end of central directory record was found.
+    NotAnArchive,
+    /// The archive is malformed.
+    InvalidArchive(&'static str),
+    /// The archive is split over several disks.
+    UnsupportedMultiDisk,
+    /// The entry uses a compression method other than stored or deflate.
+    UnsupportedMethod(u16),
+    /// The entry is encrypted.
+    UnsupportedEncryption,
+    /// The compressed data of the entry is invalid.
+    DecompressionFailed,
+    /// Compressing the entry failed.
+    CompressionFailed,
+    /// The entry decompressed to a different size than recorded in the archive.
+    UnexpectedSize,
+    /// The CRC-32 of the decompressed data doesn't match the one recorded in the archive.
+    CrcMismatch,
+    /// There is no entry with the given name.
+    FileNotFound,
+    /// The name or comment is too long, or the name is empty.
+    InvalidName,
+    /// The entry is too large to write without the zip64 extensions enabled beforehand.
+    FileTooLarge,
+}
+
+impl fmt::Display for ZipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ZipError::Io(ref err) => write!(f, "i/o error: {}", err),
+            ZipError::NotAnArchive => f.write_str("not a zip archive"),
+            ZipError::InvalidArchive(what) => write!(f, "invalid zip archive: {}", what),
+            ZipError::UnsupportedMultiDisk => f.write_str("multi-disk archives are not supported"),
+            ZipError::UnsupportedMethod(method) => {
+                write!(f, "unsupported compression method {}", method)
+            }
+            ZipError::UnsupportedEncryption => f.write_str("encrypted entries are not supported"),
+            ZipError::DecompressionFailed => f.write_str("decompression failed"),
+            ZipError::CompressionFailed => f.write_str("compression failed"),
+            ZipError::UnexpectedSize => f.write_str("unexpected decompressed size"),
+            ZipError::CrcMismatch => f.write_str("CRC-32 check failed"),
+            ZipError::FileNotFound => f.write_str("file not found in archive"),
+            ZipError::InvalidName => f.write_str("invalid entry name or comment"),
+            ZipError::FileTooLarge => f.write_str("entry too large without zip64 enabled"),
+        }
+    }
+}
+
+impl Error for ZipError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ZipError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ZipError {
+    fn from(err: io::Error) -> ZipError {
+        ZipError::Io(err)
+    }
+}
+
+impl From<ZipError> for io::Error {
+    fn from(err: ZipError) -> io::Error {
+        match err {
+            ZipError::Io(err) => err,
+            other => io::Error::new(io::ErrorKind::InvalidData, other),
+        }
+    }
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> u16 {
+    u16::from(buf[pos]) | (u16::from(buf[pos + 1]) << 8)
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    u32::from(read_u16(buf, pos)) | (u32::from(read_u16(buf, pos + 2)) << 16)
+}
+
+fn read_u64(buf: &[u8], pos: usize) -> u64 {
+    u64::from(read_u32(buf, pos)) | (u64::from(read_u32(buf, pos + 4)) << 32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dos_date_time() {
+        let time = DateTime {
+            year: 2019,
+            month: 12,
+            day: 31,
+            hour: 23,
+            minute: 59,
+            second: 58,
+        };
+        let (dos_time, dos_date) = time.to_dos();
+        assert_eq!(DateTime::from_dos(dos_time, dos_date), time);
+        assert_eq!(DateTime::default().to_dos(), (0, 0x21));
+
+        assert_eq!(time.to_unix_time(), 1_577_836_798);
+        assert_eq!(DateTime::from_unix_time(1_577_836_799), time);
+        assert_eq!(DateTime::from_unix_time(0), DateTime::default());
+        let leap_day = DateTime::from_unix_time(951_782_400);
+        assert_eq!((leap_day.year, leap_day.month, leap_day.day), (2000, 2, 29));
+        assert_eq!(DateTime::from_unix_time(i64::max_value()).year, 2107);
+    }
+}
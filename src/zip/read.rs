@@ -0,0 +1,1023 @@
+//! Reading ZIP archives.
+
+use std::borrow::Cow;
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+
+use super::*;
+
+/// Size of the buffer compressed data is read into when extracting deflated entries.
+const READ_BUF_SIZE: usize = 32 * 1024;
+
+/// Largest buffer allocated up front for the contents of an entry, the rest is allocated as
+/// the data is read since the sizes in the archive can't be trusted.
+const MAX_INITIAL_ALLOC: u64 = 1024 * 1024;
+
+/// Metadata of an entry in an archive, as stored in the central directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    pub(super) index: usize,
+    pub(super) central_dir_offset: u64,
+    pub(super) version_made_by: u16,
+    pub(super) version_needed: u16,
+    pub(super) flags: u16,
+    pub(super) method: CompressionMethod,
+    pub(super) last_modified: DateTime,
+    pub(super) crc32: u32,
+    pub(super) compressed_size: u64,
+    pub(super) uncompressed_size: u64,
+    pub(super) internal_attributes: u16,
+    pub(super) external_attributes: u32,
+    pub(super) local_header_offset: u64,
+    pub(super) name: Vec<u8>,
+    pub(super) extra: Vec<u8>,
+    pub(super) comment: Vec<u8>,
+}
+
+impl ZipEntry {
+    /// Index of the entry in the central directory.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Offset of the entry's header in the central directory.
+    pub fn central_dir_offset(&self) -> u64 {
+        self.central_dir_offset
+    }
+
+    /// Name of the entry, with invalid UTF-8 replaced.
+    ///
+    /// Names that aren't flagged as UTF-8 are in code page 437, which agrees with UTF-8 for
+    /// ASCII names.
+    pub fn name(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.name)
+    }
+
+    /// Name of the entry as stored in the archive.
+    pub fn name_raw(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Whether the name and comment are flagged as UTF-8.
+    pub fn is_utf8(&self) -> bool {
+        self.flags & FLAG_UTF8 != 0
+    }
+
+    /// The entry's comment.
+    pub fn comment(&self) -> &[u8] {
+        &self.comment
+    }
+
+    /// The extra field from the central directory.
+    pub fn extra(&self) -> &[u8] {
+        &self.extra
+    }
+
+    /// The version of the ZIP specification and the host system the entry was made with.
+    pub fn version_made_by(&self) -> u16 {
+        self.version_made_by
+    }
+
+    /// The version of the ZIP specification needed to extract the entry.
+    pub fn version_needed(&self) -> u16 {
+        self.version_needed
+    }
+
+    /// The general purpose bit flags.
+    pub fn flags(&self) -> u16 {
+        self.flags
+    }
+
+    pub fn method(&self) -> CompressionMethod {
+        self.method
+    }
+
+    pub fn last_modified(&self) -> DateTime {
+        self.last_modified
+    }
+
+    /// CRC-32 of the uncompressed data.
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    pub fn compressed_size(&self) -> u64 {
+        self.compressed_size
+    }
+
+    /// Size of the uncompressed data.
+    pub fn size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    pub fn internal_attributes(&self) -> u16 {
+        self.internal_attributes
+    }
+
+    /// Host system dependent attributes, see [`ZipEntry::unix_mode`].
+    pub fn external_attributes(&self) -> u32 {
+        self.external_attributes
+    }
+
+    /// The unix file mode, if the entry was made on a unix system.
+    pub fn unix_mode(&self) -> Option<u32> {
+        if self.version_made_by >> 8 == 3 {
+            Some(self.external_attributes >> 16)
+        } else {
+            None
+        }
+    }
+
+    /// Offset of the entry's local header from the start of the archive.
+    pub fn local_header_offset(&self) -> u64 {
+        self.local_header_offset
+    }
+
+    /// Whether the entry is a directory, going by a trailing slash in the name or the MS-DOS
+    /// directory attribute.
+    pub fn is_dir(&self) -> bool {
+        self.name.last() == Some(&b'/') || self.external_attributes & 0x10 != 0
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & (FLAG_ENCRYPTED | FLAG_STRONG_ENCRYPTION) != 0
+    }
+
+    /// Whether the entry can be extracted, that is it isn't encrypted and uses a supported
+    /// compression method.
+    pub fn is_supported(&self) -> bool {
+        !self.is_encrypted()
+            && match self.method {
+                CompressionMethod::Stored | CompressionMethod::Deflated => true,
+                CompressionMethod::Unsupported(_) => false,
+            }
+    }
+
+    /// Parse the header at the start of `buf`, returning the entry and the size of the header.
+    fn parse(
+        buf: &[u8],
+        index: usize,
+        central_dir_offset: u64,
+    ) -> Result<(ZipEntry, usize), ZipError> {
+        if buf.len() < CENTRAL_HEADER_SIZE || read_u32(buf, 0) != CENTRAL_HEADER_SIGNATURE {
+            return Err(ZipError::InvalidArchive("invalid central directory header"));
+        }
+        let name_len = usize::from(read_u16(buf, 28));
+        let extra_len = usize::from(read_u16(buf, 30));
+        let comment_len = usize::from(read_u16(buf, 32));
+        let header_len = CENTRAL_HEADER_SIZE + name_len + extra_len + comment_len;
+        if buf.len() < header_len {
+            return Err(ZipError::InvalidArchive(
+                "central directory header out of bounds",
+            ));
+        }
+        if read_u16(buf, 34) != 0 {
+            return Err(ZipError::UnsupportedMultiDisk);
+        }
+
+        let (time, date) = (read_u16(buf, 12), read_u16(buf, 14));
+        let name_end = CENTRAL_HEADER_SIZE + name_len;
+        let extra_end = name_end + extra_len;
+        let mut entry = ZipEntry {
+            index,
+            central_dir_offset,
+            version_made_by: read_u16(buf, 4),
+            version_needed: read_u16(buf, 6),
+            flags: read_u16(buf, 8),
+            method: CompressionMethod::from_u16(read_u16(buf, 10)),
+            last_modified: DateTime::from_dos(time, date),
+            crc32: read_u32(buf, 16),
+            compressed_size: u64::from(read_u32(buf, 20)),
+            uncompressed_size: u64::from(read_u32(buf, 24)),
+            internal_attributes: read_u16(buf, 36),
+            external_attributes: read_u32(buf, 38),
+            local_header_offset: u64::from(read_u32(buf, 42)),
+            name: buf[CENTRAL_HEADER_SIZE..name_end].to_vec(),
+            extra: buf[name_end..extra_end].to_vec(),
+            comment: buf[extra_end..header_len].to_vec(),
+        };
+        entry.apply_zip64_extra()?;
+        Ok((entry, header_len))
+    }
+
+    /// Replace the sizes and offset that don't fit in 32 bits with the values from the zip64
+    /// extra field.
+    fn apply_zip64_extra(&mut self) -> Result<(), ZipError> {
+        const INVALID: &str = "invalid zip64 extra field";
+        let mut pos = 0;
+        while pos + 4 <= self.extra.len() {
+            let id = read_u16(&self.extra, pos);
+            let len = usize::from(read_u16(&self.extra, pos + 2));
+            let data = self
+                .extra
+                .get(pos + 4..pos + 4 + len)
+                .ok_or(ZipError::InvalidArchive(INVALID))?;
+            if id == ZIP64_EXTRA_FIELD_ID {
+                let mut field_pos = 0;
+                for value in [
+                    &mut self.uncompressed_size,
+                    &mut self.compressed_size,
+                    &mut self.local_header_offset,
+                ]
+                .iter_mut()
+                {
+                    if **value == 0xffff_ffff {
+                        if field_pos + 8 > data.len() {
+                            return Err(ZipError::InvalidArchive(INVALID));
+                        }
+                        **value = read_u64(data, field_pos);
+                        field_pos += 8;
+                    }
+                }
+                return Ok(());
+            }
+            pos += 4 + len;
+        }
+        Ok(())
+    }
+}
+
+/// A ZIP archive opened for reading.
+///
+/// The central directory is read when opening the archive, entries are read from `R` as they
+/// are extracted.
+///
+/// ```
+/// use std::io::{Cursor, Read};
+/// use miniz_oxide_c_api::zip::ZipArchive;
+///
+/// fn print_entries(data: &[u8]) -> Result<(), miniz_oxide_c_api::zip::ZipError> {
+///     let mut archive = ZipArchive::new(Cursor::new(data))?;
+///     for i in 0..archive.len() {
+///         let mut contents = Vec::new();
+///         let mut file = archive.by_index(i)?;
+///         file.read_to_end(&mut contents)?;
+///         println!("{}: {} bytes", file.entry().name(), contents.len());
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ZipArchive<R> {
+    reader: R,
+    /// Offset of the start of the archive in `reader`, non-zero if other data was prepended
+    /// to the archive.
+    offset: u64,
+    central_dir_offset: u64,
+    central_dir_size: u64,
+    zip64: bool,
+    entries: Vec<ZipEntry>,
+    comment: Vec<u8>,
+}
+
+impl<R: Read + Seek> ZipArchive<R> {
+    /// Open an archive, reading its central directory.
+    pub fn new(mut reader: R) -> Result<ZipArchive<R>, ZipError> {
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        if file_len < END_OF_CENTRAL_DIR_SIZE as u64 {
+            return Err(ZipError::NotAnArchive);
+        }
+
+        // The record is at the end of the file followed by a comment of at most 64 KiB.
+        let search_len = cmp::min(file_len, (END_OF_CENTRAL_DIR_SIZE + 0xffff) as u64);
+        let search_start = file_len - search_len;
+        let mut buf = vec![0; search_len as usize];
+        reader.seek(SeekFrom::Start(search_start))?;
+        reader.read_exact(&mut buf)?;
+        let pos = (0..=buf.len() - END_OF_CENTRAL_DIR_SIZE)
+            .rev()
+            .find(|&pos| read_u32(&buf, pos) == END_OF_CENTRAL_DIR_SIGNATURE)
+            .ok_or(ZipError::NotAnArchive)?;
+        let eocd = &buf[pos..];
+        let eocd_pos = search_start + pos as u64;
+
+        let mut disk = u32::from(read_u16(eocd, 4));
+        let mut central_dir_disk = u32::from(read_u16(eocd, 6));
+        let mut disk_entries = u64::from(read_u16(eocd, 8));
+        let mut num_entries = u64::from(read_u16(eocd, 10));
+        let mut central_dir_size = u64::from(read_u32(eocd, 12));
+        let mut central_dir_offset = u64::from(read_u32(eocd, 16));
+        let comment_len = cmp::min(
+            usize::from(read_u16(eocd, 20)),
+            eocd.len() - END_OF_CENTRAL_DIR_SIZE,
+        );
+        let comment = eocd[END_OF_CENTRAL_DIR_SIZE..END_OF_CENTRAL_DIR_SIZE + comment_len].to_vec();
+
+        // The central directory ends where the (zip64) end of central directory record starts.
+        let mut central_dir_end = eocd_pos;
+        let zip64 = match ZipArchive::read_zip64_end(&mut reader, eocd_pos)? {
+            Some((zip64_pos, record)) => {
+                disk = read_u32(&record, 16);
+                central_dir_disk = read_u32(&record, 20);
+                disk_entries = read_u64(&record, 24);
+                num_entries = read_u64(&record, 32);
+                central_dir_size = read_u64(&record, 40);
+                central_dir_offset = read_u64(&record, 48);
+                central_dir_end = zip64_pos;
+                true
+            }
+            None => false,
+        };
+
+        if disk != 0 || central_dir_disk != 0 || disk_entries != num_entries {
+            return Err(ZipError::UnsupportedMultiDisk);
+        }
+        let offset = central_dir_offset
+            .checked_add(central_dir_size)
+            .and_then(|end| central_dir_end.checked_sub(end))
+            .ok_or(ZipError::InvalidArchive("central directory out of bounds"))?;
+
+        let mut central_dir = vec![0; central_dir_size as usize];
+        reader.seek(SeekFrom::Start(offset + central_dir_offset))?;
+        reader.read_exact(&mut central_dir)?;
+
+        // Don't trust the entry count for the allocation, every entry needs at least a header.
+        let capacity = cmp::min(num_entries, central_dir_size / CENTRAL_HEADER_SIZE as u64);
+        let mut entries = Vec::with_capacity(capacity as usize);
+        let mut pos = 0;
+        for index in 0..num_entries as usize {
+            let (entry, len) = ZipEntry::parse(&central_dir[pos..], index, pos as u64)?;
+            entries.push(entry);
+            pos += len;
+        }
+
+        Ok(ZipArchive {
+            reader,
+            offset,
+            central_dir_offset,
+            central_dir_size,
+            zip64,
+            entries,
+            comment,
+        })
+    }
+
+    /// Look for the zip64 end of central directory record through the locator before the end
+    /// of central directory record at `eocd_pos`, returning its position and contents.
+    fn read_zip64_end(reader: &mut R, eocd_pos: u64) -> Result<Option<(u64, Vec<u8>)>, ZipError> {
+        let locator_pos = match eocd_pos.checked_sub(ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIZE as u64) {
+            Some(pos) => pos,
+            None => return Ok(None),
+        };
+        let mut locator = [0; ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIZE];
+        reader.seek(SeekFrom::Start(locator_pos))?;
+        reader.read_exact(&mut locator)?;
+        if read_u32(&locator, 0) != ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE {
+            return Ok(None);
+        }
+        if read_u32(&locator, 4) != 0 || read_u32(&locator, 16) > 1 {
+            return Err(ZipError::UnsupportedMultiDisk);
+        }
+
+        // The recorded offset is off if data was prepended to the archive, in which case the
+        // record is usually right before the locator.
+        let mut record = vec![0; ZIP64_END_OF_CENTRAL_DIR_SIZE];
+        let candidates = [
+            Some(read_u64(&locator, 8)),
+            locator_pos.checked_sub(ZIP64_END_OF_CENTRAL_DIR_SIZE as u64),
+        ];
+        for &pos in candidates.iter().flatten() {
+            match pos.checked_add(ZIP64_END_OF_CENTRAL_DIR_SIZE as u64) {
+                Some(end) if end <= locator_pos => (),
+                _ => continue,
+            }
+            reader.seek(SeekFrom::Start(pos))?;
+            reader.read_exact(&mut record)?;
+            if read_u32(&record, 0) == ZIP64_END_OF_CENTRAL_DIR_SIGNATURE {
+                return Ok(Some((pos, record)));
+            }
+        }
+        Err(ZipError::InvalidArchive(
+            "zip64 end of central directory record not found",
+        ))
+    }
+
+    /// Get the entry at `index` for reading its contents.
+    pub fn by_index(&mut self, index: usize) -> Result<ZipFile<'_, R>, ZipError> {
+        let state = self.open(index)?;
+        let entry = &self.entries[index];
+        Ok(ZipFile {
+            inner: EntryReader::new((&mut self.reader).take(entry.compressed_size), entry, state),
+            entry,
+        })
+    }
+
+    /// Get a reader over the compressed data of the entry at `index`, which is neither
+    /// decompressed nor checked.
+    pub fn by_index_raw(&mut self, index: usize) -> Result<io::Take<&mut R>, ZipError> {
+        if index >= self.entries.len() {
+            return Err(ZipError::FileNotFound);
+        }
+        let data_offset = self.data_offset(index)?;
+        self.reader.seek(SeekFrom::Start(data_offset))?;
+        Ok((&mut self.reader).take(self.entries[index].compressed_size))
+    }
+
+    /// Get the entry named `name` for reading its contents.
+    pub fn by_name(&mut self, name: &str) -> Result<ZipFile<'_, R>, ZipError> {
+        let index = self.index_of(name).ok_or(ZipError::FileNotFound)?;
+        self.by_index(index)
+    }
+
+    /// Extract the contents of the entry at `index` into a vector.
+    pub fn read_to_vec(&mut self, index: usize) -> Result<Vec<u8>, ZipError> {
+        let mut file = self.by_index(index)?;
+        // The size comes from the archive, so only a limited amount is allocated up front.
+        let size = cmp::min(
+            file.entry.uncompressed_size,
+            file.entry.compressed_size.saturating_mul(2),
+        );
+        let mut out = vec![0; cmp::min(size, MAX_INITIAL_ALLOC) as usize];
+        let mut len = 0;
+        loop {
+            if len == out.len() {
+                out.resize(cmp::max(len * 2, 4096), 0);
+            }
+            let n = file.inner.read_data(&mut out[len..])?;
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+        out.truncate(len);
+        Ok(out)
+    }
+
+    /// Check that the entry at `index` can be extracted and seek to its data, returning the
+    /// decompressor state it needs.
+    fn open(&mut self, index: usize) -> Result<Option<Box<InflateState>>, ZipError> {
+        let entry = self.entries.get(index).ok_or(ZipError::FileNotFound)?;
+        if entry.is_encrypted() {
+            return Err(ZipError::UnsupportedEncryption);
+        }
+        let state = match entry.method {
+            CompressionMethod::Stored => None,
+            CompressionMethod::Deflated => Some(InflateState::new_boxed(DataFormat::Raw)),
+            CompressionMethod::Unsupported(method) => {
+                return Err(ZipError::UnsupportedMethod(method))
+            }
+        };
+        let data_offset = self.data_offset(index)?;
+        self.reader.seek(SeekFrom::Start(data_offset))?;
+        Ok(state)
+    }
+
+    /// Get the absolute offset of the data of the entry at `index`, after its local header,
+    /// checking that the compressed data is within the archive.
+    fn data_offset(&mut self, index: usize) -> Result<u64, ZipError> {
+        const OUT_OF_BOUNDS: ZipError = ZipError::InvalidArchive("entry data out of bounds");
+        let entry = &self.entries[index];
+        let header_pos = self
+            .offset
+            .checked_add(entry.local_header_offset)
+            .ok_or(OUT_OF_BOUNDS)?;
+        let compressed_size = entry.compressed_size;
+        let mut header = [0; LOCAL_HEADER_SIZE];
+        self.reader.seek(SeekFrom::Start(header_pos))?;
+        self.reader.read_exact(&mut header)?;
+        if read_u32(&header, 0) != LOCAL_HEADER_SIGNATURE {
+            return Err(ZipError::InvalidArchive("invalid local header"));
+        }
+        let name_len = u64::from(read_u16(&header, 26));
+        let extra_len = u64::from(read_u16(&header, 28));
+        let data_offset = header_pos
+            .checked_add(LOCAL_HEADER_SIZE as u64 + name_len + extra_len)
+            .ok_or(OUT_OF_BOUNDS)?;
+        let archive_end = self.reader.seek(SeekFrom::End(0))?;
+        match data_offset.checked_add(compressed_size) {
+            Some(end) if end <= archive_end => Ok(data_offset),
+            _ => Err(OUT_OF_BOUNDS),
+        }
+    }
+}
+
+impl<R: Read + Seek + Clone> ZipArchive<R> {
+    /// Get a reader for the entry at `index` that owns a copy of the underlying reader, so
+    /// it doesn't borrow the archive. With `raw`, the compressed data is read unchecked.
+    pub(super) fn entry_reader(
+        &mut self,
+        index: usize,
+        raw: bool,
+    ) -> Result<EntryReader<R>, ZipError> {
+        let state = if raw {
+            let data_offset = self.data_offset(index)?;
+            self.reader.seek(SeekFrom::Start(data_offset))?;
+            None
+        } else {
+            self.open(index)?
+        };
+        let entry = &self.entries[index];
+        let mut reader = EntryReader::new(
+            self.reader.clone().take(entry.compressed_size),
+            entry,
+            state,
+        );
+        if raw {
+            reader.expected = None;
+        }
+        Ok(reader)
+    }
+}
+
+impl<R> ZipArchive<R> {
+    /// An archive without entries, for writing a new archive to `reader`.
+    pub(super) fn empty(reader: R) -> ZipArchive<R> {
+        ZipArchive {
+            reader,
+            offset: 0,
+            central_dir_offset: 0,
+            central_dir_size: 0,
+            zip64: false,
+            entries: Vec::new(),
+            comment: Vec::new(),
+        }
+    }
+
+    /// Number of entries in the archive.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The archive's comment.
+    pub fn comment(&self) -> &[u8] {
+        &self.comment
+    }
+
+    /// Metadata of all entries, in central directory order.
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Metadata of the entry at `index`.
+    pub fn entry(&self, index: usize) -> Option<&ZipEntry> {
+        self.entries.get(index)
+    }
+
+    /// Find the index of the entry named `name`, comparing names exactly.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.name == name.as_bytes())
+    }
+
+    /// Whether the archive uses the zip64 end of central directory record.
+    pub fn is_zip64(&self) -> bool {
+        self.zip64
+    }
+
+    /// Offset of the start of the archive in the reader, which is non-zero if other data was
+    /// prepended to the archive.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Offset of the central directory from the start of the archive.
+    pub fn central_dir_offset(&self) -> u64 {
+        self.central_dir_offset
+    }
+
+    /// Size of the central directory.
+    pub fn central_dir_size(&self) -> u64 {
+        self.central_dir_size
+    }
+
+    /// Take the entries and the comment, for appending to the archive.
+    pub(super) fn into_parts(self) -> (Vec<ZipEntry>, Vec<u8>) {
+        (self.entries, self.comment)
+    }
+
+    /// Entries, for adding new ones when writing to the archive.
+    pub(super) fn entries_mut(&mut self) -> &mut Vec<ZipEntry> {
+        &mut self.entries
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// An entry being extracted from an archive.
+///
+/// The contents are read through the [`Read`] implementation, which checks the size and
+/// CRC-32 of the data once it has all been read.
+pub struct ZipFile<'a, R> {
+    inner: EntryReader<&'a mut R>,
+    entry: &'a ZipEntry,
+}
+
+impl<'a, R> ZipFile<'a, R> {
+    /// Metadata of the entry.
+    pub fn entry(&self) -> &ZipEntry {
+        self.entry
+    }
+}
+
+impl<'a, R: Read> Read for ZipFile<'a, R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        self.inner.read_data(out).map_err(io::Error::from)
+    }
+}
+
+/// Decompresses the data of an entry read from `R` and checks it against the size and CRC-32
+/// recorded in the archive.
+pub(super) struct EntryReader<R> {
+    reader: io::Take<R>,
+    /// State for deflated entries, `None` for stored ones and raw data.
+    state: Option<Box<InflateState>>,
+    buf: Vec<u8>,
+    buf_pos: usize,
+    buf_len: usize,
+    crc: crc32fast::Hasher,
+    written: u64,
+    /// Size and CRC-32 the data is checked against, `None` for raw data.
+    expected: Option<(u64, u32)>,
+    done: bool,
+}
+
+impl<R: Read> EntryReader<R> {
+    fn new(
+        reader: io::Take<R>,
+        entry: &ZipEntry,
+        state: Option<Box<InflateState>>,
+    ) -> EntryReader<R> {
+        let buf = if state.is_some() {
+            vec![0; cmp::min(READ_BUF_SIZE as u64, entry.compressed_size) as usize]
+        } else {
+            Vec::new()
+        };
+        EntryReader {
+            reader,
+            state,
+            buf,
+            buf_pos: 0,
+            buf_len: 0,
+            crc: crc32fast::Hasher::new(),
+            written: 0,
+            expected: Some((entry.uncompressed_size, entry.crc32)),
+            done: false,
+        }
+    }
+
+    /// Number of bytes of data output so far.
+    pub(super) fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Number of bytes of compressed data left to read.
+    pub(super) fn remaining(&self) -> u64 {
+        self.reader.limit() + (self.buf_len - self.buf_pos) as u64
+    }
+
+    /// Whether all the data has been read and checked.
+    pub(super) fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Decompress into `out`, returning the number of bytes written and whether the end of
+    /// the deflate stream was reached.
+    fn inflate(&mut self, out: &mut [u8]) -> Result<(usize, bool), ZipError> {
+        let state = match self.state {
+            Some(ref mut state) => state,
+            None => unreachable!(),
+        };
+        loop {
+            if self.buf_pos == self.buf_len {
+                self.buf_len = self.reader.read(&mut self.buf)?;
+                self.buf_pos = 0;
+            }
+            let res = inflate(
+                state,
+                &self.buf[self.buf_pos..self.buf_len],
+                out,
+                MZFlush::None,
+            );
+            self.buf_pos += res.bytes_consumed;
+            match res.status {
+                Ok(MZStatus::StreamEnd) => return Ok((res.bytes_written, true)),
+                Ok(_) | Err(MZError::Buf) => {
+                    if res.bytes_written > 0 {
+                        return Ok((res.bytes_written, false));
+                    }
+                    // No progress with no more input means the data is truncated.
+                    if self.buf_len == 0 || res.bytes_consumed == 0 {
+                        return Err(ZipError::DecompressionFailed);
+                    }
+                }
+                Err(_) => return Err(ZipError::DecompressionFailed),
+            }
+        }
+    }
+
+    pub(super) fn read_data(&mut self, out: &mut [u8]) -> Result<usize, ZipError> {
+        if self.done || out.is_empty() {
+            return Ok(0);
+        }
+
+        let (n, end) = if self.state.is_some() {
+            self.inflate(out)?
+        } else {
+            let n = self.reader.read(out)?;
+            (n, n == 0 || self.reader.limit() == 0)
+        };
+        self.crc.update(&out[..n]);
+        self.written += n as u64;
+
+        let (size, crc32) = match self.expected {
+            Some(expected) => expected,
+            None => {
+                self.done = end;
+                return Ok(n);
+            }
+        };
+        if self.written > size {
+            return Err(ZipError::UnexpectedSize);
+        }
+        if end {
+            self.done = true;
+            if self.written != size {
+                return Err(ZipError::UnexpectedSize);
+            }
+            if self.crc.clone().finalize() != crc32 {
+                return Err(ZipError::CrcMismatch);
+            }
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use miniz_oxide::deflate::compress_to_vec;
+    use std::io::Cursor;
+
+    /// Build an archive by hand, with `prefix` prepended to it.
+    pub(crate) fn build_archive(
+        files: &[(&str, &[u8], bool)],
+        comment: &[u8],
+        zip64: bool,
+        prefix: &[u8],
+    ) -> Vec<u8> {
+        let mut out = prefix.to_vec();
+        let mut central_dir = Vec::new();
+        for &(name, data, deflate) in files {
+            let compressed = if deflate {
+                compress_to_vec(data, 6)
+            } else {
+                data.to_vec()
+            };
+            let method: u16 = if deflate { 8 } else { 0 };
+            let crc = crc32fast::hash(data);
+            let offset = (out.len() - prefix.len()) as u32;
+            let (time, date) = DateTime {
+                year: 2020,
+                month: 2,
+                day: 29,
+                hour: 12,
+                minute: 30,
+                second: 10,
+            }
+            .to_dos();
+
+            let mut fields = Vec::new();
+            fields.extend_from_slice(&20u16.to_le_bytes());
+            fields.extend_from_slice(&0u16.to_le_bytes());
+            fields.extend_from_slice(&method.to_le_bytes());
+            fields.extend_from_slice(&time.to_le_bytes());
+            fields.extend_from_slice(&date.to_le_bytes());
+            fields.extend_from_slice(&crc.to_le_bytes());
+            let (sizes, extra) = if zip64 {
+                let mut extra = Vec::new();
+                extra.extend_from_slice(&ZIP64_EXTRA_FIELD_ID.to_le_bytes());
+                extra.extend_from_slice(&24u16.to_le_bytes());
+                extra.extend_from_slice(&(data.len() as u64).to_le_bytes());
+                extra.extend_from_slice(&(compressed.len() as u64).to_le_bytes());
+                extra.extend_from_slice(&u64::from(offset).to_le_bytes());
+                ([0xffff_ffffu32; 2], extra)
+            } else {
+                ([compressed.len() as u32, data.len() as u32], Vec::new())
+            };
+            fields.extend_from_slice(&sizes[0].to_le_bytes());
+            fields.extend_from_slice(&sizes[1].to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+            out.extend_from_slice(&LOCAL_HEADER_SIGNATURE.to_le_bytes());
+            out.extend_from_slice(&fields);
+            out.extend_from_slice(&0u16.to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&compressed);
+
+            central_dir.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+            central_dir.extend_from_slice(&((3 << 8) | 20u16).to_le_bytes());
+            central_dir.extend_from_slice(&fields);
+            central_dir.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            central_dir.extend_from_slice(&0u16.to_le_bytes());
+            central_dir.extend_from_slice(&0u16.to_le_bytes());
+            central_dir.extend_from_slice(&0u16.to_le_bytes());
+            central_dir.extend_from_slice(&(0o100_644u32 << 16).to_le_bytes());
+            let offset = if zip64 { 0xffff_ffff } else { offset };
+            central_dir.extend_from_slice(&offset.to_le_bytes());
+            central_dir.extend_from_slice(name.as_bytes());
+            central_dir.extend_from_slice(&extra);
+        }
+
+        let central_dir_offset = (out.len() - prefix.len()) as u64;
+        out.extend_from_slice(&central_dir);
+        let count = files.len() as u64;
+        if zip64 {
+            let record_offset = (out.len() - prefix.len()) as u64;
+            out.extend_from_slice(&ZIP64_END_OF_CENTRAL_DIR_SIGNATURE.to_le_bytes());
+            out.extend_from_slice(&44u64.to_le_bytes());
+            out.extend_from_slice(&45u16.to_le_bytes());
+            out.extend_from_slice(&45u16.to_le_bytes());
+            out.extend_from_slice(&[0; 8]);
+            out.extend_from_slice(&count.to_le_bytes());
+            out.extend_from_slice(&count.to_le_bytes());
+            out.extend_from_slice(&(central_dir.len() as u64).to_le_bytes());
+            out.extend_from_slice(&central_dir_offset.to_le_bytes());
+            out.extend_from_slice(&ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE.to_le_bytes());
+            out.extend_from_slice(&0u32.to_le_bytes());
+            out.extend_from_slice(&record_offset.to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+        }
+        out.extend_from_slice(&END_OF_CENTRAL_DIR_SIGNATURE.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        let (count, size, offset) = if zip64 {
+            (0xffff, 0xffff_ffff, 0xffff_ffff)
+        } else {
+            (
+                count as u16,
+                central_dir.len() as u32,
+                central_dir_offset as u32,
+            )
+        };
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+        out.extend_from_slice(comment);
+        out
+    }
+
+    fn test_files() -> Vec<(&'static str, &'static [u8], bool)> {
+        vec![
+            ("dir/", &b""[..], false),
+            ("dir/stored.txt", &b"stored contents"[..], false),
+            (
+                "numbers.txt",
+                &include_bytes!("../../miniz_oxide/tests/test_data/numbers.txt")[..],
+                true,
+            ),
+            ("empty", &b""[..], true),
+        ]
+    }
+
+    #[test]
+    fn read_archives() {
+        let files = test_files();
+        for &zip64 in &[false, true] {
+            for &prefix in &[&b""[..], &b"prepended data"[..]] {
+                let data = build_archive(&files, b"archive comment", zip64, prefix);
+                let mut archive = ZipArchive::new(Cursor::new(&data)).unwrap();
+                assert_eq!(archive.len(), files.len());
+                assert_eq!(archive.comment(), b"archive comment");
+                assert_eq!(archive.is_zip64(), zip64);
+                assert_eq!(archive.offset(), prefix.len() as u64);
+
+                for (i, &(name, contents, deflate)) in files.iter().enumerate() {
+                    let entry = archive.entry(i).unwrap().clone();
+                    assert_eq!(entry.index(), i);
+                    assert_eq!(entry.name(), name);
+                    assert_eq!(entry.size(), contents.len() as u64);
+                    assert_eq!(entry.is_dir(), name.ends_with('/'));
+                    assert!(entry.is_supported());
+                    assert_eq!(entry.unix_mode(), Some(0o100_644));
+                    assert_eq!(entry.last_modified().year, 2020);
+                    assert_eq!(entry.last_modified().second, 10);
+                    let method = if deflate {
+                        CompressionMethod::Deflated
+                    } else {
+                        CompressionMethod::Stored
+                    };
+                    assert_eq!(entry.method(), method);
+                    assert_eq!(archive.index_of(name), Some(i));
+                    assert!(archive.read_to_vec(i).unwrap() == contents);
+
+                    // Read in small pieces.
+                    let mut file = archive.by_name(name).unwrap();
+                    let mut out = Vec::new();
+                    let mut piece = [0; 7];
+                    loop {
+                        let n = file.read(&mut piece).unwrap();
+                        if n == 0 {
+                            break;
+                        }
+                        out.extend_from_slice(&piece[..n]);
+                    }
+                    assert!(out == contents);
+                }
+                assert!(archive.index_of("missing").is_none());
+                assert!(archive.by_name("missing").is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_archives() {
+        let files = test_files();
+        let data = build_archive(&files, b"", false, b"");
+
+        assert!(match ZipArchive::new(Cursor::new(&data[..100])) {
+            Err(ZipError::NotAnArchive) => true,
+            _ => false,
+        });
+
+        // Flip a bit in the stored entry's data.
+        let mut corrupted = data.clone();
+        let pos = corrupted.windows(6).position(|w| w == b"stored").unwrap();
+        corrupted[pos + 16] ^= 1;
+        let mut archive = ZipArchive::new(Cursor::new(&corrupted)).unwrap();
+        assert!(match archive.read_to_vec(1) {
+            Err(ZipError::CrcMismatch) => true,
+            _ => false,
+        });
+        assert!(archive.read_to_vec(2).is_ok());
+
+        // Change the recorded size of the deflated entry.
+        let mut archive = ZipArchive::new(Cursor::new(&data)).unwrap();
+        archive.entries[2].uncompressed_size -= 1;
+        assert!(match archive.read_to_vec(2) {
+            Err(ZipError::UnexpectedSize) => true,
+            _ => false,
+        });
+        archive.entries[2].method = CompressionMethod::Unsupported(14);
+        assert!(match archive.read_to_vec(2) {
+            Err(ZipError::UnsupportedMethod(14)) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn crafted_sizes_and_offsets() {
+        let files = [("file", &b"contents"[..], false)];
+        let data = build_archive(&files, b"", true, b"");
+        let extra_id = [0x01, 0x00, 24, 0x00];
+        let extra = data.windows(4).position(|w| w == extra_id).unwrap() + 4;
+
+        // Huge sizes in the zip64 extra field must not be trusted for allocations.
+        for &size in &[1u64 << 40, u64::max_value()] {
+            let mut crafted = data.clone();
+            crafted[extra..extra + 8].copy_from_slice(&size.to_le_bytes());
+            crafted[extra + 8..extra + 16].copy_from_slice(&size.to_le_bytes());
+            let mut archive = ZipArchive::new(Cursor::new(&crafted)).unwrap();
+            assert_eq!(archive.entry(0).unwrap().compressed_size(), size);
+            assert!(match archive.read_to_vec(0) {
+                Err(ZipError::InvalidArchive(_)) => true,
+                _ => false,
+            });
+            assert!(archive.by_index_raw(0).is_err());
+        }
+
+        // So must a huge uncompressed size with a valid compressed size.
+        let mut crafted = data.clone();
+        crafted[extra..extra + 8].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        let mut archive = ZipArchive::new(Cursor::new(&crafted)).unwrap();
+        assert!(match archive.read_to_vec(0) {
+            Err(ZipError::UnexpectedSize) => true,
+            _ => false,
+        });
+
+        // And offsets close to the end of the address space.
+        let mut crafted = data.clone();
+        crafted[extra + 16..extra + 24].copy_from_slice(&u64::max_value().to_le_bytes());
+        let mut archive = ZipArchive::new(Cursor::new(&crafted)).unwrap();
+        assert!(archive.read_to_vec(0).is_err());
+
+        // The record offset in the zip64 locator is only a hint.
+        let mut crafted = data;
+        let locator_signature = ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE.to_le_bytes();
+        let locator = crafted
+            .windows(4)
+            .position(|w| w == locator_signature)
+            .unwrap();
+        crafted[locator + 8..locator + 16].copy_from_slice(&(u64::max_value() - 10).to_le_bytes());
+        let mut archive = ZipArchive::new(Cursor::new(&crafted)).unwrap();
+        assert!(archive.read_to_vec(0).unwrap() == b"contents");
+    }
+}
@@ -0,0 +1,286 @@
+//! Writing ZIP archives to outputs that can't seek.
+
+use std::io::{self, Read, Write};
+
+use miniz_oxide::deflate::core::CompressorOxide;
+
+use super::write::{
+    central_directory, data_descriptor, local_header, start_entry, write_deflated, write_stored,
+    ZIP64_LIMIT,
+};
+use super::*;
+
+/// Keeps track of the number of bytes written, which is the current offset in the archive.
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes a ZIP archive to `W` without seeking, for example to a pipe or a network
+/// connection.
+///
+/// Entries whose compressed size isn't known before writing them have the data descriptor
+/// flag set, with the CRC-32 and sizes written in a data descriptor following the data
+/// instead of in the local header. Data is compressed as it is read, so at most one entry's
+/// compressor state and input chunk are held in memory, apart from the central directory.
+///
+/// ```
+/// use miniz_oxide_c_api::zip::{FileOptions, ZipStreamWriter};
+///
+/// let mut writer = ZipStreamWriter::new(Vec::new());
+/// writer
+///     .add_reader("hello.txt", &b"Hello, world!"[..], &FileOptions::new())
+///     .unwrap();
+/// let archive = writer.finish().unwrap();
+/// assert_eq!(&archive[..4], b"PK\x03\x04");
+/// ```
+pub struct ZipStreamWriter<W: Write> {
+    writer: CountingWriter<W>,
+    entries: Vec<ZipEntry>,
+    comment: Vec<u8>,
+    /// Compressor kept around to reuse its buffers.
+    compressor: Option<CompressorOxide>,
+}
+
+impl<W: Write> ZipStreamWriter<W> {
+    /// Start a new archive. Offsets in the archive are counted from the first byte written to
+    /// `writer`.
+    pub fn new(writer: W) -> ZipStreamWriter<W> {
+        ZipStreamWriter {
+            writer: CountingWriter {
+                inner: writer,
+                count: 0,
+            },
+            entries: Vec::new(),
+            comment: Vec::new(),
+            compressor: None,
+        }
+    }
+
+    /// Set the archive's comment, which can be at most 65535 bytes long.
+    pub fn set_comment(&mut self, comment: &[u8]) -> Result<(), ZipError> {
+        if comment.len() > 0xffff {
+            return Err(ZipError::InvalidName);
+        }
+        self.comment = comment.to_vec();
+        Ok(())
+    }
+
+    /// Add an entry with the contents of `data`.
+    ///
+    /// Stored entries don't need a data descriptor, as their size is known in advance.
+    pub fn add_mem(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        options: &FileOptions,
+    ) -> Result<(), ZipError> {
+        if options.level != 0 {
+            return self.add_streamed(name, data, options);
+        }
+        self.add_known(name, data, options, false)
+    }
+
+    /// Add an entry with the contents read from `reader` until the end.
+    pub fn add_reader<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        options: &FileOptions,
+    ) -> Result<(), ZipError> {
+        self.add_streamed(name, reader, options)
+    }
+
+    /// Add a directory entry. A slash is appended to the name if it doesn't end with one.
+    pub fn add_directory(&mut self, name: &str, options: &FileOptions) -> Result<(), ZipError> {
+        let mut name = name.to_string();
+        if !name.ends_with('/') {
+            name.push('/');
+        }
+        self.add_known(&name, &[], options, true)
+    }
+
+    /// Add a stored entry, writing the CRC and sizes in the local header.
+    fn add_known(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        options: &FileOptions,
+        directory: bool,
+    ) -> Result<(), ZipError> {
+        let size = data.len() as u64;
+        let zip64 = options.large_file || size >= ZIP64_LIMIT;
+        let mut entry = start_entry(
+            self.entries.len(),
+            name.as_bytes(),
+            options,
+            self.writer.count,
+            zip64,
+            directory,
+            false,
+        )?;
+        entry.crc32 = crc32fast::hash(data);
+        entry.uncompressed_size = size;
+        entry.compressed_size = size;
+
+        self.writer.write_all(&local_header(&entry, zip64))?;
+        self.writer.write_all(data)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Add an entry followed by a data descriptor.
+    fn add_streamed<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        options: &FileOptions,
+    ) -> Result<(), ZipError> {
+        let zip64 = options.large_file;
+        let mut entry = start_entry(
+            self.entries.len(),
+            name.as_bytes(),
+            options,
+            self.writer.count,
+            zip64,
+            false,
+            true,
+        )?;
+        self.writer.write_all(&local_header(&entry, zip64))?;
+
+        let (crc32, uncompressed_size, compressed_size) = match entry.method {
+            CompressionMethod::Deflated => write_deflated(
+                &mut self.compressor,
+                reader,
+                &mut self.writer,
+                options.level,
+            )?,
+            _ => write_stored(reader, &mut self.writer)?,
+        };
+        entry.crc32 = crc32;
+        entry.uncompressed_size = uncompressed_size;
+        entry.compressed_size = compressed_size;
+
+        self.writer.write_all(&data_descriptor(&entry, zip64))?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Write the central directory and end of central directory record, completing the
+    /// archive, and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, ZipError> {
+        let out = central_directory(&mut self.entries, &self.comment, self.writer.count)?;
+        self.writer.write_all(&out)?;
+        self.writer.flush()?;
+        Ok(self.writer.inner)
+    }
+
+    /// Metadata of the entries written so far.
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Number of bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.writer.count
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer.inner
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A writer that only accepts a few bytes at a time.
+    struct Trickle(Vec<u8>);
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = cmp::min(buf.len(), 7);
+            self.0.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stream_and_read() {
+        let numbers = include_bytes!("../../miniz_oxide/tests/test_data/numbers.txt");
+        let options = FileOptions::new().last_modified(DateTime::default());
+        let mut writer = ZipStreamWriter::new(Trickle(Vec::new()));
+        writer.add_directory("dir", &options).unwrap();
+        writer
+            .add_reader("dir/numbers.txt", &numbers[..], &options.clone().level(1))
+            .unwrap();
+        writer
+            .add_reader("stored", &b"streamed stored"[..], &options.clone().level(0))
+            .unwrap();
+        writer
+            .add_mem("known", b"known size", &options.clone().level(0))
+            .unwrap();
+        writer
+            .add_mem(
+                "large",
+                b"pretend this is large",
+                &options.clone().large_file(true),
+            )
+            .unwrap();
+        writer.set_comment(b"streamed").unwrap();
+        let written = writer.bytes_written();
+        let data = writer.finish().unwrap().0;
+        assert!(data.len() as u64 > written);
+
+        let mut archive = ZipArchive::new(Cursor::new(&data)).unwrap();
+        assert_eq!(archive.comment(), b"streamed");
+        let expected: [(&str, &[u8], bool); 5] = [
+            ("dir/", b"", false),
+            ("dir/numbers.txt", &numbers[..], true),
+            ("stored", b"streamed stored", true),
+            ("known", b"known size", false),
+            ("large", b"pretend this is large", true),
+        ];
+        assert_eq!(archive.len(), expected.len());
+        for (i, &(name, contents, descriptor)) in expected.iter().enumerate() {
+            let entry = archive.entry(i).unwrap().clone();
+            assert_eq!(entry.name(), name);
+            assert_eq!(entry.flags() & FLAG_DATA_DESCRIPTOR != 0, descriptor);
+            assert!(archive.read_to_vec(i).unwrap() == contents);
+
+            // The data descriptor follows the data, in the zip64 form for the large entry.
+            if descriptor {
+                let header_len =
+                    LOCAL_HEADER_SIZE + name.len() + if name == "large" { 20 } else { 0 };
+                let end =
+                    (entry.local_header_offset() + entry.compressed_size()) as usize + header_len;
+                assert_eq!(read_u32(&data, end), DATA_DESCRIPTOR_SIGNATURE);
+                assert_eq!(read_u32(&data, end + 4), entry.crc32());
+                if name == "large" {
+                    assert_eq!(read_u64(&data, end + 8), entry.compressed_size());
+                    assert_eq!(read_u64(&data, end + 16), entry.size());
+                } else {
+                    assert_eq!(u64::from(read_u32(&data, end + 8)), entry.compressed_size());
+                    assert_eq!(u64::from(read_u32(&data, end + 12)), entry.size());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,856 @@
+//! Writing ZIP archives.
+
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str;
+
+use miniz_oxide::deflate::core::{compress_to_output, CompressionParams, CompressorOxide};
+use miniz_oxide::deflate::core::{TDEFLFlush, TDEFLStatus};
+use miniz_oxide::DataFormat;
+
+use super::*;
+
+/// Size of the chunks input is read in.
+pub(super) const CHUNK_SIZE: usize = 64 * 1024;
+/// Sizes and offsets at least this large are stored in the zip64 extra field.
+pub(super) const ZIP64_LIMIT: u64 = 0xffff_ffff;
+/// MS-DOS attribute marking a directory.
+const DOS_DIRECTORY_ATTRIBUTE: u32 = 0x10;
+
+/// Settings for an entry added to a [`ZipWriter`].
+///
+/// By default, entries are compressed at level 6, use the current time and have no
+/// attributes or comment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileOptions {
+    pub(super) level: u8,
+    pub(super) last_modified: DateTime,
+    pub(super) external_attributes: u32,
+    pub(super) unix: bool,
+    pub(super) comment: Vec<u8>,
+    pub(super) large_file: bool,
+}
+
+impl FileOptions {
+    /// Create options using the default settings.
+    pub fn new() -> FileOptions {
+        FileOptions {
+            level: 6,
+            last_modified: DateTime::now(),
+            external_attributes: 0,
+            unix: false,
+            comment: Vec::new(),
+            large_file: false,
+        }
+    }
+
+    /// Set the compression level (0-10). Level 0 stores the data without compression.
+    pub fn level(mut self, level: u8) -> FileOptions {
+        self.level = level;
+        self
+    }
+
+    /// Set the modification time.
+    pub fn last_modified(mut self, last_modified: DateTime) -> FileOptions {
+        self.last_modified = last_modified;
+        self
+    }
+
+    /// Set the host system dependent external attributes, for example MS-DOS attributes.
+    pub fn external_attributes(mut self, external_attributes: u32) -> FileOptions {
+        self.external_attributes = external_attributes;
+        self.unix = false;
+        self
+    }
+
+    /// Set the unix file mode, marking the entry as made on a unix system.
+    pub fn unix_mode(mut self, mode: u32) -> FileOptions {
+        self.external_attributes = mode << 16;
+        self.unix = true;
+        self
+    }
+
+    /// Set the entry's comment, which can be at most 65535 bytes long.
+    pub fn comment(mut self, comment: &[u8]) -> FileOptions {
+        self.comment = comment.to_vec();
+        self
+    }
+
+    /// Write a zip64 local header, which is needed for entries of 4 GiB or more whose size is
+    /// not known in advance.
+    ///
+    /// This is done automatically when adding large entries from memory.
+    pub fn large_file(mut self, large_file: bool) -> FileOptions {
+        self.large_file = large_file;
+        self
+    }
+}
+
+impl Default for FileOptions {
+    fn default() -> FileOptions {
+        FileOptions::new()
+    }
+}
+
+/// Writes a ZIP archive to `W`.
+///
+/// Entries are written as they are added, the central directory is written by
+/// [`ZipWriter::finish`]. Adding more entries after finishing overwrites the central
+/// directory, which is written again by the next call to `finish`.
+///
+/// ```
+/// use std::io::Cursor;
+/// use miniz_oxide_c_api::zip::{FileOptions, ZipArchive, ZipWriter};
+///
+/// let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+/// writer
+///     .add_mem("hello.txt", b"Hello, world!", &FileOptions::new())
+///     .unwrap();
+/// writer.finish().unwrap();
+///
+/// let mut archive = ZipArchive::new(writer.into_inner()).unwrap();
+/// assert_eq!(archive.read_to_vec(0).unwrap(), b"Hello, world!");
+/// ```
+pub struct ZipWriter<W> {
+    writer: W,
+    /// Position of the start of the archive in `writer`.
+    offset: u64,
+    /// End of the data of the last entry, relative to the start of the archive.
+    data_end: u64,
+    entries: Vec<ZipEntry>,
+    comment: Vec<u8>,
+    /// Compressor kept around to reuse its buffers.
+    compressor: Option<CompressorOxide>,
+}
+
+impl<W: Write + Seek> ZipWriter<W> {
+    /// Start a new archive at the current position of `writer`.
+    pub fn new(mut writer: W) -> Result<ZipWriter<W>, ZipError> {
+        let offset = writer.seek(SeekFrom::Current(0))?;
+        Ok(ZipWriter {
+            writer,
+            offset,
+            data_end: 0,
+            entries: Vec::new(),
+            comment: Vec::new(),
+            compressor: None,
+        })
+    }
+
+    /// Set the archive's comment, which can be at most 65535 bytes long.
+    pub fn set_comment(&mut self, comment: &[u8]) -> Result<(), ZipError> {
+        if comment.len() > 0xffff {
+            return Err(ZipError::InvalidName);
+        }
+        self.comment = comment.to_vec();
+        Ok(())
+    }
+
+    /// Add an entry with the contents of `data`.
+    pub fn add_mem(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        options: &FileOptions,
+    ) -> Result<(), ZipError> {
+        let large = options.large_file || data.len() as u64 >= ZIP64_LIMIT;
+        self.add_entry(name, data, options, large, false)
+    }
+
+    /// Add an entry with the contents read from `reader` until the end.
+    pub fn add_reader<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        options: &FileOptions,
+    ) -> Result<(), ZipError> {
+        self.add_entry(name, reader, options, options.large_file, false)
+    }
+
+    /// Add a directory entry. A slash is appended to the name if it doesn't end with one.
+    pub fn add_directory(&mut self, name: &str, options: &FileOptions) -> Result<(), ZipError> {
+        let mut name = name.to_string();
+        if !name.ends_with('/') {
+            name.push('/');
+        }
+        self.add_entry(&name, io::empty(), options, false, true)
+    }
+
+    fn add_entry<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        options: &FileOptions,
+        zip64: bool,
+        directory: bool,
+    ) -> Result<(), ZipError> {
+        let header_offset = self.data_end;
+        let mut entry = start_entry(
+            self.entries.len(),
+            name.as_bytes(),
+            options,
+            header_offset,
+            zip64,
+            directory,
+            false,
+        )?;
+
+        // The CRC and sizes are filled in once the data is written.
+        let header_pos = self.offset + header_offset;
+        let header = local_header(&entry, zip64);
+        self.writer.seek(SeekFrom::Start(header_pos))?;
+        self.writer.write_all(&header)?;
+
+        let (crc32, uncompressed_size, compressed_size) = match entry.method {
+            CompressionMethod::Deflated => write_deflated(
+                &mut self.compressor,
+                reader,
+                &mut self.writer,
+                options.level,
+            )?,
+            _ => write_stored(reader, &mut self.writer)?,
+        };
+        if !zip64 && (uncompressed_size >= ZIP64_LIMIT || compressed_size >= ZIP64_LIMIT) {
+            return Err(ZipError::FileTooLarge);
+        }
+        entry.crc32 = crc32;
+        entry.uncompressed_size = uncompressed_size;
+        entry.compressed_size = compressed_size;
+
+        self.writer.seek(SeekFrom::Start(header_pos))?;
+        self.writer.write_all(&local_header(&entry, zip64))?;
+        self.entries.push(entry);
+        self.data_end = header_offset + header.len() as u64 + compressed_size;
+        Ok(())
+    }
+
+    /// Write the central directory and end of central directory record, completing the
+    /// archive.
+    ///
+    /// Returns the position of the end of the archive in the writer. If the writer is a file
+    /// that was appended to, it can be truncated to this length in case the old archive was
+    /// longer.
+    pub fn finish(&mut self) -> Result<u64, ZipError> {
+        let out = central_directory(&mut self.entries, &self.comment, self.data_end)?;
+        let central_dir_pos = self.offset + self.data_end;
+        self.writer.seek(SeekFrom::Start(central_dir_pos))?;
+        self.writer.write_all(&out)?;
+        self.writer.flush()?;
+        Ok(central_dir_pos + out.len() as u64)
+    }
+
+    /// Metadata of the entries added so far.
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Get the underlying writer. This does not finish the archive.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Read + Write + Seek> ZipWriter<W> {
+    /// Open the existing archive in `writer` for adding entries.
+    ///
+    /// New entries are written over the old central directory, so the archive is left
+    /// invalid until [`ZipWriter::finish`] is called.
+    pub fn new_append(mut writer: W) -> Result<ZipWriter<W>, ZipError> {
+        let archive = ZipArchive::new(&mut writer)?;
+        let offset = archive.offset();
+        let data_end = archive.central_dir_offset();
+        let (entries, comment) = archive.into_parts();
+        Ok(ZipWriter {
+            writer,
+            offset,
+            data_end,
+            entries,
+            comment,
+            compressor: None,
+        })
+    }
+}
+
+/// Add an entry to the archive at `path` in place, creating the archive if it doesn't exist
+/// or is empty.
+///
+/// If adding the entry fails, the archive is restored with the entries it had before, unless
+/// the file can't be written to anymore.
+pub fn add_to_archive_file_in_place<P: AsRef<Path>>(
+    path: P,
+    name: &str,
+    data: &[u8],
+    options: &FileOptions,
+) -> Result<(), ZipError> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let created = file.metadata()?.len() == 0;
+    let mut writer = if created {
+        ZipWriter::new(file)?
+    } else {
+        ZipWriter::new_append(file)?
+    };
+    let result = add_and_finish(&mut writer, name, data, options);
+    let file = writer.into_inner();
+    match result {
+        Ok(end) => file.set_len(end)?,
+        Err((err, restored_end)) => {
+            // Leave a file that was empty as it was, otherwise drop the data written after the
+            // restored archive.
+            let end = if created { Some(0) } else { restored_end };
+            if let Some(end) = end {
+                let _ = file.set_len(end);
+            }
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Add an entry with the contents of `data` to `writer` and finish the archive, returning its
+/// end.
+///
+/// If adding the entry fails, the archive is finished with the entries it had before instead,
+/// and the error is returned along with the end of that archive if finishing it succeeded.
+fn add_and_finish<W: Write + Seek>(
+    writer: &mut ZipWriter<W>,
+    name: &str,
+    data: &[u8],
+    options: &FileOptions,
+) -> Result<u64, (ZipError, Option<u64>)> {
+    if let Err(err) = writer.add_mem(name, data, options) {
+        // A failed entry is not added, and the central directory is written where it started.
+        return Err((err, writer.finish().ok()));
+    }
+    writer.finish().map_err(|err| (err, None))
+}
+
+/// Create the entry for `name`, starting at `header_offset`, with the CRC and sizes left at 0.
+///
+/// If `streamed` is true, the CRC and sizes are written in a data descriptor after the data.
+pub(super) fn start_entry(
+    index: usize,
+    name: &[u8],
+    options: &FileOptions,
+    header_offset: u64,
+    zip64: bool,
+    directory: bool,
+    streamed: bool,
+) -> Result<ZipEntry, ZipError> {
+    if name.is_empty() || name.len() > 0xffff || options.comment.len() > 0xffff {
+        return Err(ZipError::InvalidName);
+    }
+
+    let method = if options.level == 0 || directory {
+        CompressionMethod::Stored
+    } else {
+        CompressionMethod::Deflated
+    };
+    let version_needed = if zip64 {
+        VERSION_ZIP64
+    } else if method == CompressionMethod::Stored && !directory && !streamed {
+        10
+    } else {
+        VERSION_DEFLATE
+    };
+    let mut flags = if name.is_ascii() || str::from_utf8(name).is_err() {
+        0
+    } else {
+        FLAG_UTF8
+    };
+    if streamed {
+        flags |= FLAG_DATA_DESCRIPTOR;
+    }
+    let external_attributes = if directory {
+        options.external_attributes | DOS_DIRECTORY_ATTRIBUTE
+    } else {
+        options.external_attributes
+    };
+    let version_made_by = if options.unix {
+        (3 << 8) | VERSION_ZIP64
+    } else {
+        VERSION_ZIP64
+    };
+
+    Ok(ZipEntry {
+        index,
+        central_dir_offset: 0,
+        version_made_by,
+        version_needed,
+        flags,
+        method,
+        last_modified: options.last_modified,
+        crc32: 0,
+        compressed_size: 0,
+        uncompressed_size: 0,
+        internal_attributes: 0,
+        external_attributes,
+        local_header_offset: header_offset,
+        name: name.to_vec(),
+        extra: Vec::new(),
+        comment: options.comment.clone(),
+    })
+}
+
+/// Build the local header for `entry`. With `zip64`, the sizes are stored in a zip64 extra
+/// field, which makes the header 20 bytes longer.
+pub(super) fn local_header(entry: &ZipEntry, zip64: bool) -> Vec<u8> {
+    let (time, date) = entry.last_modified.to_dos();
+    let mut header = Vec::with_capacity(LOCAL_HEADER_SIZE + entry.name.len() + 20);
+    put_u32(&mut header, LOCAL_HEADER_SIGNATURE);
+    put_u16(&mut header, entry.version_needed);
+    put_u16(&mut header, entry.flags);
+    put_u16(&mut header, entry.method.to_u16());
+    put_u16(&mut header, time);
+    put_u16(&mut header, date);
+    put_u32(&mut header, entry.crc32);
+    if zip64 {
+        put_u32(&mut header, ZIP64_LIMIT as u32);
+        put_u32(&mut header, ZIP64_LIMIT as u32);
+    } else {
+        put_u32(&mut header, entry.compressed_size as u32);
+        put_u32(&mut header, entry.uncompressed_size as u32);
+    }
+    put_u16(&mut header, entry.name.len() as u16);
+    put_u16(&mut header, if zip64 { 20 } else { 0 });
+    header.extend_from_slice(&entry.name);
+    if zip64 {
+        put_u16(&mut header, ZIP64_EXTRA_FIELD_ID);
+        put_u16(&mut header, 16);
+        put_u64(&mut header, entry.uncompressed_size);
+        put_u64(&mut header, entry.compressed_size);
+    }
+    header
+}
+
+/// Build the data descriptor following the data of a streamed entry.
+///
+/// The local header can't be fixed up afterwards, so the zip64 form of the descriptor is used
+/// for large entries even if the header isn't a zip64 one. Readers take the sizes from the
+/// central directory in that case.
+pub(super) fn data_descriptor(entry: &ZipEntry, zip64: bool) -> Vec<u8> {
+    let mut descriptor = Vec::with_capacity(24);
+    put_u32(&mut descriptor, DATA_DESCRIPTOR_SIGNATURE);
+    put_u32(&mut descriptor, entry.crc32);
+    if zip64 || entry.uncompressed_size >= ZIP64_LIMIT || entry.compressed_size >= ZIP64_LIMIT {
+        put_u64(&mut descriptor, entry.compressed_size);
+        put_u64(&mut descriptor, entry.uncompressed_size);
+    } else {
+        put_u32(&mut descriptor, entry.compressed_size as u32);
+        put_u32(&mut descriptor, entry.uncompressed_size as u32);
+    }
+    descriptor
+}
+
+/// Copy everything from `reader` to `writer`, returning the CRC-32 of the data and its size
+/// twice.
+pub(super) fn write_stored<R: Read, W: Write>(
+    mut reader: R,
+    writer: &mut W,
+) -> Result<(u32, u64, u64), ZipError> {
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut crc = crc32fast::Hasher::new();
+    let mut size = 0;
+    loop {
+        let n = read_chunk(&mut reader, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        crc.update(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+        size += n as u64;
+    }
+    Ok((crc.finalize(), size, size))
+}
+
+/// Compress everything from `reader` to `writer`, returning the CRC-32 of the data, its size
+/// and the compressed size.
+///
+/// The compressor in `compressor` is reused if there is one, and is left there afterwards.
+pub(super) fn write_deflated<R: Read, W: Write>(
+    compressor: &mut Option<CompressorOxide>,
+    mut reader: R,
+    writer: &mut W,
+    level: u8,
+) -> Result<(u32, u64, u64), ZipError> {
+    let mut params = CompressionParams::from_level(level);
+    params.format = DataFormat::Raw;
+    let compressor = match *compressor {
+        Some(ref mut compressor) => {
+            compressor.reset();
+            compressor
+        }
+        None => compressor.get_or_insert_with(|| CompressorOxide::new(params.to_flags())),
+    };
+    compressor.set_params(params);
+
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut crc = crc32fast::Hasher::new();
+    let mut size = 0;
+    let mut compressed_size = 0;
+    let mut write_error = None;
+    loop {
+        let n = read_chunk(&mut reader, &mut buf)?;
+        crc.update(&buf[..n]);
+        size += n as u64;
+        let flush = if n == 0 {
+            TDEFLFlush::Finish
+        } else {
+            TDEFLFlush::None
+        };
+
+        let (status, _) = compress_to_output(compressor, &buf[..n], flush, |out| {
+            match writer.write_all(out) {
+                Ok(()) => {
+                    compressed_size += out.len() as u64;
+                    true
+                }
+                Err(err) => {
+                    write_error = Some(err);
+                    false
+                }
+            }
+        });
+        match status {
+            TDEFLStatus::Okay if n != 0 => (),
+            TDEFLStatus::Done if n == 0 => return Ok((crc.finalize(), size, compressed_size)),
+            _ => {
+                return Err(write_error
+                    .take()
+                    .map_or(ZipError::CompressionFailed, ZipError::Io))
+            }
+        }
+    }
+}
+
+/// Build the central directory and end of central directory records for `entries`, filling
+/// in their offsets in the central directory, for a central directory at `central_dir_offset`.
+pub(super) fn central_directory(
+    entries: &mut [ZipEntry],
+    comment: &[u8],
+    central_dir_offset: u64,
+) -> Result<Vec<u8>, ZipError> {
+    let mut out = Vec::new();
+    for entry in entries.iter_mut() {
+        entry.central_dir_offset = out.len() as u64;
+        write_central_header(entry, &mut out)?;
+    }
+    let central_dir_size = out.len() as u64;
+    let num_entries = entries.len() as u64;
+
+    if num_entries >= 0xffff || central_dir_offset >= ZIP64_LIMIT || central_dir_size >= ZIP64_LIMIT
+    {
+        let record_offset = central_dir_offset + central_dir_size;
+        put_u32(&mut out, ZIP64_END_OF_CENTRAL_DIR_SIGNATURE);
+        put_u64(&mut out, (ZIP64_END_OF_CENTRAL_DIR_SIZE - 12) as u64);
+        put_u16(&mut out, VERSION_ZIP64);
+        put_u16(&mut out, VERSION_ZIP64);
+        put_u32(&mut out, 0);
+        put_u32(&mut out, 0);
+        put_u64(&mut out, num_entries);
+        put_u64(&mut out, num_entries);
+        put_u64(&mut out, central_dir_size);
+        put_u64(&mut out, central_dir_offset);
+
+        put_u32(&mut out, ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE);
+        put_u32(&mut out, 0);
+        put_u64(&mut out, record_offset);
+        put_u32(&mut out, 1);
+    }
+
+    put_u32(&mut out, END_OF_CENTRAL_DIR_SIGNATURE);
+    put_u16(&mut out, 0);
+    put_u16(&mut out, 0);
+    put_u16(&mut out, cmp::min(num_entries, 0xffff) as u16);
+    put_u16(&mut out, cmp::min(num_entries, 0xffff) as u16);
+    put_u32(&mut out, cmp::min(central_dir_size, ZIP64_LIMIT) as u32);
+    put_u32(&mut out, cmp::min(central_dir_offset, ZIP64_LIMIT) as u32);
+    put_u16(&mut out, comment.len() as u16);
+    out.extend_from_slice(comment);
+    Ok(out)
+}
+
+/// Append the central directory header for `entry` to `out`.
+pub(super) fn write_central_header(entry: &ZipEntry, out: &mut Vec<u8>) -> Result<(), ZipError> {
+    // Rebuild the zip64 extra field, an entry from an appended archive may have one.
+    let mut zip64_extra = Vec::new();
+    for &value in &[
+        entry.uncompressed_size,
+        entry.compressed_size,
+        entry.local_header_offset,
+    ] {
+        if value >= ZIP64_LIMIT {
+            put_u64(&mut zip64_extra, value);
+        }
+    }
+    let mut extra = Vec::with_capacity(entry.extra.len() + zip64_extra.len() + 4);
+    if !zip64_extra.is_empty() {
+        put_u16(&mut extra, ZIP64_EXTRA_FIELD_ID);
+        put_u16(&mut extra, zip64_extra.len() as u16);
+        extra.extend_from_slice(&zip64_extra);
+    }
+    let mut pos = 0;
+    while pos + 4 <= entry.extra.len() {
+        let id = read_u16(&entry.extra, pos);
+        let end = cmp::min(
+            pos + 4 + usize::from(read_u16(&entry.extra, pos + 2)),
+            entry.extra.len(),
+        );
+        if id != ZIP64_EXTRA_FIELD_ID {
+            extra.extend_from_slice(&entry.extra[pos..end]);
+        }
+        pos = end;
+    }
+    if extra.len() > 0xffff {
+        return Err(ZipError::InvalidArchive("extra field too long"));
+    }
+
+    let (time, date) = entry.last_modified.to_dos();
+    let version_needed = if zip64_extra.is_empty() {
+        entry.version_needed
+    } else {
+        cmp::max(entry.version_needed, VERSION_ZIP64)
+    };
+    put_u32(out, CENTRAL_HEADER_SIGNATURE);
+    put_u16(out, entry.version_made_by);
+    put_u16(out, version_needed);
+    put_u16(out, entry.flags);
+    put_u16(out, entry.method.to_u16());
+    put_u16(out, time);
+    put_u16(out, date);
+    put_u32(out, entry.crc32);
+    put_u32(out, cmp::min(entry.compressed_size, ZIP64_LIMIT) as u32);
+    put_u32(out, cmp::min(entry.uncompressed_size, ZIP64_LIMIT) as u32);
+    put_u16(out, entry.name.len() as u16);
+    put_u16(out, extra.len() as u16);
+    put_u16(out, entry.comment.len() as u16);
+    // Disk number.
+    put_u16(out, 0);
+    put_u16(out, entry.internal_attributes);
+    put_u32(out, entry.external_attributes);
+    put_u32(out, cmp::min(entry.local_header_offset, ZIP64_LIMIT) as u32);
+    out.extend_from_slice(&entry.name);
+    out.extend_from_slice(&extra);
+    out.extend_from_slice(&entry.comment);
+    Ok(())
+}
+
+/// Read as much as is available up to the size of `buf`, retrying if interrupted.
+pub(super) fn read_chunk<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buf) {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            result => return result,
+        }
+    }
+}
+
+pub(super) fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(super) fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(super) fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn options() -> FileOptions {
+        FileOptions::new().last_modified(DateTime {
+            year: 2021,
+            month: 6,
+            day: 15,
+            hour: 8,
+            minute: 0,
+            second: 42,
+        })
+    }
+
+    #[test]
+    fn write_and_read() {
+        let numbers = include_bytes!("../../miniz_oxide/tests/test_data/numbers.txt");
+        let mut prefixed = Cursor::new(b"prefix".to_vec());
+        prefixed.set_position(6);
+        let mut writer = ZipWriter::new(prefixed).unwrap();
+        writer.add_directory("dir", &options()).unwrap();
+        writer
+            .add_mem("dir/stored.txt", b"stored", &options().level(0))
+            .unwrap();
+        writer
+            .add_reader(
+                "numbers.txt",
+                &numbers[..],
+                &options().level(9).unix_mode(0o100_600).comment(b"numbers"),
+            )
+            .unwrap();
+        writer
+            .add_mem("größe", b"", &options().large_file(true))
+            .unwrap();
+        assert!(match writer.add_mem("", b"", &options()) {
+            Err(ZipError::InvalidName) => true,
+            _ => false,
+        });
+        writer.set_comment(b"comment").unwrap();
+        let end = writer.finish().unwrap();
+        let data = writer.into_inner().into_inner();
+        assert_eq!(end, data.len() as u64);
+
+        let mut archive = ZipArchive::new(Cursor::new(&data)).unwrap();
+        assert_eq!(archive.offset(), 6);
+        assert_eq!(archive.comment(), b"comment");
+        assert_eq!(archive.len(), 4);
+
+        let entry = archive.entry(0).unwrap();
+        assert_eq!(entry.name(), "dir/");
+        assert!(entry.is_dir());
+        assert_eq!(entry.last_modified(), options().last_modified);
+        assert_eq!(
+            archive.entry(1).unwrap().method(),
+            CompressionMethod::Stored
+        );
+        let entry = archive.entry(2).unwrap();
+        assert_eq!(entry.method(), CompressionMethod::Deflated);
+        assert_eq!(entry.unix_mode(), Some(0o100_600));
+        assert_eq!(entry.comment(), b"numbers");
+        assert!(entry.compressed_size() < entry.size());
+        let entry = archive.entry(3).unwrap();
+        assert!(entry.is_utf8());
+        assert_eq!(entry.version_needed(), VERSION_ZIP64);
+
+        assert!(archive.read_to_vec(0).unwrap().is_empty());
+        assert_eq!(archive.read_to_vec(1).unwrap(), b"stored");
+        assert!(archive.read_to_vec(2).unwrap() == &numbers[..]);
+        assert!(archive.read_to_vec(3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn append() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.add_mem("first", b"first", &options()).unwrap();
+        writer.set_comment(b"kept").unwrap();
+        writer.finish().unwrap();
+        // Adding after finishing overwrites the central directory.
+        writer.add_mem("second", b"second", &options()).unwrap();
+        writer.finish().unwrap();
+
+        let mut writer = ZipWriter::new_append(writer.into_inner()).unwrap();
+        writer
+            .add_mem("third", b"third", &options().level(1))
+            .unwrap();
+        writer.finish().unwrap();
+
+        let mut archive = ZipArchive::new(writer.into_inner()).unwrap();
+        assert_eq!(archive.comment(), b"kept");
+        let names: Vec<String> = archive.entries().iter().map(|e| e.name().into()).collect();
+        assert_eq!(names, ["first", "second", "third"]);
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(archive.read_to_vec(i).unwrap(), name.as_bytes());
+        }
+    }
+
+    /// A writer that fails when writing past `limit` bytes, like a full disk.
+    struct FullWriter {
+        inner: Cursor<Vec<u8>>,
+        limit: u64,
+    }
+
+    impl Read for FullWriter {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Write for FullWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let room = self.limit.saturating_sub(self.inner.position());
+            if room == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "disk full"));
+            }
+            let len = cmp::min(buf.len() as u64, room) as usize;
+            self.inner.write(&buf[..len])
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FullWriter {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn restore_after_failed_add() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.add_mem("first", b"first", &options()).unwrap();
+        writer.add_mem("second", b"second", &options()).unwrap();
+        let end = writer.finish().unwrap();
+        let original = writer.into_inner().into_inner();
+
+        // Data that doesn't compress, so it doesn't fit when compressed either.
+        let mut state = 0x2545_f491_u32;
+        let data: Vec<u8> = (0..20_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        for &level in &[0, 6] {
+            let full = FullWriter {
+                inner: Cursor::new(original.clone()),
+                limit: end + 1000,
+            };
+            let mut writer = ZipWriter::new_append(full).unwrap();
+            let (err, restored_end) =
+                add_and_finish(&mut writer, "third", &data, &options().level(level)).unwrap_err();
+            assert!(match err {
+                ZipError::Io(_) => true,
+                _ => false,
+            });
+            assert_eq!(restored_end, Some(end));
+            let mut restored = writer.into_inner().inner.into_inner();
+            restored.truncate(end as usize);
+            assert!(restored == original);
+
+            let mut archive = ZipArchive::new(Cursor::new(restored)).unwrap();
+            assert_eq!(archive.len(), 2);
+            assert_eq!(archive.read_to_vec(1).unwrap(), b"second");
+        }
+    }
+
+    #[test]
+    fn append_to_file() {
+        let path = std::env::temp_dir().join(format!("miniz_oxide_zip_{}.zip", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        add_to_archive_file_in_place(&path, "a", b"aaaa", &options()).unwrap();
+        add_to_archive_file_in_place(&path, "b", b"bbbb", &options().level(0)).unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        assert!(add_to_archive_file_in_place(&path, "", b"cccc", &options()).is_err());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        let file = std::fs::File::open(&path).unwrap();
+        let mut archive = ZipArchive::new(file).unwrap();
+        assert_eq!(archive.len(), 2);
+        assert_eq!(archive.read_to_vec(1).unwrap(), b"bbbb");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,586 @@
+//! Exports of the C API under the unprefixed zlib names, so the library can be linked or
+//! preloaded in place of zlib. Enabled by the `zlib_compat` feature.
+//!
+//! `mz_stream` has the same layout as zlib's `z_stream`, so the functions forward to their `mz_`
+//! counterparts after doing the version and parameter checks zlib does. The matching C headers
+//! are `zlib.h` and `zconf.h` in the root of the repository.
+
+use std::{mem, ptr};
+
+use libc::{c_char, c_int, c_long, c_uint, c_ulong, c_void, size_t};
+
+use crate::*;
+
+#[allow(bad_style)]
+pub type z_stream = mz_stream;
+
+/// Version of zlib whose API is provided, as a null-terminated string.
+pub const ZLIB_VERSION: &[u8] = b"1.2.11\0";
+/// `ZLIB_VERSION` as a number.
+pub const ZLIB_VERNUM: c_int = 0x12b0;
+
+/// zlib's `MAX_WBITS`, the largest window size.
+const MAX_WBITS: c_int = 15;
+
+/// Reversed CRC-32 polynomial.
+const CRC32_POLY: u32 = 0xedb8_8320;
+/// Largest prime below 2^16, the modulus of adler-32.
+const ADLER32_BASE: u32 = 65521;
+
+/// Map the return codes that zlib does not have to their zlib equivalents.
+fn zlib_return_code(status: c_int) -> c_int {
+    if status == MZError::Param as c_int {
+        MZError::Stream as c_int
+    } else {
+        status
+    }
+}
+
+/// Check that the caller was compiled against a compatible `zlib.h`, as zlib does.
+unsafe fn version_ok(version: *const c_char, stream_size: c_int) -> bool {
+    !version.is_null()
+        && *version as u8 == ZLIB_VERSION[0]
+        && stream_size as usize == mem::size_of::<z_stream>()
+}
+
+/// Prepare a stream that may contain uninitialized data for initialization.
+///
+/// zlib does not require `state` and `msg` to be set before the init functions are called, so
+/// they are cleared here without dropping the old values.
+unsafe fn clear_stream(stream: &mut z_stream) {
+    ptr::write(&mut stream.state, None);
+    stream.msg = ptr::null();
+}
+
+/// Multiply the polynomials `a` and `b` modulo the CRC-32 polynomial.
+fn crc32_multiply(a: u32, mut b: u32) -> u32 {
+    let mut product = 0;
+    let mut bit = 1 << 31;
+    while bit != 0 {
+        if a & bit != 0 {
+            product ^= b;
+        }
+        bit >>= 1;
+        b = if b & 1 != 0 {
+            (b >> 1) ^ CRC32_POLY
+        } else {
+            b >> 1
+        };
+    }
+    product
+}
+
+/// Get the CRC-32 of two concatenated blocks from their checksums and the length of the second.
+pub fn crc32_combine_oxide(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    // x^(8 * 2^k) modulo the polynomial, starting with x^8 (x^0 is the top bit).
+    let mut square = 1 << 23;
+    // x^(8 * len2) modulo the polynomial.
+    let mut shift = 1 << 31;
+    let mut len2 = len2;
+    while len2 != 0 {
+        if len2 & 1 != 0 {
+            shift = crc32_multiply(square, shift);
+        }
+        square = crc32_multiply(square, square);
+        len2 >>= 1;
+    }
+    crc32_multiply(shift, crc1) ^ crc2
+}
+
+/// Get the adler-32 of two concatenated blocks from their checksums and the length of the second.
+pub fn adler32_combine_oxide(adler1: u32, adler2: u32, len2: u64) -> u32 {
+    let rem = (len2 % u64::from(ADLER32_BASE)) as u32;
+    let a1 = adler1 & 0xffff;
+    let b1 = (adler1 >> 16) % ADLER32_BASE;
+    let a2 = adler2 & 0xffff;
+    let b2 = (adler2 >> 16) % ADLER32_BASE;
+
+    let a = (a1 + a2 + ADLER32_BASE - 1) % ADLER32_BASE;
+    let b = ((rem * a1) % ADLER32_BASE + b1 + b2 + ADLER32_BASE - rem) % ADLER32_BASE;
+    a | (b << 16)
+}
+
+/// Export `$name` as a zlib function calling `$target`, with the return code mapped to the
+/// zlib one.
+macro_rules! zlib_function {
+    ($($(#[$attr:meta])* fn $name:ident => $target:ident($($arg:ident: $ty:ty),*);)*) => {
+        unmangle!($(
+            $(#[$attr])*
+            pub unsafe extern "C" fn $name($($arg: $ty),*) -> c_int {
+                zlib_return_code($target($($arg),*))
+            }
+        )*);
+    };
+}
+
+zlib_function!(
+    fn deflate => mz_deflate(stream: *mut z_stream, flush: c_int);
+    fn deflateEnd => mz_deflateEnd(stream: *mut z_stream);
+    fn deflateReset => mz_deflateReset(stream: *mut z_stream);
+    fn deflateParams => mz_deflateParams(stream: *mut z_stream, level: c_int, strategy: c_int);
+    fn deflateSetDictionary => mz_deflateSetDictionary(
+        stream: *mut z_stream,
+        dictionary: *const u8,
+        dict_length: c_uint
+    );
+    fn deflateCopy => mz_deflateCopy(dest: *mut z_stream, source: *mut z_stream);
+    fn deflatePending => mz_deflatePending(
+        stream: *mut z_stream,
+        pending: *mut c_uint,
+        bits: *mut c_int
+    );
+    fn deflatePrime => mz_deflatePrime(stream: *mut z_stream, bits: c_int, value: c_int);
+    fn deflateTune => mz_deflateTune(
+        stream: *mut z_stream,
+        good_length: c_int,
+        max_lazy: c_int,
+        nice_length: c_int,
+        max_chain: c_int
+    );
+
+    fn inflate => mz_inflate(stream: *mut z_stream, flush: c_int);
+    fn inflateEnd => mz_inflateEnd(stream: *mut z_stream);
+    fn inflateReset => mz_inflateReset(stream: *mut z_stream);
+    fn inflateSetDictionary => mz_inflateSetDictionary(
+        stream: *mut z_stream,
+        dictionary: *const u8,
+        dict_length: c_uint
+    );
+    fn inflateGetDictionary => mz_inflateGetDictionary(
+        stream: *mut z_stream,
+        dictionary: *mut u8,
+        dict_length: *mut c_uint
+    );
+    fn inflateCopy => mz_inflateCopy(dest: *mut z_stream, source: *mut z_stream);
+    fn inflateBack => mz_inflateBack(
+        stream: *mut z_stream,
+        in_func: mz_in_func,
+        in_desc: *mut c_void,
+        out_func: mz_out_func,
+        out_desc: *mut c_void
+    );
+    fn inflateBackEnd => mz_inflateBackEnd(stream: *mut z_stream);
+
+    fn compress => mz_compress(
+        dest: *mut u8,
+        dest_len: *mut c_ulong,
+        source: *const u8,
+        source_len: c_ulong
+    );
+    fn compress2 => mz_compress2(
+        dest: *mut u8,
+        dest_len: *mut c_ulong,
+        source: *const u8,
+        source_len: c_ulong,
+        level: c_int
+    );
+    fn uncompress => mz_uncompress(
+        dest: *mut u8,
+        dest_len: *mut c_ulong,
+        source: *const u8,
+        source_len: c_ulong
+    );
+);
+
+/// Convert zlib window bits for decompression to the ones accepted by `mz_inflateInit2`.
+///
+/// zlib takes a window size of 0, with or without the gzip bits added, to use the size stated
+/// in the zlib header. The window is always 32 KiB, so the largest size is used instead.
+fn inflate_window_bits(window_bits: c_int) -> c_int {
+    match window_bits {
+        0 | 16 | 32 => window_bits + MAX_WBITS,
+        _ => window_bits,
+    }
+}
+
+unmangle!(
+    /// Get the version of zlib whose API is provided.
+    pub extern "C" fn zlibVersion() -> *const c_char {
+        ZLIB_VERSION.as_ptr() as *const c_char
+    }
+
+    /// Get the sizes of the types used in the API in the format zlib uses.
+    pub extern "C" fn zlibCompileFlags() -> c_ulong {
+        fn size_flag(size: usize) -> c_ulong {
+            match size {
+                2 => 0,
+                4 => 1,
+                8 => 2,
+                _ => 3,
+            }
+        }
+        size_flag(mem::size_of::<c_uint>())
+            | size_flag(mem::size_of::<c_ulong>()) << 2
+            | size_flag(mem::size_of::<*mut u8>()) << 4
+            | size_flag(mem::size_of::<c_long>()) << 6
+    }
+
+    /// Get a description of the zlib return code `err`, or null if it is not one.
+    pub extern "C" fn zError(err: c_int) -> *const c_char {
+        let message: &[u8] = match err {
+            2 => b"need dictionary\0",
+            1 => b"stream end\0",
+            0 => b"\0",
+            -1 => b"file error\0",
+            -2 => b"stream error\0",
+            -3 => b"data error\0",
+            -4 => b"insufficient memory\0",
+            -5 => b"buffer error\0",
+            -6 => b"incompatible version\0",
+            _ => return ptr::null(),
+        };
+        message.as_ptr() as *const c_char
+    }
+
+    pub unsafe extern "C" fn deflateInit_(
+        stream: *mut z_stream,
+        level: c_int,
+        version: *const c_char,
+        stream_size: c_int,
+    ) -> c_int {
+        deflateInit2_(
+            stream,
+            level,
+            MZ_DEFLATED,
+            MAX_WBITS,
+            8,
+            CompressionStrategy::Default as c_int,
+            version,
+            stream_size,
+        )
+    }
+
+    /// Initialize a compressor. Adding 16 to `window_bits` writes a gzip wrapper instead of a
+    /// zlib one. Windows of 9 bits, and 8 bits which zlib treats as 9, use a 1 KiB window as
+    /// smaller ones are not supported, so the zlib header states 10 bits.
+    pub unsafe extern "C" fn deflateInit2_(
+        stream: *mut z_stream,
+        level: c_int,
+        method: c_int,
+        window_bits: c_int,
+        mem_level: c_int,
+        strategy: c_int,
+        version: *const c_char,
+        stream_size: c_int,
+    ) -> c_int {
+        if !version_ok(version, stream_size) {
+            return MZError::Version as c_int;
+        }
+        match stream.as_mut() {
+            None => MZError::Stream as c_int,
+            Some(stream) => {
+                clear_stream(stream);
+                zlib_return_code(mz_deflateInit2(
+                    stream,
+                    level,
+                    method,
+                    window_bits,
+                    mem_level,
+                    strategy,
+                ))
+            }
+        }
+    }
+
+    pub extern "C" fn deflateBound(stream: *mut z_stream, source_len: c_ulong) -> c_ulong {
+        mz_deflateBound(stream, source_len)
+    }
+
+    pub extern "C" fn compressBound(source_len: c_ulong) -> c_ulong {
+        mz_compressBound(source_len)
+    }
+
+    pub unsafe extern "C" fn inflateInit_(
+        stream: *mut z_stream,
+        version: *const c_char,
+        stream_size: c_int,
+    ) -> c_int {
+        inflateInit2_(stream, MAX_WBITS, version, stream_size)
+    }
+
+    /// Initialize a decompressor. Adding 16 to `window_bits` decompresses a gzip stream, and
+    /// adding 32 detects whether the stream is zlib or gzip.
+    pub unsafe extern "C" fn inflateInit2_(
+        stream: *mut z_stream,
+        window_bits: c_int,
+        version: *const c_char,
+        stream_size: c_int,
+    ) -> c_int {
+        if !version_ok(version, stream_size) {
+            return MZError::Version as c_int;
+        }
+        match stream.as_mut() {
+            None => MZError::Stream as c_int,
+            Some(stream) => {
+                clear_stream(stream);
+                let window_bits = inflate_window_bits(window_bits);
+                zlib_return_code(mz_inflateInit2(stream, window_bits))
+            }
+        }
+    }
+
+    pub unsafe extern "C" fn inflateBackInit_(
+        stream: *mut z_stream,
+        window_bits: c_int,
+        window: *mut u8,
+        version: *const c_char,
+        stream_size: c_int,
+    ) -> c_int {
+        if !version_ok(version, stream_size) {
+            return MZError::Version as c_int;
+        }
+        match stream.as_mut() {
+            None => MZError::Stream as c_int,
+            Some(stream) => {
+                clear_stream(stream);
+                zlib_return_code(mz_inflateBackInit(stream, window_bits, window))
+            }
+        }
+    }
+
+    pub unsafe extern "C" fn inflateReset2(stream: *mut z_stream, window_bits: c_int) -> c_int {
+        zlib_return_code(mz_inflateReset2(stream, inflate_window_bits(window_bits)))
+    }
+
+    pub unsafe extern "C" fn adler32(adler: c_ulong, data: *const u8, len: c_uint) -> c_ulong {
+        mz_adler32(adler, data, len as usize)
+    }
+
+    pub unsafe extern "C" fn adler32_z(adler: c_ulong, data: *const u8, len: size_t) -> c_ulong {
+        mz_adler32(adler, data, len)
+    }
+
+    /// Get the adler-32 checksum of two concatenated blocks of data, where the second is `len2`
+    /// bytes long.
+    pub extern "C" fn adler32_combine(adler1: c_ulong, adler2: c_ulong, len2: c_long) -> c_ulong {
+        if len2 < 0 {
+            return 0xffff_ffff;
+        }
+        c_ulong::from(adler32_combine_oxide(
+            adler1 as u32,
+            adler2 as u32,
+            len2 as u64,
+        ))
+    }
+
+    pub unsafe extern "C" fn crc32(crc: c_ulong, data: *const u8, len: c_uint) -> c_ulong {
+        mz_crc32(crc, data, len as size_t)
+    }
+
+    pub unsafe extern "C" fn crc32_z(crc: c_ulong, data: *const u8, len: size_t) -> c_ulong {
+        mz_crc32(crc, data, len)
+    }
+
+    /// Get the CRC-32 of two concatenated blocks of data, where the second is `len2` bytes long.
+    pub extern "C" fn crc32_combine(crc1: c_ulong, crc2: c_ulong, len2: c_long) -> c_ulong {
+        // Like zlib, an empty or negative length leaves the first checksum unchanged.
+        if len2 <= 0 {
+            return crc1;
+        }
+        c_ulong::from(crc32_combine_oxide(crc1 as u32, crc2 as u32, len2 as u64))
+    }
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lib_oxide::MZ_ADLER32_INIT;
+    use std::mem::{self, MaybeUninit};
+
+    /// Offsets and size of `z_stream` on LP64 platforms.
+    #[cfg(all(target_pointer_width = "64", not(windows)))]
+    #[test]
+    fn z_stream_layout() {
+        let stream = z_stream::default();
+        let base = &stream as *const _ as usize;
+        let offset = |field: *const u8| field as usize - base;
+        assert_eq!(offset(&stream.avail_in as *const _ as *const u8), 8);
+        assert_eq!(offset(&stream.total_in as *const _ as *const u8), 16);
+        assert_eq!(offset(&stream.next_out as *const _ as *const u8), 24);
+        assert_eq!(offset(&stream.msg as *const _ as *const u8), 48);
+        assert_eq!(offset(&stream.state as *const _ as *const u8), 56);
+        assert_eq!(offset(&stream.opaque as *const _ as *const u8), 80);
+        assert_eq!(offset(&stream.data_type as *const _ as *const u8), 88);
+        assert_eq!(offset(&stream.adler as *const _ as *const u8), 96);
+        assert_eq!(mem::size_of::<z_stream>(), 112);
+    }
+
+    #[test]
+    fn combine() {
+        let data = b"The quick brown fox jumps over the lazy dog, again and again and again.";
+        for &split in &[0, 1, 17, data.len()] {
+            let (first, second) = data.split_at(split);
+            let len2 = second.len() as u64;
+            assert_eq!(
+                crc32_combine_oxide(mz_crc32_oxide(0, first), mz_crc32_oxide(0, second), len2),
+                mz_crc32_oxide(0, data)
+            );
+            let adler = |data: &[u8]| miniz_oxide::mz_adler32_oxide(MZ_ADLER32_INIT, data);
+            assert_eq!(
+                adler32_combine_oxide(adler(first), adler(second), len2),
+                adler(data)
+            );
+        }
+        assert_eq!(
+            crc32_combine_oxide(0x1234_5678, 0, 1 << 40),
+            crc32_combine_oxide(crc32_combine_oxide(0x1234_5678, 0, 1 << 39), 0, 1 << 39)
+        );
+        assert_eq!(crc32_combine(0x1234_5678, 0x9abc_def0, 0), 0x1234_5678);
+        assert_eq!(crc32_combine(0x1234_5678, 0x9abc_def0, -1), 0x1234_5678);
+    }
+
+    #[test]
+    fn stream_roundtrip() {
+        let data = b"zlib compatible stream, zlib compatible stream, zlib compatible stream";
+        let mut compressed = [0; 256];
+        let mut decompressed = [0; 256];
+        let version = ZLIB_VERSION.as_ptr() as *const c_char;
+        let size = mem::size_of::<z_stream>() as c_int;
+        unsafe {
+            // zlib only requires the allocation functions and opaque pointer to be set.
+            let mut stream = MaybeUninit::<z_stream>::uninit();
+            let stream_ptr = stream.as_mut_ptr();
+            (*stream_ptr).zalloc = None;
+            (*stream_ptr).zfree = None;
+            (*stream_ptr).opaque = ptr::null_mut();
+            (*stream_ptr).next_in = data.as_ptr();
+            (*stream_ptr).avail_in = data.len() as c_uint;
+            assert_eq!(deflateInit_(stream_ptr, 6, b"0\0".as_ptr() as _, size), -6);
+            assert_eq!(deflateInit_(stream_ptr, 6, version, size + 1), -6);
+            assert_eq!(deflateInit2_(stream_ptr, 6, 8, 7, 8, 0, version, size), -2);
+            assert_eq!(deflateInit_(stream_ptr, 6, version, size), 0);
+            let stream = &mut *stream_ptr;
+            stream.next_out = compressed.as_mut_ptr();
+            stream.avail_out = compressed.len() as c_uint;
+            assert_eq!(deflate(stream, 4), 1);
+            let compressed_len = stream.total_out as usize;
+            assert_eq!(deflateEnd(stream), 0);
+
+            let mut stream = z_stream {
+                next_in: compressed.as_ptr(),
+                avail_in: compressed_len as c_uint,
+                next_out: decompressed.as_mut_ptr(),
+                avail_out: decompressed.len() as c_uint,
+                ..Default::default()
+            };
+            assert_eq!(inflateInit2_(&mut stream, 48, version, size), -2);
+            assert_eq!(inflateInit2_(&mut stream, 0, version, size), 0);
+            assert_eq!(inflate(&mut stream, 4), 1);
+            assert_eq!(&decompressed[..stream.total_out as usize], &data[..]);
+            assert_eq!(inflateEnd(&mut stream), 0);
+
+            let mut dest_len = decompressed.len() as c_ulong;
+            let status = uncompress(
+                decompressed.as_mut_ptr(),
+                &mut dest_len,
+                compressed.as_ptr(),
+                compressed_len as c_ulong,
+            );
+            assert_eq!(status, 0);
+            assert_eq!(&decompressed[..dest_len as usize], &data[..]);
+        }
+    }
+
+    /// Compress `data` with `deflateInit2_` and the given window bits.
+    fn deflate_with_window_bits(data: &[u8], window_bits: c_int) -> Vec<u8> {
+        let mut compressed = vec![0; data.len() + 64];
+        let version = ZLIB_VERSION.as_ptr() as *const c_char;
+        let size = mem::size_of::<z_stream>() as c_int;
+        let mut stream = z_stream {
+            next_in: data.as_ptr(),
+            avail_in: data.len() as c_uint,
+            next_out: compressed.as_mut_ptr(),
+            avail_out: compressed.len() as c_uint,
+            ..Default::default()
+        };
+        unsafe {
+            assert_eq!(
+                deflateInit2_(&mut stream, 6, 8, window_bits, 8, 0, version, size),
+                0
+            );
+            assert_eq!(deflate(&mut stream, 4), 1);
+            compressed.truncate(stream.total_out as usize);
+            assert_eq!(deflateEnd(&mut stream), 0);
+        }
+        compressed
+    }
+
+    /// Decompress `compressed` feeding one byte at a time, returning the status of the last call
+    /// to `inflate`, the output and the stream so it can be ended.
+    fn inflate_bytewise(compressed: &[u8], window_bits: c_int) -> (c_int, Vec<u8>, z_stream) {
+        let mut decompressed = vec![0; 1024];
+        let version = ZLIB_VERSION.as_ptr() as *const c_char;
+        let size = mem::size_of::<z_stream>() as c_int;
+        let mut stream = z_stream {
+            next_out: decompressed.as_mut_ptr(),
+            avail_out: decompressed.len() as c_uint,
+            ..Default::default()
+        };
+        let mut status = 0;
+        unsafe {
+            assert_eq!(inflateInit2_(&mut stream, window_bits, version, size), 0);
+            for byte in compressed {
+                stream.next_in = byte;
+                stream.avail_in = 1;
+                status = inflate(&mut stream, 0);
+                if status != 0 {
+                    break;
+                }
+            }
+        }
+        decompressed.truncate(stream.total_out as usize);
+        (status, decompressed, stream)
+    }
+
+    #[test]
+    fn gzip_roundtrip() {
+        let data = b"gzip wrapped stream, gzip wrapped stream, gzip wrapped stream";
+        let compressed = deflate_with_window_bits(data, 31);
+        assert_eq!(&compressed[..3], &[0x1f, 0x8b, 8][..]);
+        assert_eq!(
+            &compressed[compressed.len() - 4..],
+            &(data.len() as u32).to_le_bytes()
+        );
+
+        for &window_bits in &[31, 47, 16, 32] {
+            let (status, decompressed, mut stream) = inflate_bytewise(&compressed, window_bits);
+            assert_eq!(status, 1);
+            assert_eq!(&decompressed[..], &data[..]);
+            assert_eq!(stream.adler, c_ulong::from(mz_crc32_oxide(0, data)));
+            assert_eq!(unsafe { inflateEnd(&mut stream) }, 0);
+        }
+
+        // Detecting the wrapper also accepts zlib streams.
+        let (status, decompressed, mut stream) =
+            inflate_bytewise(&deflate_with_window_bits(data, 15), 47);
+        assert_eq!(status, 1);
+        assert_eq!(&decompressed[..], &data[..]);
+        assert_eq!(unsafe { inflateEnd(&mut stream) }, 0);
+
+        let mut corrupted = compressed;
+        let crc_pos = corrupted.len() - 8;
+        corrupted[crc_pos] ^= 1;
+        let (status, _, mut stream) = inflate_bytewise(&corrupted, 31);
+        assert_eq!(status, -3);
+        let msg = unsafe { std::ffi::CStr::from_ptr(stream.msg) };
+        assert_eq!(msg.to_bytes(), b"incorrect data check");
+        assert_eq!(unsafe { inflateEnd(&mut stream) }, 0);
+    }
+
+    #[test]
+    fn small_window_bits() {
+        let data = b"small window, small window, small window, small window";
+        for &(window_bits, cmf) in &[(8, 0x28), (9, 0x28), (12, 0x48), (14, 0x68), (15, 0x78)] {
+            let compressed = deflate_with_window_bits(data, window_bits);
+            assert_eq!(compressed[0], cmf);
+            assert_eq!(
+                (u16::from(compressed[0]) << 8 | u16::from(compressed[1])) % 31,
+                0
+            );
+            let (status, decompressed, mut stream) = inflate_bytewise(&compressed, window_bits);
+            assert_eq!(status, 1);
+            assert_eq!(&decompressed[..], &data[..]);
+            assert_eq!(unsafe { inflateEnd(&mut stream) }, 0);
+        }
+    }
+}
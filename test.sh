@@ -31,9 +31,8 @@ echo "valgrind -v -r a"
 valgrind --error-exitcode=1 --leak-check=full ../bin/miniz_tester -v -r a linux-4.8.11/mm > /dev/null
 echo "valgrind -v -b a"
 valgrind --error-exitcode=1 --leak-check=full ../bin/miniz_tester -v -b a linux-4.8.11/mm > /dev/null
-## zip is broken right now due to struct difference between c and rust version
-#echo "valgrind -v -a a"
-#valgrind --error-exitcode=1 --leak-check=full ../bin/miniz_tester -v -a a linux-4.8.11/mm/kasan > /dev/null
+echo "valgrind -v -a a"
+valgrind --error-exitcode=1 --leak-check=full ../bin/miniz_tester -v -a a linux-4.8.11/mm/kasan > /dev/null
 
 echo "-v a"
 ../bin/miniz_tester -v a linux-4.8.11 > /dev/null
@@ -41,9 +40,8 @@ echo "-v -r a"
 ../bin/miniz_tester -v -r a linux-4.8.11 > /dev/null
 echo "-v -b -r a"
 ../bin/miniz_tester -v -b -r a linux-4.8.11 > /dev/null
-## zip is broken right now due to struct difference between c and rust version
-#echo "-v -a a"
-#../bin/miniz_tester -v -a a linux-4.8.11/mm > /dev/null
+echo "-v -a a"
+../bin/miniz_tester -v -a a linux-4.8.11/mm > /dev/null
 
 #echo "Large file"
 #mkdir -p large_file
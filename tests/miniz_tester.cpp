@@ -31,6 +31,12 @@
 
 #include "timer.h"
 
+// The structs C code can allocate itself have to match the layout used by the library.
+static_assert(sizeof(tdefl_compressor) == TDEFL_COMPRESSOR_SIZE, "tdefl_compressor size differs from the library");
+static_assert(alignof(tdefl_compressor) == 8, "tdefl_compressor alignment differs from the library");
+static_assert(sizeof(tinfl_decompressor) == TINFL_DECOMPRESSOR_SIZE, "tinfl_decompressor size differs from the library");
+static_assert(alignof(tinfl_decompressor) == 8, "tinfl_decompressor alignment differs from the library");
+
 #define my_max(a,b) (((a) > (b)) ? (a) : (b))
 #define my_min(a,b) (((a) < (b)) ? (a) : (b))
 
@@ -2,6 +2,7 @@ extern crate miniz_oxide;
 extern crate miniz_oxide_c_api;
 
 use std::io::Read;
+use std::os::raw::{c_uint, c_void};
 
 use miniz_oxide::deflate::compress_to_vec;
 use miniz_oxide::inflate::decompress_to_vec;
@@ -123,3 +124,716 @@ fn c_api() {
 
     assert_eq!(data[..], decompressed[0..decompressed_size as usize]);
 }
+
+/// Change the level and strategy in the middle of a stream using the C API.
+#[test]
+fn c_api_deflate_params() {
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+    use miniz_oxide::inflate::stream::{inflate, InflateState};
+    use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_deflate, mz_deflateEnd, mz_deflateInit, mz_deflateParams, mz_stream,
+    };
+
+    let mut data = get_test_data();
+    let mut compressed = vec![0; data.len() * 2];
+    let chunk = data.len() / 4;
+    unsafe {
+        let mut stream = mz_stream {
+            next_in: data.as_mut_ptr(),
+            avail_in: chunk as u32,
+            next_out: compressed.as_mut_ptr(),
+            avail_out: compressed.len() as u32,
+            ..Default::default()
+        };
+
+        assert_eq!(mz_deflateInit(&mut stream, 6), MZStatus::Ok as i32);
+        for &(level, strategy) in [(11, 0), (-2, 0), (6, -1), (6, 5), (6, 6)].iter() {
+            assert_eq!(
+                mz_deflateParams(&mut stream, level, strategy),
+                MZError::Param as i32
+            );
+        }
+        for &(level, strategy) in [(1, 0), (6, 2), (9, 4)].iter() {
+            assert_eq!(mz_deflate(&mut stream, 0), MZStatus::Ok as i32);
+
+            // Without output space the input so far can't be compressed with the old settings.
+            let avail_out = stream.avail_out;
+            stream.avail_out = 0;
+            assert_eq!(
+                mz_deflateParams(&mut stream, level, strategy),
+                MZError::Buf as i32
+            );
+            stream.avail_out = avail_out;
+
+            assert_eq!(
+                mz_deflateParams(&mut stream, level, strategy),
+                MZStatus::Ok as i32
+            );
+            // All of the input so far has been compressed and the block ended, so the output
+            // decompresses to it.
+            let total_out = stream.total_out as usize;
+            let mut inflater = InflateState::new_boxed(DataFormat::Zlib);
+            let mut decompressed = vec![0; data.len()];
+            let res = inflate(
+                &mut inflater,
+                &compressed[..total_out],
+                &mut decompressed,
+                MZFlush::None,
+            );
+            assert_eq!(res.bytes_written, stream.total_in as usize);
+            assert!(decompressed[..res.bytes_written] == data[..res.bytes_written]);
+            stream.avail_in += chunk as u32;
+        }
+        stream.avail_in = (data.len() - stream.total_in as usize) as u32;
+        assert_eq!(mz_deflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+        assert_eq!(data.len() as libc::c_ulong, stream.total_in);
+        let compressed_size = stream.total_out as usize;
+        assert_eq!(mz_deflateEnd(&mut stream), MZStatus::Ok as i32);
+
+        let decompressed = decompress_to_vec_zlib(&compressed[..compressed_size]).unwrap();
+        assert!(decompressed == data);
+    }
+}
+
+/// Partial and block flushes end the block, so after a partial flush everything written so far
+/// can be decompressed.
+#[test]
+fn c_api_partial_and_block_flush() {
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+    use miniz_oxide::{MZError, MZFlush, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_deflate, mz_deflateEnd, mz_deflateInit, mz_inflate, mz_inflateEnd, mz_inflateInit,
+        mz_stream,
+    };
+
+    let mut data = get_test_data();
+    let mut compressed = vec![0; data.len() * 2];
+    let mut decompressed = vec![0; data.len()];
+    let chunk = data.len() / 3;
+    unsafe {
+        let mut stream = mz_stream {
+            next_in: data.as_mut_ptr(),
+            avail_in: chunk as u32,
+            next_out: compressed.as_mut_ptr(),
+            avail_out: compressed.len() as u32,
+            ..Default::default()
+        };
+        assert_eq!(mz_deflateInit(&mut stream, 6), MZStatus::Ok as i32);
+        assert_eq!(
+            mz_deflate(&mut stream, MZFlush::Partial as i32),
+            MZStatus::Ok as i32
+        );
+        assert_eq!(stream.total_in as usize, chunk);
+
+        let mut inflate_stream = mz_stream {
+            next_in: compressed.as_mut_ptr(),
+            avail_in: stream.total_out as u32,
+            next_out: decompressed.as_mut_ptr(),
+            avail_out: decompressed.len() as u32,
+            ..Default::default()
+        };
+        assert_eq!(mz_inflateInit(&mut inflate_stream), MZStatus::Ok as i32);
+        // The partial and block flushes are only accepted when compressing.
+        for &flush in [MZFlush::Partial, MZFlush::Block].iter() {
+            assert_eq!(
+                mz_inflate(&mut inflate_stream, flush as i32),
+                MZError::Param as i32
+            );
+        }
+        assert_eq!(
+            mz_inflate(&mut inflate_stream, MZFlush::None as i32),
+            MZStatus::Ok as i32
+        );
+        assert_eq!(inflate_stream.total_out as usize, chunk);
+        assert!(decompressed[..chunk] == data[..chunk]);
+        assert_eq!(mz_inflateEnd(&mut inflate_stream), MZStatus::Ok as i32);
+
+        stream.avail_in = chunk as u32;
+        assert_eq!(
+            mz_deflate(&mut stream, MZFlush::Block as i32),
+            MZStatus::Ok as i32
+        );
+        stream.avail_in = (data.len() - stream.total_in as usize) as u32;
+        assert_eq!(
+            mz_deflate(&mut stream, MZFlush::Finish as i32),
+            MZStatus::StreamEnd as i32
+        );
+        let compressed_size = stream.total_out as usize;
+        assert_eq!(mz_deflateEnd(&mut stream), MZStatus::Ok as i32);
+
+        let decompressed = decompress_to_vec_zlib(&compressed[..compressed_size]).unwrap();
+        assert!(decompressed == data);
+    }
+}
+
+/// Compress and decompress with a preset dictionary using the C API.
+#[test]
+fn c_api_dictionary() {
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_adler32, mz_deflate, mz_deflateEnd, mz_deflateInit, mz_deflateSetDictionary,
+        mz_deflateTune, mz_inflate, mz_inflateEnd, mz_inflateGetDictionary, mz_inflateInit,
+        mz_inflateReset, mz_inflateSetDictionary, mz_stream,
+    };
+
+    let data = get_test_data();
+    let dictionary = &data[data.len() - 20_000..];
+    let mut input = data[..20_000].to_vec();
+    let mut compressed = vec![0; input.len() * 2];
+    let mut decompressed = vec![0; input.len()];
+    let dict_id = unsafe { mz_adler32(1, dictionary.as_ptr(), dictionary.len()) };
+    unsafe {
+        let mut stream = mz_stream {
+            next_in: input.as_mut_ptr(),
+            avail_in: input.len() as u32,
+            next_out: compressed.as_mut_ptr(),
+            avail_out: compressed.len() as u32,
+            ..Default::default()
+        };
+
+        assert_eq!(mz_deflateInit(&mut stream, 9), MZStatus::Ok as i32);
+        assert_eq!(
+            mz_deflateTune(&mut stream, 8, 32, 128, 256),
+            MZStatus::Ok as i32
+        );
+        assert_eq!(
+            mz_deflateSetDictionary(&mut stream, dictionary.as_ptr(), dictionary.len() as u32),
+            MZStatus::Ok as i32
+        );
+        assert_eq!(stream.adler, dict_id);
+        assert_eq!(mz_deflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+        assert_eq!(
+            mz_deflateSetDictionary(&mut stream, dictionary.as_ptr(), dictionary.len() as u32),
+            MZError::Stream as i32
+        );
+        let compressed_size = stream.total_out as usize;
+        assert_eq!(mz_deflateEnd(&mut stream), MZStatus::Ok as i32);
+
+        let mut stream = mz_stream {
+            next_in: compressed.as_mut_ptr(),
+            avail_in: compressed_size as u32,
+            next_out: decompressed.as_mut_ptr(),
+            avail_out: decompressed.len() as u32,
+            ..Default::default()
+        };
+        assert_eq!(mz_inflateInit(&mut stream), MZStatus::Ok as i32);
+        for _ in 0..2 {
+            assert_eq!(mz_inflate(&mut stream, 0), MZStatus::NeedDict as i32);
+            assert_eq!(stream.adler, dict_id);
+            assert_eq!(
+                mz_inflateSetDictionary(&mut stream, input.as_ptr(), 100),
+                MZError::Data as i32
+            );
+            assert_eq!(
+                mz_inflateSetDictionary(&mut stream, dictionary.as_ptr(), dictionary.len() as u32),
+                MZStatus::Ok as i32
+            );
+            assert_eq!(mz_inflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+            assert_eq!(stream.total_out as usize, input.len());
+            assert!(decompressed == input);
+
+            // The window is the end of the output.
+            let mut window = vec![0; 32_768];
+            let mut window_len = 0;
+            assert_eq!(
+                mz_inflateGetDictionary(&mut stream, window.as_mut_ptr(), &mut window_len),
+                MZStatus::Ok as i32
+            );
+            assert_eq!(window_len, 32_768);
+            assert_eq!(window[12_768..], input[..]);
+
+            // Decompress the same data again after a reset.
+            assert_eq!(mz_inflateReset(&mut stream), MZStatus::Ok as i32);
+            assert_eq!(stream.total_out, 0);
+            stream.next_in = compressed.as_mut_ptr();
+            stream.avail_in = compressed_size as u32;
+            stream.next_out = decompressed.as_mut_ptr();
+            stream.avail_out = decompressed.len() as u32;
+            decompressed.iter_mut().for_each(|b| *b = 0);
+        }
+        assert_eq!(mz_inflateEnd(&mut stream), MZStatus::Ok as i32);
+    }
+}
+
+/// Copy streams, and check pending output and priming using the C API.
+#[test]
+fn c_api_copy_pending_prime() {
+    use miniz_oxide::inflate::decompress_to_vec;
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_deflate, mz_deflateCopy, mz_deflateEnd, mz_deflateInit2, mz_deflatePending,
+        mz_deflatePrime, mz_inflate, mz_inflateCopy, mz_inflateEnd, mz_inflateInit2,
+        mz_inflateReset2, mz_stream,
+    };
+    use std::mem::MaybeUninit;
+
+    let mut data = get_test_data();
+    let mut compressed = vec![0; data.len() * 2];
+    let mut compressed_copy = vec![0; data.len() * 2];
+    unsafe {
+        let mut stream = mz_stream {
+            next_in: data.as_mut_ptr(),
+            avail_in: data.len() as u32 / 2,
+            next_out: compressed.as_mut_ptr(),
+            avail_out: 10,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            mz_deflateInit2(&mut stream, 6, 8, -15, 9, 0),
+            MZStatus::Ok as i32
+        );
+        // The primed bits come first in the output.
+        assert_eq!(mz_deflatePrime(&mut stream, 8, 0x5A), MZStatus::Ok as i32);
+        assert_eq!(mz_deflatePrime(&mut stream, 17, 0), MZError::Stream as i32);
+        assert_eq!(mz_deflate(&mut stream, 2), MZStatus::Ok as i32);
+        let (mut pending, mut bits) = (0, 0);
+        assert_eq!(
+            mz_deflatePending(&mut stream, &mut pending, &mut bits),
+            MZStatus::Ok as i32
+        );
+        assert!(pending > 0);
+        assert_eq!(bits, 0);
+        stream.avail_out = (compressed.len() - 10) as u32;
+
+        let mut copy = MaybeUninit::<mz_stream>::uninit();
+        assert_eq!(
+            mz_deflateCopy(copy.as_mut_ptr(), &mut stream),
+            MZStatus::Ok as i32
+        );
+        let mut copy = copy.assume_init();
+        copy.next_out = compressed_copy.as_mut_ptr().add(10);
+        compressed_copy[..10].copy_from_slice(&compressed[..10]);
+        for stream in [&mut stream, &mut copy].iter_mut() {
+            stream.avail_in = (data.len() - stream.total_in as usize) as u32;
+            assert_eq!(mz_deflate(*stream, 4), MZStatus::StreamEnd as i32);
+        }
+        let compressed_size = stream.total_out as usize;
+        assert_eq!(copy.total_out as usize, compressed_size);
+        assert_eq!(mz_deflateEnd(&mut stream), MZStatus::Ok as i32);
+        assert_eq!(mz_deflateEnd(&mut copy), MZStatus::Ok as i32);
+        assert!(compressed[..compressed_size] == compressed_copy[..compressed_size]);
+        assert_eq!(compressed[0], 0x5A);
+        assert!(decompress_to_vec(&compressed[1..compressed_size]).unwrap() == data);
+
+        // Decompress half in one stream, and the rest in a copy.
+        let mut decompressed = vec![0; data.len()];
+        let mut stream = mz_stream {
+            next_in: compressed.as_mut_ptr().add(1),
+            avail_in: compressed_size as u32 / 2,
+            next_out: decompressed.as_mut_ptr(),
+            avail_out: decompressed.len() as u32,
+            ..Default::default()
+        };
+        assert_eq!(mz_inflateInit2(&mut stream, 15), MZStatus::Ok as i32);
+        assert_eq!(mz_inflateReset2(&mut stream, -15), MZStatus::Ok as i32);
+        assert_eq!(mz_inflate(&mut stream, 0), MZStatus::Ok as i32);
+        let mut copy = MaybeUninit::<mz_stream>::uninit();
+        assert_eq!(
+            mz_inflateCopy(copy.as_mut_ptr(), &mut stream),
+            MZStatus::Ok as i32
+        );
+        assert_eq!(mz_inflateEnd(&mut stream), MZStatus::Ok as i32);
+        let mut copy = copy.assume_init();
+        copy.avail_in = (compressed_size - 1 - copy.total_in as usize) as u32;
+        assert_eq!(mz_inflate(&mut copy, 4), MZStatus::StreamEnd as i32);
+        assert_eq!(mz_inflateEnd(&mut copy), MZStatus::Ok as i32);
+        assert!(decompressed == data);
+    }
+}
+
+/// Compress with a gzip wrapper and a small window, and decompress detecting the wrapper.
+#[test]
+fn c_api_gzip_and_window_bits() {
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_crc32, mz_deflate, mz_deflateEnd, mz_deflateInit2, mz_inflate, mz_inflateEnd,
+        mz_inflateInit2, mz_stream,
+    };
+
+    let mut data = get_test_data();
+    for &(window_bits, header) in &[(31, [0x1f, 0x8b]), (11, [0x38, 0x8d])] {
+        let mut compressed = vec![0; data.len() * 2];
+        let mut decompressed = vec![0; data.len()];
+        unsafe {
+            let mut stream = mz_stream {
+                next_in: data.as_mut_ptr(),
+                avail_in: data.len() as u32,
+                next_out: compressed.as_mut_ptr(),
+                avail_out: compressed.len() as u32,
+                ..Default::default()
+            };
+            assert_eq!(
+                mz_deflateInit2(&mut stream, 6, 8, window_bits, 9, 0),
+                MZStatus::Ok as i32
+            );
+            assert_eq!(mz_deflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+            let compressed_size = stream.total_out as usize;
+            assert_eq!(mz_deflateEnd(&mut stream), MZStatus::Ok as i32);
+            assert_eq!(compressed[..2], header);
+
+            let mut stream = mz_stream {
+                next_in: compressed.as_mut_ptr(),
+                avail_in: compressed_size as u32,
+                next_out: decompressed.as_mut_ptr(),
+                avail_out: decompressed.len() as u32,
+                ..Default::default()
+            };
+            assert_eq!(mz_inflateInit2(&mut stream, 47), MZStatus::Ok as i32);
+            assert_eq!(mz_inflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+            if window_bits > 15 {
+                let crc = mz_crc32(0, data.as_ptr(), data.len());
+                assert_eq!(stream.adler, crc);
+            }
+            assert_eq!(mz_inflateEnd(&mut stream), MZStatus::Ok as i32);
+            assert!(decompressed == data);
+        }
+    }
+
+    let mut stream = mz_stream::default();
+    unsafe {
+        // Detecting the wrapper is only possible when decompressing, and gzip needs 9 bits.
+        assert_eq!(
+            mz_deflateInit2(&mut stream, 6, 8, 47, 9, 0),
+            MZError::Param as i32
+        );
+        assert_eq!(
+            mz_deflateInit2(&mut stream, 6, 8, 24, 9, 0),
+            MZError::Param as i32
+        );
+        // Only the zlib strategies are accepted.
+        for &strategy in [-1, 5].iter() {
+            assert_eq!(
+                mz_deflateInit2(&mut stream, 6, 8, 15, 9, strategy),
+                MZError::Param as i32
+            );
+        }
+        assert_eq!(mz_inflateInit2(&mut stream, 48), MZError::Param as i32);
+    }
+}
+
+/// Allocation counts and limit used by `counting_alloc` and `counting_free`.
+#[derive(Default)]
+struct AllocStats {
+    allocs: usize,
+    frees: usize,
+    /// Number of allocations to allow before failing.
+    limit: usize,
+}
+
+unsafe extern "C" fn counting_alloc(
+    opaque: *mut std::os::raw::c_void,
+    items: usize,
+    size: usize,
+) -> *mut std::os::raw::c_void {
+    let stats = &mut *(opaque as *mut AllocStats);
+    if stats.allocs == stats.limit {
+        return std::ptr::null_mut();
+    }
+    stats.allocs += 1;
+    miniz_oxide_c_api::miniz_def_alloc_func(std::ptr::null_mut(), items, size)
+}
+
+unsafe extern "C" fn counting_free(
+    opaque: *mut std::os::raw::c_void,
+    address: *mut std::os::raw::c_void,
+) {
+    (*(opaque as *mut AllocStats)).frees += 1;
+    miniz_oxide_c_api::miniz_def_free_func(std::ptr::null_mut(), address)
+}
+
+/// Check that streams allocate their state with the allocation functions of the stream, and
+/// fail cleanly when they return null.
+#[test]
+fn c_api_allocators() {
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_deflate, mz_deflateCopy, mz_deflateEnd, mz_deflateInit, mz_inflate, mz_inflateEnd,
+        mz_inflateInit, mz_stream,
+    };
+    use std::mem::MaybeUninit;
+
+    let mut data = get_test_data();
+    let mut compressed = vec![0; data.len() * 2];
+    let mut decompressed = vec![0; data.len()];
+    let mut stats = AllocStats {
+        limit: usize::max_value(),
+        ..Default::default()
+    };
+    unsafe {
+        let mut stream = mz_stream {
+            next_in: data.as_mut_ptr(),
+            avail_in: data.len() as u32,
+            next_out: compressed.as_mut_ptr(),
+            avail_out: compressed.len() as u32,
+            zalloc: Some(counting_alloc),
+            zfree: Some(counting_free),
+            opaque: &mut stats as *mut AllocStats as *mut _,
+            ..Default::default()
+        };
+        assert_eq!(mz_deflateInit(&mut stream, 6), MZStatus::Ok as i32);
+        let init_allocs = stats.allocs;
+        assert!(init_allocs > 0);
+        let mut copy = MaybeUninit::<mz_stream>::uninit();
+        assert_eq!(
+            mz_deflateCopy(copy.as_mut_ptr(), &mut stream),
+            MZStatus::Ok as i32
+        );
+        assert_eq!(stats.allocs, init_allocs * 2);
+        assert_eq!(mz_deflateEnd(&mut copy.assume_init()), MZStatus::Ok as i32);
+        assert_eq!(mz_deflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+        assert_eq!(mz_deflateEnd(&mut stream), MZStatus::Ok as i32);
+        assert_eq!(stats.allocs, init_allocs * 2);
+        assert_eq!(stats.frees, stats.allocs);
+        let compressed_size = stream.total_out as usize;
+
+        let mut stream = mz_stream {
+            next_in: compressed.as_mut_ptr(),
+            avail_in: compressed_size as u32,
+            next_out: decompressed.as_mut_ptr(),
+            avail_out: decompressed.len() as u32,
+            zalloc: Some(counting_alloc),
+            zfree: Some(counting_free),
+            opaque: &mut stats as *mut AllocStats as *mut _,
+            ..Default::default()
+        };
+        assert_eq!(mz_inflateInit(&mut stream), MZStatus::Ok as i32);
+        assert!(stats.frees < stats.allocs);
+        assert_eq!(mz_inflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+        assert_eq!(mz_inflateEnd(&mut stream), MZStatus::Ok as i32);
+        assert_eq!(stats.frees, stats.allocs);
+        assert!(decompressed == data);
+
+        // Fail each allocation in turn, which must not leak the earlier ones.
+        for limit in 0..init_allocs {
+            let mut stats = AllocStats {
+                limit,
+                ..Default::default()
+            };
+            let mut stream = mz_stream {
+                zalloc: Some(counting_alloc),
+                zfree: Some(counting_free),
+                opaque: &mut stats as *mut AllocStats as *mut _,
+                ..Default::default()
+            };
+            assert_eq!(mz_deflateInit(&mut stream, 6), MZError::Mem as i32);
+            assert!(stream.state.is_none());
+            assert_eq!(stats.frees, stats.allocs);
+            assert_eq!(mz_inflateInit(&mut stream), MZError::Mem as i32);
+            assert_eq!(stats.frees, stats.allocs);
+        }
+    }
+}
+
+/// Check the messages set in `msg` when decompression fails.
+#[test]
+fn c_api_error_messages() {
+    use miniz_oxide::deflate::compress_to_vec_zlib;
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_error, mz_inflate, mz_inflateEnd, mz_inflateInit, mz_stream, mz_version,
+    };
+    use std::ffi::CStr;
+
+    unsafe fn inflate_error(mut data: Vec<u8>) -> String {
+        let mut output = vec![0; 1024];
+        let mut stream = mz_stream {
+            next_in: data.as_mut_ptr(),
+            avail_in: data.len() as u32,
+            next_out: output.as_mut_ptr(),
+            avail_out: output.len() as u32,
+            ..Default::default()
+        };
+        assert_eq!(mz_inflateInit(&mut stream), MZStatus::Ok as i32);
+        assert!(stream.msg.is_null());
+        assert_eq!(mz_inflate(&mut stream, 4), MZError::Data as i32);
+        assert_eq!(mz_inflateEnd(&mut stream), MZStatus::Ok as i32);
+        CStr::from_ptr(stream.msg).to_str().unwrap().to_string()
+    }
+
+    let mut bad_checksum = compress_to_vec_zlib(b"Hello, world!", 6);
+    *bad_checksum.last_mut().unwrap() ^= 1;
+    unsafe {
+        assert_eq!(inflate_error(vec![0, 0]), "incorrect header check");
+        assert_eq!(inflate_error(vec![0x78, 0x01, 7]), "invalid block type");
+        assert_eq!(inflate_error(bad_checksum), "incorrect data check");
+
+        let message = |code: MZError| CStr::from_ptr(mz_error(code as i32)).to_str().unwrap();
+        assert_eq!(message(MZError::Data), "data error");
+        assert_eq!(message(MZError::Param), "parameter error");
+        assert!(mz_error(7).is_null());
+        assert_eq!(CStr::from_ptr(mz_version()).to_str().unwrap(), "0.0");
+    }
+}
+
+/// Check that the sizes of the structs C code can allocate itself match `miniz.h`.
+#[test]
+fn c_header_struct_sizes() {
+    use miniz_oxide_c_api::{
+        tdefl_compressor, tinfl_decompressor, TDEFL_COMPRESSOR_SIZE, TINFL_DECOMPRESSOR_SIZE,
+    };
+    use std::mem;
+
+    let header = String::from_utf8(get_test_file_data("miniz.h")).unwrap();
+    let define = |name: &str| -> usize {
+        let prefix = format!("#define {} ", name);
+        let line = header.lines().find(|l| l.starts_with(&prefix)).unwrap();
+        line[prefix.len()..].trim().parse().unwrap()
+    };
+    assert_eq!(define("TDEFL_COMPRESSOR_SIZE"), TDEFL_COMPRESSOR_SIZE);
+    assert_eq!(define("TINFL_DECOMPRESSOR_SIZE"), TINFL_DECOMPRESSOR_SIZE);
+    assert_eq!(mem::size_of::<tdefl_compressor>(), TDEFL_COMPRESSOR_SIZE);
+    assert_eq!(
+        mem::size_of::<tinfl_decompressor>(),
+        TINFL_DECOMPRESSOR_SIZE
+    );
+    assert_eq!(mem::align_of::<tdefl_compressor>(), mem::align_of::<u64>());
+    assert_eq!(
+        mem::align_of::<tinfl_decompressor>(),
+        mem::align_of::<u64>()
+    );
+}
+
+/// Input for the `mz_inflateBack` callbacks, handed out in chunks.
+struct BackInput<'a> {
+    chunks: std::slice::Chunks<'a, u8>,
+}
+
+unsafe extern "C" fn back_in(desc: *mut c_void, buf: *mut *const u8) -> c_uint {
+    let input = &mut *(desc as *mut BackInput);
+    match input.chunks.next() {
+        Some(chunk) => {
+            *buf = chunk.as_ptr();
+            chunk.len() as c_uint
+        }
+        None => 0,
+    }
+}
+
+unsafe extern "C" fn back_out(desc: *mut c_void, buf: *mut u8, len: c_uint) -> i32 {
+    let output = &mut *(desc as *mut Vec<u8>);
+    output.extend_from_slice(std::slice::from_raw_parts(buf, len as usize));
+    0
+}
+
+/// Decompress with callbacks and a window given by the caller using the C API.
+#[test]
+fn c_api_inflate_back() {
+    use miniz_oxide::deflate::compress_to_vec;
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_inflate, mz_inflateBack, mz_inflateBackEnd, mz_inflateBackInit, mz_stream,
+    };
+    use std::ffi::CStr;
+
+    let data = get_test_data();
+    let mut compressed = compress_to_vec(&data, 6);
+    let mut window = vec![0u8; 1 << 15];
+    unsafe {
+        let mut stream = mz_stream::default();
+        assert_eq!(
+            mz_inflateBackInit(&mut stream, 16, window.as_mut_ptr()),
+            MZError::Stream as i32
+        );
+        assert_eq!(
+            mz_inflateBackInit(&mut stream, 15, window.as_mut_ptr()),
+            MZStatus::Ok as i32
+        );
+
+        // Part of the input is given in `next_in`, and there is data after the stream.
+        let split = compressed.len() / 3;
+        compressed.extend_from_slice(b"trailer");
+        stream.next_in = compressed.as_ptr();
+        stream.avail_in = split as u32;
+        let mut input = BackInput {
+            chunks: compressed[split..].chunks(1000),
+        };
+        let mut output = Vec::new();
+        assert_eq!(
+            mz_inflateBack(
+                &mut stream,
+                Some(back_in),
+                &mut input as *mut BackInput as *mut _,
+                Some(back_out),
+                &mut output as *mut Vec<u8> as *mut _,
+            ),
+            MZStatus::StreamEnd as i32
+        );
+        assert!(output == data);
+        let rest = std::slice::from_raw_parts(stream.next_in, stream.avail_in as usize);
+        assert!(b"trailer".ends_with(rest));
+
+        // Running out of input.
+        let truncated = &compressed[..split];
+        stream.next_in = std::ptr::null();
+        stream.avail_in = 0;
+        let mut input = BackInput {
+            chunks: truncated.chunks(1000),
+        };
+        let mut output = Vec::new();
+        assert_eq!(
+            mz_inflateBack(
+                &mut stream,
+                Some(back_in),
+                &mut input as *mut BackInput as *mut _,
+                Some(back_out),
+                &mut output as *mut Vec<u8> as *mut _,
+            ),
+            MZError::Buf as i32
+        );
+        assert!(stream.next_in.is_null());
+        assert!(!output.is_empty() && data.starts_with(&output));
+
+        // Invalid data.
+        let invalid = [0xffu8; 4];
+        let mut input = BackInput {
+            chunks: invalid.chunks(4),
+        };
+        assert_eq!(
+            mz_inflateBack(
+                &mut stream,
+                Some(back_in),
+                &mut input as *mut BackInput as *mut _,
+                Some(back_out),
+                &mut Vec::<u8>::new() as *mut Vec<u8> as *mut _,
+            ),
+            MZError::Data as i32
+        );
+        assert_eq!(
+            CStr::from_ptr(stream.msg).to_str(),
+            Ok("invalid block type")
+        );
+
+        // The stream can't be used with the other decompression functions.
+        assert_eq!(mz_inflate(&mut stream, 0), MZError::Param as i32);
+        assert_eq!(mz_inflateBackEnd(&mut stream), MZStatus::Ok as i32);
+        assert!(stream.state.is_none());
+
+        // The data refers further back than a 256 byte window.
+        assert_eq!(
+            mz_inflateBackInit(&mut stream, 8, window.as_mut_ptr()),
+            MZStatus::Ok as i32
+        );
+        stream.next_in = std::ptr::null();
+        stream.avail_in = 0;
+        let mut input = BackInput {
+            chunks: compressed.chunks(1000),
+        };
+        assert_eq!(
+            mz_inflateBack(
+                &mut stream,
+                Some(back_in),
+                &mut input as *mut BackInput as *mut _,
+                Some(back_out),
+                &mut Vec::<u8>::new() as *mut Vec<u8> as *mut _,
+            ),
+            MZError::Data as i32
+        );
+        assert_eq!(
+            CStr::from_ptr(stream.msg).to_str(),
+            Ok("invalid distance too far back")
+        );
+        assert_eq!(mz_inflateBackEnd(&mut stream), MZStatus::Ok as i32);
+    }
+}
@@ -0,0 +1,48 @@
+/* This library (excluding the miniz C code used for tests) is licensed under the MIT license. The library is based on the miniz C library, of which the parts used are dual-licensed under the MIT license and also the unlicense. The parts of miniz that are not covered by the unlicense is some Zip64 code which is only MIT licensed. This and other Zip functionality in miniz is not part of the miniz_oxidde and miniz_oxide_c_api rust libraries.*/
+
+/* Types used by zlib.h, for use with miniz_oxide_c_api built with the `zlib_compat` feature.
+ * The types must match the ones used in src/zlib.rs.
+ */
+
+#pragma once
+
+#include <stddef.h>
+#include <sys/types.h>
+
+#define MAX_MEM_LEVEL 9
+#define MAX_WBITS 15
+
+#ifndef OF
+#define OF(args) args
+#endif
+
+#ifndef Z_ARG
+#define Z_ARG(args) args
+#endif
+
+#define z_const const
+#define FAR
+#define ZEXTERN extern
+#define ZEXPORT
+#define ZEXPORTVA
+
+typedef unsigned char Byte;
+typedef unsigned int uInt;
+typedef unsigned long uLong;
+
+typedef Byte FAR Bytef;
+typedef char FAR charf;
+typedef int FAR intf;
+typedef uInt FAR uIntf;
+typedef uLong FAR uLongf;
+
+typedef void const *voidpc;
+typedef void FAR *voidpf;
+typedef void *voidp;
+
+typedef size_t z_size_t;
+typedef unsigned int z_crc_t;
+
+#ifndef z_off_t
+#define z_off_t long
+#endif
@@ -0,0 +1,197 @@
+/* This library (excluding the miniz C code used for tests) is licensed under the MIT license. The library is based on the miniz C library, of which the parts used are dual-licensed under the MIT license and also the unlicense. The parts of miniz that are not covered by the unlicense is some Zip64 code which is only MIT licensed. This and other Zip functionality in miniz is not part of the miniz_oxidde and miniz_oxide_c_api rust libraries.*/
+
+/* zlib compatible interface of miniz_oxide_c_api, available when it is built with the
+ * `zlib_compat` feature. The declarations must match the ones in src/zlib.rs.
+ *
+ * Differences from zlib:
+ *   - Compression uses a window of at least 10 bits, so windowBits 8 and 9 give a 10 bit header.
+ *   - gzprintf and transparent writing (mode "T") are not supported.
+ */
+
+#pragma once
+
+#include "zconf.h"
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define ZLIB_VERSION "1.2.11"
+#define ZLIB_VERNUM 0x12b0
+#define ZLIB_VER_MAJOR 1
+#define ZLIB_VER_MINOR 2
+#define ZLIB_VER_REVISION 11
+#define ZLIB_VER_SUBREVISION 0
+
+typedef voidpf (*alloc_func) OF((voidpf opaque, uInt items, uInt size));
+typedef void (*free_func) OF((voidpf opaque, voidpf address));
+
+struct internal_state;
+
+typedef struct z_stream_s {
+    z_const Bytef *next_in; /* next input byte */
+    uInt avail_in;          /* number of bytes available at next_in */
+    uLong total_in;         /* total number of input bytes read so far */
+
+    Bytef *next_out; /* next output byte will go here */
+    uInt avail_out;  /* remaining free space at next_out */
+    uLong total_out; /* total number of bytes output so far */
+
+    z_const char *msg;                    /* last error message, NULL if no error */
+    struct internal_state FAR *state;     /* not visible by applications */
+
+    alloc_func zalloc; /* used to allocate the internal state */
+    free_func zfree;   /* used to free the internal state */
+    voidpf opaque;     /* private data object passed to zalloc and zfree */
+
+    int data_type; /* used internally to tell compressors and decompressors apart */
+    uLong adler;   /* Adler-32 value of the uncompressed data */
+    uLong reserved; /* reserved for future use */
+} z_stream;
+
+typedef z_stream FAR *z_streamp;
+
+/* Allowed flush values. */
+#define Z_NO_FLUSH 0
+#define Z_PARTIAL_FLUSH 1
+#define Z_SYNC_FLUSH 2
+#define Z_FULL_FLUSH 3
+#define Z_FINISH 4
+#define Z_BLOCK 5
+#define Z_TREES 6
+
+/* Return codes for the compression/decompression functions. */
+#define Z_OK 0
+#define Z_STREAM_END 1
+#define Z_NEED_DICT 2
+#define Z_ERRNO (-1)
+#define Z_STREAM_ERROR (-2)
+#define Z_DATA_ERROR (-3)
+#define Z_MEM_ERROR (-4)
+#define Z_BUF_ERROR (-5)
+#define Z_VERSION_ERROR (-6)
+
+/* Compression levels. */
+#define Z_NO_COMPRESSION 0
+#define Z_BEST_SPEED 1
+#define Z_BEST_COMPRESSION 9
+#define Z_DEFAULT_COMPRESSION (-1)
+
+/* Compression strategies. */
+#define Z_FILTERED 1
+#define Z_HUFFMAN_ONLY 2
+#define Z_RLE 3
+#define Z_FIXED 4
+#define Z_DEFAULT_STRATEGY 0
+
+/* Possible values of the data_type field for deflate(). */
+#define Z_BINARY 0
+#define Z_TEXT 1
+#define Z_ASCII Z_TEXT
+#define Z_UNKNOWN 2
+
+/* The deflate compression method. */
+#define Z_DEFLATED 8
+
+#define Z_NULL 0
+
+#define zlib_version zlibVersion()
+
+ZEXTERN const char *ZEXPORT zlibVersion OF((void));
+ZEXTERN uLong ZEXPORT zlibCompileFlags OF((void));
+ZEXTERN const char *ZEXPORT zError OF((int err));
+
+ZEXTERN int ZEXPORT deflateInit_ OF((z_streamp strm, int level, const char *version,
+                                     int stream_size));
+ZEXTERN int ZEXPORT deflateInit2_ OF((z_streamp strm, int level, int method, int windowBits,
+                                      int memLevel, int strategy, const char *version,
+                                      int stream_size));
+ZEXTERN int ZEXPORT deflate OF((z_streamp strm, int flush));
+ZEXTERN int ZEXPORT deflateEnd OF((z_streamp strm));
+ZEXTERN int ZEXPORT deflateReset OF((z_streamp strm));
+ZEXTERN int ZEXPORT deflateParams OF((z_streamp strm, int level, int strategy));
+ZEXTERN uLong ZEXPORT deflateBound OF((z_streamp strm, uLong sourceLen));
+ZEXTERN int ZEXPORT deflateSetDictionary OF((z_streamp strm, const Bytef *dictionary,
+                                             uInt dictLength));
+ZEXTERN int ZEXPORT deflateCopy OF((z_streamp dest, z_streamp source));
+ZEXTERN int ZEXPORT deflatePending OF((z_streamp strm, unsigned *pending, int *bits));
+ZEXTERN int ZEXPORT deflatePrime OF((z_streamp strm, int bits, int value));
+ZEXTERN int ZEXPORT deflateTune OF((z_streamp strm, int good_length, int max_lazy,
+                                    int nice_length, int max_chain));
+
+ZEXTERN int ZEXPORT inflateInit_ OF((z_streamp strm, const char *version, int stream_size));
+ZEXTERN int ZEXPORT inflateInit2_ OF((z_streamp strm, int windowBits, const char *version,
+                                      int stream_size));
+ZEXTERN int ZEXPORT inflate OF((z_streamp strm, int flush));
+ZEXTERN int ZEXPORT inflateEnd OF((z_streamp strm));
+ZEXTERN int ZEXPORT inflateReset OF((z_streamp strm));
+ZEXTERN int ZEXPORT inflateReset2 OF((z_streamp strm, int windowBits));
+ZEXTERN int ZEXPORT inflateSetDictionary OF((z_streamp strm, const Bytef *dictionary,
+                                             uInt dictLength));
+ZEXTERN int ZEXPORT inflateGetDictionary OF((z_streamp strm, Bytef *dictionary,
+                                             uInt *dictLength));
+ZEXTERN int ZEXPORT inflateCopy OF((z_streamp dest, z_streamp source));
+
+typedef unsigned (*in_func) OF((void FAR *, z_const unsigned char FAR * FAR *));
+typedef int (*out_func) OF((void FAR *, unsigned char FAR *, unsigned));
+
+ZEXTERN int ZEXPORT inflateBackInit_ OF((z_streamp strm, int windowBits, unsigned char FAR *window,
+                                         const char *version, int stream_size));
+ZEXTERN int ZEXPORT inflateBack OF((z_streamp strm, in_func in, void FAR *in_desc, out_func out,
+                                    void FAR *out_desc));
+ZEXTERN int ZEXPORT inflateBackEnd OF((z_streamp strm));
+
+#define deflateInit(strm, level) \
+    deflateInit_((strm), (level), ZLIB_VERSION, (int)sizeof(z_stream))
+#define deflateInit2(strm, level, method, windowBits, memLevel, strategy)                  \
+    deflateInit2_((strm), (level), (method), (windowBits), (memLevel), (strategy), ZLIB_VERSION, \
+                  (int)sizeof(z_stream))
+#define inflateInit(strm) inflateInit_((strm), ZLIB_VERSION, (int)sizeof(z_stream))
+#define inflateInit2(strm, windowBits) \
+    inflateInit2_((strm), (windowBits), ZLIB_VERSION, (int)sizeof(z_stream))
+#define inflateBackInit(strm, windowBits, window) \
+    inflateBackInit_((strm), (windowBits), (window), ZLIB_VERSION, (int)sizeof(z_stream))
+
+ZEXTERN int ZEXPORT compress OF((Bytef *dest, uLongf *destLen, const Bytef *source,
+                                 uLong sourceLen));
+ZEXTERN int ZEXPORT compress2 OF((Bytef *dest, uLongf *destLen, const Bytef *source,
+                                  uLong sourceLen, int level));
+ZEXTERN uLong ZEXPORT compressBound OF((uLong sourceLen));
+ZEXTERN int ZEXPORT uncompress OF((Bytef *dest, uLongf *destLen, const Bytef *source,
+                                   uLong sourceLen));
+
+ZEXTERN uLong ZEXPORT adler32 OF((uLong adler, const Bytef *buf, uInt len));
+ZEXTERN uLong ZEXPORT adler32_z OF((uLong adler, const Bytef *buf, z_size_t len));
+ZEXTERN uLong ZEXPORT adler32_combine OF((uLong adler1, uLong adler2, z_off_t len2));
+ZEXTERN uLong ZEXPORT crc32 OF((uLong crc, const Bytef *buf, uInt len));
+ZEXTERN uLong ZEXPORT crc32_z OF((uLong crc, const Bytef *buf, z_size_t len));
+ZEXTERN uLong ZEXPORT crc32_combine OF((uLong crc1, uLong crc2, z_off_t len2));
+
+/* Only the position is meaningful, the buffer used by zlib's gzgetc macro is always empty. */
+struct gzFile_s {
+    unsigned have;
+    unsigned char *next;
+    long long pos;
+};
+typedef struct gzFile_s *gzFile;
+
+ZEXTERN gzFile ZEXPORT gzopen OF((const char *path, const char *mode));
+ZEXTERN gzFile ZEXPORT gzdopen OF((int fd, const char *mode));
+ZEXTERN int ZEXPORT gzread OF((gzFile file, voidp buf, unsigned len));
+ZEXTERN int ZEXPORT gzgetc OF((gzFile file));
+ZEXTERN char *ZEXPORT gzgets OF((gzFile file, char *buf, int len));
+ZEXTERN int ZEXPORT gzwrite OF((gzFile file, voidpc buf, unsigned len));
+ZEXTERN int ZEXPORT gzputs OF((gzFile file, const char *s));
+ZEXTERN int ZEXPORT gzputc OF((gzFile file, int c));
+ZEXTERN int ZEXPORT gzflush OF((gzFile file, int flush));
+ZEXTERN z_off_t ZEXPORT gzseek OF((gzFile file, z_off_t offset, int whence));
+ZEXTERN int ZEXPORT gzrewind OF((gzFile file));
+ZEXTERN z_off_t ZEXPORT gztell OF((gzFile file));
+ZEXTERN int ZEXPORT gzeof OF((gzFile file));
+ZEXTERN const char *ZEXPORT gzerror OF((gzFile file, int *errnum));
+ZEXTERN void ZEXPORT gzclearerr OF((gzFile file));
+ZEXTERN int ZEXPORT gzclose OF((gzFile file));
+
+#ifdef __cplusplus
+}
+#endif